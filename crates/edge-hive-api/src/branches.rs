@@ -0,0 +1,210 @@
+//! Branch routing
+//!
+//! Branches of an API's database are served under `/b/<branch>`, by a router
+//! built from a copy of the API's state that uses the branch's database. The
+//! branch accepts the same tokens, enforces the same policies and uses the
+//! same mail, login providers, passkeys and job options as its base, so a
+//! preview behaves like the API it was branched from. Routers are
+//! opened on the first request to a branch.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use edge_hive_auth::{OidcClient, TokenGenerator, TokenValidator, Webauthn};
+use edge_hive_cache::{CacheConfig, CacheService};
+use edge_hive_db::{Branch, DatabaseService, DbError, PolicySet};
+use edge_hive_identity::NodeIdentity;
+use edge_hive_realtime::{RealtimeServer, RealtimeServerConfig};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tower::ServiceExt;
+
+use crate::jobs::JobOptions;
+use crate::mail::Mailer;
+use crate::state::ApiState;
+
+/// Path prefix of branch requests
+pub const BRANCH_PREFIX: &str = "/b/";
+
+/// Branches served by one API
+pub struct BranchRegistry {
+    db: Arc<DatabaseService>,
+    data_dir: PathBuf,
+    token_generator: Arc<TokenGenerator>,
+    token_validator: Arc<TokenValidator>,
+    policies: Arc<PolicySet>,
+    identity: Option<Arc<NodeIdentity>>,
+    jobs: Option<JobOptions>,
+    mailer: Option<Arc<Mailer>>,
+    oidc: Option<Arc<OidcClient>>,
+    webauthn: Option<Arc<Webauthn>>,
+    routes: RwLock<HashMap<String, Router>>,
+}
+
+impl BranchRegistry {
+    /// Registry serving the branches of the API's database
+    pub fn new(base: &ApiState) -> Self {
+        Self {
+            db: base.db.clone(),
+            data_dir: base.data_dir.clone(),
+            token_generator: base.token_generator.clone(),
+            token_validator: base.token_validator.clone(),
+            policies: base.policies.clone(),
+            identity: base.identity.clone(),
+            jobs: base.jobs.clone(),
+            mailer: base.mailer.clone(),
+            oidc: base.oidc.clone(),
+            webauthn: base.webauthn.clone(),
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Router of a branch, opening it if needed; `None` if there is no such branch
+    pub async fn router(&self, name: &str) -> Result<Option<Router>, DbError> {
+        if let Some(router) = self.read().get(name) {
+            return Ok(Some(router.clone()));
+        }
+        let Some(branch) = self.db.get_branch(name).await? else {
+            return Ok(None);
+        };
+
+        let router = crate::create_router(self.state(&branch).await?);
+        tracing::info!("🌿 Serving branch '{}'", branch.name);
+        self.write().insert(branch.name, router.clone());
+        Ok(Some(router))
+    }
+
+    /// State the handlers of a branch run with
+    async fn state(&self, branch: &Branch) -> Result<ApiState, DbError> {
+        let db = Arc::new(self.db.open_branch(branch).await?);
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let mut state = ApiState::new(
+            cache,
+            db.clone(),
+            RealtimeServer::new(RealtimeServerConfig::default()).with_db(db),
+            self.data_dir.join("branches").join(&branch.name),
+            self.token_generator.as_ref().clone(),
+            self.token_validator.as_ref().clone(),
+        )
+        .with_policies(self.policies.as_ref().clone());
+        state.identity = self.identity.clone();
+        state.jobs = self.jobs.clone();
+        state.mailer = self.mailer.clone();
+        state.oidc = self.oidc.clone();
+        state.webauthn = self.webauthn.clone();
+        Ok(state)
+    }
+
+    /// Stop serving a branch; `false` if it was not served
+    pub fn close(&self, name: &str) -> bool {
+        self.write().remove(name).is_some()
+    }
+
+    /// Names of the served branches
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Router>> {
+        self.routes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Router>> {
+        self.routes.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Branch name and remaining path of a branch request
+fn split_branch_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(BRANCH_PREFIX)?;
+    let (name, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    Some((name, if path.is_empty() { "/" } else { path }))
+}
+
+/// Send branch requests to their branch's router
+pub async fn route_branches(
+    State(registry): State<Arc<BranchRegistry>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some((name, path)) = split_branch_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let name = name.to_string();
+    let Some(uri) = crate::projects::strip_uri(request.uri(), path) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let router = match registry.router(&name).await {
+        Ok(Some(router)) => router,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to open branch '{}': {}", name, e);
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+
+    *request.uri_mut() = uri;
+    match router.oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_split_branch_path() {
+        assert_eq!(
+            split_branch_path("/b/feature-x/api/v1/health"),
+            Some(("feature-x", "/api/v1/health"))
+        );
+        assert_eq!(split_branch_path("/b/feature-x"), Some(("feature-x", "/")));
+        assert_eq!(split_branch_path("/api/v1/health"), None);
+    }
+
+    #[tokio::test]
+    async fn test_opens_existing_branches() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let base = ApiState::new_minimal(cache, db.clone(), tempdir().unwrap().path().into());
+        db.create_branch("feature-x").await.unwrap();
+
+        let registry = BranchRegistry::new(&base);
+        assert!(registry.router("feature-y").await.unwrap().is_none());
+        assert!(registry.router("feature-x").await.unwrap().is_some());
+        assert_eq!(registry.names(), vec!["feature-x"]);
+
+        assert!(registry.close("feature-x"));
+        assert!(registry.names().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_branches_use_the_services_of_their_base() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let base = ApiState::new_minimal(cache, db.clone(), tempdir().unwrap().path().into())
+            .with_webauthn(Webauthn::new(edge_hive_auth::RelyingParty {
+                id: "example.com".to_string(),
+                name: "Example".to_string(),
+                origins: vec!["https://example.com".to_string()],
+            }))
+            .with_oidc(OidcClient::new(Default::default()));
+        let branch = db.create_branch("feature-x").await.unwrap();
+
+        let state = BranchRegistry::new(&base).state(&branch).await.unwrap();
+        assert!(Arc::ptr_eq(state.webauthn.as_ref().unwrap(), base.webauthn.as_ref().unwrap()));
+        assert!(Arc::ptr_eq(state.oidc.as_ref().unwrap(), base.oidc.as_ref().unwrap()));
+    }
+}
@@ -0,0 +1,392 @@
+//! Node administration handlers
+//!
+//! Only callers whose token carries the `admin` role can use these.
+
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Json,
+};
+use edge_hive_auth::JwtClaims;
+use edge_hive_db::{dump, DbError, DumpHeader};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use super::data::{db_error_status, parse_table};
+use crate::mail::MailError;
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+
+/// Largest dump accepted by the restore endpoint
+pub const MAX_DUMP_BYTES: usize = 512 * 1024 * 1024;
+
+pub(crate) fn require_admin(claims: &JwtClaims) -> Result<(), StatusCode> {
+    if claims.role() == "admin" {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+fn dump_error_status(error: DbError) -> StatusCode {
+    tracing::error!("Database dump failed: {}", error);
+    match error {
+        DbError::Dump(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Query parameters of `GET /api/v1/admin/db/dump`
+#[derive(Debug, Default, Deserialize)]
+pub struct DumpParams {
+    /// Encrypt the dump to the node identity
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+/// Download a dump of the whole database
+pub async fn dump_database(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Query(params): Query<DumpParams>,
+) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
+    require_admin(&claims)?;
+
+    let mut body = Vec::new();
+    let dump_header = if params.encrypt {
+        let identity = state.identity.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        let recipient = identity
+            .age_recipient()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        state.db.export_encrypted(&mut body, &recipient).await
+    } else {
+        state.db.export(&mut body).await
+    }
+    .map_err(dump_error_status)?;
+
+    let extension = if params.encrypt { "surql.age" } else { "surql" };
+    let filename = format!(
+        "edge-hive-{}.{}",
+        dump_header.created_at.format("%Y%m%dT%H%M%SZ"),
+        extension
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    if let Ok(disposition) =
+        HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+    {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+
+    Ok((headers, body))
+}
+
+/// Replace the whole database with an uploaded dump
+///
+/// Encrypted dumps are decrypted with the node identity. Every cached data
+/// query is dropped afterwards.
+pub async fn restore_database(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    body: Bytes,
+) -> Result<Json<DumpHeader>, StatusCode> {
+    require_admin(&claims)?;
+
+    let header = if dump::is_encrypted(&body) {
+        let identity = state.identity.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        let key = identity
+            .age_identity()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        state.db.import_encrypted(body.as_ref(), &key).await
+    } else {
+        state.db.import(body.as_ref()).await
+    }
+    .map_err(dump_error_status)?;
+
+    let cache = state.cache.lock().await;
+    cache.delete_pattern("data:*").await;
+
+    Ok(Json(header))
+}
+
+/// Add a data key for the encrypted fields
+///
+/// Values under older keys are re-encrypted in the background and stay
+/// readable meanwhile.
+pub async fn rotate_key(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Value>, StatusCode> {
+    require_admin(&claims)?;
+
+    let version = state.db.rotate_key().await.map_err(|e| match e {
+        DbError::Encryption(_) => StatusCode::SERVICE_UNAVAILABLE,
+        e => db_error_status(e),
+    })?;
+
+    let db = state.db.clone();
+    tokio::spawn(async move {
+        match db.reencrypt().await {
+            Ok(count) => tracing::info!("Re-encrypted {} records under key {}", count, version),
+            Err(e) => tracing::error!("Re-encryption failed: {}", e),
+        }
+    });
+
+    Ok(Json(serde_json::json!({ "version": version })))
+}
+
+/// Recipient of the test message
+#[derive(Debug, Deserialize)]
+pub struct TestEmail {
+    pub to: String,
+}
+
+/// Send the test message to check the SMTP settings
+pub async fn send_test_email(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(body): Json<TestEmail>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+    let Some(mailer) = &state.mailer else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+
+    match mailer.send_test(&body.to).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(MailError::Address(_)) => StatusCode::BAD_REQUEST,
+        Err(e) => {
+            tracing::warn!("Test message to {} failed: {}", body.to, e);
+            StatusCode::BAD_GATEWAY
+        }
+    }
+}
+
+/// List the JSON Schema of every table that has one
+pub async fn list_schemas(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<BTreeMap<String, Value>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.table_schemas().await.map(Json).map_err(db_error_status)
+}
+
+/// Get the JSON Schema of a table
+pub async fn get_schema(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    require_admin(&claims)?;
+    let table = parse_table(&table)?;
+
+    state
+        .db
+        .table_schema(&table)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Set or replace the JSON Schema of a table
+///
+/// Only later writes are validated; existing records are left as they are.
+pub async fn put_schema(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+    Json(schema): Json<Value>,
+) -> Result<Json<Value>, StatusCode> {
+    require_admin(&claims)?;
+    let table = parse_table(&table)?;
+
+    state
+        .db
+        .set_table_schema(&table, &schema)
+        .await
+        .map_err(db_error_status)?;
+
+    Ok(Json(schema))
+}
+
+/// Remove the JSON Schema of a table, making it schemaless again
+pub async fn delete_schema(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+    let table = match parse_table(&table) {
+        Ok(table) => table,
+        Err(status) => return status,
+    };
+
+    match state.db.remove_table_schema(&table).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{BoundQuery, DatabaseService, Table};
+    use edge_hive_identity::NodeIdentity;
+    use std::{path::PathBuf, sync::Arc};
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+            .with_identity(NodeIdentity::generate().unwrap())
+    }
+
+    fn caller(role: &str) -> BearerClaims {
+        let claims =
+            JwtClaims::new("users:root".to_string(), "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    #[tokio::test]
+    async fn test_dump_requires_admin() {
+        let state = setup_test_state().await;
+
+        let result = dump_database(
+            Extension(state),
+            caller("user"),
+            Query(DumpParams::default()),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_send_test_email() {
+        let state = setup_test_state().await;
+        let to = || Json(TestEmail { to: "ops@example.com".to_string() });
+
+        let status = send_test_email(Extension(state.clone()), caller("admin"), to()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let (mailer, inbox) = crate::mail::sink::start().await;
+        let state = state.with_mailer(mailer);
+        let status = send_test_email(Extension(state.clone()), caller("user"), to()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = send_test_email(Extension(state), caller("admin"), to()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(inbox.lock().unwrap()[0].contains("Subject: Edge Hive test message"));
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key() {
+        let state = setup_test_state().await;
+
+        let result = rotate_key(Extension(state.clone()), caller("user")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        // Nothing to rotate until the data keys are unlocked
+        let result = rotate_key(Extension(state.clone()), caller("admin")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let identity = state.identity.as_ref().unwrap().age_identity().unwrap();
+        assert_eq!(state.db.unlock_keys(&identity, &[]).await.unwrap(), 1);
+        let Json(body) = rotate_key(Extension(state), caller("admin")).await.unwrap();
+        assert_eq!(body["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_dump_restores() {
+        let state = setup_test_state().await;
+        let table = Table::new("notes").unwrap();
+        state.db.create_record(&table, serde_json::json!({"text": "keep"})).await.unwrap();
+
+        let (headers, body) = dump_database(
+            Extension(state.clone()),
+            caller("admin"),
+            Query(DumpParams { encrypt: true }),
+        )
+        .await
+        .unwrap();
+        assert!(dump::is_encrypted(&body));
+        assert!(headers[header::CONTENT_DISPOSITION].to_str().unwrap().ends_with(".surql.age\""));
+
+        state.db.create_record(&table, serde_json::json!({"text": "drop"})).await.unwrap();
+        restore_database(Extension(state.clone()), caller("admin"), Bytes::from(body))
+            .await
+            .unwrap();
+
+        let rows = state.db.execute(BoundQuery::new("SELECT * FROM notes")).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["text"], "keep");
+    }
+
+    #[tokio::test]
+    async fn test_restore_rejects_garbage() {
+        let state = setup_test_state().await;
+
+        let result = restore_database(
+            Extension(state),
+            caller("admin"),
+            Bytes::from_static(b"DELETE notes;"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_manage_schemas() {
+        let state = setup_test_state().await;
+        let schema = serde_json::json!({"type": "object", "required": ["title"]});
+
+        let result = put_schema(
+            Extension(state.clone()),
+            caller("user"),
+            Path("posts".to_string()),
+            Json(schema.clone()),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let invalid = put_schema(
+            Extension(state.clone()),
+            caller("admin"),
+            Path("posts".to_string()),
+            Json(serde_json::json!({"type": 12})),
+        )
+        .await;
+        assert_eq!(invalid.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        put_schema(
+            Extension(state.clone()),
+            caller("admin"),
+            Path("posts".to_string()),
+            Json(schema.clone()),
+        )
+        .await
+        .unwrap();
+
+        let Json(schemas) = list_schemas(Extension(state.clone()), caller("admin")).await.unwrap();
+        assert_eq!(schemas["posts"], schema);
+
+        let status =
+            delete_schema(Extension(state.clone()), caller("admin"), Path("posts".to_string()))
+                .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let missing =
+            get_schema(Extension(state), caller("admin"), Path("posts".to_string())).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+}
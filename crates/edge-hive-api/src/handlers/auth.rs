@@ -1,12 +1,41 @@
 //! Authentication handlers
 
+use super::mfa::{begin_session, user_key};
+use crate::mail::MailTemplate;
 use crate::state::ApiState;
-use axum::{extract::Extension, http::StatusCode, response::Json};
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::{Json, Redirect},
+};
 use chrono::{Duration, Utc};
-use edge_hive_db::session::StoredSession;
+use edge_hive_auth::{JwtClaims, OidcClient, OidcUser};
+use edge_hive_db::user::HashedPassword;
+use edge_hive_db::{
+    DbError, OAuthState, SessionRotation, StoredSession, StoredUser, TokenPurpose,
+};
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Lifetime of an access token, in seconds
+const ACCESS_TOKEN_SECS: i64 = 3600;
+
+/// Lifetime of a refresh token, renewed by every rotation
+const REFRESH_TOKEN_DAYS: i64 = 30;
+
+/// Lifetime of an email verification link, in hours
+const VERIFY_EMAIL_HOURS: i64 = 24;
+
+/// Lifetime of a password reset link, in minutes
+const RESET_PASSWORD_MINUTES: i64 = 30;
+
+/// Shortest password accepted
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// How long a login may wait for the identity provider's redirect, in minutes
+const OAUTH_STATE_MINUTES: i64 = 10;
 
 #[derive(Deserialize)]
 #[serde(untagged)]
@@ -16,10 +45,11 @@ pub enum LoginRequest {
         email: String,
         password: String,
     },
-    /// OAuth2 callback
+    /// Redirect back from an identity provider (see `oauth_authorize`)
     OAuth {
         provider: String, // "github", "google"
         code: String,
+        state: String,
     },
 }
 
@@ -27,6 +57,7 @@ pub enum LoginRequest {
 pub struct UserInfo {
     pub email: String,
     pub name: Option<String>,
+    pub email_verified: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -37,6 +68,34 @@ pub struct LoginResponse {
     user: Option<UserInfo>,
 }
 
+/// Outcome of a login: a session, or a second step before it
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum LoginStep {
+    Session(LoginResponse),
+    Mfa(MfaChallenge),
+}
+
+#[cfg(test)]
+impl LoginStep {
+    pub(crate) fn session(self) -> LoginResponse {
+        match self {
+            LoginStep::Session(session) => session,
+            LoginStep::Mfa(challenge) => panic!("expected a session, got {:?}", challenge),
+        }
+    }
+}
+
+/// Second step of a login, completed at `/api/v1/auth/mfa/verify`
+#[derive(Serialize, Debug)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    /// The user's role requires MFA but they have not enrolled yet
+    pub enrollment_required: bool,
+    pub mfa_token: String,
+    pub expires_in: i64,
+}
+
 #[derive(Serialize, Debug)]
 pub struct ErrorResponse {
     error: String,
@@ -47,11 +106,213 @@ pub struct RefreshRequest {
     refresh_token: String,
 }
 
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    refresh_token: String,
+    /// Revoke every session of the user, not just this one
+    #[serde(default)]
+    all: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    email: String,
+    password: String,
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegisterResponse {
+    pub email: String,
+    pub message: String,
+    /// Whether a message was sent to `email`: the verification link, or a
+    /// notice if the address already has an account
+    pub verification_sent: bool,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailParams {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct OAuthCallbackParams {
+    code: String,
+    state: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageResponse {
+    message: String,
+}
+
+pub(crate) type AuthError = (StatusCode, Json<ErrorResponse>);
+
+pub(crate) fn auth_error(status: StatusCode, error: &str) -> AuthError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.into(),
+        }),
+    )
+}
+
+pub(crate) fn db_failure(_: DbError) -> AuthError {
+    auth_error(StatusCode::INTERNAL_SERVER_ERROR, "DB error")
+}
+
+fn message(message: &str) -> Json<MessageResponse> {
+    Json(MessageResponse {
+        message: message.into(),
+    })
+}
+
+/// Registration handler
+///
+/// Sends a verification link to the address when the node can send email.
+/// A registered address gets a notice with a password reset link instead,
+/// and the answer is the same either way so addresses cannot be probed.
+pub async fn register(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<RegisterResponse>), AuthError> {
+    let email = payload.email.trim().to_string();
+    if !is_email(&email) {
+        return Err(auth_error(StatusCode::BAD_REQUEST, "Invalid email"));
+    }
+    check_password(&payload.password)?;
+    // Hashed either way, so registered addresses do not answer faster
+    let password_hash = HashedPassword::new(&payload.password)
+        .map_err(|_| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Password hashing error"))?;
+
+    if let Some(user) = state.db.get_user_by_email(&email).await.map_err(db_failure)? {
+        let sent = match send_auth_mail(&state, &user, MailTemplate::AccountExists).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                warn!("Could not send the account notice to {}: {}", user.email, e);
+                false
+            }
+        };
+        return Ok(registration_accepted(email, sent));
+    }
+
+    let user = StoredUser {
+        id: None,
+        email,
+        name: payload.name,
+        password_hash: password_hash.to_string(),
+        provider: None,
+        provider_id: None,
+        avatar_url: None,
+        role: "user".to_string(),
+        email_verified: false,
+        created_at: Utc::now().into(),
+        updated_at: Utc::now().into(),
+    };
+    let user = state.db.create_user(&user).await.map_err(db_failure)?;
+
+    let sent = match send_auth_mail(&state, &user, MailTemplate::VerifyEmail).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            warn!("Could not send the verification message to {}: {}", user.email, e);
+            false
+        }
+    };
+    Ok(registration_accepted(user.email, sent))
+}
+
+fn registration_accepted(email: String, sent: bool) -> (StatusCode, Json<RegisterResponse>) {
+    (
+        StatusCode::ACCEPTED,
+        Json(RegisterResponse {
+            email,
+            message: "Registration received".to_string(),
+            verification_sent: sent,
+        }),
+    )
+}
+
+/// Email verification handler, the target of the verification link
+pub async fn verify_email(
+    Extension(state): Extension<ApiState>,
+    Query(params): Query<VerifyEmailParams>,
+) -> Result<Json<MessageResponse>, AuthError> {
+    let mut user = redeem_one_time_token(&state, &params.token, TokenPurpose::VerifyEmail).await?;
+    user.email_verified = true;
+    user.updated_at = Utc::now().into();
+    state.db.update_user(&user).await.map_err(db_failure)?;
+
+    Ok(message("Email verified"))
+}
+
+/// Forgotten password handler
+///
+/// Mails a reset link if the address is registered, and answers the same
+/// either way so addresses cannot be probed.
+pub async fn forgot_password(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), AuthError> {
+    if state.mailer.is_none() {
+        return Err(auth_error(StatusCode::SERVICE_UNAVAILABLE, "Email is not configured"));
+    }
+
+    let user = state.db.get_user_by_email(payload.email.trim()).await.map_err(db_failure)?;
+    if let Some(user) = user {
+        if let Err(e) = send_auth_mail(&state, &user, MailTemplate::ResetPassword).await {
+            warn!("Could not send the password reset message to {}: {}", user.email, e);
+        }
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        message("If the address is registered, a reset link was sent to it"),
+    ))
+}
+
+/// Password reset handler
+///
+/// Signs the user out everywhere. The link went to their address, so the
+/// address counts as verified too.
+pub async fn reset_password(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+    check_password(&payload.password)?;
+    let mut user =
+        redeem_one_time_token(&state, &payload.token, TokenPurpose::ResetPassword).await?;
+
+    let password_hash = HashedPassword::new(&payload.password)
+        .map_err(|_| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Password hashing error"))?;
+    user.password_hash = password_hash.to_string();
+    user.email_verified = true;
+    user.updated_at = Utc::now().into();
+    state.db.update_user(&user).await.map_err(db_failure)?;
+    if let Some(id) = &user.id {
+        state.db.revoke_all_user_sessions(&id.to_string()).await.map_err(db_failure)?;
+    }
+
+    Ok(message("Password reset"))
+}
+
 /// OAuth2 login handler
+///
+/// Users who log in with a second factor get an MFA challenge instead of a
+/// session (see `handlers::mfa`).
 pub async fn login(
     Extension(state): Extension<ApiState>,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Json<LoginStep>, (StatusCode, Json<ErrorResponse>)> {
     match payload {
         LoginRequest::Credentials { email, password } => {
             // 1. Buscar usuario por email
@@ -84,82 +345,398 @@ pub async fn login(
                 ));
             }
 
-            // 3. Generar access token
-            let access_token = state
-                .token_generator
-                .generate_token(
-                    user.id.clone().unwrap().to_string(),
-                    vec!["user:read".into(), "user:write".into()],
-                    None,
-                )
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ErrorResponse {
-                            error: "Token error".into(),
-                        }),
-                    )
-                })?;
-
-            // 4. Generar refresh token y guardar sesión
-            let refresh_token = generate_refresh_token();
-            let session = StoredSession {
-                id: None,
-                user_id: user.id.clone().unwrap(),
-                refresh_token_hash: hash_token(&refresh_token),
-                expires_at: (Utc::now() + Duration::days(30)).into(),
-                created_at: Utc::now().into(),
-                updated_at: Utc::now().into(),
-            };
-            state.db.create_session(&session).await.map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "DB error".into(),
-                    }),
-                )
-            })?;
-
-            Ok(Json(LoginResponse {
-                access_token,
-                refresh_token,
-                expires_in: 3600,
-                user: Some(UserInfo {
-                    email: user.email,
-                    name: user.name,
-                }),
-            }))
+            // 3. Generar tokens y guardar sesión, o pedir el segundo factor
+            begin_session(&state, user).await.map(Json)
+        }
+        LoginRequest::OAuth {
+            provider,
+            code,
+            state: oauth_state,
+        } => oauth_login(&state, &provider, &code, &oauth_state).await.map(Json),
+    }
+}
+
+/// Issue an access token and a refresh token of a new session family
+pub(crate) async fn start_session(
+    state: &ApiState,
+    user: StoredUser,
+) -> Result<LoginResponse, AuthError> {
+    let access_token = issue_access_token(state, &user)?;
+
+    let refresh_token = generate_refresh_token();
+    let user_id = user
+        .id
+        .clone()
+        .ok_or_else(|| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "User has no ID"))?;
+    let session = StoredSession {
+        id: None,
+        user_id,
+        refresh_token_hash: hash_token(&refresh_token),
+        device_info: None,
+        ip_address: None,
+        created_at: Utc::now().into(),
+        expires_at: (Utc::now() + Duration::days(REFRESH_TOKEN_DAYS)).into(),
+        revoked: false,
+        family: None,
+        rotated_at: None,
+    };
+    state.db.create_session(&session).await.map_err(db_failure)?;
+
+    Ok(LoginResponse {
+        access_token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_SECS,
+        user: Some(UserInfo {
+            email: user.email,
+            name: user.name,
+            email_verified: user.email_verified,
+        }),
+    })
+}
+
+/// Names of the identity providers users can log in with
+pub async fn oauth_providers(Extension(state): Extension<ApiState>) -> Json<Vec<String>> {
+    Json(state.oidc.as_ref().map(|oidc| oidc.providers()).unwrap_or_default())
+}
+
+/// Start a login with an identity provider by redirecting to it
+///
+/// The provider sends the user back to its `redirect_uri`: either
+/// `oauth_callback`, or a page posting the code and state to `login`.
+pub async fn oauth_authorize(
+    Extension(state): Extension<ApiState>,
+    Path(provider): Path<String>,
+) -> Result<Redirect, AuthError> {
+    let authorization = oidc_client(&state)?.authorize(&provider).map_err(provider_failure)?;
+    let login = OAuthState {
+        state: authorization.state,
+        provider,
+        verifier: authorization.verifier,
+    };
+    let expires_at = Utc::now() + Duration::minutes(OAUTH_STATE_MINUTES);
+    state.db.save_oauth_state(&login, expires_at).await.map_err(db_failure)?;
+
+    Ok(Redirect::to(&authorization.url))
+}
+
+/// Redirect target of identity providers
+pub async fn oauth_callback(
+    Extension(state): Extension<ApiState>,
+    Path(provider): Path<String>,
+    Query(params): Query<OAuthCallbackParams>,
+) -> Result<Json<LoginStep>, AuthError> {
+    oauth_login(&state, &provider, &params.code, &params.state).await.map(Json)
+}
+
+fn oidc_client(state: &ApiState) -> Result<&OidcClient, AuthError> {
+    state
+        .oidc
+        .as_deref()
+        .ok_or_else(|| auth_error(StatusCode::NOT_FOUND, "Unknown identity provider"))
+}
+
+fn provider_failure(e: edge_hive_auth::AuthError) -> AuthError {
+    match e {
+        edge_hive_auth::AuthError::UnknownProvider(_) => {
+            auth_error(StatusCode::NOT_FOUND, "Unknown identity provider")
+        }
+        e => {
+            warn!("Login with an identity provider failed: {}", e);
+            auth_error(StatusCode::BAD_GATEWAY, "Identity provider error")
         }
-        LoginRequest::OAuth { .. } => {
-            // TODO: Implementar OAuth flow
-            Err((
-                StatusCode::NOT_IMPLEMENTED,
-                Json(ErrorResponse {
-                    error: "OAuth not implemented".into(),
-                }),
-            ))
+    }
+}
+
+/// Finish a login with an identity provider
+async fn oauth_login(
+    state: &ApiState,
+    provider: &str,
+    code: &str,
+    oauth_state: &str,
+) -> Result<LoginStep, AuthError> {
+    let oidc = oidc_client(state)?;
+    let invalid = || auth_error(StatusCode::BAD_REQUEST, "Invalid or expired OAuth state");
+    let login = state
+        .db
+        .take_oauth_state(oauth_state)
+        .await
+        .map_err(db_failure)?
+        .ok_or_else(invalid)?;
+    if login.provider != provider {
+        return Err(invalid());
+    }
+
+    let identity = oidc.login(provider, code, &login.verifier).await.map_err(provider_failure)?;
+    let user = provider_user(state, identity).await?;
+    begin_session(state, user).await
+}
+
+/// User of a provider identity
+///
+/// That is the user linked to the identity, else the user the identity gets
+/// linked to by a verified email, else a new user.
+async fn provider_user(state: &ApiState, identity: OidcUser) -> Result<StoredUser, AuthError> {
+    let linked = state
+        .db
+        .get_user_by_provider(&identity.provider, &identity.subject)
+        .await
+        .map_err(db_failure)?;
+    if let Some(user) = linked {
+        return Ok(user);
+    }
+
+    let email = identity.email.clone().filter(|email| is_email(email)).ok_or_else(|| {
+        auth_error(StatusCode::BAD_REQUEST, "The identity provider shared no email address")
+    })?;
+    let Some(mut user) = state.db.get_user_by_email(&email).await.map_err(db_failure)? else {
+        let user = StoredUser {
+            id: None,
+            email,
+            name: identity.name,
+            password_hash: String::new(),
+            provider: Some(identity.provider),
+            provider_id: Some(identity.subject),
+            avatar_url: identity.picture,
+            role: "user".to_string(),
+            email_verified: identity.email_verified,
+            created_at: Utc::now().into(),
+            updated_at: Utc::now().into(),
+        };
+        return state.db.create_user(&user).await.map_err(db_failure);
+    };
+
+    // Only an address the provider verified shows the account is theirs
+    if !identity.email_verified || user.provider.is_some() {
+        return Err(auth_error(StatusCode::CONFLICT, "Email already registered"));
+    }
+    if !user.email_verified {
+        // Whoever registered the unverified address may not own it: lock them out
+        user.password_hash = String::new();
+        user.email_verified = true;
+        let key = user_key(&user)?;
+        state.db.disable_mfa(&key).await.map_err(db_failure)?;
+        state.db.delete_passkeys(&key).await.map_err(db_failure)?;
+        if let Some(id) = &user.id {
+            state.db.revoke_all_user_sessions(&id.to_string()).await.map_err(db_failure)?;
         }
     }
+    user.provider = Some(identity.provider);
+    user.provider_id = Some(identity.subject);
+    user.avatar_url = user.avatar_url.or(identity.picture);
+    user.updated_at = Utc::now().into();
+    state.db.update_user(&user).await.map_err(db_failure)?;
+    Ok(user)
 }
 
 /// Refresh token handler
+///
+/// Rotates the refresh token: the presented one stops working and a new pair
+/// is issued. Presenting an already rotated token revokes every session
+/// descending from the same login.
 pub async fn refresh_token(
-    Extension(_state): Extension<ApiState>,
+    Extension(state): Extension<ApiState>,
     Json(payload): Json<RefreshRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
-    // Placeholder: would validate and refresh token
+) -> Result<Json<LoginResponse>, AuthError> {
+    let refresh_token = generate_refresh_token();
+    let rotation = state
+        .db
+        .rotate_session(
+            &hash_token(&payload.refresh_token),
+            &hash_token(&refresh_token),
+            Utc::now() + Duration::days(REFRESH_TOKEN_DAYS),
+        )
+        .await
+        .map_err(db_failure)?;
+
+    let session = match rotation {
+        SessionRotation::Rotated(session) => session,
+        SessionRotation::Reused(session) => {
+            warn!(
+                "Refresh token of {} was reused, revoked its session family",
+                session.user_id
+            );
+            return Err(auth_error(StatusCode::UNAUTHORIZED, "Invalid refresh token"));
+        }
+        SessionRotation::Invalid => {
+            return Err(auth_error(StatusCode::UNAUTHORIZED, "Invalid refresh token"));
+        }
+    };
+
+    let user = state
+        .db
+        .get_user_by_id(&session.user_id.id.to_raw())
+        .await
+        .map_err(db_failure)?
+        .ok_or_else(|| auth_error(StatusCode::UNAUTHORIZED, "Invalid refresh token"))?;
+    let access_token = issue_access_token(&state, &user)?;
+
     Ok(Json(LoginResponse {
-        access_token: "new_access_token".to_string(),
-        refresh_token: "new_refresh_token".to_string(),
-        expires_in: 3600,
-        user: None,
+        access_token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_SECS,
+        user: Some(UserInfo {
+            email: user.email,
+            name: user.name,
+            email_verified: user.email_verified,
+        }),
     }))
 }
 
 /// Logout handler
-pub async fn logout(Extension(_state): Extension<ApiState>) -> StatusCode {
-    // Placeholder: would invalidate session
-    StatusCode::OK
+///
+/// Revokes the session of the refresh token, or every session of its user
+/// when `all` is set. A token that was already rotated or revoked counts as
+/// reuse: its whole session family is revoked and the logout is refused.
+pub async fn logout(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<LogoutRequest>,
+) -> StatusCode {
+    let session = match state.db.get_session_by_token(&hash_token(&payload.refresh_token)).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return StatusCode::UNAUTHORIZED,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if session.rotated_at.is_some() || session.revoked {
+        if let Some(family) = &session.family {
+            if state.db.revoke_session_family(family).await.is_err() {
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+        warn!(
+            "Stale refresh token of {} was used to log out, revoked its session family",
+            session.user_id
+        );
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let revoked = if payload.all {
+        state
+            .db
+            .revoke_all_user_sessions(&session.user_id.to_string())
+            .await
+            .map(|_| ())
+    } else {
+        match &session.id {
+            Some(id) => state.db.revoke_session(&id.to_string()).await,
+            None => Ok(()),
+        }
+    };
+    match revoked {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn issue_access_token(state: &ApiState, user: &StoredUser) -> Result<String, AuthError> {
+    state
+        .token_generator
+        .generate_user_token(
+            user.id.clone().unwrap().to_string(),
+            user.role.clone(),
+            vec!["user:read".into(), "user:write".into()],
+        )
+        .map_err(|_| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Token error"))
+}
+
+/// Issue a one-time token of `user` and mail its link; `false` without a mailer
+async fn send_auth_mail(
+    state: &ApiState,
+    user: &StoredUser,
+    template: MailTemplate,
+) -> Result<bool, String> {
+    let Some(mailer) = &state.mailer else {
+        return Ok(false);
+    };
+    let (purpose, ttl, expires_in) = match template {
+        MailTemplate::VerifyEmail => (
+            TokenPurpose::VerifyEmail,
+            Duration::hours(VERIFY_EMAIL_HOURS),
+            format!("{} hours", VERIFY_EMAIL_HOURS),
+        ),
+        MailTemplate::ResetPassword | MailTemplate::AccountExists => (
+            TokenPurpose::ResetPassword,
+            Duration::minutes(RESET_PASSWORD_MINUTES),
+            format!("{} minutes", RESET_PASSWORD_MINUTES),
+        ),
+        MailTemplate::Test => return Err("the test message carries no token".to_string()),
+    };
+
+    let token = issue_one_time_token(state, user, purpose, ttl).await?;
+    let link = mailer.link(template, &token);
+    let name = user.name.as_deref().unwrap_or(&user.email);
+    let vars = [
+        ("name", name),
+        ("email", user.email.as_str()),
+        ("link", link.as_str()),
+        ("expires_in", expires_in.as_str()),
+    ];
+    mailer.send(&user.email, template, &vars).await.map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Signed token for `purpose` that the database lets `user` redeem once
+pub(crate) async fn issue_one_time_token(
+    state: &ApiState,
+    user: &StoredUser,
+    purpose: TokenPurpose,
+    ttl: Duration,
+) -> Result<String, String> {
+    let user_id = user.id.as_ref().map(|id| id.id.to_raw()).ok_or("user has no ID")?;
+    let issuer = state.token_generator.issuer().to_string();
+    let claims = JwtClaims::new(user_id, issuer, vec![], None)
+        .with_audience(purpose.as_str())
+        .expires_in(ttl);
+
+    state
+        .db
+        .issue_one_time_token(&claims.jti, purpose, &claims.sub, Utc::now() + ttl)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.token_generator.generate_token_from_claims(&claims).map_err(|e| e.to_string())
+}
+
+/// Check a one-time token and spend it, returning its user
+async fn redeem_one_time_token(
+    state: &ApiState,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<StoredUser, AuthError> {
+    let invalid = || auth_error(StatusCode::BAD_REQUEST, "Invalid or expired token");
+    let claims = state
+        .token_validator
+        .validate_token_for(token, purpose.as_str())
+        .map_err(|_| invalid())?;
+    let user_id = state
+        .db
+        .redeem_one_time_token(&claims.jti, purpose)
+        .await
+        .map_err(db_failure)?
+        .ok_or_else(invalid)?;
+    if user_id != claims.sub {
+        return Err(invalid());
+    }
+    state.db.get_user_by_id(&user_id).await.map_err(db_failure)?.ok_or_else(invalid)
+}
+
+fn is_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.contains(char::is_whitespace)
+}
+
+fn check_password(password: &str) -> Result<(), AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        let error = format!("Password must have at least {} characters", MIN_PASSWORD_LEN);
+        return Err(auth_error(StatusCode::BAD_REQUEST, &error));
+    }
+    Ok(())
 }
 
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
@@ -182,7 +759,7 @@ fn generate_refresh_token() -> String {
         .collect()
 }
 
-fn hash_token(token: &str) -> String {
+pub(crate) fn hash_token(token: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(token.as_bytes());
     format!("{:x}", hasher.finalize())
@@ -202,12 +779,7 @@ mod tests {
     use tempfile::tempdir;
 
     async fn setup_test_state() -> ApiState {
-        let db_dir = tempdir().unwrap();
-        let db = Arc::new(
-            DatabaseService::new(&db_dir.path().join("test.db"))
-                .await
-                .unwrap(),
-        );
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
         let cache = CacheService::new(CacheConfig {
             l1_max_capacity: 100,
             l1_ttl_secs: 60,
@@ -227,6 +799,11 @@ mod tests {
             email: email.to_string(),
             name: Some("Test User".to_string()),
             password_hash: HashedPassword::new(password).unwrap().to_string(),
+            provider: None,
+            provider_id: None,
+            avatar_url: None,
+            role: "user".to_string(),
+            email_verified: false,
             created_at: Utc::now().into(),
             updated_at: Utc::now().into(),
         };
@@ -246,10 +823,10 @@ mod tests {
         let result = login(Extension(state), Json(payload)).await;
         assert!(result.is_ok());
 
-        let response = result.unwrap();
-        assert!(!response.0.access_token.is_empty());
-        assert!(!response.0.refresh_token.is_empty());
-        assert_eq!(response.0.user.as_ref().unwrap().email, "test@example.com");
+        let response = result.unwrap().0.session();
+        assert!(!response.access_token.is_empty());
+        assert!(!response.refresh_token.is_empty());
+        assert_eq!(response.user.as_ref().unwrap().email, "test@example.com");
     }
 
     #[tokio::test]
@@ -286,4 +863,408 @@ mod tests {
         assert_eq!(status, StatusCode::UNAUTHORIZED);
         assert_eq!(error.0.error, "Invalid credentials");
     }
+
+    async fn login_as(state: &ApiState, email: &str) -> LoginResponse {
+        let payload = LoginRequest::Credentials {
+            email: email.to_string(),
+            password: "password123".to_string(),
+        };
+        login(Extension(state.clone()), Json(payload)).await.unwrap().0.session()
+    }
+
+    async fn refresh(state: &ApiState, token: &str) -> Result<LoginResponse, StatusCode> {
+        let payload = RefreshRequest {
+            refresh_token: token.to_string(),
+        };
+        refresh_token(Extension(state.clone()), Json(payload))
+            .await
+            .map(|response| response.0)
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotates_token() {
+        let state = setup_test_state().await;
+        create_test_user(&state.db, "test@example.com", "password123").await;
+        let session = login_as(&state, "test@example.com").await;
+
+        let rotated = refresh(&state, &session.refresh_token).await.unwrap();
+        assert_ne!(rotated.refresh_token, session.refresh_token);
+        assert!(state.token_validator.validate_token(&rotated.access_token).is_ok());
+        assert_eq!(rotated.user.unwrap().email, "test@example.com");
+
+        let rotated_again = refresh(&state, &rotated.refresh_token).await.unwrap();
+        assert_ne!(rotated_again.refresh_token, rotated.refresh_token);
+        assert_eq!(refresh(&state, "bogus").await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_reuse_revokes_family() {
+        let state = setup_test_state().await;
+        create_test_user(&state.db, "test@example.com", "password123").await;
+        let session = login_as(&state, "test@example.com").await;
+        let other = login_as(&state, "test@example.com").await;
+
+        let rotated = refresh(&state, &session.refresh_token).await.unwrap();
+        let reused = refresh(&state, &session.refresh_token).await;
+        assert_eq!(reused.unwrap_err(), StatusCode::UNAUTHORIZED);
+        let revoked = refresh(&state, &rotated.refresh_token).await;
+        assert_eq!(revoked.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // Another login of the same user is a separate family
+        assert!(refresh(&state, &other.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        let state = setup_test_state().await;
+        create_test_user(&state.db, "test@example.com", "password123").await;
+        let first = login_as(&state, "test@example.com").await;
+        let second = login_as(&state, "test@example.com").await;
+        let third = login_as(&state, "test@example.com").await;
+
+        let payload = LogoutRequest {
+            refresh_token: first.refresh_token.clone(),
+            all: false,
+        };
+        let status = logout(Extension(state.clone()), Json(payload)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let result = refresh(&state, &first.refresh_token).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+        let second = refresh(&state, &second.refresh_token).await.unwrap();
+
+        let payload = LogoutRequest {
+            refresh_token: second.refresh_token.clone(),
+            all: true,
+        };
+        let status = logout(Extension(state.clone()), Json(payload)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let result = refresh(&state, &third.refresh_token).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        let payload = LogoutRequest {
+            refresh_token: "bogus".to_string(),
+            all: false,
+        };
+        let status = logout(Extension(state), Json(payload)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_logout_with_rotated_token_revokes_family() {
+        let state = setup_test_state().await;
+        create_test_user(&state.db, "test@example.com", "password123").await;
+        let session = login_as(&state, "test@example.com").await;
+        let other = login_as(&state, "test@example.com").await;
+        let rotated = refresh(&state, &session.refresh_token).await.unwrap();
+
+        let payload = LogoutRequest {
+            refresh_token: session.refresh_token.clone(),
+            all: false,
+        };
+        let status = logout(Extension(state.clone()), Json(payload)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let result = refresh(&state, &rotated.refresh_token).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // Another login of the same user is a separate family
+        assert!(refresh(&state, &other.refresh_token).await.is_ok());
+    }
+
+    fn registration(email: &str, password: &str) -> Json<RegisterRequest> {
+        Json(RegisterRequest {
+            email: email.to_string(),
+            password: password.to_string(),
+            name: Some("Ada".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_register_and_verify_email() {
+        let (mailer, inbox) = crate::mail::sink::start().await;
+        let state = setup_test_state().await.with_mailer(mailer);
+
+        let (status, Json(registered)) =
+            register(Extension(state.clone()), registration("ada@example.com", "password123"))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(registered.email, "ada@example.com");
+        assert!(registered.verification_sent);
+        let user = state.db.get_user_by_email("ada@example.com").await.unwrap().unwrap();
+        assert!(!user.email_verified);
+        assert!(verify_password("password123", &user.password_hash));
+
+        let token = crate::mail::sink::token(&inbox.lock().unwrap()[0]);
+        let params = || {
+            Query(VerifyEmailParams {
+                token: token.clone(),
+            })
+        };
+        let _ = verify_email(Extension(state.clone()), params()).await.unwrap();
+        let user = state.db.get_user_by_email("ada@example.com").await.unwrap().unwrap();
+        assert!(user.email_verified);
+
+        // Single use
+        let (status, _) = verify_email(Extension(state), params()).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_invalid_input() {
+        let state = setup_test_state().await;
+
+        let (_, Json(registered)) =
+            register(Extension(state.clone()), registration("ada@example.com", "password123"))
+                .await
+                .unwrap();
+        assert!(!registered.verification_sent);
+
+        let cases = [
+            ("bob@example.com", "short", StatusCode::BAD_REQUEST),
+            ("not-an-email", "password123", StatusCode::BAD_REQUEST),
+        ];
+        for (email, password, expected) in cases {
+            let result = register(Extension(state.clone()), registration(email, password)).await;
+            assert_eq!(result.unwrap_err().0, expected, "{}", email);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_registered_email() {
+        let (mailer, inbox) = crate::mail::sink::start().await;
+        let state = setup_test_state().await.with_mailer(mailer);
+        create_test_user(&state.db, "ada@example.com", "password123").await;
+
+        let (status, Json(registered)) =
+            register(Extension(state.clone()), registration("ada@example.com", "other-password"))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(registered.message, "Registration received");
+        assert!(registered.verification_sent);
+
+        let user = state.db.get_user_by_email("ada@example.com").await.unwrap().unwrap();
+        assert!(verify_password("password123", &user.password_hash));
+        let message = inbox.lock().unwrap()[0].clone();
+        assert!(message.contains("Subject: You already have an account"));
+        assert!(message.contains("/reset-password?token="));
+    }
+
+    #[tokio::test]
+    async fn test_password_reset() {
+        let (mailer, inbox) = crate::mail::sink::start().await;
+        let state = setup_test_state().await.with_mailer(mailer);
+        create_test_user(&state.db, "test@example.com", "password123").await;
+        let session = login_as(&state, "test@example.com").await;
+
+        let forgot = |email: &str| {
+            Json(ForgotPasswordRequest {
+                email: email.to_string(),
+            })
+        };
+        let (status, _) = forgot_password(Extension(state.clone()), forgot("nobody@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(inbox.lock().unwrap().is_empty());
+        let (status, _) = forgot_password(Extension(state.clone()), forgot("test@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let token = crate::mail::sink::token(&inbox.lock().unwrap()[0]);
+        let reset = |password: &str| {
+            Json(ResetPasswordRequest {
+                token: token.clone(),
+                password: password.to_string(),
+            })
+        };
+        let (status, _) =
+            reset_password(Extension(state.clone()), reset("short")).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let _ = reset_password(Extension(state.clone()), reset("new-password")).await.unwrap();
+        let (status, _) =
+            reset_password(Extension(state.clone()), reset("other-password")).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let user = state.db.get_user_by_email("test@example.com").await.unwrap().unwrap();
+        assert!(verify_password("new-password", &user.password_hash));
+        assert!(user.email_verified);
+        // Signed out everywhere
+        let result = refresh(&state, &session.refresh_token).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // A verification token is no reset token
+        let user = state.db.get_user_by_email("test@example.com").await.unwrap().unwrap();
+        let ttl = Duration::hours(1);
+        let token = issue_one_time_token(&state, &user, TokenPurpose::VerifyEmail, ttl)
+            .await
+            .unwrap();
+        let payload = Json(ResetPasswordRequest {
+            token,
+            password: "new-password".to_string(),
+        });
+        let (status, _) = reset_password(Extension(state), payload).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_forgot_password_without_mailer() {
+        let state = setup_test_state().await;
+        let payload = Json(ForgotPasswordRequest {
+            email: "test@example.com".to_string(),
+        });
+
+        let (status, _) = forgot_password(Extension(state), payload).await.unwrap_err();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// Identity provider whose access tokens are the codes it was given
+    async fn mock_provider() -> String {
+        use axum::{http::HeaderMap, routing::post, Form, Router};
+        use serde_json::{json, Value};
+        use std::collections::HashMap;
+
+        async fn token(Form(form): Form<HashMap<String, String>>) -> Json<Value> {
+            Json(json!({ "access_token": form["code"], "token_type": "Bearer" }))
+        }
+        async fn userinfo(headers: HeaderMap) -> Json<Value> {
+            let token = headers["authorization"].to_str().unwrap().trim_start_matches("Bearer ");
+            let (sub, email, verified) = match token {
+                "verified" => ("ada-1", "test@example.com", true),
+                "unverified" => ("ada-2", "test@example.com", false),
+                _ => ("bob-1", "bob@example.com", true),
+            };
+            Json(json!({ "sub": sub, "email": email, "email_verified": verified, "name": "Ada" }))
+        }
+
+        let app = Router::new()
+            .route("/token", post(token))
+            .route("/userinfo", axum::routing::get(userinfo));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        base
+    }
+
+    async fn setup_oidc_state() -> ApiState {
+        let base = mock_provider().await;
+        let provider = edge_hive_auth::OidcProvider {
+            client_id: "edge-hive".to_string(),
+            client_secret: None,
+            authorization_endpoint: format!("{}/authorize", base),
+            token_endpoint: format!("{}/token", base),
+            userinfo_endpoint: format!("{}/userinfo", base),
+            redirect_uri: "http://localhost:8080/api/v1/auth/oauth/mock/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            claims: Default::default(),
+        };
+        let providers = std::collections::HashMap::from([("mock".to_string(), provider)]);
+        setup_test_state().await.with_oidc(OidcClient::new(providers))
+    }
+
+    /// Start a login with the mock provider, returning its state
+    async fn authorize(state: &ApiState) -> String {
+        use axum::response::IntoResponse;
+
+        let redirect = oauth_authorize(Extension(state.clone()), Path("mock".to_string()))
+            .await
+            .unwrap()
+            .into_response();
+        let location = redirect.headers()["location"].to_str().unwrap().to_string();
+        assert!(location.contains("code_challenge_method=S256"));
+        let query = location.split("state=").nth(1).unwrap();
+        query.split('&').next().unwrap().to_string()
+    }
+
+    async fn oauth(state: &ApiState, code: &str) -> Result<LoginResponse, StatusCode> {
+        let params = OAuthCallbackParams {
+            code: code.to_string(),
+            state: authorize(state).await,
+        };
+        oauth_callback(Extension(state.clone()), Path("mock".to_string()), Query(params))
+            .await
+            .map(|response| response.0.session())
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_oauth_login_links_verified_email() {
+        let state = setup_oidc_state().await;
+        create_test_user(&state.db, "test@example.com", "password123").await;
+
+        // Unverified at the provider: no proof the account is theirs
+        assert_eq!(oauth(&state, "unverified").await.unwrap_err(), StatusCode::CONFLICT);
+
+        let linked = oauth(&state, "verified").await.unwrap().user.unwrap();
+        assert_eq!(linked.email, "test@example.com");
+        assert!(linked.email_verified);
+        let user = state.db.get_user_by_provider("mock", "ada-1").await.unwrap().unwrap();
+        assert_eq!(user.email, "test@example.com");
+        // The unverified local password is gone
+        let payload = LoginRequest::Credentials {
+            email: "test@example.com".to_string(),
+            password: "password123".to_string(),
+        };
+        let (status, _) = login(Extension(state.clone()), Json(payload)).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        assert!(oauth(&state, "verified").await.is_ok());
+        let created = oauth(&state, "new").await.unwrap().user.unwrap();
+        assert_eq!(created.email, "bob@example.com");
+        let bob = state.db.get_user_by_provider("mock", "bob-1").await.unwrap().unwrap();
+        assert_ne!(bob.id, user.id);
+    }
+
+    #[tokio::test]
+    async fn test_oauth_takeover_removes_second_factors() {
+        let state = setup_oidc_state().await;
+        register(Extension(state.clone()), registration("test@example.com", "password123"))
+            .await
+            .unwrap();
+        let user = state.db.get_user_by_email("test@example.com").await.unwrap().unwrap();
+        let key = user_key(&user).unwrap();
+        state.db.save_totp_secret(&key, "JBSWY3DPEHPK3PXP").await.unwrap();
+        let codes = vec!["hash-1".to_string(), "hash-2".to_string()];
+        assert!(state.db.confirm_totp(&key, 1, &codes).await.unwrap());
+        // Added before passkeys needed a verified address
+        let passkey = edge_hive_db::NewPasskey {
+            id: "cred-1".to_string(),
+            user: key.clone(),
+            name: None,
+            public_key: "key".to_string(),
+            sign_count: 0,
+        };
+        state.db.add_passkey(&passkey).await.unwrap().unwrap();
+
+        let linked = oauth(&state, "verified").await.unwrap().user.unwrap();
+        assert_eq!(linked.email, "test@example.com");
+        assert_eq!(state.db.totp_enrollment(&key).await.unwrap(), None);
+        assert_eq!(state.db.recovery_codes_left(&key).await.unwrap(), 0);
+        assert!(state.db.passkeys(&key).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_oauth_state_is_single_use() {
+        let state = setup_oidc_state().await;
+        let oauth_state = authorize(&state).await;
+        let callback = |oauth_state: &str| {
+            let params = OAuthCallbackParams {
+                code: "new".to_string(),
+                state: oauth_state.to_string(),
+            };
+            oauth_callback(Extension(state.clone()), Path("mock".to_string()), Query(params))
+        };
+
+        assert!(callback(&oauth_state).await.is_ok());
+        let (status, _) = callback(&oauth_state).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let result = oauth_authorize(Extension(state.clone()), Path("other".to_string())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+        let unconfigured = Extension(setup_test_state().await);
+        let result = oauth_authorize(unconfigured, Path("mock".to_string())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
 }
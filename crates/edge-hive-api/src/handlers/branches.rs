@@ -0,0 +1,213 @@
+//! Database branch administration handlers
+//!
+//! Branches belong to the database of the API they are created in, so
+//! `/p/<project>/api/v1/admin/branches` manages the branches of that project.
+//! A branch is served under `/b/<branch>` from its first request on.
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::Json,
+};
+use edge_hive_db::{Branch, BranchDiff, SchemaChange};
+use serde::Deserialize;
+use super::admin::require_admin;
+use super::data::db_error_status;
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+
+/// Body of a branch creation
+#[derive(Debug, Deserialize)]
+pub struct NewBranch {
+    pub name: String,
+}
+
+/// Query parameters of a merge
+#[derive(Debug, Default, Deserialize)]
+pub struct MergeParams {
+    /// Also remove definitions missing on the branch
+    #[serde(default)]
+    pub remove: bool,
+}
+
+/// List the branches of the database
+pub async fn list_branches(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<Branch>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.branches().await.map(Json).map_err(db_error_status)
+}
+
+/// Copy the database into a new branch
+pub async fn create_branch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(branch): Json<NewBranch>,
+) -> Result<(StatusCode, Json<Branch>), StatusCode> {
+    require_admin(&claims)?;
+
+    let branch = state.db.create_branch(&branch.name).await.map_err(db_error_status)?;
+    Ok((StatusCode::CREATED, Json(branch)))
+}
+
+/// Get a branch
+pub async fn get_branch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> Result<Json<Branch>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .get_branch(&name)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Stop serving a branch and drop its data
+pub async fn delete_branch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+
+    if let Some(branches) = &state.branches {
+        branches.close(&name);
+    }
+    match state.db.delete_branch(&name).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+/// Compare a branch with the database
+pub async fn diff_branch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> Result<Json<BranchDiff>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .diff_branch(&name)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Apply the schema changes of a branch to the database
+///
+/// Returns the applied changes. Data is never merged.
+pub async fn merge_branch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+    Query(params): Query<MergeParams>,
+) -> Result<Json<Vec<SchemaChange>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .merge_branch(&name, params.remove)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::JwtClaims;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{BoundQuery, DatabaseService, DiffKind, SchemaItem};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir).with_branches()
+    }
+
+    fn caller(role: &str) -> BearerClaims {
+        let claims =
+            JwtClaims::new("users:root".to_string(), "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    fn new_branch(name: &str) -> Json<NewBranch> {
+        Json(NewBranch {
+            name: name.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_branches_require_admin() {
+        let state = setup_test_state().await;
+
+        let result =
+            create_branch(Extension(state.clone()), caller("user"), new_branch("feature-x")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let result = list_branches(Extension(state), caller("user")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_branch_diff_and_merge() {
+        let state = setup_test_state().await;
+
+        let (status, Json(branch)) =
+            create_branch(Extension(state.clone()), caller("admin"), new_branch("feature-x"))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let result =
+            create_branch(Extension(state.clone()), caller("admin"), new_branch("Feature X"))
+                .await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let branch_db = state.db.open_branch(&branch).await.unwrap();
+        branch_db.execute(BoundQuery::new("DEFINE TABLE notes SCHEMALESS")).await.unwrap();
+
+        let Json(diff) =
+            diff_branch(Extension(state.clone()), caller("admin"), Path("feature-x".to_string()))
+                .await
+                .unwrap();
+        assert_eq!(diff.schema.len(), 1);
+        assert_eq!(diff.schema[0].kind, DiffKind::Added);
+        assert_eq!(diff.schema[0].item, SchemaItem::Table);
+
+        let Json(merged) = merge_branch(
+            Extension(state.clone()),
+            caller("admin"),
+            Path("feature-x".to_string()),
+            Query(MergeParams::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(merged, diff.schema);
+
+        let status =
+            delete_branch(Extension(state.clone()), caller("admin"), Path("feature-x".to_string()))
+                .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let result =
+            get_branch(Extension(state), caller("admin"), Path("feature-x".to_string())).await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+}
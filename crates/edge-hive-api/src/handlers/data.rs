@@ -1,146 +1,973 @@
 //! Database CRUD handlers with automatic caching
+//!
+//! Every handler runs as the caller: the bearer token's claims become the
+//! `$identity` of the table's row-level policy, and system tables are never
+//! reachable.
+//!
+//! Writes to tables with a JSON Schema are validated; a mismatch is answered
+//! with `422 Unprocessable Entity` and the failing paths.
+//!
+//! Versioned tables keep the history of their records: deletes can be undone
+//! and reads can ask for the table as it was (`?as_of=`).
+//!
+//! Whole tables can be exported and imported as CSV, NDJSON or Parquet
+//! (`_export`, `_import`), under the same policies as single records.
 
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::Json,
+    body::{Body, Bytes},
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
-use serde::Deserialize;
+use edge_hive_auth::JwtClaims;
+use edge_hive_cache::CacheService;
+use edge_hive_db::{
+    BatchOp, BatchOutcome, ColumnMap, DbError, ExportOptions, Identity, ImportOptions,
+    ImportReport, KnnHit, KnnQuery, KnnRequest, ListQuery, Page, RecordId, RecordVersion,
+    SchemaViolation, SearchHit, SearchQuery, Table, TableInfo, TransferFormat,
+};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeSet;
+use tokio::sync::Mutex;
+use crate::middleware::auth::BearerClaims;
 use crate::state::ApiState;
 
-#[derive(Deserialize)]
-pub struct QueryParams {
-    limit: Option<u64>,
-    offset: Option<u64>,
+/// Header carrying the number of rows matching the filters
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// Header carrying the cursor of the next page
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Largest file accepted by the import endpoint
+pub const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+pub(crate) fn parse_table(table: &str) -> Result<Table, StatusCode> {
+    Table::new(table).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+fn parse_record_id(table: &str, id: &str) -> Result<RecordId, StatusCode> {
+    RecordId::new(parse_table(table)?, id).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+pub(crate) fn db_error_status(error: DbError) -> StatusCode {
+    match error {
+        DbError::InvalidIdentifier(_) | DbError::InvalidQuery(_) | DbError::Transfer(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        DbError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Error of the write handlers
+#[derive(Debug)]
+pub enum WriteError {
+    Status(StatusCode),
+    /// The record does not match the table's JSON Schema
+    Invalid(Vec<SchemaViolation>),
+}
+
+/// Body of a `422` response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidationErrorBody {
+    pub error: String,
+    pub violations: Vec<SchemaViolation>,
+}
+
+impl From<StatusCode> for WriteError {
+    fn from(status: StatusCode) -> Self {
+        WriteError::Status(status)
+    }
+}
+
+impl From<DbError> for WriteError {
+    fn from(error: DbError) -> Self {
+        match error {
+            DbError::Validation(violations) => WriteError::Invalid(violations),
+            other => WriteError::Status(db_error_status(other)),
+        }
+    }
+}
+
+impl IntoResponse for WriteError {
+    fn into_response(self) -> Response {
+        match self {
+            WriteError::Status(status) => status.into_response(),
+            WriteError::Invalid(violations) => {
+                let body = ValidationErrorBody {
+                    error: "validation_failed".to_string(),
+                    violations,
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response()
+            }
+        }
+    }
+}
+
+/// Policy identity of the caller
+pub(crate) fn identity(claims: &JwtClaims) -> Identity {
+    Identity::new(claims.sub.clone(), claims.role(), claims.scopes.clone())
 }
 
-fn is_safe_ident(s: &str) -> bool {
-    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+/// Drop every cached query of a table
+pub(crate) async fn invalidate_table_cache(state: &ApiState, table: &str) {
+    clear_table_cache(&state.cache, table).await;
 }
 
-fn is_safe_record_id(s: &str) -> bool {
-    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+/// Drop every cached query of a table, for writers without the whole state
+pub(crate) async fn clear_table_cache(cache: &Mutex<CacheService>, table: &str) {
+    cache.lock().await.delete_pattern(&format!("data:{}:query*", table)).await;
+}
+
+/// Drop every cached query, after writes that may have touched any table
+pub(crate) async fn clear_data_cache(cache: &Mutex<CacheService>) {
+    cache.lock().await.delete_pattern("data:*").await;
+}
+
+fn page_headers(page: &Page) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(page.total));
+    if let Some(cursor) = page.next_cursor.as_deref().and_then(|c| HeaderValue::from_str(c).ok()) {
+        headers.insert(NEXT_CURSOR_HEADER, cursor);
+    }
+    headers
 }
 
 /// Query records from a table (auto-cached)
+///
+/// Supports PostgREST-style parameters, e.g.
+/// `?age=gt.18&order=created_at.desc&select=id,name&limit=50`. The total
+/// number of matching rows is returned in `X-Total-Count` and the cursor of
+/// the next page in `X-Next-Cursor` (pass it back as `?cursor=`). On a
+/// versioned table, `?as_of=2024-05-01T12:00:00Z` reads the rows as they were
+/// at that time.
 pub async fn query_records(
     Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
     Path(table): Path<String>,
-) -> Result<Json<Vec<Value>>, StatusCode> {
-    if !is_safe_ident(&table) {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<(HeaderMap, Json<Vec<Value>>), StatusCode> {
+    let table = parse_table(&table)?;
+    let query = ListQuery::from_params(table, params).map_err(db_error_status)?;
+
+    // The policy becomes part of the query, so cached pages are per caller
+    let identity = identity(&claims);
+    let query = state
+        .db
+        .scoped(&state.policies, &identity)
+        .restrict(query)
+        .map_err(db_error_status)?;
 
-    // Cache key: "data:{table}:query"
-    let cache_key = format!("data:{}:query", table);
+    // Cache key: "data:{table}:query:{normalized query}"
+    let cache_key = format!("data:{}:query:{}", query.table, query.normalized());
 
     // Try cache first
     {
         let mut cache = state.cache.lock().await;
         if let Some(cached) = cache.get(&cache_key).await {
-            if let Ok(records) = serde_json::from_slice::<Vec<Value>>(&cached) {
-                return Ok(Json(records));
+            if let Ok(page) = serde_json::from_slice::<Page>(&cached) {
+                return Ok((page_headers(&page), Json(page.rows)));
             }
         }
     }
 
     // Query from SurrealDB
-    let sql = format!("SELECT * FROM {};", table);
-    let records: Vec<Value> = state
-        .db
-        .query_json(&sql)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let page = state.db.list_records(&query).await.map_err(db_error_status)?;
 
     // Cache the result
-    if let Ok(serialized) = serde_json::to_vec(&records) {
+    if let Ok(serialized) = serde_json::to_vec(&page) {
         let mut cache = state.cache.lock().await;
         let _ = cache.set(cache_key, serialized).await;
     }
 
-    Ok(Json(records))
+    Ok((page_headers(&page), Json(page.rows)))
 }
 
-/// Insert a record into a table
-pub async fn insert_record(
+/// Tables of the data API with their fields, indexes and record counts
+///
+/// Counts only include the rows the caller may read.
+pub async fn describe_tables(
     Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<TableInfo>>, StatusCode> {
+    let identity = identity(&claims);
+    state
+        .db
+        .scoped(&state.policies, &identity)
+        .describe()
+        .await
+        .map(Json)
+        .map_err(db_error_status)
+}
+
+/// Full-text search over the searchable fields of a table
+///
+/// `?q=rust async&limit=20&offset=0`. Hits come best match first with their
+/// BM25 score and highlighted fields; the number of matches is returned in
+/// `X-Total-Count`. Only rows the caller may read are searched.
+pub async fn search_records(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
     Path(table): Path<String>,
-    Json(payload): Json<Value>,
-) -> Result<Json<Value>, StatusCode> {
-    if !is_safe_ident(&table) {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<(HeaderMap, Json<Vec<SearchHit>>), StatusCode> {
+    let table = parse_table(&table)?;
+    let query = SearchQuery::from_params(table, params).map_err(db_error_status)?;
 
-    // Invalidate cache for this table
-    let cache_key = format!("data:{}:query", table);
-    let mut cache = state.cache.lock().await;
-    cache.delete(&cache_key).await;
+    let identity = identity(&claims);
+    let page = state
+        .db
+        .scoped(&state.policies, &identity)
+        .search(query)
+        .await
+        .map_err(db_error_status)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(page.total));
+    Ok((headers, Json(page.hits)))
+}
 
-    let payload_json = serde_json::to_string(&payload).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let sql = format!("CREATE {} CONTENT {};", table, payload_json);
+/// Nearest neighbours of a query vector
+///
+/// Body: `{"vector": [...], "k": 5, "field": "embedding", "filters": {"lang": "eq.en"}}`.
+/// `field` can be left out when the table has a single vector field; filters
+/// use the same syntax as query parameters of a normal read. Only rows the
+/// caller may read are considered.
+pub async fn knn_records(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+    Json(request): Json<KnnRequest>,
+) -> Result<Json<Vec<KnnHit>>, StatusCode> {
+    let table = parse_table(&table)?;
+    let query = KnnQuery::new(table, request).map_err(db_error_status)?;
 
-    let created: Vec<Value> = state
+    let identity = identity(&claims);
+    state
         .db
-        .query_json(&sql)
+        .scoped(&state.policies, &identity)
+        .knn(query)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map(Json)
+        .map_err(db_error_status)
+}
 
-    if let Some(first) = created.into_iter().next() {
-        return Ok(Json(first));
+/// Insert a record into a table
+pub async fn insert_record(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+    Json(payload): Json<Value>,
+) -> Result<Json<Value>, WriteError> {
+    let table = parse_table(&table)?;
+
+    if !payload.is_object() {
+        return Err(StatusCode::BAD_REQUEST.into());
     }
 
-    Err(StatusCode::INTERNAL_SERVER_ERROR)
+    let identity = identity(&claims);
+    let created = state
+        .db
+        .scoped(&state.policies, &identity)
+        .create_record(&table, payload)
+        .await?;
+
+    // Invalidate after the write so no read can cache the old rows again
+    invalidate_table_cache(&state, table.as_str()).await;
+
+    Ok(Json(created))
 }
 
 /// Update a record by ID
 pub async fn update_record(
     Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
     Path((table, id)): Path<(String, String)>,
     Json(payload): Json<Value>,
-) -> Result<Json<Value>, StatusCode> {
-    if !is_safe_ident(&table) || !is_safe_record_id(&id) {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+) -> Result<Json<Value>, WriteError> {
+    let record_id = parse_record_id(&table, &id)?;
 
-    // Invalidate cache
-    let cache_key = format!("data:{}:query", table);
-    let mut cache = state.cache.lock().await;
-    cache.delete(&cache_key).await;
-
-    let payload_json = serde_json::to_string(&payload).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let sql = format!("UPDATE {}:{} MERGE {};", table, id, payload_json);
+    if !payload.is_object() {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
 
-    let updated: Vec<Value> = state
+    let identity = identity(&claims);
+    let updated = state
         .db
-        .query_json(&sql)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .scoped(&state.policies, &identity)
+        .merge_record(&record_id, payload)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
-    if let Some(first) = updated.into_iter().next() {
-        return Ok(Json(first));
-    }
+    invalidate_table_cache(&state, &table).await;
 
-    Err(StatusCode::INTERNAL_SERVER_ERROR)
+    Ok(Json(updated))
 }
 
 /// Delete a record by ID
 pub async fn delete_record(
     Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
     Path((table, id)): Path<(String, String)>,
 ) -> StatusCode {
-    if !is_safe_ident(&table) || !is_safe_record_id(&id) {
-        return StatusCode::BAD_REQUEST;
+    let record_id = match parse_record_id(&table, &id) {
+        Ok(record_id) => record_id,
+        Err(status) => return status,
+    };
+
+    let identity = identity(&claims);
+    match state
+        .db
+        .scoped(&state.policies, &identity)
+        .delete_record(&record_id)
+        .await
+    {
+        Ok(Some(_)) => {
+            invalidate_table_cache(&state, &table).await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+/// Every version of a record of a versioned table, oldest first
+pub async fn record_history(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path((table, id)): Path<(String, String)>,
+) -> Result<Json<Vec<RecordVersion>>, StatusCode> {
+    let record_id = parse_record_id(&table, &id)?;
+
+    let identity = identity(&claims);
+    state
+        .db
+        .scoped(&state.policies, &identity)
+        .history(&record_id)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Restore a deleted record of a versioned table
+///
+/// The caller needs the table's delete permission on the deleted record.
+pub async fn restore_record(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path((table, id)): Path<(String, String)>,
+) -> Result<Json<Value>, StatusCode> {
+    let record_id = parse_record_id(&table, &id)?;
+
+    let identity = identity(&claims);
+    let restored = state
+        .db
+        .scoped(&state.policies, &identity)
+        .restore_record(&record_id)
+        .await
+        .map_err(db_error_status)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    invalidate_table_cache(&state, &table).await;
+
+    Ok(Json(restored))
+}
+
+/// Body of `POST /api/v1/data/_batch`
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOp>,
+}
+
+/// Run create/update/delete operations atomically
+///
+/// Operations run in order inside one transaction. The response lists the
+/// result of every operation; when one fails nothing is applied and the
+/// outcome is returned with `409 Conflict`. Schema violations are reported
+/// with `422` before anything runs.
+pub async fn batch(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(request): Json<BatchRequest>,
+) -> Result<(StatusCode, Json<BatchOutcome>), WriteError> {
+    let tables: BTreeSet<String> =
+        request.operations.iter().map(|op| op.table().to_string()).collect();
+
+    let identity = identity(&claims);
+    let outcome = state
+        .db
+        .scoped(&state.policies, &identity)
+        .batch(request.operations)
+        .await?;
+
+    if !outcome.committed {
+        return Ok((StatusCode::CONFLICT, Json(outcome)));
+    }
+
+    // Invalidate once per affected table
+    for table in &tables {
+        invalidate_table_cache(&state, table).await;
+    }
+
+    Ok((StatusCode::OK, Json(outcome)))
+}
+
+/// Export a table as CSV, NDJSON or Parquet
+///
+/// `?format=csv|ndjson|parquet` (default `ndjson`) picks the file format and
+/// `?columns=title:headline,views` the fields and their column names. Every
+/// other parameter filters and sorts like [`query_records`]; the export covers
+/// all matching rows the caller may read and is streamed page by page.
+pub async fn export_records(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<(HeaderMap, Body), StatusCode> {
+    let table = parse_table(&table)?;
+
+    let mut options = ExportOptions {
+        format: TransferFormat::Ndjson,
+        columns: None,
+    };
+    let mut filters = Vec::new();
+    for (key, value) in params {
+        match key.as_str() {
+            "format" => options.format = value.parse().map_err(db_error_status)?,
+            "columns" => options.columns = Some(value.parse().map_err(db_error_status)?),
+            _ => filters.push((key, value)),
+        }
+    }
+    let query = ListQuery::from_params(table.clone(), filters).map_err(db_error_status)?;
+
+    let identity = identity(&claims);
+    let format = options.format;
+    let mut export = state
+        .db
+        .scoped(&state.policies, &identity)
+        .export_table(query, options)
+        .map_err(db_error_status)?;
+
+    // The first page is read up front, so a bad query still gets a status code
+    let first = export.next_chunk(&state.db).await.map_err(db_error_status)?;
+    let db = state.db.clone();
+    let rest = stream::unfold(Some(export), move |export| {
+        let db = db.clone();
+        async move {
+            let mut export = export?;
+            match export.next_chunk(&db).await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(export))),
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::warn!("Export aborted after {} rows: {}", export.rows(), e);
+                    Some((Err(std::io::Error::other(e.to_string())), None))
+                }
+            }
+        }
+    });
+    let body = Body::from_stream(stream::iter(first.map(Ok)).chain(rest));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    if let Ok(disposition) = HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}.{}\"",
+        table,
+        format.extension()
+    )) {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+
+    Ok((headers, body))
+}
+
+/// Parameters of a table import
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    pub format: TransferFormat,
+    /// `column:field` pairs, e.g. `headline:title,views`
+    pub columns: Option<String>,
+    /// Field holding the record key; rows with a key update existing records
+    pub key: Option<String>,
+    /// Check every row without writing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Import a CSV, NDJSON or Parquet file into a table
+///
+/// Rows are written one by one as the caller, so a failing row does not stop
+/// the import: the report counts created, updated and failed rows and lists
+/// the first errors.
+pub async fn import_records(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(table): Path<String>,
+    Query(params): Query<ImportParams>,
+    body: Bytes,
+) -> Result<Json<ImportReport>, StatusCode> {
+    let table = parse_table(&table)?;
+
+    let columns = params
+        .columns
+        .as_deref()
+        .map(str::parse::<ColumnMap>)
+        .transpose()
+        .map_err(db_error_status)?;
+    let options = ImportOptions {
+        format: params.format,
+        columns,
+        key: params.key,
+        dry_run: params.dry_run,
+    };
+
+    let identity = identity(&claims);
+    let report = state
+        .db
+        .scoped(&state.policies, &identity)
+        .import_table(&table, body.as_ref(), &options, |_| {})
+        .await
+        .map_err(db_error_status)?;
+
+    if !report.dry_run {
+        invalidate_table_cache(&state, table.as_str()).await;
+    }
+
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::DatabaseService;
+    use std::{path::PathBuf, sync::Arc};
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Query<Vec<(String, String)>> {
+        Query(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    fn caller(sub: &str) -> BearerClaims {
+        BearerClaims(JwtClaims::new(sub.to_string(), "edge-hive-test".to_string(), vec![], None))
+    }
+
+    async fn insert(state: &ApiState, payload: Value) {
+        insert_record(
+            Extension(state.clone()),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            Json(payload),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_records_filters_and_counts() {
+        let state = setup_test_state().await;
+        for views in [5, 20, 40] {
+            insert(&state, serde_json::json!({"views": views})).await;
+        }
+
+        let (headers, Json(rows)) = query_records(
+            Extension(state.clone()),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[("views", "gt.10"), ("order", "views.desc"), ("limit", "1")]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["views"], 40);
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "2");
+        assert!(headers.contains_key(NEXT_CURSOR_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_query_records_rejects_invalid_filter() {
+        let state = setup_test_state().await;
+
+        let result = query_records(
+            Extension(state),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[("views", "between.1")]),
+        )
+        .await;
+
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_insert_invalidates_cached_queries() {
+        let state = setup_test_state().await;
+        insert(&state, serde_json::json!({"views": 1})).await;
+
+        let list = |pairs: &'static [(&'static str, &'static str)]| {
+            query_records(
+                Extension(state.clone()),
+                caller("users:alice"),
+                Path("posts".to_string()),
+                params(pairs),
+            )
+        };
+
+        let (_, Json(before)) = list(&[("views", "gte.0")]).await.unwrap();
+        assert_eq!(before.len(), 1);
+
+        insert(&state, serde_json::json!({"views": 2})).await;
+
+        let (headers, Json(after)) = list(&[("views", "gte.0")]).await.unwrap();
+        assert_eq!(after.len(), 2);
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "2");
+    }
+
+    #[tokio::test]
+    async fn test_rows_are_scoped_to_the_caller() {
+        let state = setup_test_state().await;
+        insert(&state, serde_json::json!({"views": 1})).await;
+
+        let list = |sub: &'static str| {
+            query_records(
+                Extension(state.clone()),
+                caller(sub),
+                Path("posts".to_string()),
+                params(&[]),
+            )
+        };
+
+        let (_, Json(own)) = list("users:alice").await.unwrap();
+        assert_eq!(own.len(), 1);
+
+        // Same query from another caller must not be served from alice's cache entry
+        let (headers, Json(other)) = list("users:bob").await.unwrap();
+        assert!(other.is_empty());
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "0");
+    }
+
+    #[tokio::test]
+    async fn test_system_tables_are_forbidden() {
+        let state = setup_test_state().await;
+
+        for table in ["users", "sessions", "_migrations"] {
+            let result = query_records(
+                Extension(state.clone()),
+                caller("users:alice"),
+                Path(table.to_string()),
+                params(&[]),
+            )
+            .await;
+            assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+        }
+
+        let status = delete_record(
+            Extension(state),
+            caller("users:alice"),
+            Path(("sessions".to_string(), "abc".to_string())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_batch_invalidates_affected_tables() {
+        let state = setup_test_state().await;
+        insert(&state, serde_json::json!({"views": 1})).await;
+
+        let list = || {
+            query_records(
+                Extension(state.clone()),
+                caller("users:alice"),
+                Path("posts".to_string()),
+                params(&[]),
+            )
+        };
+        let (_, Json(before)) = list().await.unwrap();
+        assert_eq!(before.len(), 1);
+
+        let request: BatchRequest = serde_json::from_value(serde_json::json!({
+            "operations": [
+                {"op": "create", "table": "posts", "data": {"views": 2}},
+                {"op": "create", "table": "comments", "data": {"text": "hi"}},
+            ]
+        }))
+        .unwrap();
+        let (status, Json(outcome)) =
+            batch(Extension(state.clone()), caller("users:alice"), Json(request)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(outcome.results.len(), 2);
+
+        let (_, Json(after)) = list().await.unwrap();
+        assert_eq!(after.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_is_a_conflict() {
+        let state = setup_test_state().await;
+
+        let request: BatchRequest = serde_json::from_value(serde_json::json!({
+            "operations": [
+                {"op": "create", "table": "posts", "data": {"views": 2}},
+                {"op": "delete", "table": "posts", "id": "missing"},
+            ]
+        }))
+        .unwrap();
+        let (status, Json(outcome)) =
+            batch(Extension(state.clone()), caller("users:alice"), Json(request)).await.unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(!outcome.committed);
+
+        let (_, Json(rows)) = query_records(
+            Extension(state),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[]),
+        )
+        .await
+        .unwrap();
+        assert!(rows.is_empty());
     }
 
-    // Invalidate cache
-    let cache_key = format!("data:{}:query", table);
-    let mut cache = state.cache.lock().await;
-    cache.delete(&cache_key).await;
+    #[tokio::test]
+    async fn test_search_records() {
+        let state = setup_test_state().await;
+        let posts = Table::new("posts").unwrap();
+        let fields = [edge_hive_db::filter::Field::new("title").unwrap()];
+        state.db.define_search(&posts, &fields).await.unwrap();
+        for title in ["Edge computing on phones", "Baking bread"] {
+            insert(&state, serde_json::json!({"title": title})).await;
+        }
+
+        let (headers, Json(hits)) = search_records(
+            Extension(state.clone()),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[("q", "edge")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].record["title"], "Edge computing on phones");
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "1");
+
+        let missing_q = search_records(
+            Extension(state),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[]),
+        )
+        .await;
+        assert_eq!(missing_q.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_knn_records() {
+        let state = setup_test_state().await;
+        let posts = Table::new("posts").unwrap();
+        let field: edge_hive_db::VectorField =
+            serde_json::from_value(serde_json::json!({"field": "embedding", "dimension": 2}))
+                .unwrap();
+        state.db.define_vectors(&posts, &[field]).await.unwrap();
+        insert(&state, serde_json::json!({"title": "near", "embedding": [1.0, 0.0]})).await;
+        insert(&state, serde_json::json!({"title": "far", "embedding": [0.0, 1.0]})).await;
+
+        let knn = |body: Value| {
+            knn_records(
+                Extension(state.clone()),
+                caller("users:alice"),
+                Path("posts".to_string()),
+                Json(serde_json::from_value(body).unwrap()),
+            )
+        };
 
-    let sql = format!("DELETE {}:{};", table, id);
-    let _ = state.db.query(&sql).await;
+        let Json(hits) = knn(serde_json::json!({"vector": [0.9, 0.1], "k": 1})).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].record["title"], "near");
+
+        let wrong_dimension = knn(serde_json::json!({"vector": [1.0], "k": 1})).await;
+        assert_eq!(wrong_dimension.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_insert_reports_schema_violations() {
+        let state = setup_test_state().await;
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["views"],
+            "properties": {"views": {"type": "integer"}}
+        });
+        state.db.set_table_schema(&Table::new("posts").unwrap(), &schema).await.unwrap();
+
+        let result = insert_record(
+            Extension(state.clone()),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            Json(serde_json::json!({"views": "many"})),
+        )
+        .await;
+        let WriteError::Invalid(violations) = result.unwrap_err() else {
+            panic!("expected schema violations");
+        };
+        assert_eq!(violations[0].path, "/views");
+
+        let response = WriteError::Invalid(violations).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        insert(&state, serde_json::json!({"views": 3})).await;
+    }
 
-    // Placeholder: would delete from edge-hive-db
-    StatusCode::NO_CONTENT
+    #[tokio::test]
+    async fn test_deleted_records_of_versioned_tables_can_be_restored() {
+        let state = setup_test_state().await;
+        state.db.enable_versioning(&Table::new("posts").unwrap()).await.unwrap();
+        insert(&state, serde_json::json!({"views": 1})).await;
+        let keys = state
+            .db
+            .execute(edge_hive_db::BoundQuery::new("SELECT VALUE record::id(id) FROM posts"))
+            .await
+            .unwrap();
+        let key = keys[0].as_str().unwrap().to_string();
+        let path = || Path(("posts".to_string(), key.clone()));
+
+        let status = delete_record(Extension(state.clone()), caller("users:alice"), path()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let missing = restore_record(Extension(state.clone()), caller("users:bob"), path()).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let Json(restored) =
+            restore_record(Extension(state.clone()), caller("users:alice"), path())
+                .await
+                .unwrap();
+        assert_eq!(restored["views"], 1);
+
+        let Json(history) =
+            record_history(Extension(state.clone()), caller("users:alice"), path())
+                .await
+                .unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1].author.as_deref(), Some("users:alice"));
+
+        let as_of = history[0].at.to_rfc3339();
+        let (headers, _) = query_records(
+            Extension(state),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[("as_of", as_of.as_str())]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "1");
+    }
+
+    #[tokio::test]
+    async fn test_describe_tables_counts_visible_rows() {
+        let state = setup_test_state().await;
+        insert(&state, serde_json::json!({"views": 1})).await;
+
+        let Json(tables) =
+            describe_tables(Extension(state.clone()), caller("users:alice")).await.unwrap();
+        let posts = tables.iter().find(|t| t.name == "posts").unwrap();
+        assert_eq!(posts.count, 1);
+        assert!(tables.iter().all(|t| t.name != "users"));
+
+        let Json(tables) = describe_tables(Extension(state), caller("users:bob")).await.unwrap();
+        assert_eq!(tables.iter().find(|t| t.name == "posts").unwrap().count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import_records() {
+        let state = setup_test_state().await;
+        for views in [5, 20] {
+            insert(&state, serde_json::json!({"title": "post", "views": views})).await;
+        }
+
+        let (headers, body) = export_records(
+            Extension(state.clone()),
+            caller("users:alice"),
+            Path("posts".to_string()),
+            params(&[("format", "csv"), ("columns", "title,views"), ("order", "views")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv");
+        let file = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(file, "title,views\npost,5\npost,20\n");
+
+        let import = |dry_run| {
+            let state = state.clone();
+            let file = file.clone();
+            async move {
+                import_records(
+                    Extension(state),
+                    caller("users:bob"),
+                    Path("posts".to_string()),
+                    Query(ImportParams {
+                        format: TransferFormat::Csv,
+                        columns: None,
+                        key: None,
+                        dry_run,
+                    }),
+                    file,
+                )
+                .await
+                .unwrap()
+                .0
+            }
+        };
+        let report = import(true).await;
+        assert_eq!((report.rows, report.created, report.dry_run), (2, 2, true));
+        let report = import(false).await;
+        assert_eq!((report.created, report.failed), (2, 0));
+
+        let (headers, _) = query_records(
+            Extension(state),
+            caller("users:bob"),
+            Path("posts".to_string()),
+            params(&[]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[TOTAL_COUNT_HEADER], "2");
+    }
+
+    #[tokio::test]
+    async fn test_purges_clear_cached_queries() {
+        let state = setup_test_state().await;
+        for days in [1, 5] {
+            let sql = format!("CREATE logs SET created_at = time::now() - {}d", days);
+            state.db.query_json(&sql).await.unwrap();
+        }
+        let (logs, posts) = ("data:logs:query:limit=10", "data:posts:query:limit=10");
+        for key in [logs, posts] {
+            state.cache.lock().await.set(key.to_string(), b"[]".to_vec()).await;
+        }
+
+        let rules = std::collections::HashMap::from([(
+            "logs".to_string(),
+            serde_json::from_value(serde_json::json!({"max_age": "2d"})).unwrap(),
+        )]);
+        let janitor = edge_hive_db::Janitor::new(
+            state.db.clone(),
+            &rules,
+            std::time::Duration::from_secs(60),
+        )
+        .unwrap();
+        assert_eq!(state.clear_purged(janitor).sweep().await, 1);
+
+        let mut cache = state.cache.lock().await;
+        assert!(cache.get(logs).await.is_none());
+        assert!(cache.get(posts).await.is_some());
+    }
 }
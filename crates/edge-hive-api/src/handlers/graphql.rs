@@ -0,0 +1,786 @@
+//! GraphQL endpoint generated from the data tables
+//!
+//! `POST /api/v1/graphql` answers queries and mutations; subscriptions run
+//! over a WebSocket upgraded from `GET /api/v1/graphql` (`graphql-ws` or
+//! `graphql-transport-ws`). The schema is generated from the introspected
+//! tables (see `describe.rs`) and kept until their definitions or JSON
+//! Schemas change, so new tables show up without a restart. For a table
+//! `posts`:
+//!
+//! ```graphql
+//! type postsRecord { id: ID, title: String, ..., _json: JSON! }
+//! type postsPage { rows: [postsRecord!]!, total: Int!, next_cursor: String }
+//! type postsChange { action: String!, record: postsRecord }
+//!
+//! posts(filter: JSON, order: [String!], limit: Int, offset: Int,
+//!       cursor: String, as_of: String): postsPage!
+//! create_posts(data: JSON!): postsRecord!
+//! update_posts(id: ID!, data: JSON!): postsRecord
+//! delete_posts(id: ID!): postsRecord
+//! posts_changes: postsChange!    # subscription
+//! ```
+//!
+//! Record types have the table's defined fields; `_json` is the whole record,
+//! which is all there is to read on schemaless tables. `_tables` describes
+//! the tables, counting the records visible to the caller. `filter` maps fields to
+//! the expressions of the REST query parameters (`{"views": "gt.10"}`).
+//!
+//! Everything runs as the caller, under the same row policies as the REST
+//! handlers, and subscriptions only deliver the changes the caller may read,
+//! with their encrypted fields decrypted.
+//! Errors carry a `code` extension: `BAD_REQUEST`, `FORBIDDEN`,
+//! `VALIDATION_FAILED` (with the `violations`) or `INTERNAL`.
+
+use async_graphql::dynamic::{
+    Field, FieldFuture, FieldValue, InputValue, Object, ResolverContext, Scalar, Schema,
+    Subscription, SubscriptionField, SubscriptionFieldFuture, TypeRef, ValueAccessor,
+};
+use async_graphql::http::{WebSocket as GraphqlSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{Data, ErrorExtensions, Request, Response, Value as GraphqlValue};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Extension,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+};
+use edge_hive_db::filter::{self, Filter};
+use edge_hive_db::{DbError, FieldInfo, Identity, ListQuery, Page, RecordId, Table, TableInfo};
+use edge_hive_realtime::ServerMessage;
+use futures_util::{future, SinkExt, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use super::data::{db_error_status, identity, invalidate_table_cache};
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+
+/// Scalar holding any JSON value
+const JSON: &str = "JSON";
+
+type GraphqlResult<T> = async_graphql::Result<T>;
+
+/// The last schema built, with the tables it was built from
+///
+/// The schema holds no state: resolvers find the [`ApiState`] and the
+/// caller's [`Identity`] in the data of each request or connection.
+#[derive(Clone, Default)]
+pub struct SchemaCache(Arc<Mutex<Option<CachedSchema>>>);
+
+struct CachedSchema {
+    tables: Vec<TableInfo>,
+    schema: Schema,
+}
+
+impl SchemaCache {
+    fn get(&self, tables: &[TableInfo]) -> Option<Schema> {
+        let cached = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        cached.as_ref().filter(|cached| cached.tables == tables).map(|c| c.schema.clone())
+    }
+
+    fn set(&self, tables: Vec<TableInfo>, schema: Schema) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(CachedSchema { tables, schema });
+    }
+}
+
+/// Run a query or mutation
+pub async fn graphql(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(request): Json<Request>,
+) -> Result<Json<Response>, StatusCode> {
+    let schema = schema(&state).await?;
+    let request = request.data(state).data(identity(&claims));
+    Ok(Json(schema.execute(request).await))
+}
+
+/// Upgrade to a GraphQL WebSocket for subscriptions
+///
+/// The bearer token of the upgrade request is the identity of the whole
+/// connection.
+pub async fn graphql_ws(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let protocol = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| {
+            protocols.split(',').find_map(|p| p.trim().parse::<WebSocketProtocols>().ok())
+        })
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let schema = schema(&state).await?;
+    let mut data = Data::default();
+    data.insert(state);
+    data.insert(identity(&claims));
+    Ok(upgrade
+        .protocols(async_graphql::http::ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| serve_socket(socket, schema, data, protocol)))
+}
+
+/// Relay the messages of a WebSocket to and from the schema
+///
+/// `async-graphql-axum` is built for another axum release, so the socket goes
+/// through async-graphql's own protocol implementation.
+async fn serve_socket(
+    socket: WebSocket,
+    schema: Schema,
+    data: Data,
+    protocol: WebSocketProtocols,
+) {
+    let (mut sink, stream) = socket.split();
+    let input = stream
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(text.into_bytes()),
+                Ok(Message::Binary(bytes)) => Some(bytes),
+                _ => None,
+            })
+        });
+
+    let mut output = GraphqlSocket::new(schema, input, protocol).connection_data(data);
+    while let Some(message) = output.next().await {
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        };
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Schema over the current data tables, built again when they changed
+async fn schema(state: &ApiState) -> Result<Schema, StatusCode> {
+    let tables = state.db.describe_shapes().await.map_err(db_error_status)?;
+    if let Some(schema) = state.graphql.get(&tables) {
+        return Ok(schema);
+    }
+    let schema = build_schema(&tables)?;
+    state.graphql.set(tables, schema.clone());
+    Ok(schema)
+}
+
+fn build_schema(tables: &[TableInfo]) -> Result<Schema, StatusCode> {
+    // Every schema needs a query field, even without tables
+    let mut query = Object::new("Query").field(Field::new(
+        "_tables",
+        TypeRef::named_nn(JSON),
+        |ctx| FieldFuture::new(describe_tables(ctx)),
+    ));
+    let mut mutation = Object::new("Mutation");
+    let mut subscription = Subscription::new("Subscription");
+    let mut types = Vec::new();
+
+    for info in tables {
+        let table = Table::new(info.name.as_str()).map_err(db_error_status)?;
+        query = query.field(list_field(&table));
+        mutation = mutation
+            .field(create_field(&table))
+            .field(update_field(&table))
+            .field(delete_field(&table));
+        subscription = subscription.field(changes_field(&table));
+        types.extend([record_type(info), page_type(&table), change_type(&table)]);
+    }
+
+    let writable = !tables.is_empty();
+    let mut builder = Schema::build(
+        "Query",
+        writable.then_some("Mutation"),
+        writable.then_some("Subscription"),
+    )
+    .register(Scalar::new(JSON))
+    .register(query);
+    if writable {
+        builder = builder.register(mutation).register(subscription);
+    }
+    for object in types {
+        builder = builder.register(object);
+    }
+
+    builder.finish().map_err(|e| {
+        warn!("Failed to build the GraphQL schema: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+fn record_type_name(table: &Table) -> String {
+    format!("{}Record", table)
+}
+
+/// GraphQL type of a defined field; anything without a scalar equivalent is JSON
+fn field_type(field: &FieldInfo) -> &'static str {
+    let kind = field.kind.as_deref().map(|kind| {
+        kind.strip_prefix("option<").and_then(|k| k.strip_suffix('>')).unwrap_or(kind)
+    });
+    match kind {
+        Some("string" | "datetime" | "uuid" | "duration") => TypeRef::STRING,
+        Some("int") => TypeRef::INT,
+        Some("float" | "decimal" | "number") => TypeRef::FLOAT,
+        Some("bool") => TypeRef::BOOLEAN,
+        _ => JSON,
+    }
+}
+
+/// Whether a defined field can become a field of the record type
+///
+/// Nested paths (`address.city`) stay reachable through `_json`.
+fn is_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+        && !matches!(name, "id" | "_json")
+}
+
+fn record_type(info: &TableInfo) -> Object {
+    let mut object = Object::new(format!("{}Record", info.name))
+        .field(Field::new("id", TypeRef::named(TypeRef::ID), |ctx| {
+            FieldFuture::new(record_id_of(ctx))
+        }))
+        .field(Field::new("_json", TypeRef::named_nn(JSON), |ctx| {
+            FieldFuture::new(async move { json_value(parent(&ctx)?.clone()).await })
+        }));
+
+    for field in info.fields.iter().filter(|field| is_field_name(&field.name)) {
+        let name = field.name.clone();
+        let kind = TypeRef::named(field_type(field));
+        object = object.field(Field::new(field.name.as_str(), kind, move |ctx| {
+            let name = name.clone();
+            FieldFuture::new(async move {
+                match parent(&ctx)?.get(&name) {
+                    Some(value) => json_value(value.clone()).await,
+                    None => Ok(None),
+                }
+            })
+        }));
+    }
+    object
+}
+
+fn page_type(table: &Table) -> Object {
+    Object::new(format!("{}Page", table))
+        .field(Field::new("rows", TypeRef::named_nn_list_nn(record_type_name(table)), |ctx| {
+            FieldFuture::new(async move {
+                let rows = ctx.parent_value.try_downcast_ref::<Page>()?.rows.clone();
+                Ok(Some(FieldValue::list(rows.into_iter().map(FieldValue::owned_any))))
+            })
+        }))
+        .field(Field::new("total", TypeRef::named_nn(TypeRef::INT), |ctx| {
+            FieldFuture::new(async move {
+                let total = ctx.parent_value.try_downcast_ref::<Page>()?.total;
+                Ok(Some(FieldValue::value(total)))
+            })
+        }))
+        .field(Field::new("next_cursor", TypeRef::named(TypeRef::STRING), |ctx| {
+            FieldFuture::new(async move {
+                let cursor = ctx.parent_value.try_downcast_ref::<Page>()?.next_cursor.clone();
+                Ok(cursor.map(FieldValue::value))
+            })
+        }))
+}
+
+fn change_type(table: &Table) -> Object {
+    Object::new(format!("{}Change", table))
+        .field(Field::new("action", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+            FieldFuture::new(async move { json_value(parent(&ctx)?["action"].clone()).await })
+        }))
+        .field(Field::new("record", TypeRef::named(record_type_name(table)), |ctx| {
+            FieldFuture::new(async move {
+                let record = parent(&ctx)?["record"].clone();
+                Ok((!record.is_null()).then(|| FieldValue::owned_any(record)))
+            })
+        }))
+}
+
+fn list_field(table: &Table) -> Field {
+    let table = table.clone();
+    Field::new(table.to_string(), TypeRef::named_nn(format!("{}Page", table)), move |ctx| {
+        FieldFuture::new(list_records(ctx, table.clone()))
+    })
+    .argument(InputValue::new("filter", TypeRef::named(JSON)))
+    .argument(InputValue::new("order", TypeRef::named_nn_list(TypeRef::STRING)))
+    .argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("offset", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("cursor", TypeRef::named(TypeRef::STRING)))
+    .argument(InputValue::new("as_of", TypeRef::named(TypeRef::STRING)))
+}
+
+fn create_field(table: &Table) -> Field {
+    let table = table.clone();
+    Field::new(
+        format!("create_{}", table),
+        TypeRef::named_nn(record_type_name(&table)),
+        move |ctx| FieldFuture::new(create_record(ctx, table.clone())),
+    )
+    .argument(InputValue::new("data", TypeRef::named_nn(JSON)))
+}
+
+fn update_field(table: &Table) -> Field {
+    let table = table.clone();
+    Field::new(
+        format!("update_{}", table),
+        TypeRef::named(record_type_name(&table)),
+        move |ctx| FieldFuture::new(update_record(ctx, table.clone())),
+    )
+    .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+    .argument(InputValue::new("data", TypeRef::named_nn(JSON)))
+}
+
+fn delete_field(table: &Table) -> Field {
+    let table = table.clone();
+    Field::new(
+        format!("delete_{}", table),
+        TypeRef::named(record_type_name(&table)),
+        move |ctx| FieldFuture::new(delete_record(ctx, table.clone())),
+    )
+    .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+}
+
+fn changes_field(table: &Table) -> SubscriptionField {
+    let table = table.clone();
+    SubscriptionField::new(
+        format!("{}_changes", table),
+        TypeRef::named_nn(format!("{}Change", table)),
+        move |ctx| {
+            let table = table.clone();
+            SubscriptionFieldFuture::new(async move {
+                let (state, identity) = caller(&ctx)?;
+                let events = state.realtime.subscribe(table.as_str());
+                Ok(visible_changes(events, state.clone(), identity.clone(), table))
+            })
+        },
+    )
+}
+
+/// Changes of `table` from its realtime topic, as far as the caller may read them
+fn visible_changes<'a>(
+    events: broadcast::Receiver<ServerMessage>,
+    state: ApiState,
+    identity: Identity,
+    table: Table,
+) -> impl Stream<Item = GraphqlResult<FieldValue<'a>>> + 'a {
+    futures_util::stream::unfold(
+        (events, state, identity, table),
+        |(mut events, state, identity, table)| async move {
+            loop {
+                let (action, record) = match events.recv().await {
+                    Ok(ServerMessage::Event { action, data, .. }) => (action, data),
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                };
+
+                let scoped = state.db.scoped(&state.policies, &identity);
+                let record = match scoped.readable(&table, record).await {
+                    Ok(Some(record)) => record,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Dropping change of {} for a GraphQL subscriber: {}", table, e);
+                        continue;
+                    }
+                };
+
+                let change = FieldValue::owned_any(json!({"action": action, "record": record}));
+                return Some((Ok(change), (events, state, identity, table)));
+            }
+        },
+    )
+}
+
+async fn describe_tables(ctx: ResolverContext<'_>) -> GraphqlResult<Option<FieldValue<'_>>> {
+    let (state, identity) = caller(&ctx)?;
+    let tables = state
+        .db
+        .scoped(&state.policies, identity)
+        .describe()
+        .await
+        .map_err(graphql_error)?;
+    json_value(serde_json::to_value(&tables)?).await
+}
+
+async fn list_records<'a>(
+    ctx: ResolverContext<'a>,
+    table: Table,
+) -> GraphqlResult<Option<FieldValue<'a>>> {
+    let (state, identity) = caller(&ctx)?;
+    let query = list_query(&ctx, table)?;
+    let page = state
+        .db
+        .scoped(&state.policies, identity)
+        .list_records(query)
+        .await
+        .map_err(graphql_error)?;
+    Ok(Some(FieldValue::owned_any(page)))
+}
+
+async fn create_record<'a>(
+    ctx: ResolverContext<'a>,
+    table: Table,
+) -> GraphqlResult<Option<FieldValue<'a>>> {
+    let (state, identity) = caller(&ctx)?;
+    let data = object_argument(&ctx, "data")?;
+
+    invalidate_table_cache(state, table.as_str()).await;
+    let created = state
+        .db
+        .scoped(&state.policies, identity)
+        .create_record(&table, data)
+        .await
+        .map_err(graphql_error)?;
+    Ok(Some(FieldValue::owned_any(created)))
+}
+
+async fn update_record<'a>(
+    ctx: ResolverContext<'a>,
+    table: Table,
+) -> GraphqlResult<Option<FieldValue<'a>>> {
+    let (state, identity) = caller(&ctx)?;
+    let id = record_id(&ctx, table)?;
+    let data = object_argument(&ctx, "data")?;
+
+    invalidate_table_cache(state, id.table.as_str()).await;
+    let updated = state
+        .db
+        .scoped(&state.policies, identity)
+        .merge_record(&id, data)
+        .await
+        .map_err(graphql_error)?;
+    Ok(updated.map(FieldValue::owned_any))
+}
+
+async fn delete_record<'a>(
+    ctx: ResolverContext<'a>,
+    table: Table,
+) -> GraphqlResult<Option<FieldValue<'a>>> {
+    let (state, identity) = caller(&ctx)?;
+    let id = record_id(&ctx, table)?;
+
+    invalidate_table_cache(state, id.table.as_str()).await;
+    let deleted = state
+        .db
+        .scoped(&state.policies, identity)
+        .delete_record(&id)
+        .await
+        .map_err(graphql_error)?;
+    Ok(deleted.map(FieldValue::owned_any))
+}
+
+async fn record_id_of(ctx: ResolverContext<'_>) -> GraphqlResult<Option<FieldValue<'_>>> {
+    Ok(parent(&ctx)?.get("id").map(|id| match id {
+        Value::String(id) => FieldValue::value(id.clone()),
+        other => FieldValue::value(other.to_string()),
+    }))
+}
+
+async fn json_value<'a>(value: Value) -> GraphqlResult<Option<FieldValue<'a>>> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(FieldValue::value(GraphqlValue::from_json(value)?)))
+}
+
+fn parent<'a>(ctx: &'a ResolverContext<'_>) -> GraphqlResult<&'a Value> {
+    ctx.parent_value.try_downcast_ref::<Value>()
+}
+
+fn caller<'a>(ctx: &ResolverContext<'a>) -> GraphqlResult<(&'a ApiState, &'a Identity)> {
+    Ok((ctx.data::<ApiState>()?, ctx.data::<Identity>()?))
+}
+
+/// A given, non-null argument
+fn argument<'a>(ctx: &'a ResolverContext<'_>, name: &str) -> Option<ValueAccessor<'a>> {
+    ctx.args.get(name).filter(|value| !value.is_null())
+}
+
+fn object_argument(ctx: &ResolverContext<'_>, name: &str) -> GraphqlResult<Value> {
+    let value = argument(ctx, name)
+        .map(|value| value.as_value().clone().into_json())
+        .transpose()?
+        .unwrap_or_default();
+    if !value.is_object() {
+        return Err(bad_request(format!("'{}' must be an object", name)));
+    }
+    Ok(value)
+}
+
+/// ID of a record of `table`, given with or without the `table:` prefix
+fn record_id(ctx: &ResolverContext<'_>, table: Table) -> GraphqlResult<RecordId> {
+    let id = argument(ctx, "id").ok_or_else(|| bad_request("'id' is required"))?.string()?;
+    let prefix = format!("{}:", table);
+    let key = id.strip_prefix(&prefix).unwrap_or(id).to_string();
+    RecordId::new(table, key).map_err(graphql_error)
+}
+
+/// The [`ListQuery`] described by the arguments of a list field
+fn list_query(ctx: &ResolverContext<'_>, table: Table) -> GraphqlResult<ListQuery> {
+    let mut params = Vec::new();
+    if let Some(order) = argument(ctx, "order") {
+        let order = order
+            .list()?
+            .iter()
+            .map(|field| field.string().map(str::to_string))
+            .collect::<GraphqlResult<Vec<_>>>()?;
+        params.push(("order", order.join(",")));
+    }
+    for name in ["limit", "offset"] {
+        if let Some(value) = argument(ctx, name) {
+            params.push((name, value.i64()?.to_string()));
+        }
+    }
+    for name in ["cursor", "as_of"] {
+        if let Some(value) = argument(ctx, name) {
+            params.push((name, value.string()?.to_string()));
+        }
+    }
+    let mut query = ListQuery::from_params(table, params).map_err(graphql_error)?;
+
+    // Filters are added one by one, so fields named like a reserved parameter still work
+    if let Some(filters) = argument(ctx, "filter") {
+        let Value::Object(filters) = filters.as_value().clone().into_json()? else {
+            return Err(bad_request("'filter' must map fields to expressions"));
+        };
+        for (field, expr) in filters {
+            let expr = expr
+                .as_str()
+                .ok_or_else(|| bad_request(format!("filter on '{}' must be a string", field)))?;
+            let field = filter::Field::new(field).map_err(graphql_error)?;
+            query.filters.push(Filter::parse(field, expr).map_err(graphql_error)?);
+        }
+    }
+    Ok(query)
+}
+
+fn bad_request(message: impl Into<String>) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", "BAD_REQUEST"))
+}
+
+/// GraphQL counterpart of [`db_error_status`]
+fn graphql_error(error: DbError) -> async_graphql::Error {
+    match error {
+        DbError::InvalidIdentifier(_) | DbError::InvalidQuery(_) => bad_request(error.to_string()),
+        DbError::PermissionDenied(_) => async_graphql::Error::new(error.to_string())
+            .extend_with(|_, e| e.set("code", "FORBIDDEN")),
+        DbError::Validation(violations) => {
+            let violations = serde_json::to_value(&violations)
+                .ok()
+                .and_then(|v| GraphqlValue::from_json(v).ok())
+                .unwrap_or_default();
+            async_graphql::Error::new("validation_failed").extend_with(|_, e| {
+                e.set("code", "VALIDATION_FAILED");
+                e.set("violations", violations.clone());
+            })
+        }
+        other => {
+            warn!("GraphQL resolver failed: {}", other);
+            async_graphql::Error::new("internal error")
+                .extend_with(|_, e| e.set("code", "INTERNAL"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::JwtClaims;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::DatabaseService;
+    use futures_util::StreamExt;
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+    }
+
+    fn caller(sub: &str) -> BearerClaims {
+        BearerClaims(JwtClaims::new(sub.to_string(), "edge-hive-test".to_string(), vec![], None))
+    }
+
+    /// Run a request as `sub`, returning the response as JSON
+    async fn run(state: &ApiState, sub: &str, request: &str) -> Value {
+        let Json(response) =
+            graphql(Extension(state.clone()), caller(sub), Json(Request::new(request)))
+                .await
+                .unwrap();
+        serde_json::to_value(&response).unwrap()
+    }
+
+    async fn create_post(state: &ApiState, sub: &str, views: u64) -> Value {
+        let db = &state.db;
+        let identity = Identity::new(sub, "user", vec![]);
+        db.scoped(&state.policies, &identity)
+            .create_record(&Table::new("posts").unwrap(), json!({"views": views}))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_schema_without_tables() {
+        let state = setup_test_state().await;
+
+        let response = run(&state, "users:alice", "{ _tables }").await;
+        assert_eq!(response["data"]["_tables"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_schema_is_rebuilt_when_tables_change() {
+        let state = setup_test_state().await;
+        let cached = |state: &ApiState| {
+            let cached = state.graphql.0.lock().unwrap();
+            cached.as_ref().map(|c| c.tables.iter().map(|t| t.name.clone()).collect())
+        };
+
+        let response = run(&state, "users:alice", "{ posts { total } }").await;
+        assert!(response["errors"].is_array());
+        assert_eq!(cached(&state), Some(Vec::<String>::new()));
+
+        create_post(&state, "users:alice", 1).await;
+        let response = run(&state, "users:alice", "{ posts { total } _tables }").await;
+        assert_eq!(response["data"]["posts"]["total"], 1);
+        assert_eq!(response["data"]["_tables"][0]["count"], 1);
+        assert_eq!(cached(&state), Some(vec!["posts".to_string()]));
+
+        let response = run(&state, "users:bob", "{ _tables }").await;
+        assert_eq!(response["data"]["_tables"][0]["count"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_queries_and_mutations_follow_policies() {
+        let state = setup_test_state().await;
+        for views in [5, 20, 40] {
+            create_post(&state, "users:alice", views).await;
+        }
+
+        let response = run(
+            &state,
+            "users:alice",
+            r#"{ posts(filter: {views: "gt.10"}, order: ["views.desc"], limit: 1) {
+                rows { id _json } total next_cursor } }"#,
+        )
+        .await;
+        let page = &response["data"]["posts"];
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["rows"][0]["_json"]["views"], 40);
+        assert!(page["next_cursor"].is_string());
+
+        let response = run(&state, "users:bob", "{ posts { total } }").await;
+        assert_eq!(response["data"]["posts"]["total"], 0);
+
+        let response = run(
+            &state,
+            "users:alice",
+            r#"mutation { create_posts(data: {views: 1, owner: "users:bob"}) { id _json } }"#,
+        )
+        .await;
+        let created = &response["data"]["create_posts"];
+        assert_eq!(created["_json"]["owner"], "users:alice");
+        let id = created["id"].as_str().unwrap().to_string();
+
+        let update =
+            format!(r#"mutation {{ update_posts(id: "{}", data: {{views: 2}}) {{ id }} }}"#, id);
+        let response = run(&state, "users:bob", &update).await;
+        assert_eq!(response["data"]["update_posts"], Value::Null);
+        let response = run(&state, "users:alice", &update).await;
+        assert_eq!(response["data"]["update_posts"]["id"], id.as_str());
+
+        let delete = format!(r#"mutation {{ delete_posts(id: "{}") {{ _json }} }}"#, id);
+        let response = run(&state, "users:alice", &delete).await;
+        assert_eq!(response["data"]["delete_posts"]["_json"]["views"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_filter_is_a_bad_request() {
+        let state = setup_test_state().await;
+        create_post(&state, "users:alice", 1).await;
+
+        let query = r#"{ posts(filter: {views: "between.1"}) { total } }"#;
+        let response = run(&state, "users:alice", query).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "BAD_REQUEST");
+    }
+
+    #[tokio::test]
+    async fn test_subscription_delivers_visible_changes() {
+        let state = setup_test_state().await;
+        create_post(&state, "users:alice", 1).await;
+
+        let schema = schema(&state).await.unwrap();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let subscription = "subscription { posts_changes { action record { _json } } }";
+        let request = Request::new(subscription).data(state.clone()).data(alice);
+        let mut stream = Box::pin(schema.execute_stream(request));
+
+        // The subscription starts when the stream is first polled
+        let realtime = state.realtime.clone();
+        let publisher = tokio::spawn(async move {
+            loop {
+                realtime.broadcast_event("posts", "create", json!({"owner": "users:bob"}));
+                realtime.broadcast_event("posts", "create", json!({"owner": "users:alice"}));
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        let response = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        publisher.abort();
+
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["posts_changes"]["action"], "create");
+        assert_eq!(data["posts_changes"]["record"]["_json"]["owner"], "users:alice");
+    }
+
+    #[tokio::test]
+    async fn test_subscription_decrypts_changes() {
+        let state = setup_test_state().await;
+        let posts = Table::new("posts").unwrap();
+        let secret = edge_hive_db::filter::Field::new("secret").unwrap();
+        state.db.encrypt_fields(&posts, &[secret]).unwrap();
+        state.db.unlock_keys(&age::x25519::Identity::generate(), &[]).await.unwrap();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        state
+            .db
+            .scoped(&state.policies, &alice)
+            .create_record(&posts, json!({"secret": "s3cret"}))
+            .await
+            .unwrap();
+
+        // Live queries carry the rows as stored
+        let query = edge_hive_db::BoundQuery::new("SELECT * FROM posts");
+        let stored = state.db.execute(query).await.unwrap().remove(0);
+        assert_ne!(stored["secret"], "s3cret");
+
+        let schema = schema(&state).await.unwrap();
+        let subscription = "subscription { posts_changes { record { _json } } }";
+        let request = Request::new(subscription).data(state.clone()).data(alice);
+        let mut stream = Box::pin(schema.execute_stream(request));
+        let realtime = state.realtime.clone();
+        let publisher = tokio::spawn(async move {
+            loop {
+                realtime.broadcast_event("posts", "create", stored.clone());
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        let response = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        publisher.abort();
+
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["posts_changes"]["record"]["_json"]["secret"], "s3cret");
+    }
+}
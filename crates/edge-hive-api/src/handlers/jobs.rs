@@ -0,0 +1,238 @@
+//! Background job administration handlers
+//!
+//! Jobs belong to the database of the API they are queued in and are run by
+//! that API's worker (see [`crate::jobs`]).
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::Json,
+};
+use edge_hive_db::{Job, JobQuery, JobSchedule, JobStatus, NewJob, NewJobSchedule};
+use serde::Deserialize;
+use super::admin::require_admin;
+use super::data::db_error_status;
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+
+/// Jobs returned when no limit is given
+const DEFAULT_LIMIT: u64 = 100;
+
+/// Query parameters of `GET /api/v1/admin/jobs`
+#[derive(Debug, Default, Deserialize)]
+pub struct JobParams {
+    pub status: Option<JobStatus>,
+    pub handler: Option<String>,
+    pub limit: Option<u64>,
+}
+
+/// List jobs, newest first
+pub async fn list_jobs(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Query(params): Query<JobParams>,
+) -> Result<Json<Vec<Job>>, StatusCode> {
+    require_admin(&claims)?;
+
+    let query = JobQuery {
+        status: params.status,
+        handler: params.handler,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT),
+    };
+    state.db.jobs(&query).await.map(Json).map_err(db_error_status)
+}
+
+/// Queue a job
+pub async fn enqueue_job(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(job): Json<NewJob>,
+) -> Result<(StatusCode, Json<Job>), StatusCode> {
+    require_admin(&claims)?;
+
+    let job = state.db.enqueue_job(job).await.map_err(db_error_status)?;
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+/// Get a job
+pub async fn get_job(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.job(&id).await.map_err(db_error_status)?.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Remove a job that is not running
+pub async fn delete_job(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+
+    match state.db.delete_job(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+/// Run a queued or dead-lettered job right away
+pub async fn retry_job(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .retry_job(&id)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// List the job schedules
+pub async fn list_schedules(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<JobSchedule>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.job_schedules().await.map(Json).map_err(db_error_status)
+}
+
+/// Add a job schedule
+pub async fn create_schedule(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(schedule): Json<NewJobSchedule>,
+) -> Result<(StatusCode, Json<JobSchedule>), StatusCode> {
+    require_admin(&claims)?;
+
+    let schedule = state.db.create_job_schedule(schedule).await.map_err(db_error_status)?;
+    Ok((StatusCode::CREATED, Json(schedule)))
+}
+
+/// Remove a job schedule, keeping the jobs it queued
+pub async fn delete_schedule(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+
+    match state.db.delete_job_schedule(&name).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::JwtClaims;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::DatabaseService;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+    }
+
+    fn caller(role: &str) -> BearerClaims {
+        let claims =
+            JwtClaims::new("users:root".to_string(), "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    fn new_job(handler: &str) -> Json<NewJob> {
+        Json(NewJob {
+            handler: handler.to_string(),
+            payload: json!({"sql": "RETURN 1"}),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_jobs_require_admin() {
+        let state = setup_test_state().await;
+
+        let result = enqueue_job(Extension(state.clone()), caller("user"), new_job("query")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let result = list_schedules(Extension(state), caller("user")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_manage_jobs_and_schedules() {
+        let state = setup_test_state().await;
+
+        let (status, Json(job)) =
+            enqueue_job(Extension(state.clone()), caller("admin"), new_job("query"))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(job.status, JobStatus::Queued);
+        let result = enqueue_job(Extension(state.clone()), caller("admin"), new_job("")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let Json(jobs) = list_jobs(
+            Extension(state.clone()),
+            caller("admin"),
+            Query(JobParams {
+                status: Some(JobStatus::Queued),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(jobs, vec![job.clone()]);
+
+        let status =
+            delete_job(Extension(state.clone()), caller("admin"), Path(job.id.clone())).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let result = get_job(Extension(state.clone()), caller("admin"), Path(job.id)).await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let schedule = NewJobSchedule {
+            name: "hourly".to_string(),
+            cron: "0 * * * *".to_string(),
+            handler: "query".to_string(),
+            ..Default::default()
+        };
+        let (status, _) =
+            create_schedule(Extension(state.clone()), caller("admin"), Json(schedule.clone()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let invalid = NewJobSchedule {
+            cron: "whenever".to_string(),
+            ..schedule
+        };
+        let result =
+            create_schedule(Extension(state.clone()), caller("admin"), Json(invalid)).await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let status =
+            delete_schedule(Extension(state), caller("admin"), Path("hourly".to_string())).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
@@ -2,7 +2,7 @@
 
 use crate::state::ApiState;
 use axum::{extract::Extension, http::StatusCode, response::Json};
-use edge_hive_auth::AuthenticatedUser;
+use edge_hive_auth::middleware::AuthenticatedUser;
 use edge_hive_mcp::{MCPRequest, MCPResponse};
 
 /// MCP JSON-RPC endpoint (authenticated)
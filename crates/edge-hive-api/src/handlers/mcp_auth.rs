@@ -2,10 +2,14 @@
 
 use crate::state::ApiState;
 use axum::{extract::Extension, http::StatusCode, response::Json};
-use edge_hive_auth::{TokenRequest, TokenResponse};
+use edge_hive_auth::oauth2::{TokenRequest, TokenResponse};
+use edge_hive_auth::AuthError;
 use serde::Serialize;
 use tracing::warn;
 
+/// Lifetime of issued tokens, as set by `JwtClaims::new`
+const TOKEN_LIFETIME_SECS: i64 = 3600;
+
 #[derive(Serialize, Debug)]
 pub struct ErrorResponse {
     error: String,
@@ -30,30 +34,19 @@ pub async fn issue_mcp_token(
     }
 
     // 2. Find client by client_id
-    let client = state
-        .db
-        .get_client_by_id(&payload.client_id)
-        .await
-        .map_err(|e| {
-            warn!("Database error while fetching client: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "server_error".into(),
-                    error_description: "Database operation failed".into(),
-                }),
-            )
-        })?
-        .ok_or_else(|| {
-            warn!("Client not found: {}", payload.client_id);
-            (
-                StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse {
-                    error: "invalid_client".into(),
-                    error_description: "Client authentication failed".into(),
-                }),
-            )
-        })?;
+    let client = state.clients.get_client(&payload.client_id).await.map_err(|e| {
+        match e {
+            AuthError::ClientNotFound(_) => warn!("Client not found: {}", payload.client_id),
+            e => warn!("Failed to fetch client: {}", e),
+        }
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_client".into(),
+                error_description: "Client authentication failed".into(),
+            }),
+        )
+    })?;
 
     // 3. Verify client secret
     if !client.verify_secret(&payload.client_secret) {
@@ -73,7 +66,7 @@ pub async fn issue_mcp_token(
         .generate_token(
             client.client_id,
             client.scopes.clone(),
-            Some(state.config.oauth2.token_expiration_secs),
+            None,
         )
         .map_err(|e| {
             warn!("Token generation failed: {}", e);
@@ -89,7 +82,7 @@ pub async fn issue_mcp_token(
     // 5. Create response
     let response = TokenResponse::new(
         token,
-        state.config.oauth2.token_expiration_secs,
+        TOKEN_LIFETIME_SECS,
         client.scopes,
     );
 
@@ -101,26 +94,22 @@ mod tests {
     use super::*;
     use crate::state::ApiState;
     use axum::http::StatusCode;
-    use edge_hive_auth::{ClientCredentials, TokenRequest};
+    use edge_hive_auth::client::ClientStore;
+    use edge_hive_auth::ClientCredentials;
     use edge_hive_cache::{CacheConfig, CacheService};
     use edge_hive_db::DatabaseService;
     use std::{path::PathBuf, sync::Arc};
     use tempfile::tempdir;
 
     async fn setup_test_state() -> ApiState {
-        let db_dir = tempdir().unwrap();
-        let db = Arc::new(
-            DatabaseService::new(&db_dir.path().join("test.db"))
-                .await
-                .unwrap(),
-        );
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
         let cache = CacheService::new(CacheConfig::default()).await;
         let data_dir = PathBuf::from(tempdir().unwrap().path());
-        ApiState::new(cache, db, data_dir)
+        ApiState::new_minimal(cache, db, data_dir)
     }
 
     async fn create_test_client(
-        db: &DatabaseService,
+        clients: &ClientStore,
         client_id: &str,
         client_secret: &str,
         scopes: Vec<String>,
@@ -131,7 +120,7 @@ mod tests {
             scopes,
             "Test MCP Client".to_string(),
         );
-        db.create_client(&client).await.unwrap();
+        clients.add_client(client).await.unwrap();
     }
 
     #[tokio::test]
@@ -140,7 +129,7 @@ mod tests {
         let client_id = "test_client_1";
         let client_secret = "test_secret_123";
         let scopes = vec!["mcp:read".to_string(), "mcp:call".to_string()];
-        create_test_client(&state.db, client_id, client_secret, scopes.clone()).await;
+        create_test_client(&state.clients, client_id, client_secret, scopes.clone()).await;
 
         let payload = TokenRequest {
             grant_type: "client_credentials".to_string(),
@@ -163,7 +152,7 @@ mod tests {
         let state = setup_test_state().await;
         let client_id = "test_client_2";
         let client_secret = "test_secret_123";
-        create_test_client(&state.db, client_id, client_secret, vec![]).await;
+        create_test_client(&state.clients, client_id, client_secret, vec![]).await;
 
         let payload = TokenRequest {
             grant_type: "client_credentials".to_string(),
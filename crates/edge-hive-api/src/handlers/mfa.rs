@@ -0,0 +1,589 @@
+//! Multi-factor authentication handlers
+//!
+//! Users with a confirmed TOTP secret, and users of the roles an admin made
+//! MFA mandatory for, log in in two steps: `login` answers with an MFA
+//! challenge instead of a session, and `verify_mfa` trades the challenge
+//! token and a code (TOTP or recovery) for the session. Users who must use
+//! MFA but have not enrolled yet enroll with the challenge token in place of
+//! an access token, which also completes their login.
+
+use super::admin::require_admin;
+use super::auth::{
+    auth_error, db_failure, hash_token, issue_one_time_token, start_session, AuthError,
+    LoginResponse, LoginStep, MfaChallenge,
+};
+use super::data::db_error_status;
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+use axum::{extract::Extension, http::StatusCode, response::Json};
+use chrono::{Duration, Utc};
+use edge_hive_auth::{totp, JwtClaims, Totp};
+use edge_hive_db::{StoredUser, TokenPurpose, TotpEnrollment};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// Lifetime of an MFA challenge, in minutes
+const MFA_CHALLENGE_MINUTES: i64 = 5;
+
+/// Wrong codes after which an MFA challenge is spent
+const MAX_MFA_ATTEMPTS: u32 = 5;
+
+/// Recovery codes handed out when enrolling
+const RECOVERY_CODES: usize = 10;
+
+/// Issuer shown by authenticator apps
+const TOTP_ISSUER: &str = "Edge Hive";
+
+#[derive(Deserialize, Default)]
+pub struct EnrollTotpRequest {
+    /// Token of a login waiting for enrollment, instead of an access token
+    #[serde(default)]
+    mfa_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TotpSecret {
+    /// Base32 secret, for manual entry
+    pub secret: String,
+    /// `otpauth://` URI, the payload of the QR code to scan
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    #[serde(default)]
+    mfa_token: Option<String>,
+    code: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConfirmTotpResponse {
+    /// Shown once; each one replaces a TOTP code once
+    pub recovery_codes: Vec<String>,
+    /// Session of the login that enrolled with an MFA token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<LoginResponse>,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyMfaRequest {
+    mfa_token: String,
+    /// TOTP code or recovery code
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    code: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MfaStatus {
+    pub enabled: bool,
+    /// Whether the user's role requires MFA
+    pub required: bool,
+    pub recovery_codes_left: usize,
+}
+
+/// Roles whose users must log in with a second factor
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MfaPolicy {
+    pub required_roles: Vec<String>,
+}
+
+/// Start a session for `user`, or an MFA challenge if they need a second factor
+pub(crate) async fn begin_session(
+    state: &ApiState,
+    user: StoredUser,
+) -> Result<LoginStep, AuthError> {
+    let enrolled = confirmed_totp(state, &user).await?.is_some();
+    let required = state.db.mfa_required_roles().await.map_err(db_failure)?.contains(&user.role);
+    if !enrolled && !required {
+        return start_session(state, user).await.map(LoginStep::Session);
+    }
+
+    let ttl = Duration::minutes(MFA_CHALLENGE_MINUTES);
+    let mfa_token = issue_one_time_token(state, &user, TokenPurpose::MfaChallenge, ttl)
+        .await
+        .map_err(|e| {
+            error!("Failed to issue an MFA challenge: {}", e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Token error")
+        })?;
+    Ok(LoginStep::Mfa(MfaChallenge {
+        mfa_required: true,
+        enrollment_required: !enrolled,
+        mfa_token,
+        expires_in: ttl.num_seconds(),
+    }))
+}
+
+/// Second step of a login: trade an MFA challenge and a code for the session
+pub async fn verify_mfa(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<VerifyMfaRequest>,
+) -> Result<Json<LoginResponse>, AuthError> {
+    let (claims, user) = challenge(&state, &payload.mfa_token).await?;
+    let Some(totp) = confirmed_totp(&state, &user).await? else {
+        return Err(auth_error(StatusCode::FORBIDDEN, "MFA enrollment required"));
+    };
+
+    if !check_code(&state, &user, &totp, &payload.code).await? {
+        return Err(wrong_code(&state, &claims).await);
+    }
+    spend_challenge(&state, &claims).await?;
+    start_session(&state, user).await.map(Json)
+}
+
+/// MFA state of the caller
+pub async fn mfa_status(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<MfaStatus>, AuthError> {
+    let user = bearer_user(&state, &claims).await?;
+    let enabled = confirmed_totp(&state, &user).await?.is_some();
+    let required = state.db.mfa_required_roles().await.map_err(db_failure)?.contains(&user.role);
+    let recovery_codes_left =
+        state.db.recovery_codes_left(&user_key(&user)?).await.map_err(db_failure)?;
+
+    Ok(Json(MfaStatus {
+        enabled,
+        required,
+        recovery_codes_left,
+    }))
+}
+
+/// Start enrolling a TOTP authenticator
+///
+/// Authenticated by an access token, or by the MFA token of a login waiting
+/// for enrollment.
+pub async fn enroll_totp(
+    Extension(state): Extension<ApiState>,
+    bearer: Option<BearerClaims>,
+    payload: Option<Json<EnrollTotpRequest>>,
+) -> Result<Json<TotpSecret>, AuthError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let (user, _) = enrolling_user(&state, bearer, payload.mfa_token.as_deref()).await?;
+
+    let totp = Totp::generate();
+    let secret = totp.to_base32();
+    if !state.db.save_totp_secret(&user_key(&user)?, &secret).await.map_err(db_failure)? {
+        return Err(auth_error(StatusCode::CONFLICT, "MFA already enabled"));
+    }
+
+    Ok(Json(TotpSecret {
+        otpauth_uri: totp.uri(TOTP_ISSUER, &user.email),
+        secret,
+    }))
+}
+
+/// Confirm a TOTP enrollment with a code, handing out the recovery codes
+pub async fn confirm_totp(
+    Extension(state): Extension<ApiState>,
+    bearer: Option<BearerClaims>,
+    Json(payload): Json<ConfirmTotpRequest>,
+) -> Result<Json<ConfirmTotpResponse>, AuthError> {
+    let (user, challenge) = enrolling_user(&state, bearer, payload.mfa_token.as_deref()).await?;
+    let key = user_key(&user)?;
+    let enrollment = state.db.totp_enrollment(&key).await.map_err(db_failure)?;
+    let Some(enrollment) = enrollment.filter(|totp| !totp.confirmed) else {
+        return Err(auth_error(StatusCode::CONFLICT, "No TOTP enrollment in progress"));
+    };
+
+    let step = secret(&enrollment)?.verify(&payload.code, unix_now());
+    let Some(step) = step else {
+        return Err(match &challenge {
+            Some(claims) => wrong_code(&state, claims).await,
+            None => auth_error(StatusCode::BAD_REQUEST, "Invalid MFA code"),
+        });
+    };
+
+    let recovery_codes = totp::recovery_codes(RECOVERY_CODES);
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| recovery_hash(code)).collect();
+    if !state.db.confirm_totp(&key, step, &hashes).await.map_err(db_failure)? {
+        return Err(auth_error(StatusCode::CONFLICT, "No TOTP enrollment in progress"));
+    }
+
+    let session = match challenge {
+        Some(claims) => {
+            spend_challenge(&state, &claims).await?;
+            Some(start_session(&state, user).await?)
+        }
+        None => None,
+    };
+    Ok(Json(ConfirmTotpResponse {
+        recovery_codes,
+        session,
+    }))
+}
+
+/// Turn MFA off for the caller, given a current code
+pub async fn disable_mfa(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(payload): Json<MfaCodeRequest>,
+) -> Result<StatusCode, AuthError> {
+    let user = bearer_user(&state, &claims).await?;
+    let Some(totp) = confirmed_totp(&state, &user).await? else {
+        return Err(auth_error(StatusCode::BAD_REQUEST, "MFA is not enabled"));
+    };
+    if !check_code(&state, &user, &totp, &payload.code).await? {
+        return Err(auth_error(StatusCode::UNAUTHORIZED, "Invalid MFA code"));
+    }
+
+    state.db.disable_mfa(&user_key(&user)?).await.map_err(db_failure)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Roles that must use MFA
+pub async fn get_mfa_policy(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<MfaPolicy>, StatusCode> {
+    require_admin(&claims)?;
+
+    let required_roles = state.db.mfa_required_roles().await.map_err(db_error_status)?;
+    Ok(Json(MfaPolicy { required_roles }))
+}
+
+/// Set the roles that must use MFA
+///
+/// Users of these roles without MFA are asked to enroll at their next login.
+pub async fn set_mfa_policy(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(policy): Json<MfaPolicy>,
+) -> Result<Json<MfaPolicy>, StatusCode> {
+    require_admin(&claims)?;
+
+    let mut required_roles: Vec<String> =
+        policy.required_roles.iter().map(|role| role.trim().to_string()).collect();
+    if required_roles.iter().any(String::is_empty) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    required_roles.sort();
+    required_roles.dedup();
+
+    state.db.set_mfa_required_roles(&required_roles).await.map_err(db_error_status)?;
+    Ok(Json(MfaPolicy { required_roles }))
+}
+
+/// Claims and user of a live MFA challenge
+async fn challenge(state: &ApiState, token: &str) -> Result<(JwtClaims, StoredUser), AuthError> {
+    let invalid = || auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired MFA token");
+    let purpose = TokenPurpose::MfaChallenge;
+    let claims = state
+        .token_validator
+        .validate_token_for(token, purpose.as_str())
+        .map_err(|_| invalid())?;
+    let user_id = state
+        .db
+        .check_one_time_token(&claims.jti, purpose)
+        .await
+        .map_err(db_failure)?
+        .ok_or_else(invalid)?;
+    if user_id != claims.sub {
+        return Err(invalid());
+    }
+
+    let user = state.db.get_user_by_id(&user_id).await.map_err(db_failure)?.ok_or_else(invalid)?;
+    Ok((claims, user))
+}
+
+/// Spend an MFA challenge once its login went through
+async fn spend_challenge(state: &ApiState, claims: &JwtClaims) -> Result<(), AuthError> {
+    let redeemed = state
+        .db
+        .redeem_one_time_token(&claims.jti, TokenPurpose::MfaChallenge)
+        .await
+        .map_err(db_failure)?;
+    match redeemed {
+        Some(_) => Ok(()),
+        None => Err(auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired MFA token")),
+    }
+}
+
+/// Count a wrong code against an MFA challenge
+async fn wrong_code(state: &ApiState, claims: &JwtClaims) -> AuthError {
+    if let Err(e) = state.db.fail_one_time_token(&claims.jti, MAX_MFA_ATTEMPTS).await {
+        return db_failure(e);
+    }
+    auth_error(StatusCode::UNAUTHORIZED, "Invalid MFA code")
+}
+
+/// User enrolling, with the MFA challenge they enroll through, if any
+async fn enrolling_user(
+    state: &ApiState,
+    bearer: Option<BearerClaims>,
+    mfa_token: Option<&str>,
+) -> Result<(StoredUser, Option<JwtClaims>), AuthError> {
+    match (mfa_token, bearer) {
+        (Some(token), _) => {
+            let (claims, user) = challenge(state, token).await?;
+            Ok((user, Some(claims)))
+        }
+        (None, Some(BearerClaims(claims))) => Ok((bearer_user(state, &claims).await?, None)),
+        (None, None) => Err(auth_error(StatusCode::UNAUTHORIZED, "Missing access token")),
+    }
+}
+
+/// User of an access token (API keys have none)
+pub(crate) async fn bearer_user(
+    state: &ApiState,
+    claims: &JwtClaims,
+) -> Result<StoredUser, AuthError> {
+    let not_a_user = || auth_error(StatusCode::FORBIDDEN, "Only users can do this");
+    let user_id = claims.sub.strip_prefix("users:").ok_or_else(not_a_user)?;
+    state.db.get_user_by_id(user_id).await.map_err(db_failure)?.ok_or_else(not_a_user)
+}
+
+/// Key of `user` in the MFA and passkey tables
+pub(crate) fn user_key(user: &StoredUser) -> Result<String, AuthError> {
+    user.id
+        .as_ref()
+        .map(|id| id.id.to_raw())
+        .ok_or_else(|| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "User has no ID"))
+}
+
+async fn confirmed_totp(
+    state: &ApiState,
+    user: &StoredUser,
+) -> Result<Option<TotpEnrollment>, AuthError> {
+    let enrollment = state.db.totp_enrollment(&user_key(user)?).await.map_err(db_failure)?;
+    Ok(enrollment.filter(|totp| totp.confirmed))
+}
+
+/// Accept a TOTP code not used before, or spend a recovery code
+async fn check_code(
+    state: &ApiState,
+    user: &StoredUser,
+    totp: &TotpEnrollment,
+    code: &str,
+) -> Result<bool, AuthError> {
+    let key = user_key(user)?;
+    let compact: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if compact.len() == totp::DIGITS && compact.bytes().all(|b| b.is_ascii_digit()) {
+        let Some(step) = secret(totp)?.verify(&compact, unix_now()) else {
+            return Ok(false);
+        };
+        state.db.use_totp_step(&key, step).await.map_err(db_failure)
+    } else {
+        state.db.redeem_recovery_code(&key, &recovery_hash(code)).await.map_err(db_failure)
+    }
+}
+
+fn secret(totp: &TotpEnrollment) -> Result<Totp, AuthError> {
+    Totp::from_base32(&totp.secret).map_err(|e| {
+        error!("Stored TOTP secret is unreadable: {}", e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "MFA error")
+    })
+}
+
+/// Hash of a recovery code, ignoring case, spaces and dashes
+fn recovery_hash(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&code)
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::auth::{login, LoginRequest};
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{user::HashedPassword, DatabaseService};
+    use std::{path::PathBuf, sync::Arc};
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+    }
+
+    async fn create_user(state: &ApiState, email: &str, role: &str) -> BearerClaims {
+        let user = StoredUser {
+            id: None,
+            email: email.to_string(),
+            name: None,
+            password_hash: HashedPassword::new("password123").unwrap().to_string(),
+            provider: None,
+            provider_id: None,
+            avatar_url: None,
+            role: role.to_string(),
+            email_verified: true,
+            created_at: Utc::now().into(),
+            updated_at: Utc::now().into(),
+        };
+        let user = state.db.create_user(&user).await.unwrap();
+        let sub = user.id.unwrap().to_string();
+        let claims = JwtClaims::new(sub, "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    async fn login_as(state: &ApiState, email: &str) -> LoginStep {
+        let payload = LoginRequest::Credentials {
+            email: email.to_string(),
+            password: "password123".to_string(),
+        };
+        login(Extension(state.clone()), Json(payload)).await.unwrap().0
+    }
+
+    async fn challenge_of(state: &ApiState, email: &str) -> MfaChallenge {
+        match login_as(state, email).await {
+            LoginStep::Mfa(challenge) => challenge,
+            LoginStep::Session(_) => panic!("expected an MFA challenge"),
+        }
+    }
+
+    async fn verify(
+        state: &ApiState,
+        token: &str,
+        code: &str,
+    ) -> Result<LoginResponse, StatusCode> {
+        let payload = VerifyMfaRequest {
+            mfa_token: token.to_string(),
+            code: code.to_string(),
+        };
+        verify_mfa(Extension(state.clone()), Json(payload))
+            .await
+            .map(|response| response.0)
+            .map_err(|(status, _)| status)
+    }
+
+    async fn confirm(
+        state: &ApiState,
+        bearer: Option<BearerClaims>,
+        mfa_token: Option<&str>,
+        code: &str,
+    ) -> Result<ConfirmTotpResponse, StatusCode> {
+        let payload = ConfirmTotpRequest {
+            mfa_token: mfa_token.map(str::to_string),
+            code: code.to_string(),
+        };
+        confirm_totp(Extension(state.clone()), bearer, Json(payload))
+            .await
+            .map(|response| response.0)
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_totp_login() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com", "user").await;
+        let now = unix_now();
+        assert!(matches!(login_as(&state, "ada@example.com").await, LoginStep::Session(_)));
+
+        let enrolled = enroll_totp(Extension(state.clone()), Some(ada.clone()), None)
+            .await
+            .unwrap()
+            .0;
+        assert!(enrolled.otpauth_uri.starts_with("otpauth://totp/Edge%20Hive:ada@example.com"));
+        let totp = Totp::from_base32(&enrolled.secret).unwrap();
+        let result = confirm(&state, Some(ada.clone()), None, "000000").await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+        let confirmed = confirm(&state, Some(ada.clone()), None, &totp.code_at(now))
+            .await
+            .unwrap();
+        assert_eq!(confirmed.recovery_codes.len(), RECOVERY_CODES);
+        assert!(confirmed.session.is_none());
+
+        // The code used to confirm cannot log in again; the next one can, once
+        let challenge = challenge_of(&state, "ada@example.com").await;
+        assert!(!challenge.enrollment_required);
+        let used = totp.code_at(now);
+        let result = verify(&state, &challenge.mfa_token, &used).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+        let next = totp.code_at(now + totp::PERIOD);
+        assert!(verify(&state, &challenge.mfa_token, &next).await.is_ok());
+        let result = verify(&state, &challenge.mfa_token, &next).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        // Recovery codes work once, dashes and case aside
+        let recovery = confirmed.recovery_codes[0].to_uppercase().replace('-', "");
+        let challenge = challenge_of(&state, "ada@example.com").await;
+        assert!(verify(&state, &challenge.mfa_token, &recovery).await.is_ok());
+        let challenge = challenge_of(&state, "ada@example.com").await;
+        let result = verify(&state, &challenge.mfa_token, &recovery).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        let status = mfa_status(Extension(state.clone()), ada.clone()).await.unwrap().0;
+        assert!(status.enabled);
+        assert_eq!(status.recovery_codes_left, RECOVERY_CODES - 1);
+    }
+
+    #[tokio::test]
+    async fn test_challenge_attempts_are_limited() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com", "user").await;
+        let secret = enroll_totp(Extension(state.clone()), Some(ada.clone()), None)
+            .await
+            .unwrap()
+            .0
+            .secret;
+        let totp = Totp::from_base32(&secret).unwrap();
+        let codes = confirm(&state, Some(ada), None, &totp.code_at(unix_now()))
+            .await
+            .unwrap()
+            .recovery_codes;
+
+        let challenge = challenge_of(&state, "ada@example.com").await;
+        for _ in 0..MAX_MFA_ATTEMPTS {
+            let result = verify(&state, &challenge.mfa_token, "wrong-code").await;
+            assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+        }
+        let result = verify(&state, &challenge.mfa_token, &codes[0]).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_required_role_enrolls_at_login() {
+        let state = setup_test_state().await;
+        let root = create_user(&state, "root@example.com", "admin").await;
+        let ada = create_user(&state, "ada@example.com", "user").await;
+        let policy = || {
+            Json(MfaPolicy {
+                required_roles: vec!["admin".to_string()],
+            })
+        };
+        let result = set_mfa_policy(Extension(state.clone()), ada, policy()).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+        set_mfa_policy(Extension(state.clone()), root.clone(), policy()).await.unwrap();
+        assert!(matches!(login_as(&state, "ada@example.com").await, LoginStep::Session(_)));
+
+        let challenge = challenge_of(&state, "root@example.com").await;
+        assert!(challenge.enrollment_required);
+        let result = verify(&state, &challenge.mfa_token, "123456").await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let token = challenge.mfa_token.as_str();
+        let payload = Json(EnrollTotpRequest {
+            mfa_token: Some(token.to_string()),
+        });
+        let secret = enroll_totp(Extension(state.clone()), None, Some(payload)).await.unwrap();
+        let totp = Totp::from_base32(&secret.0.secret).unwrap();
+        let confirmed = confirm(&state, None, Some(token), &totp.code_at(unix_now()))
+            .await
+            .unwrap();
+        assert!(confirmed.session.is_some());
+        // The challenge went into that session
+        let result = confirm(&state, None, Some(token), &totp.code_at(unix_now())).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        let status = mfa_status(Extension(state.clone()), root.clone()).await.unwrap().0;
+        assert!(status.enabled && status.required);
+        let code = Json(MfaCodeRequest {
+            code: confirmed.recovery_codes[0].clone(),
+        });
+        let disabled = disable_mfa(Extension(state.clone()), root.clone(), code).await;
+        assert_eq!(disabled.unwrap(), StatusCode::NO_CONTENT);
+        assert!(challenge_of(&state, "root@example.com").await.enrollment_required);
+    }
+}
@@ -3,9 +3,17 @@
 pub mod health;
 pub mod data;
 pub mod auth;
+pub mod mfa;
+pub mod passkey;
 pub mod edge;
 pub mod realtime;
 pub mod mcp;
 pub mod user;
 pub mod mcp_auth;
 pub mod wasm;
+pub mod admin;
+pub mod webhooks;
+pub mod projects;
+pub mod branches;
+pub mod jobs;
+pub mod graphql;
@@ -0,0 +1,432 @@
+//! Passkey handlers
+//!
+//! Logged-in users with a verified email address register passkeys in two
+//! calls: `register_options` hands
+//! out the options of `navigator.credentials.create()`, and
+//! `register_passkey` stores the credential the browser answers with. Logins
+//! work the same way with `login_options` and `passkey_login`, which issues
+//! the session pair of a password login. Passkeys verify the user on the
+//! device, so a passkey login is not asked for a second factor.
+
+use super::auth::{auth_error, db_failure, start_session, AuthError, LoginResponse};
+use super::mfa::{bearer_user, user_key};
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use edge_hive_auth::webauthn::{
+    client_challenge, user_handle, AuthenticationResponse, RegistrationResponse,
+};
+use edge_hive_auth::{Credential, JwtClaims, Webauthn};
+use edge_hive_db::{NewPasskey, Passkey, StoredUser, WebauthnCeremony, WebauthnChallenge};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+/// Lifetime of a ceremony, in minutes
+const CHALLENGE_MINUTES: i64 = 5;
+
+/// Options to pass to `navigator.credentials`
+#[derive(Serialize, Debug)]
+pub struct CeremonyOptions {
+    #[serde(rename = "publicKey")]
+    pub public_key: Value,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyRegistration {
+    /// Label of the passkey, e.g. the device it lives on
+    #[serde(default)]
+    name: Option<String>,
+    credential: RegistrationResponse,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLogin {
+    credential: AuthenticationResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PasskeyInfo {
+    pub id: String,
+    pub name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<Passkey> for PasskeyInfo {
+    fn from(passkey: Passkey) -> Self {
+        Self {
+            id: passkey.id,
+            name: passkey.name,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+        }
+    }
+}
+
+/// Start registering a passkey for the caller
+pub async fn register_options(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<CeremonyOptions>, AuthError> {
+    let webauthn = webauthn(&state)?;
+    let user = verified_user(&state, &claims).await?;
+    let key = user_key(&user)?;
+    let registered: Vec<String> = state
+        .db
+        .passkeys(&key)
+        .await
+        .map_err(db_failure)?
+        .into_iter()
+        .map(|passkey| passkey.id)
+        .collect();
+
+    let challenge = Webauthn::challenge();
+    let public_key =
+        webauthn.registration_options(&challenge, &user_handle(&key), &user.email, &registered);
+    let ceremony = WebauthnChallenge {
+        challenge,
+        ceremony: WebauthnCeremony::Register,
+        user: Some(key),
+    };
+    save_challenge(&state, &ceremony).await?;
+
+    Ok(Json(CeremonyOptions { public_key }))
+}
+
+/// Finish registering a passkey with the credential the authenticator created
+pub async fn register_passkey(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(payload): Json<PasskeyRegistration>,
+) -> Result<(StatusCode, Json<PasskeyInfo>), AuthError> {
+    let webauthn = webauthn(&state)?;
+    let user = verified_user(&state, &claims).await?;
+    let key = user_key(&user)?;
+    let invalid = || auth_error(StatusCode::BAD_REQUEST, "Invalid or expired passkey challenge");
+
+    let client_data = &payload.credential.response.client_data_json;
+    let challenge = client_challenge(client_data).map_err(|_| invalid())?;
+    let ceremony = state
+        .db
+        .take_webauthn_challenge(&challenge, WebauthnCeremony::Register)
+        .await
+        .map_err(db_failure)?
+        .filter(|ceremony| ceremony.user.as_deref() == Some(key.as_str()))
+        .ok_or_else(invalid)?;
+
+    let credential = webauthn.register(&ceremony.challenge, &payload.credential).map_err(|e| {
+        warn!("Rejected passkey registration: {}", e);
+        auth_error(StatusCode::BAD_REQUEST, "Invalid passkey")
+    })?;
+    let passkey = NewPasskey {
+        id: credential.id,
+        user: key,
+        name: payload.name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty()),
+        public_key: credential.public_key,
+        sign_count: credential.sign_count,
+    };
+    let Some(passkey) = state.db.add_passkey(&passkey).await.map_err(db_failure)? else {
+        return Err(auth_error(StatusCode::CONFLICT, "Passkey already registered"));
+    };
+
+    Ok((StatusCode::CREATED, Json(passkey.into())))
+}
+
+/// Passkeys of the caller
+pub async fn list_passkeys(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<PasskeyInfo>>, AuthError> {
+    let user = bearer_user(&state, &claims).await?;
+    let passkeys = state.db.passkeys(&user_key(&user)?).await.map_err(db_failure)?;
+    Ok(Json(passkeys.into_iter().map(PasskeyInfo::from).collect()))
+}
+
+/// Remove a passkey of the caller
+pub async fn delete_passkey(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AuthError> {
+    let user = bearer_user(&state, &claims).await?;
+    if !state.db.delete_passkey(&user_key(&user)?, &id).await.map_err(db_failure)? {
+        return Err(auth_error(StatusCode::NOT_FOUND, "Passkey not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Start a passkey login
+///
+/// No credentials are listed: the authenticator offers the passkeys it holds
+/// for this site, so nobody learns which accounts have passkeys.
+pub async fn login_options(
+    Extension(state): Extension<ApiState>,
+) -> Result<Json<CeremonyOptions>, AuthError> {
+    let webauthn = webauthn(&state)?;
+    let challenge = Webauthn::challenge();
+    let public_key = webauthn.authentication_options(&challenge, &[]);
+    let ceremony = WebauthnChallenge {
+        challenge,
+        ceremony: WebauthnCeremony::Authenticate,
+        user: None,
+    };
+    save_challenge(&state, &ceremony).await?;
+
+    Ok(Json(CeremonyOptions { public_key }))
+}
+
+/// Log in with the assertion of a passkey
+pub async fn passkey_login(
+    Extension(state): Extension<ApiState>,
+    Json(payload): Json<PasskeyLogin>,
+) -> Result<Json<LoginResponse>, AuthError> {
+    let webauthn = webauthn(&state)?;
+    let invalid = || auth_error(StatusCode::UNAUTHORIZED, "Invalid passkey");
+    let assertion = &payload.credential;
+
+    let challenge = client_challenge(&assertion.response.client_data_json).map_err(|_| invalid())?;
+    let ceremony = state
+        .db
+        .take_webauthn_challenge(&challenge, WebauthnCeremony::Authenticate)
+        .await
+        .map_err(db_failure)?
+        .ok_or_else(invalid)?;
+    let passkey = state.db.passkey(&assertion.id).await.map_err(db_failure)?.ok_or_else(invalid)?;
+    if let Some(handle) = &assertion.response.user_handle {
+        if *handle != user_handle(&passkey.user) {
+            return Err(invalid());
+        }
+    }
+
+    let credential = Credential {
+        id: passkey.id.clone(),
+        public_key: passkey.public_key.clone(),
+        sign_count: passkey.sign_count,
+    };
+    let sign_count = webauthn
+        .authenticate(&ceremony.challenge, assertion, &credential)
+        .map_err(|e| {
+            warn!("Rejected passkey login with {}: {}", passkey.id, e);
+            invalid()
+        })?;
+    // Another login with the same counter value got in first
+    let used = state.db.use_passkey(&passkey.id, passkey.sign_count, sign_count).await;
+    if !used.map_err(db_failure)? {
+        return Err(invalid());
+    }
+
+    let user = state.db.get_user_by_id(&passkey.user).await.map_err(db_failure)?;
+    start_session(&state, user.ok_or_else(invalid)?).await.map(Json)
+}
+
+fn webauthn(state: &ApiState) -> Result<&Webauthn, AuthError> {
+    state
+        .webauthn
+        .as_deref()
+        .ok_or_else(|| auth_error(StatusCode::NOT_FOUND, "Passkeys are not enabled"))
+}
+
+/// The caller, if their address is verified
+///
+/// A passkey on an unverified account would survive it being claimed by
+/// whoever owns the address.
+async fn verified_user(state: &ApiState, claims: &JwtClaims) -> Result<StoredUser, AuthError> {
+    let user = bearer_user(state, claims).await?;
+    if !user.email_verified {
+        return Err(auth_error(StatusCode::FORBIDDEN, "Verify your email address first"));
+    }
+    Ok(user)
+}
+
+async fn save_challenge(state: &ApiState, ceremony: &WebauthnChallenge) -> Result<(), AuthError> {
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_MINUTES);
+    state.db.save_webauthn_challenge(ceremony, expires_at).await.map_err(db_failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::webauthn::testing::SoftAuthenticator;
+    use edge_hive_auth::RelyingParty;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{user::HashedPassword, DatabaseService};
+    use std::{path::PathBuf, sync::Arc};
+    use tempfile::tempdir;
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "http://localhost:8080";
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir).with_webauthn(Webauthn::new(RelyingParty {
+            id: RP_ID.to_string(),
+            name: "Edge Hive".to_string(),
+            origins: vec![ORIGIN.to_string()],
+        }))
+    }
+
+    async fn create_user(state: &ApiState, email: &str) -> BearerClaims {
+        let user = StoredUser {
+            id: None,
+            email: email.to_string(),
+            name: None,
+            password_hash: HashedPassword::new("password123").unwrap().to_string(),
+            provider: None,
+            provider_id: None,
+            avatar_url: None,
+            role: "user".to_string(),
+            email_verified: true,
+            created_at: Utc::now().into(),
+            updated_at: Utc::now().into(),
+        };
+        let user = state.db.create_user(&user).await.unwrap();
+        let sub = user.id.unwrap().to_string();
+        BearerClaims(JwtClaims::new(sub, "edge-hive-test".to_string(), vec![], None))
+    }
+
+    async fn register(
+        state: &ApiState,
+        bearer: &BearerClaims,
+        authenticator: &mut SoftAuthenticator,
+    ) -> Result<PasskeyInfo, StatusCode> {
+        let options = register_options(Extension(state.clone()), bearer.clone())
+            .await
+            .unwrap()
+            .0
+            .public_key;
+        let payload = PasskeyRegistration {
+            name: Some("Laptop".to_string()),
+            credential: authenticator.create(&options),
+        };
+        register_passkey(Extension(state.clone()), bearer.clone(), Json(payload))
+            .await
+            .map(|(_, response)| response.0)
+            .map_err(|(status, _)| status)
+    }
+
+    async fn assertion(
+        state: &ApiState,
+        authenticator: &mut SoftAuthenticator,
+    ) -> AuthenticationResponse {
+        let options = login_options(Extension(state.clone())).await.unwrap().0.public_key;
+        authenticator.get(&options)
+    }
+
+    async fn log_in(
+        state: &ApiState,
+        credential: AuthenticationResponse,
+    ) -> Result<LoginResponse, StatusCode> {
+        passkey_login(Extension(state.clone()), Json(PasskeyLogin { credential }))
+            .await
+            .map(|response| response.0)
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_register_and_log_in() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com").await;
+        let mut authenticator = SoftAuthenticator::new(RP_ID, ORIGIN);
+
+        let passkey = register(&state, &ada, &mut authenticator).await.unwrap();
+        assert_eq!(passkey.id, authenticator.credential_id());
+        assert_eq!(passkey.name.as_deref(), Some("Laptop"));
+        let again = register(&state, &ada, &mut authenticator).await;
+        assert_eq!(again.unwrap_err(), StatusCode::CONFLICT);
+
+        let session = log_in(&state, assertion(&state, &mut authenticator).await).await.unwrap();
+        let session = serde_json::to_value(&session).unwrap();
+        let access_token = session["access_token"].as_str().unwrap();
+        let claims = state.token_validator.validate_token(access_token).unwrap();
+        assert_eq!(claims.sub, ada.0.sub);
+
+        // An assertion answers its challenge once
+        let credential = assertion(&state, &mut authenticator).await;
+        assert!(log_in(&state, credential.clone()).await.is_ok());
+        assert_eq!(log_in(&state, credential).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_registration_needs_verified_email() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com").await;
+        let mut user = state.db.get_user_by_email("ada@example.com").await.unwrap().unwrap();
+        user.email_verified = false;
+        state.db.update_user(&user).await.unwrap();
+
+        let result = register_options(Extension(state.clone()), ada.clone()).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+        let mut authenticator = SoftAuthenticator::new(RP_ID, ORIGIN);
+        let webauthn = state.webauthn.as_deref().unwrap();
+        let options = webauthn.registration_options(
+            &Webauthn::challenge(),
+            &user_handle(&user_key(&user).unwrap()),
+            &user.email,
+            &[],
+        );
+        let payload = PasskeyRegistration {
+            name: None,
+            credential: authenticator.create(&options),
+        };
+        let result = register_passkey(Extension(state), ada, Json(payload)).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cloned_authenticator_is_refused() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com").await;
+        let mut authenticator = SoftAuthenticator::new(RP_ID, ORIGIN);
+        register(&state, &ada, &mut authenticator).await.unwrap();
+
+        let mut clone = authenticator.clone();
+        assert!(log_in(&state, assertion(&state, &mut authenticator).await).await.is_ok());
+        // The clone signs with a counter the original already used
+        let result = log_in(&state, assertion(&state, &mut clone).await).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_several_passkeys_per_user() {
+        let state = setup_test_state().await;
+        let ada = create_user(&state, "ada@example.com").await;
+        let bob = create_user(&state, "bob@example.com").await;
+        let mut laptop = SoftAuthenticator::new(RP_ID, ORIGIN);
+        let mut phone = SoftAuthenticator::new(RP_ID, ORIGIN);
+        register(&state, &ada, &mut laptop).await.unwrap();
+        register(&state, &ada, &mut phone).await.unwrap();
+
+        let listed = list_passkeys(Extension(state.clone()), ada.clone()).await.unwrap().0;
+        let ids: Vec<String> = listed.into_iter().map(|passkey| passkey.id).collect();
+        assert_eq!(ids, vec![laptop.credential_id(), phone.credential_id()]);
+        assert!(log_in(&state, assertion(&state, &mut phone).await).await.is_ok());
+
+        let id = Path(phone.credential_id());
+        let result = delete_passkey(Extension(state.clone()), bob, id).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+        let id = Path(phone.credential_id());
+        let result = delete_passkey(Extension(state.clone()), ada.clone(), id).await;
+        assert_eq!(result.unwrap(), StatusCode::NO_CONTENT);
+        let result = log_in(&state, assertion(&state, &mut phone).await).await;
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+        assert!(log_in(&state, assertion(&state, &mut laptop).await).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_without_relying_party() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        let state = ApiState::new_minimal(cache, db, data_dir);
+        let result = login_options(Extension(state)).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+}
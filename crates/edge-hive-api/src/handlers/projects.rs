@@ -0,0 +1,262 @@
+//! Project and API key administration handlers
+//!
+//! Projects are managed on the node only; in a project's own API these
+//! routes answer `404`. API keys belong to the API they are created in, so
+//! `/p/<project>/api/v1/admin/api-keys` manages the keys of that project.
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::Json,
+};
+use edge_hive_db::{ApiKey, CreatedApiKey, NewApiKey, NewProject, Project};
+use serde::Serialize;
+use std::sync::Arc;
+use super::admin::require_admin;
+use super::data::db_error_status;
+use crate::middleware::auth::BearerClaims;
+use crate::projects::ProjectRegistry;
+use crate::state::ApiState;
+
+/// A project just created, with the first admin key of its API
+#[derive(Debug, Serialize)]
+pub struct CreatedProject {
+    #[serde(flatten)]
+    pub project: Project,
+    pub admin_key: CreatedApiKey,
+}
+
+fn registry(state: &ApiState) -> Result<&Arc<ProjectRegistry>, StatusCode> {
+    state.projects.as_ref().ok_or(StatusCode::NOT_FOUND)
+}
+
+/// List every project
+pub async fn list_projects(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<Project>>, StatusCode> {
+    require_admin(&claims)?;
+    registry(&state)?;
+
+    state.db.projects().await.map(Json).map_err(db_error_status)
+}
+
+/// Create a project and start serving it
+///
+/// The response carries the project's token secret and an admin API key for
+/// the project's own API; neither is shown again by the key endpoints.
+pub async fn create_project(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(project): Json<NewProject>,
+) -> Result<(StatusCode, Json<CreatedProject>), StatusCode> {
+    require_admin(&claims)?;
+    let registry = registry(&state)?;
+
+    let project = state.db.create_project(project).await.map_err(db_error_status)?;
+    let admin_key = async {
+        let project_state = registry.open(project.clone()).await?;
+        let admin_key = NewApiKey {
+            name: "admin".to_string(),
+            role: "admin".to_string(),
+        };
+        project_state.db.create_api_key(admin_key).await
+    }
+    .await;
+
+    match admin_key {
+        Ok(admin_key) => Ok((StatusCode::CREATED, Json(CreatedProject { project, admin_key }))),
+        Err(e) => {
+            tracing::error!("Failed to set up project '{}': {}", project.name, e);
+            registry.close(&project.name);
+            if let Err(e) = state.db.delete_project(&project.name).await {
+                tracing::error!("Failed to remove project '{}': {}", project.name, e);
+            }
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get a project
+pub async fn get_project(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> Result<Json<Project>, StatusCode> {
+    require_admin(&claims)?;
+    registry(&state)?;
+
+    state
+        .db
+        .get_project(&name)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Stop serving a project and drop all of its data
+pub async fn delete_project(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(name): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+    let registry = match registry(&state) {
+        Ok(registry) => registry,
+        Err(status) => return status,
+    };
+
+    registry.close(&name);
+    match state.db.delete_project(&name).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+/// List the API keys, without the keys themselves
+pub async fn list_api_keys(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<ApiKey>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.api_keys().await.map(Json).map_err(db_error_status)
+}
+
+/// Create an API key
+///
+/// The response is the only place the key itself is shown.
+pub async fn create_api_key(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(api_key): Json<NewApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), StatusCode> {
+    require_admin(&claims)?;
+
+    let api_key = state.db.create_api_key(api_key).await.map_err(db_error_status)?;
+    Ok((StatusCode::CREATED, Json(api_key)))
+}
+
+/// Revoke an API key
+pub async fn delete_api_key(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+
+    match state.db.delete_api_key(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::JwtClaims;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::DatabaseService;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        let state = ApiState::new_minimal(cache, db, data_dir);
+        let projects = ProjectRegistry::new(&state);
+        state.with_projects(projects)
+    }
+
+    fn caller(role: &str) -> BearerClaims {
+        let claims =
+            JwtClaims::new("users:root".to_string(), "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    fn new_project(name: &str) -> NewProject {
+        NewProject {
+            name: name.to_string(),
+            hosts: vec![],
+            issuer: None,
+            jwt_secret: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_projects_require_admin() {
+        let state = setup_test_state().await;
+
+        let result =
+            create_project(Extension(state.clone()), caller("user"), Json(new_project("shop")))
+                .await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let result = list_api_keys(Extension(state), caller("user")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_manage_projects() {
+        let state = setup_test_state().await;
+
+        let (status, Json(created)) =
+            create_project(Extension(state.clone()), caller("admin"), Json(new_project("shop")))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created.admin_key.api_key.role, "admin");
+        assert_eq!(state.projects.as_ref().unwrap().names(), vec!["shop"]);
+
+        // The admin key belongs to the project, not to the node
+        assert!(state.db.verify_api_key(&created.admin_key.key).await.unwrap().is_none());
+        let project_db = state.db.open_project(&created.project).await.unwrap();
+        assert!(project_db.verify_api_key(&created.admin_key.key).await.unwrap().is_some());
+
+        let result =
+            create_project(Extension(state.clone()), caller("admin"), Json(new_project("shop")))
+                .await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let Json(projects) =
+            list_projects(Extension(state.clone()), caller("admin")).await.unwrap();
+        assert_eq!(projects, vec![created.project]);
+
+        let status =
+            delete_project(Extension(state.clone()), caller("admin"), Path("shop".to_string()))
+                .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.projects.as_ref().unwrap().names().is_empty());
+        let result = get_project(Extension(state), caller("admin"), Path("shop".to_string())).await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_projects_are_managed_on_the_node_only() {
+        let state = setup_test_state().await;
+        let project = state.db.create_project(new_project("shop")).await.unwrap();
+        let project_state = state.projects.as_ref().unwrap().open(project).await.unwrap();
+
+        let result = list_projects(Extension(project_state.clone()), caller("admin")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let new_key = NewApiKey {
+            name: "ci".to_string(),
+            role: "user".to_string(),
+        };
+        let (_, Json(key)) =
+            create_api_key(Extension(project_state.clone()), caller("admin"), Json(new_key))
+                .await
+                .unwrap();
+        let Json(keys) = list_api_keys(Extension(project_state), caller("admin")).await.unwrap();
+        assert_eq!(keys, vec![key.api_key]);
+    }
+}
@@ -3,19 +3,151 @@ use axum::{
     http::StatusCode,
     Json,
 };
-use edge_hive_wasm::WasmRuntime;
+use edge_hive_cache::CacheService;
+use edge_hive_db::{
+    BoundQuery, DatabaseService, Identity, KnnQuery, KnnRequest, PolicySet, RecordId, Table,
+};
+use edge_hive_wasm::{async_trait, HostContext, LogLevel, WasmRuntime};
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::sync::Arc;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
 
+use super::data::{clear_data_cache, clear_table_cache, identity};
+use crate::middleware::auth::BearerClaims;
 use crate::state::ApiState;
 
+/// Subject of edge function calls made without a token
+const ANONYMOUS: &str = "anonymous";
+
+/// A `db_record` host call: one record operation on behalf of the caller
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum RecordRequest {
+    Select { table: String, id: String },
+    Create { table: String, id: Option<String>, data: Value },
+    Merge { table: String, id: String, data: Value },
+    Delete { table: String, id: String },
+}
+
+fn record_id(table: &str, id: &str) -> Result<RecordId, String> {
+    let table = Table::new(table).map_err(|e| e.to_string())?;
+    RecordId::new(table, id).map_err(|e| e.to_string())
+}
+
+/// Host context giving edge functions access to the node database
+pub(crate) struct DbHostContext {
+    pub(crate) db: Arc<DatabaseService>,
+    /// Policies data API host calls run under
+    pub(crate) policies: Arc<PolicySet>,
+    /// Who the function runs for
+    pub(crate) identity: Identity,
+    /// Response cache of the data API, cleared of tables the function writes
+    pub(crate) cache: Arc<Mutex<CacheService>>,
+}
+
+#[async_trait]
+impl HostContext for DbHostContext {
+    /// Raw queries run without row policies, so only admins may send them
+    ///
+    /// A raw query may write any table, so every cached data query is dropped.
+    async fn query(&self, sql: &str, vars: &Map<String, Value>) -> Result<Value, String> {
+        if self.identity.role != "admin" {
+            return Err("permission denied: raw queries need an admin, use db_record".to_string());
+        }
+
+        let query = BoundQuery::new(sql).bind_all(vars.clone());
+        let rows = self.db.execute(query).await.map_err(|e| e.to_string())?;
+        clear_data_cache(&self.cache).await;
+        Ok(Value::Array(rows))
+    }
+
+    /// Record operations need a signed-in caller and run under row policies
+    async fn record(&self, request: &Value) -> Result<Value, String> {
+        if self.identity.sub == ANONYMOUS {
+            return Err("permission denied: records need a signed-in caller".to_string());
+        }
+        let request: RecordRequest =
+            serde_json::from_value(request.clone()).map_err(|e| e.to_string())?;
+
+        let written = match &request {
+            RecordRequest::Select { .. } => None,
+            RecordRequest::Create { table, .. }
+            | RecordRequest::Merge { table, .. }
+            | RecordRequest::Delete { table, .. } => Some(table.clone()),
+        };
+
+        let db = self.db.scoped(&self.policies, &self.identity);
+        let record = match request {
+            RecordRequest::Select { table, id } => db.select_record(&record_id(&table, &id)?).await,
+            RecordRequest::Create { table, id: None, data } => {
+                let table = Table::new(table).map_err(|e| e.to_string())?;
+                db.create_record(&table, data).await.map(Some)
+            }
+            RecordRequest::Create { table, id: Some(id), data } => {
+                db.create_record_at(&record_id(&table, &id)?, data).await.map(Some)
+            }
+            RecordRequest::Merge { table, id, data } => {
+                db.merge_record(&record_id(&table, &id)?, data).await
+            }
+            RecordRequest::Delete { table, id } => db.delete_record(&record_id(&table, &id)?).await,
+        };
+
+        let record = record.map_err(|e| e.to_string())?;
+
+        // Invalidate after the write so no read can cache the old rows again
+        if let (Some(table), Some(_)) = (&written, &record) {
+            clear_table_cache(&self.cache, table).await;
+        }
+        Ok(record.unwrap_or(Value::Null))
+    }
+
+    /// Vector searches need a signed-in caller and run under row policies
+    async fn knn(&self, request: &Value) -> Result<Value, String> {
+        if self.identity.sub == ANONYMOUS {
+            return Err("permission denied: vector searches need a signed-in caller".to_string());
+        }
+        let table = request
+            .get("table")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "'table' is required".to_string())?;
+        let table = Table::new(table).map_err(|e| e.to_string())?;
+        let request: KnnRequest =
+            serde_json::from_value(request.clone()).map_err(|e| e.to_string())?;
+        let query = KnnQuery::new(table, request).map_err(|e| e.to_string())?;
+
+        let hits = self
+            .db
+            .scoped(&self.policies, &self.identity)
+            .knn(query)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        serde_json::to_value(hits).map_err(|e| e.to_string())
+    }
+
+    fn log(&self, level: LogLevel, msg: &str) {
+        match level {
+            LogLevel::Trace => tracing::trace!(target: "edge_function", "{}", msg),
+            LogLevel::Debug => tracing::debug!(target: "edge_function", "{}", msg),
+            LogLevel::Info => tracing::info!(target: "edge_function", "{}", msg),
+            LogLevel::Warn => tracing::warn!(target: "edge_function", "{}", msg),
+            LogLevel::Error => tracing::error!(target: "edge_function", "{}", msg),
+        }
+    }
+}
+
 /// Execute a WASM edge function
+///
+/// Record operations and vector searches of the function see the rows the
+/// caller may read. Raw queries bypass row policies and are only run for
+/// admins.
 pub async fn execute_wasm_function(
     Path(name): Path<String>,
     State(state): State<Arc<ApiState>>,
+    bearer: Option<BearerClaims>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>, StatusCode> {
     let wasm_path = state.data_dir.join("wasm-functions").join(format!("{}.wasm", name));
@@ -24,7 +156,17 @@ pub async fn execute_wasm_function(
         return Err(StatusCode::NOT_FOUND);
     }
 
-    let runtime = WasmRuntime::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let identity = match bearer {
+        Some(BearerClaims(claims)) => identity(&claims),
+        None => Identity::new(ANONYMOUS, "anon", vec![]),
+    };
+    let host = Arc::new(DbHostContext {
+        db: state.db.clone(),
+        policies: state.policies.clone(),
+        identity,
+        cache: state.cache.clone(),
+    });
+    let runtime = WasmRuntime::new(host).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let result = runtime
         .execute_wasm(&wasm_path, payload)
@@ -246,3 +388,145 @@ pub async fn delete_function(
         "name": function_name,
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_cache::CacheConfig;
+    use serde_json::json;
+
+    async fn cache() -> Arc<Mutex<CacheService>> {
+        Arc::new(Mutex::new(CacheService::new(CacheConfig::default()).await))
+    }
+
+    // `#[tokio::test]` runs on a current-thread runtime, where blocking host
+    // calls used to panic
+    #[tokio::test]
+    async fn test_host_query_on_current_thread_runtime() {
+        let host = DbHostContext {
+            db: Arc::new(DatabaseService::new_in_memory().await.unwrap()),
+            policies: Arc::new(PolicySet::default()),
+            identity: Identity::new("users:root", "admin", vec![]),
+            cache: cache().await,
+        };
+        let vars = json!({"name": "ada"}).as_object().unwrap().clone();
+        host.query("CREATE people CONTENT { name: $name }", &vars).await.unwrap();
+
+        let rows = host.query("SELECT name FROM people", &Map::new()).await.unwrap();
+        assert_eq!(rows, json!([{"name": "ada"}]));
+    }
+
+    #[tokio::test]
+    async fn test_host_query_needs_an_admin() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = cache().await;
+        let host = |sub: &str, role: &str| DbHostContext {
+            db: db.clone(),
+            policies: Arc::new(PolicySet::default()),
+            identity: Identity::new(sub, role, vec![]),
+            cache: cache.clone(),
+        };
+
+        let user = host("users:alice", "user");
+        assert!(user.query("SELECT * FROM people", &Map::new()).await.is_err());
+        assert!(user.query("DELETE people", &Map::new()).await.is_err());
+        assert!(host(ANONYMOUS, "anon").query("SELECT * FROM people", &Map::new()).await.is_err());
+        let admin = host("users:root", "admin");
+        assert!(admin.query("SELECT * FROM users", &Map::new()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_host_records_follow_row_policies() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = cache().await;
+        let host = |sub: &str| DbHostContext {
+            db: db.clone(),
+            policies: Arc::new(PolicySet::default()),
+            identity: Identity::new(sub, "user", vec![]),
+            cache: cache.clone(),
+        };
+        let (alice, bob) = (host("users:alice"), host("users:bob"));
+
+        let created = alice
+            .record(&json!({"op": "create", "table": "notes", "id": "n1", "data": {"text": "mine"}}))
+            .await
+            .unwrap();
+        assert_eq!(created["owner"], "users:alice");
+
+        let select = json!({"op": "select", "table": "notes", "id": "n1"});
+        assert_eq!(alice.record(&select).await.unwrap()["text"], "mine");
+        assert_eq!(bob.record(&select).await.unwrap(), Value::Null);
+        let merge = json!({"op": "merge", "table": "notes", "id": "n1", "data": {"text": "x"}});
+        assert_eq!(bob.record(&merge).await.unwrap(), Value::Null);
+        let delete = json!({"op": "delete", "table": "notes", "id": "n1"});
+        assert_eq!(bob.record(&delete).await.unwrap(), Value::Null);
+        assert_eq!(alice.record(&select).await.unwrap()["text"], "mine");
+
+        let system = json!({"op": "select", "table": "users", "id": "root"});
+        assert!(alice.record(&system).await.is_err());
+        assert!(host(ANONYMOUS).record(&select).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_writes_invalidate_cached_queries() {
+        let cache = cache().await;
+        let host = DbHostContext {
+            db: Arc::new(DatabaseService::new_in_memory().await.unwrap()),
+            policies: Arc::new(PolicySet::default()),
+            identity: Identity::new("users:alice", "user", vec![]),
+            cache: cache.clone(),
+        };
+        let cached = |table: &str| format!("data:{}:query:limit=10", table);
+        for table in ["notes", "other"] {
+            cache.lock().await.set(cached(table), b"[]".to_vec()).await;
+        }
+
+        let select = json!({"op": "select", "table": "notes", "id": "n1"});
+        host.record(&select).await.unwrap();
+        assert!(cache.lock().await.get(&cached("notes")).await.is_some());
+
+        let create = json!({"op": "create", "table": "notes", "id": "n1", "data": {"text": "a"}});
+        host.record(&create).await.unwrap();
+        assert!(cache.lock().await.get(&cached("notes")).await.is_none());
+        assert!(cache.lock().await.get(&cached("other")).await.is_some());
+
+        for op in [
+            json!({"op": "merge", "table": "notes", "id": "n1", "data": {"text": "b"}}),
+            json!({"op": "delete", "table": "notes", "id": "n1"}),
+        ] {
+            cache.lock().await.set(cached("notes"), b"[]".to_vec()).await;
+            host.record(&op).await.unwrap();
+            assert!(cache.lock().await.get(&cached("notes")).await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_knn_sees_only_readable_rows() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let docs = Table::new("docs").unwrap();
+        let field = serde_json::from_value(json!({"field": "embedding", "dimension": 2})).unwrap();
+        db.define_vectors(&docs, &[field]).await.unwrap();
+
+        let policies = Arc::new(PolicySet::default());
+        for (owner, name) in [("users:alice", "mine"), ("users:bob", "hidden")] {
+            let identity = Identity::new(owner, "user", vec![]);
+            db.scoped(&policies, &identity)
+                .create_record(&docs, json!({"name": name, "embedding": [1.0, 0.0]}))
+                .await
+                .unwrap();
+        }
+
+        let cache = cache().await;
+        let host = |sub: &str| DbHostContext {
+            db: db.clone(),
+            policies: policies.clone(),
+            identity: Identity::new(sub, "user", vec![]),
+            cache: cache.clone(),
+        };
+        let search = json!({"table": "docs", "vector": [1.0, 0.0], "k": 5});
+        let hits = host("users:alice").knn(&search).await.unwrap();
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+        assert_eq!(hits[0]["record"]["name"], "mine");
+        assert!(host(ANONYMOUS).knn(&search).await.is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Webhook and change log administration handlers
+//!
+//! Admin only, like the rest of `/api/v1/admin`. Deliveries are sent by the
+//! node's dispatcher; these endpoints manage subscriptions and let an admin
+//! inspect failed deliveries and send dead-lettered ones again.
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::Json,
+};
+use edge_hive_db::{
+    Change, ChangeQuery, Delivery, DeliveryQuery, DeliveryStatus, NewWebhook, Webhook,
+};
+use serde::Deserialize;
+use super::admin::require_admin;
+use super::data::{db_error_status, parse_table};
+use crate::middleware::auth::BearerClaims;
+use crate::state::ApiState;
+
+/// Page size when the request sets none
+const DEFAULT_LIMIT: u64 = 100;
+
+/// List every webhook
+pub async fn list_webhooks(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+) -> Result<Json<Vec<Webhook>>, StatusCode> {
+    require_admin(&claims)?;
+
+    state.db.webhooks().await.map(Json).map_err(db_error_status)
+}
+
+/// Subscribe a URL to changes of a table
+///
+/// The response carries the signing secret, generated unless one was given.
+pub async fn create_webhook(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Json(webhook): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), StatusCode> {
+    require_admin(&claims)?;
+
+    let webhook = state.db.create_webhook(webhook).await.map_err(db_error_status)?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// Get a webhook
+pub async fn get_webhook(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> Result<Json<Webhook>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .webhook(&id)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Remove a webhook and its deliveries
+pub async fn delete_webhook(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if let Err(status) = require_admin(&claims) {
+        return status;
+    }
+
+    match state.db.delete_webhook(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => db_error_status(e),
+    }
+}
+
+/// Query parameters of `GET /api/v1/admin/deliveries`
+#[derive(Debug, Default, Deserialize)]
+pub struct DeliveryParams {
+    pub webhook: Option<String>,
+    pub status: Option<DeliveryStatus>,
+    pub limit: Option<u64>,
+}
+
+/// List deliveries, newest first
+pub async fn list_deliveries(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Query(params): Query<DeliveryParams>,
+) -> Result<Json<Vec<Delivery>>, StatusCode> {
+    require_admin(&claims)?;
+
+    let query = DeliveryQuery {
+        webhook: params.webhook,
+        status: params.status,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT),
+    };
+    state.db.deliveries(&query).await.map(Json).map_err(db_error_status)
+}
+
+/// Send a pending or dead-lettered delivery again on the next dispatch
+pub async fn retry_delivery(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Path(id): Path<String>,
+) -> Result<Json<Delivery>, StatusCode> {
+    require_admin(&claims)?;
+
+    state
+        .db
+        .retry_delivery(&id)
+        .await
+        .map_err(db_error_status)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Query parameters of `GET /api/v1/admin/changes`
+#[derive(Debug, Default, Deserialize)]
+pub struct ChangeParams {
+    pub table: Option<String>,
+    /// ID of the last change already read
+    pub after: Option<String>,
+    pub limit: Option<u64>,
+}
+
+/// Read the change log, oldest first
+pub async fn list_changes(
+    Extension(state): Extension<ApiState>,
+    BearerClaims(claims): BearerClaims,
+    Query(params): Query<ChangeParams>,
+) -> Result<Json<Vec<Change>>, StatusCode> {
+    require_admin(&claims)?;
+
+    let query = ChangeQuery {
+        table: params.table.as_deref().map(parse_table).transpose()?,
+        after: params.after,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT),
+    };
+    state.db.changes(&query).await.map(Json).map_err(db_error_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_auth::JwtClaims;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{ChangeAction, DatabaseService, RetryPolicy, Table};
+    use serde_json::json;
+    use std::{path::PathBuf, sync::Arc};
+    use tempfile::tempdir;
+
+    async fn setup_test_state() -> ApiState {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let data_dir = PathBuf::from(tempdir().unwrap().path());
+        ApiState::new_minimal(cache, db, data_dir)
+    }
+
+    fn caller(role: &str) -> BearerClaims {
+        let claims =
+            JwtClaims::new("users:root".to_string(), "edge-hive-test".to_string(), vec![], None);
+        BearerClaims(claims.with_role(role))
+    }
+
+    fn new_webhook() -> NewWebhook {
+        serde_json::from_value(json!({
+            "table": "posts",
+            "actions": ["create"],
+            "url": "http://127.0.0.1:9/hook"
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_require_admin() {
+        let state = setup_test_state().await;
+
+        let result = create_webhook(Extension(state.clone()), caller("user"), Json(new_webhook()))
+            .await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let result = list_changes(Extension(state), caller("user"), Query(Default::default()))
+            .await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_manage_webhooks() {
+        let state = setup_test_state().await;
+
+        let (status, Json(webhook)) =
+            create_webhook(Extension(state.clone()), caller("admin"), Json(new_webhook()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(webhook.actions, vec![ChangeAction::Create]);
+
+        let mut invalid = new_webhook();
+        invalid.url = "ftp://example.com".to_string();
+        let result = create_webhook(Extension(state.clone()), caller("admin"), Json(invalid)).await;
+        assert_eq!(result.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let Json(webhooks) =
+            list_webhooks(Extension(state.clone()), caller("admin")).await.unwrap();
+        assert_eq!(webhooks, vec![webhook.clone()]);
+
+        let status =
+            delete_webhook(Extension(state.clone()), caller("admin"), Path(webhook.id.clone()))
+                .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let missing = get_webhook(Extension(state), caller("admin"), Path(webhook.id)).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_and_retry_deliveries() {
+        let state = setup_test_state().await;
+        create_webhook(Extension(state.clone()), caller("admin"), Json(new_webhook()))
+            .await
+            .unwrap();
+        let table = Table::new("posts").unwrap();
+        state.db.create_record(&table, json!({"title": "a"})).await.unwrap();
+
+        let Json(changes) = list_changes(
+            Extension(state.clone()),
+            caller("admin"),
+            Query(ChangeParams {
+                table: Some("posts".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(changes.len(), 1);
+
+        // Dead-letter the only delivery
+        state.db.fan_out_changes(10).await.unwrap();
+        let due = state.db.due_deliveries(10).await.unwrap().remove(0);
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        state.db.record_delivery_failure(&due.delivery, "HTTP 500", &policy).await.unwrap();
+
+        let Json(dead) = list_deliveries(
+            Extension(state.clone()),
+            caller("admin"),
+            Query(DeliveryParams {
+                status: Some(DeliveryStatus::Dead),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(dead.len(), 1);
+
+        let Json(retried) =
+            retry_delivery(Extension(state.clone()), caller("admin"), Path(dead[0].id.clone()))
+                .await
+                .unwrap();
+        assert_eq!(retried.status, DeliveryStatus::Pending);
+
+        let missing =
+            retry_delivery(Extension(state), caller("admin"), Path("nope".to_string())).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,373 @@
+//! Background job worker
+//!
+//! Runs the jobs queued in the database of one API (see
+//! [`edge_hive_db::job`]). A job's handler is a built-in action or an edge
+//! function:
+//!
+//! - `query`: runs `payload.sql` with the variables of `payload.vars`,
+//!   returning the rows
+//! - `function:<name>`: runs the edge function `<name>` with the payload as
+//!   its input, returning its output
+//!
+//! Raw SQL can write any table, so the data API's cached queries are dropped
+//! after each `query` job. Embedders register further handlers with
+//! [`JobWorker::with_handler`]. A
+//! handler still running when its lease expires is cancelled and the attempt
+//! counts as failed, so a job never runs twice at once.
+
+use edge_hive_cache::CacheService;
+use edge_hive_db::{
+    BoundQuery, DatabaseService, DbError, Identity, Job, JobStatus, PolicySet, RetryPolicy,
+};
+use edge_hive_wasm::WasmRuntime;
+use futures_util::future::{join_all, BoxFuture};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::handlers::data::clear_data_cache;
+use crate::handlers::wasm::DbHostContext;
+use crate::state::ApiState;
+
+/// Handler prefix of jobs run by an edge function
+pub const FUNCTION_PREFIX: &str = "function:";
+
+/// Subject edge functions of jobs run as; only admins enqueue jobs
+const JOB_SUBJECT: &str = "_jobs";
+
+/// What a handler gets to run a job with
+#[derive(Clone)]
+pub struct JobContext {
+    pub db: Arc<DatabaseService>,
+    /// Policies of the API, applied to data access of edge functions
+    pub policies: Arc<PolicySet>,
+    /// Response cache of the API, cleared of data the job may have changed
+    pub cache: Arc<Mutex<CacheService>>,
+    /// Data directory of the API (edge functions live in `wasm-functions/`)
+    pub data_dir: PathBuf,
+}
+
+/// Outcome of a job: its result, or why the attempt failed
+pub type JobFuture = BoxFuture<'static, Result<Value, String>>;
+
+/// Code running the jobs of one handler name
+pub type JobHandler = Arc<dyn Fn(JobContext, Job) -> JobFuture + Send + Sync>;
+
+/// How a worker runs jobs
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Backoff of failed attempts, and attempts of jobs without their own limit
+    pub retry: RetryPolicy,
+    /// How long a job may run before another worker may claim it
+    pub lease: Duration,
+    /// How often due jobs are looked for
+    pub poll_interval: Duration,
+    /// Jobs claimed (and run concurrently) per poll
+    pub batch_size: u64,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_secs(10),
+                max_delay: Duration::from_secs(3600),
+            },
+            lease: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(1),
+            batch_size: 10,
+        }
+    }
+}
+
+/// Background task running the jobs of one API
+pub struct JobWorker {
+    id: String,
+    context: JobContext,
+    handlers: HashMap<String, JobHandler>,
+    options: JobOptions,
+}
+
+impl JobWorker {
+    /// Worker for the jobs of an API, with the built-in handlers
+    pub fn new(state: &ApiState, options: JobOptions) -> Self {
+        let query: JobHandler = Arc::new(|context: JobContext, job: Job| -> JobFuture {
+            Box::pin(run_query(context, job))
+        });
+
+        Self {
+            id: format!("worker-{:016x}", rand::random::<u64>()),
+            context: JobContext {
+                db: state.db.clone(),
+                policies: state.policies.clone(),
+                cache: state.cache.clone(),
+                data_dir: state.data_dir.clone(),
+            },
+            handlers: HashMap::from([("query".to_string(), query)]),
+            options,
+        }
+    }
+
+    /// Run the jobs of `name` with `handler`, replacing any handler of that name
+    pub fn with_handler(mut self, name: impl Into<String>, handler: JobHandler) -> Self {
+        self.handlers.insert(name.into(), handler);
+        self
+    }
+
+    /// Name the worker holds its leases under
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Enqueue the jobs of fired schedules, then run every due job once
+    ///
+    /// Returns the number of jobs run.
+    pub async fn tick(&self) -> Result<usize, DbError> {
+        self.context.db.enqueue_scheduled_jobs().await?;
+
+        let jobs = self
+            .context
+            .db
+            .claim_jobs(
+                &self.id,
+                self.options.lease,
+                self.options.retry.max_attempts,
+                self.options.batch_size,
+            )
+            .await?;
+        let count = jobs.len();
+        for outcome in join_all(jobs.into_iter().map(|job| self.execute(job))).await {
+            outcome?;
+        }
+        Ok(count)
+    }
+
+    async fn execute(&self, job: Job) -> Result<(), DbError> {
+        let db = &self.context.db;
+        let Some(handler) = self.handler(&job.handler) else {
+            let error = format!("unknown job handler '{}'", job.handler);
+            db.fail_job(&job, &self.id, &error, None).await?;
+            return Ok(());
+        };
+
+        let run = handler(self.context.clone(), job.clone());
+        let outcome = match tokio::time::timeout(self.options.lease, run).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err(format!("timed out after {:?}", self.options.lease)),
+        };
+
+        let status = match outcome {
+            Ok(result) => db
+                .complete_job(&job.id, &self.id, result)
+                .await?
+                .then_some(JobStatus::Succeeded),
+            Err(error) => db.fail_job(&job, &self.id, &error, Some(&self.options.retry)).await?,
+        };
+        match status {
+            Some(status) => debug!("Job {} ({}) now {}", job.id, job.handler, status.as_str()),
+            None => warn!("Job {} ({}) finished after its lease expired", job.id, job.handler),
+        }
+        Ok(())
+    }
+
+    fn handler(&self, name: &str) -> Option<JobHandler> {
+        if let Some(handler) = self.handlers.get(name) {
+            return Some(handler.clone());
+        }
+        let function = name.strip_prefix(FUNCTION_PREFIX)?.to_string();
+        Some(Arc::new(move |context: JobContext, job: Job| -> JobFuture {
+            Box::pin(run_function(context, function.clone(), job))
+        }))
+    }
+
+    /// Poll until the task is dropped
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.options.poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.tick().await {
+                warn!("Job worker failed: {}", e);
+            }
+        }
+    }
+
+    /// Run in the background until the returned handle is dropped
+    pub fn spawn(self) -> JobWorkerHandle {
+        JobWorkerHandle(tokio::spawn(self.run()))
+    }
+}
+
+/// A running worker, stopped when dropped
+pub struct JobWorkerHandle(JoinHandle<()>);
+
+impl JobWorkerHandle {
+    /// Stop the worker and wait until it has let go of the database
+    pub async fn stop(mut self) {
+        self.0.abort();
+        let _ = (&mut self.0).await;
+    }
+}
+
+impl Drop for JobWorkerHandle {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Built-in `query` handler
+async fn run_query(context: JobContext, job: Job) -> Result<Value, String> {
+    let sql = job
+        .payload
+        .get("sql")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "'sql' is required".to_string())?;
+    let vars = match job.payload.get("vars") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(vars)) => vars.clone(),
+        Some(_) => return Err("'vars' must be an object".to_string()),
+    };
+
+    let rows = context
+        .db
+        .execute(BoundQuery::new(sql).bind_all(vars))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    clear_data_cache(&context.cache).await;
+    Ok(Value::Array(rows))
+}
+
+/// Handler of `function:<name>` jobs
+async fn run_function(context: JobContext, name: String, job: Job) -> Result<Value, String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("invalid edge function name '{}'", name));
+    }
+    let path = context.data_dir.join("wasm-functions").join(format!("{}.wasm", name));
+    if !path.exists() {
+        return Err(format!("edge function '{}' is not deployed", name));
+    }
+
+    let host = Arc::new(DbHostContext {
+        db: context.db,
+        policies: context.policies,
+        identity: Identity::new(JOB_SUBJECT, "admin", vec![]),
+        cache: context.cache,
+    });
+    let runtime = WasmRuntime::new(host).map_err(|e| e.to_string())?;
+    runtime.execute_wasm(&path, job.payload).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_cache::{CacheConfig, CacheService};
+    use edge_hive_db::{JobQuery, NewJob};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    async fn worker() -> JobWorker {
+        let db = std::sync::Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let state = ApiState::new_minimal(cache, db, tempdir().unwrap().path().into());
+        let options = JobOptions {
+            retry: RetryPolicy {
+                max_attempts: 2,
+                base_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+            },
+            ..Default::default()
+        };
+
+        let echo: JobHandler = Arc::new(|_, job: Job| -> JobFuture {
+            Box::pin(async move { Ok(job.payload) })
+        });
+        let fail: JobHandler =
+            Arc::new(|_, _| -> JobFuture { Box::pin(async { Err("boom".to_string()) }) });
+        JobWorker::new(&state, options).with_handler("echo", echo).with_handler("fail", fail)
+    }
+
+    async fn enqueue(worker: &JobWorker, handler: &str, payload: Value) -> Job {
+        let job = NewJob {
+            handler: handler.to_string(),
+            payload,
+            ..Default::default()
+        };
+        worker.context.db.enqueue_job(job).await.unwrap()
+    }
+
+    async fn status(worker: &JobWorker, job: &Job) -> Job {
+        worker.context.db.job(&job.id).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_runs_builtin_and_registered_handlers() {
+        let worker = worker().await;
+        let echo = enqueue(&worker, "echo", json!({"hello": "world"})).await;
+        let payload = json!({"sql": "RETURN $n * 2", "vars": {"n": 21}});
+        let query = enqueue(&worker, "query", payload).await;
+
+        assert_eq!(worker.tick().await.unwrap(), 2);
+        assert_eq!(worker.tick().await.unwrap(), 0);
+
+        let echo = status(&worker, &echo).await;
+        assert_eq!(echo.status, JobStatus::Succeeded);
+        assert_eq!(echo.result, Some(json!({"hello": "world"})));
+        let query = status(&worker, &query).await;
+        assert_eq!(query.result, Some(json!([42])));
+    }
+
+    #[tokio::test]
+    async fn test_query_jobs_clear_cached_data() {
+        let worker = worker().await;
+        let key = "data:people:query:limit=10".to_string();
+        worker.context.cache.lock().await.set(key.clone(), b"[]".to_vec()).await;
+
+        enqueue(&worker, "query", json!({"sql": "CREATE people SET name = 'ada'"})).await;
+        assert_eq!(worker.tick().await.unwrap(), 1);
+        assert!(worker.context.cache.lock().await.get(&key).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failing_jobs_are_dead_lettered() {
+        let worker = worker().await;
+        let fail = enqueue(&worker, "fail", Value::Null).await;
+        let unknown = enqueue(&worker, "nope", Value::Null).await;
+        let missing = enqueue(&worker, "function:missing", Value::Null).await;
+
+        assert_eq!(worker.tick().await.unwrap(), 3);
+        // Unknown handlers are not retried
+        assert_eq!(status(&worker, &unknown).await.status, JobStatus::Dead);
+        assert_eq!(status(&worker, &fail).await.status, JobStatus::Queued);
+        assert_eq!(worker.tick().await.unwrap(), 2);
+        assert_eq!(worker.tick().await.unwrap(), 0);
+
+        let fail = status(&worker, &fail).await;
+        assert_eq!(fail.status, JobStatus::Dead);
+        assert_eq!(fail.attempts, 2);
+        assert_eq!(fail.last_error.as_deref(), Some("boom"));
+        let missing = status(&worker, &missing).await;
+        let error = missing.last_error.unwrap();
+        assert_eq!(error, "edge function 'missing' is not deployed");
+
+        let dead = worker
+            .context
+            .db
+            .jobs(&JobQuery {
+                status: Some(JobStatus::Dead),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(dead.len(), 3);
+    }
+}
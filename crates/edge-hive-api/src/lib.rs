@@ -16,13 +16,20 @@
 //!
 //! - `/api/v1/health` - Health check
 //! - `/api/v1/data/*` - Database operations
+//! - `/api/v1/graphql` - GraphQL over the data tables (WebSocket for subscriptions)
 //! - `/api/v1/auth/*` - Authentication
+//! - `/api/v1/admin/*` - Node administration (admin role)
 //! - `/api/v1/edge/*` - Edge functions (WASM)
 //! - `/api/v1/realtime` - WebSocket upgrade
 //! - `/api/v1/mcp` - MCP JSON-RPC
 //! - `/api/v1/mcp/auth/token` - MCP OAuth2 token endpoint
+//!
+//! Hosted projects serve the same routes under `/p/<project>` or on their
+//! own host names (see [`projects`]). Branches of a database are served
+//! under `/b/<branch>` (see [`branches`]).
 
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post, put, delete},
     Router,
 };
@@ -36,8 +43,11 @@ use edge_hive_auth::AuthLayer;
 
 pub mod handlers;
 pub mod middleware;
+pub mod projects;
+pub mod branches;
+pub mod jobs;
+pub mod mail;
 pub mod state;
-pub mod handlers;
 
 pub use state::ApiState;
 
@@ -54,17 +64,94 @@ pub fn create_router(state: ApiState) -> Router {
 
     // Database routes (auto-cached)
     let data_routes = Router::new()
+        .route("/api/v1/data/_batch", post(handlers::data::batch))
+        .route("/api/v1/data/_schema", get(handlers::data::describe_tables))
         .route("/api/v1/data/:table", get(handlers::data::query_records))
         .route("/api/v1/data/:table", post(handlers::data::insert_record))
+        .route("/api/v1/data/:table/_search", get(handlers::data::search_records))
+        .route("/api/v1/data/:table/_knn", post(handlers::data::knn_records))
         .route("/api/v1/data/:table/:id", put(handlers::data::update_record))
-        .route("/api/v1/data/:table/:id", delete(handlers::data::delete_record));
+        .route("/api/v1/data/:table/:id", delete(handlers::data::delete_record))
+        .route("/api/v1/data/:table/:id/history", get(handlers::data::record_history))
+        .route("/api/v1/data/:table/:id/restore", post(handlers::data::restore_record));
+
+    // Table exports and imports (files can be much larger than the default body limit)
+    let transfer_routes = Router::new()
+        .route("/api/v1/data/:table/_export", get(handlers::data::export_records))
+        .route("/api/v1/data/:table/_import", post(handlers::data::import_records))
+        .layer(DefaultBodyLimit::max(handlers::data::MAX_IMPORT_BYTES));
+
+    // GraphQL over the same tables and policies (GET upgrades to a WebSocket)
+    let graphql_routes = Router::new().route(
+        "/api/v1/graphql",
+        post(handlers::graphql::graphql).get(handlers::graphql::graphql_ws),
+    );
+
+    // Admin routes (dumps can be much larger than the default body limit)
+    let admin_routes = Router::new()
+        .route("/api/v1/admin/schemas", get(handlers::admin::list_schemas))
+        .route("/api/v1/admin/schemas/:table", get(handlers::admin::get_schema))
+        .route("/api/v1/admin/schemas/:table", put(handlers::admin::put_schema))
+        .route("/api/v1/admin/schemas/:table", delete(handlers::admin::delete_schema))
+        .route("/api/v1/admin/webhooks", get(handlers::webhooks::list_webhooks))
+        .route("/api/v1/admin/webhooks", post(handlers::webhooks::create_webhook))
+        .route("/api/v1/admin/webhooks/:id", get(handlers::webhooks::get_webhook))
+        .route("/api/v1/admin/webhooks/:id", delete(handlers::webhooks::delete_webhook))
+        .route("/api/v1/admin/deliveries", get(handlers::webhooks::list_deliveries))
+        .route("/api/v1/admin/deliveries/:id/retry", post(handlers::webhooks::retry_delivery))
+        .route("/api/v1/admin/changes", get(handlers::webhooks::list_changes))
+        .route("/api/v1/admin/db/dump", get(handlers::admin::dump_database))
+        .route("/api/v1/admin/db/restore", post(handlers::admin::restore_database))
+        .route("/api/v1/admin/keys/rotate", post(handlers::admin::rotate_key))
+        .route("/api/v1/admin/mail/test", post(handlers::admin::send_test_email))
+        .route("/api/v1/admin/mfa/policy", get(handlers::mfa::get_mfa_policy))
+        .route("/api/v1/admin/mfa/policy", put(handlers::mfa::set_mfa_policy))
+        .route("/api/v1/admin/projects", get(handlers::projects::list_projects))
+        .route("/api/v1/admin/projects", post(handlers::projects::create_project))
+        .route("/api/v1/admin/projects/:name", get(handlers::projects::get_project))
+        .route("/api/v1/admin/projects/:name", delete(handlers::projects::delete_project))
+        .route("/api/v1/admin/api-keys", get(handlers::projects::list_api_keys))
+        .route("/api/v1/admin/api-keys", post(handlers::projects::create_api_key))
+        .route("/api/v1/admin/api-keys/:id", delete(handlers::projects::delete_api_key))
+        .route("/api/v1/admin/branches", get(handlers::branches::list_branches))
+        .route("/api/v1/admin/branches", post(handlers::branches::create_branch))
+        .route("/api/v1/admin/branches/:name", get(handlers::branches::get_branch))
+        .route("/api/v1/admin/branches/:name", delete(handlers::branches::delete_branch))
+        .route("/api/v1/admin/branches/:name/diff", get(handlers::branches::diff_branch))
+        .route("/api/v1/admin/branches/:name/merge", post(handlers::branches::merge_branch))
+        .route("/api/v1/admin/jobs", get(handlers::jobs::list_jobs))
+        .route("/api/v1/admin/jobs", post(handlers::jobs::enqueue_job))
+        .route("/api/v1/admin/jobs/:id", get(handlers::jobs::get_job))
+        .route("/api/v1/admin/jobs/:id", delete(handlers::jobs::delete_job))
+        .route("/api/v1/admin/jobs/:id/retry", post(handlers::jobs::retry_job))
+        .route("/api/v1/admin/schedules", get(handlers::jobs::list_schedules))
+        .route("/api/v1/admin/schedules", post(handlers::jobs::create_schedule))
+        .route("/api/v1/admin/schedules/:name", delete(handlers::jobs::delete_schedule))
+        .layer(DefaultBodyLimit::max(handlers::admin::MAX_DUMP_BYTES));
 
     // Auth routes
     let auth_routes = Router::new()
         .route("/api/v1/auth/register", post(handlers::auth::register))
         .route("/api/v1/auth/login", post(handlers::auth::login))
         .route("/api/v1/auth/refresh", post(handlers::auth::refresh_token))
-        .route("/api/v1/auth/logout", get(handlers::auth::logout));
+        .route("/api/v1/auth/logout", post(handlers::auth::logout))
+        .route("/api/v1/auth/verify-email", get(handlers::auth::verify_email))
+        .route("/api/v1/auth/forgot-password", post(handlers::auth::forgot_password))
+        .route("/api/v1/auth/reset-password", post(handlers::auth::reset_password))
+        .route("/api/v1/auth/oauth", get(handlers::auth::oauth_providers))
+        .route("/api/v1/auth/oauth/:provider", get(handlers::auth::oauth_authorize))
+        .route("/api/v1/auth/oauth/:provider/callback", get(handlers::auth::oauth_callback))
+        .route("/api/v1/auth/mfa", get(handlers::mfa::mfa_status))
+        .route("/api/v1/auth/mfa/verify", post(handlers::mfa::verify_mfa))
+        .route("/api/v1/auth/mfa/totp", post(handlers::mfa::enroll_totp))
+        .route("/api/v1/auth/mfa/totp/confirm", post(handlers::mfa::confirm_totp))
+        .route("/api/v1/auth/mfa/disable", post(handlers::mfa::disable_mfa))
+        .route("/api/v1/auth/passkeys", get(handlers::passkey::list_passkeys))
+        .route("/api/v1/auth/passkeys/:id", delete(handlers::passkey::delete_passkey))
+        .route("/api/v1/auth/passkey/register/options", post(handlers::passkey::register_options))
+        .route("/api/v1/auth/passkey/register", post(handlers::passkey::register_passkey))
+        .route("/api/v1/auth/passkey/login/options", post(handlers::passkey::login_options))
+        .route("/api/v1/auth/passkey/login", post(handlers::passkey::passkey_login));
 
     // Edge functions routes (placeholder for future WASM integration)
     let wasm_routes = Router::new()
@@ -90,10 +177,16 @@ pub fn create_router(state: ApiState) -> Router {
         .route("/api/v1/mcp/auth/token", post(handlers::mcp_auth::issue_mcp_token));
 
 
+    let registry = state.projects.clone();
+    let branch_registry = state.branches.clone();
+
     // Combine all routes
-    Router::new()
+    let router = Router::new()
         .merge(health_routes)
         .merge(data_routes)
+        .merge(transfer_routes)
+        .merge(graphql_routes)
+        .merge(admin_routes)
         .merge(auth_routes)
         .merge(wasm_routes)
         .merge(realtime_routes)
@@ -105,7 +198,24 @@ pub fn create_router(state: ApiState) -> Router {
                 .allow_headers(Any),
         )
         .layer(TraceLayer::new_for_http())
-        .with_state(state)
+        .layer(axum::Extension(state.clone()))
+        .with_state(std::sync::Arc::new(state));
+
+    // Branch and project requests never reach the base's routes
+    let router = match branch_registry {
+        Some(branch_registry) => router.layer(axum::middleware::from_fn_with_state(
+            branch_registry,
+            branches::route_branches,
+        )),
+        None => router,
+    };
+    match registry {
+        Some(registry) => router.layer(axum::middleware::from_fn_with_state(
+            registry,
+            projects::route_projects,
+        )),
+        None => router,
+    }
 }
 
 #[cfg(test)]
@@ -118,8 +228,7 @@ mod tests {
     async fn test_router_creation() {
         let cache = edge_hive_cache::CacheService::new(CacheConfig::default()).await;
         let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let db = std::sync::Arc::new(edge_hive_db::DatabaseService::new(&db_path).await.unwrap());
+        let db = std::sync::Arc::new(edge_hive_db::DatabaseService::new_in_memory().await.unwrap());
         let state = ApiState::new_minimal(cache, db, dir.path().to_path_buf());
         let router = create_router(state);
 
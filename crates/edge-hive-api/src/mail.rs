@@ -0,0 +1,391 @@
+//! Outgoing email
+//!
+//! The auth flows send templated plain-text messages through an SMTP relay.
+//! A template's first line is the subject and the rest its body; `{{name}}`
+//! placeholders are replaced when the message is rendered. The built-in
+//! templates can be replaced by `<template>.txt` files (see
+//! [`Mailer::with_templates`]).
+
+use lettre::message::header::{ContentTransferEncoding, ContentType};
+use lettre::message::{Body, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// Errors of sending email
+#[derive(Debug, Error)]
+pub enum MailError {
+    #[error("Invalid address '{0}'")]
+    Address(String),
+
+    #[error("Invalid message: {0}")]
+    Message(String),
+
+    #[error("Template error: {0}")]
+    Template(String),
+
+    #[error("SMTP error: {0}")]
+    Smtp(String),
+}
+
+/// How the connection to the relay is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, for local relays and test sinks only
+    None,
+    /// Upgrade with STARTTLS (usually port 587)
+    StartTls,
+    /// TLS from the start (usually port 465)
+    #[default]
+    Tls,
+}
+
+/// Relay the messages are sent through
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, e.g. `Edge Hive <noreply@example.com>`
+    pub from: String,
+}
+
+/// Messages the node sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MailTemplate {
+    /// Placeholders: `name`, `email`, `link`, `expires_in`
+    VerifyEmail,
+    /// Placeholders: `name`, `email`, `link`, `expires_in`
+    ResetPassword,
+    /// Sign-up with a registered address. Placeholders: `name`, `email`,
+    /// `link` (to reset the password), `expires_in`
+    AccountExists,
+    /// Placeholders: `host`
+    Test,
+}
+
+impl MailTemplate {
+    pub const ALL: [MailTemplate; 4] = [
+        MailTemplate::VerifyEmail,
+        MailTemplate::ResetPassword,
+        MailTemplate::AccountExists,
+        MailTemplate::Test,
+    ];
+
+    /// File name (without `.txt`) of the template
+    pub fn name(&self) -> &'static str {
+        match self {
+            MailTemplate::VerifyEmail => "verify_email",
+            MailTemplate::ResetPassword => "reset_password",
+            MailTemplate::AccountExists => "account_exists",
+            MailTemplate::Test => "test",
+        }
+    }
+
+    fn builtin(&self) -> &'static str {
+        match self {
+            MailTemplate::VerifyEmail => {
+                "Confirm your email address\n\
+                 Hi {{name}},\n\n\
+                 Please confirm that {{email}} is your address by opening this link:\n\n\
+                 {{link}}\n\n\
+                 The link expires in {{expires_in}}. If you did not sign up, ignore this \
+                 message.\n"
+            }
+            MailTemplate::ResetPassword => {
+                "Reset your password\n\
+                 Hi {{name}},\n\n\
+                 Someone asked to reset the password of {{email}}. Choose a new one here:\n\n\
+                 {{link}}\n\n\
+                 The link works once and expires in {{expires_in}}. If it was not you, ignore \
+                 this message and your password stays the same.\n"
+            }
+            MailTemplate::AccountExists => {
+                "You already have an account\n\
+                 Hi {{name}},\n\n\
+                 Someone tried to sign up with {{email}}, which already has an account. If it \
+                 was you, sign in instead, or choose a new password here:\n\n\
+                 {{link}}\n\n\
+                 The link works once and expires in {{expires_in}}. If it was not you, ignore \
+                 this message.\n"
+            }
+            MailTemplate::Test => {
+                "Edge Hive test message\n\
+                 This message was sent through {{host}}, so Edge Hive can send email.\n"
+            }
+        }
+    }
+}
+
+/// Where the links of auth messages point; the token is appended as `?token=`
+#[derive(Debug, Clone)]
+pub struct MailLinks {
+    pub verify_email: String,
+    pub reset_password: String,
+}
+
+impl Default for MailLinks {
+    fn default() -> Self {
+        Self {
+            verify_email: "http://localhost:8080/api/v1/auth/verify-email".to_string(),
+            reset_password: "http://localhost:8080/reset-password".to_string(),
+        }
+    }
+}
+
+/// Sends templated messages through one relay
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    host: String,
+    links: MailLinks,
+    templates: HashMap<MailTemplate, String>,
+}
+
+impl Mailer {
+    /// Mailer with the built-in templates
+    pub fn new(settings: &SmtpSettings) -> Result<Self, MailError> {
+        let smtp_error = |e: lettre::transport::smtp::Error| MailError::Smtp(e.to_string());
+        let builder = match settings.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)
+                    .map_err(smtp_error)?
+            }
+            SmtpSecurity::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host).map_err(smtp_error)?
+            }
+        };
+        let mut builder = builder.port(settings.port);
+        if let Some(username) = &settings.username {
+            let password = settings.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: parse_mailbox(&settings.from)?,
+            host: format!("{}:{}", settings.host, settings.port),
+            links: MailLinks::default(),
+            templates: MailTemplate::ALL
+                .iter()
+                .map(|template| (*template, template.builtin().to_string()))
+                .collect(),
+        })
+    }
+
+    /// Point the links of auth messages to `links`
+    pub fn with_links(mut self, links: MailLinks) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Replace built-in templates by the `<template>.txt` files of `dir`
+    ///
+    /// Templates without a file keep their built-in text.
+    pub fn with_templates(mut self, dir: &Path) -> Result<Self, MailError> {
+        for template in MailTemplate::ALL {
+            let path = dir.join(format!("{}.txt", template.name()));
+            if !path.exists() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| MailError::Template(format!("{}: {}", path.display(), e)))?;
+            if !text.contains('\n') {
+                let message = format!("{}: expected a subject line and a body", path.display());
+                return Err(MailError::Template(message));
+            }
+            self.templates.insert(template, text);
+        }
+        Ok(self)
+    }
+
+    /// Link of an auth message carrying `token`
+    pub fn link(&self, template: MailTemplate, token: &str) -> String {
+        let base = match template {
+            MailTemplate::ResetPassword | MailTemplate::AccountExists => {
+                &self.links.reset_password
+            }
+            _ => &self.links.verify_email,
+        };
+        let separator = if base.contains('?') { '&' } else { '?' };
+        format!("{}{}token={}", base, separator, token)
+    }
+
+    /// Subject and body of a template with its placeholders replaced
+    pub fn render(&self, template: MailTemplate, vars: &[(&str, &str)]) -> (String, String) {
+        let mut text = self.templates[&template].clone();
+        for (name, value) in vars {
+            text = text.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        match text.split_once('\n') {
+            Some((subject, body)) => (subject.trim().to_string(), body.to_string()),
+            None => (text.trim().to_string(), String::new()),
+        }
+    }
+
+    /// Render a template and send it to `to`
+    pub async fn send(
+        &self,
+        to: &str,
+        template: MailTemplate,
+        vars: &[(&str, &str)],
+    ) -> Result<(), MailError> {
+        let (subject, body) = self.render(template, vars);
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(parse_mailbox(to)?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(encode_body(body))
+            .map_err(|e| MailError::Message(e.to_string()))?;
+
+        self.transport.send(message).await.map_err(|e| MailError::Smtp(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Send the test message to `to`
+    pub async fn send_test(&self, to: &str) -> Result<(), MailError> {
+        self.send(to, MailTemplate::Test, &[("host", &self.host)]).await
+    }
+}
+
+/// Longest line SMTP carries without encoding (RFC 5321)
+const MAX_LINE: usize = 998;
+
+/// Body sent as 8bit, so links stay on one line
+///
+/// lettre only picks 8bit for lines shorter than 76 characters and would
+/// wrap longer ones with quoted-printable; bodies with lines SMTP cannot carry
+/// are still left to it.
+fn encode_body(body: String) -> Body {
+    if body.lines().any(|line| line.len() > MAX_LINE) {
+        return Body::new(body);
+    }
+    let crlf: String = body.lines().map(|line| format!("{}\r\n", line)).collect();
+    Body::dangerous_pre_encoded(crlf.into_bytes(), ContentTransferEncoding::EightBit)
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, MailError> {
+    address.parse().map_err(|_| MailError::Address(address.to_string()))
+}
+
+/// SMTP server keeping every message it receives, for tests
+#[cfg(test)]
+pub(crate) mod sink {
+    use super::{Mailer, SmtpSecurity, SmtpSettings};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Raw messages received so far (headers and body)
+    pub(crate) type Inbox = Arc<Mutex<Vec<String>>>;
+
+    /// Start a sink on a free local port, with a mailer sending to it
+    pub(crate) async fn start() -> (Mailer, Inbox) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let inbox = Inbox::default();
+
+        let received = inbox.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(session(stream, received.clone()));
+            }
+        });
+
+        let settings = SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "Edge Hive <noreply@example.com>".to_string(),
+        };
+        (Mailer::new(&settings).unwrap(), inbox)
+    }
+
+    async fn session(stream: tokio::net::TcpStream, inbox: Inbox) {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let _ = write.write_all(b"220 sink ESMTP\r\n").await;
+
+        let mut data: Option<Vec<String>> = None;
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(message) = data.as_mut() {
+                if line == "." {
+                    inbox.lock().unwrap().push(message.join("\n"));
+                    data = None;
+                    let _ = write.write_all(b"250 OK\r\n").await;
+                } else {
+                    message.push(line.strip_prefix('.').unwrap_or(&line).to_string());
+                }
+                continue;
+            }
+
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("DATA") {
+                data = Some(Vec::new());
+                b"354 Go ahead\r\n"
+            } else if command.starts_with("QUIT") {
+                let _ = write.write_all(b"221 Bye\r\n").await;
+                return;
+            } else {
+                b"250 OK\r\n"
+            };
+            let _ = write.write_all(reply).await;
+        }
+    }
+
+    /// Value of the `token` query parameter of the first link of a message
+    pub(crate) fn token(message: &str) -> String {
+        let start = message.find("token=").expect("message has no token") + "token=".len();
+        message[start..].split_whitespace().next().unwrap().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sends_rendered_template() {
+        let (mailer, inbox) = sink::start().await;
+        let link = mailer.link(MailTemplate::VerifyEmail, "abc.def");
+        assert_eq!(link, "http://localhost:8080/api/v1/auth/verify-email?token=abc.def");
+
+        let vars = [("name", "Ada"), ("email", "ada@example.com"), ("link", link.as_str())];
+        mailer.send("ada@example.com", MailTemplate::VerifyEmail, &vars).await.unwrap();
+
+        let inbox = inbox.lock().unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert!(inbox[0].contains("Subject: Confirm your email address"));
+        assert!(inbox[0].contains("Hi Ada,"));
+        assert_eq!(sink::token(&inbox[0]), "abc.def");
+    }
+
+    #[tokio::test]
+    async fn test_template_overrides() {
+        let (mailer, _) = sink::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("reset_password.txt"), "Reset\nGo to {{link}}\n").unwrap();
+        let mailer = mailer.with_templates(dir.path()).unwrap();
+
+        let (subject, body) = mailer.render(MailTemplate::ResetPassword, &[("link", "here")]);
+        assert_eq!(subject, "Reset");
+        assert_eq!(body, "Go to here\n");
+        let (subject, _) = mailer.render(MailTemplate::VerifyEmail, &[]);
+        assert_eq!(subject, "Confirm your email address");
+
+        std::fs::write(dir.path().join("test.txt"), "no body").unwrap();
+        assert!(matches!(mailer.with_templates(dir.path()), Err(MailError::Template(_))));
+    }
+}
@@ -6,7 +6,8 @@ use axum::{
     middleware::Next,
     response::Response,
 };
-use edge_hive_db::StoredUser;
+use edge_hive_auth::JwtClaims;
+use edge_hive_db::{ApiKey, StoredUser};
 
 use crate::state::ApiState;
 
@@ -57,3 +58,60 @@ where
             .ok_or(StatusCode::UNAUTHORIZED)
     }
 }
+
+/// Header carrying an API key instead of a bearer token
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Validated JWT claims of the caller
+///
+/// Reuses the claims stored by [`auth_middleware`] when it ran, otherwise
+/// validates the bearer token against the [`ApiState`] extension. Without a
+/// bearer token, an API key stands for claims with the key's role.
+#[derive(Clone)]
+pub struct BearerClaims(pub JwtClaims);
+
+fn api_key_claims(api_key: &ApiKey, issuer: &str) -> JwtClaims {
+    JwtClaims::new(format!("_api_keys:{}", api_key.id), issuer.to_string(), vec![], None)
+        .with_role(api_key.role.as_str())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BearerClaims
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(claims) = parts.extensions.get::<JwtClaims>() {
+            return Ok(BearerClaims(claims.clone()));
+        }
+
+        let state = parts
+            .extensions
+            .get::<ApiState>()
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+            let key = parts
+                .headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            let api_key = state
+                .db
+                .verify_api_key(key)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            return Ok(BearerClaims(api_key_claims(&api_key, state.token_validator.issuer())));
+        };
+        let auth_header = auth_header.to_str().map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        state
+            .token_validator
+            .validate_bearer_token(auth_header)
+            .map(BearerClaims)
+            .map_err(|_| StatusCode::UNAUTHORIZED)
+    }
+}
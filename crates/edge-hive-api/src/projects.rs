@@ -0,0 +1,307 @@
+//! Project routing
+//!
+//! Every project is served by a router of its own, built from an
+//! [`ApiState`] with the project's database, token keys, cache and data
+//! directory (edge functions included), and runs its jobs with a worker of
+//! its own. Policies, mail, login providers and passkeys are the node's.
+//! Webhook deliveries and retention run per project too, started by the
+//! node's [`ProjectTasks`] hook. A request belongs to a project when its path starts with
+//! `/p/<project>`, which is stripped, or when its `Host` is one of the
+//! project's hosts. Everything else is served by the node.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header::HOST, Request, Uri},
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use edge_hive_auth::{OidcClient, TokenGenerator, TokenValidator, Webauthn};
+use edge_hive_cache::{CacheConfig, CacheService};
+use edge_hive_db::{DatabaseService, DbError, PolicySet, Project};
+use edge_hive_identity::NodeIdentity;
+use edge_hive_realtime::{RealtimeServer, RealtimeServerConfig};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+use crate::jobs::{JobOptions, JobWorker, JobWorkerHandle};
+use crate::mail::Mailer;
+use crate::state::ApiState;
+
+/// Path prefix of project requests
+pub const PROJECT_PREFIX: &str = "/p/";
+
+/// Starts the background tasks of a project, such as webhook deliveries and
+/// retention, given the state its handlers run with
+pub type ProjectTasks = Arc<
+    dyn Fn(&ApiState) -> BoxFuture<'static, Result<Vec<JoinHandle<()>>, DbError>> + Send + Sync,
+>;
+
+/// A background task of a project, aborted when dropped
+struct ProjectTask(JoinHandle<()>);
+
+impl Drop for ProjectTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+struct ProjectRoute {
+    project: Project,
+    router: Router,
+    /// Worker running the project's jobs, stopped with the route
+    _jobs: Option<JobWorkerHandle>,
+    /// Tasks started by the node's hook, stopped with the route
+    _tasks: Vec<ProjectTask>,
+}
+
+/// Projects served by the node
+pub struct ProjectRegistry {
+    db: Arc<DatabaseService>,
+    data_dir: PathBuf,
+    identity: Option<Arc<NodeIdentity>>,
+    policies: Arc<PolicySet>,
+    jobs: Option<JobOptions>,
+    tasks: Option<ProjectTasks>,
+    mailer: Option<Arc<Mailer>>,
+    oidc: Option<Arc<OidcClient>>,
+    webauthn: Option<Arc<Webauthn>>,
+    routes: RwLock<HashMap<String, ProjectRoute>>,
+}
+
+impl ProjectRegistry {
+    /// Registry without any project, serving projects of the node's database
+    pub fn new(node: &ApiState) -> Self {
+        Self {
+            db: node.db.clone(),
+            data_dir: node.data_dir.clone(),
+            identity: node.identity.clone(),
+            policies: node.policies.clone(),
+            jobs: node.jobs.clone(),
+            tasks: node.project_tasks.clone(),
+            mailer: node.mailer.clone(),
+            oidc: node.oidc.clone(),
+            webauthn: node.webauthn.clone(),
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registry serving every project registered in the node's database
+    pub async fn load(node: &ApiState) -> Result<Self, DbError> {
+        let registry = Self::new(node);
+        for project in node.db.projects().await? {
+            registry.open(project).await?;
+        }
+        Ok(registry)
+    }
+
+    /// Start serving a project, replacing its previous router
+    ///
+    /// Returns the state the project's handlers run with.
+    pub async fn open(&self, project: Project) -> Result<ApiState, DbError> {
+        let db = Arc::new(self.db.open_project(&project).await?);
+        let secret = project.jwt_secret.as_bytes();
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let mut state = ApiState::new(
+            cache,
+            db.clone(),
+            RealtimeServer::new(RealtimeServerConfig::default()).with_db(db),
+            self.data_dir.join("projects").join(&project.name),
+            TokenGenerator::new(secret, project.issuer.clone()),
+            TokenValidator::new(secret, project.issuer.clone()),
+        )
+        .with_policies(self.policies.as_ref().clone());
+        state.identity = self.identity.clone();
+        state.mailer = self.mailer.clone();
+        state.oidc = self.oidc.clone();
+        state.webauthn = self.webauthn.clone();
+        let state = state.with_branches();
+
+        let tasks = match &self.tasks {
+            Some(start) => start(&state).await?.into_iter().map(ProjectTask).collect(),
+            None => Vec::new(),
+        };
+        let router = crate::create_router(state.clone());
+        let jobs = self.jobs.clone().map(|options| JobWorker::new(&state, options).spawn());
+        tracing::info!("📁 Serving project '{}'", project.name);
+        let route = ProjectRoute {
+            project,
+            router,
+            _jobs: jobs,
+            _tasks: tasks,
+        };
+        self.write().insert(route.project.name.clone(), route);
+        Ok(state)
+    }
+
+    /// Stop serving a project; `false` if it was not served
+    pub fn close(&self, name: &str) -> bool {
+        self.write().remove(name).is_some()
+    }
+
+    /// Names of the served projects
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Router of the project a request belongs to, stripping the path prefix
+    pub fn route(&self, request: &mut Request<Body>) -> Option<Router> {
+        let routes = self.read();
+
+        if let Some(rest) = request.uri().path().strip_prefix(PROJECT_PREFIX) {
+            let (name, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let route = routes.get(name)?;
+            let path = if path.is_empty() { "/" } else { path };
+            let uri = strip_uri(request.uri(), path)?;
+            *request.uri_mut() = uri;
+            return Some(route.router.clone());
+        }
+
+        let host = request.headers().get(HOST)?.to_str().ok()?;
+        let host = host.rsplit_once(':').map_or(host, |(name, _port)| name).to_ascii_lowercase();
+        routes
+            .values()
+            .find(|route| route.project.hosts.contains(&host))
+            .map(|route| route.router.clone())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, ProjectRoute>> {
+        self.routes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, ProjectRoute>> {
+        self.routes.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) fn strip_uri(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Send project requests to their project's router
+pub async fn route_projects(
+    State(registry): State<Arc<ProjectRegistry>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    match registry.route(&mut request) {
+        Some(router) => match router.oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        },
+        None => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edge_hive_db::NewProject;
+    use tempfile::tempdir;
+
+    async fn registry() -> ProjectRegistry {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let node = ApiState::new_minimal(cache, db.clone(), tempdir().unwrap().path().into());
+        let shop = db
+            .create_project(NewProject {
+                name: "shop".to_string(),
+                hosts: vec!["shop.example.com".to_string()],
+                issuer: None,
+                jwt_secret: None,
+            })
+            .await
+            .unwrap();
+        let registry = ProjectRegistry::new(&node);
+        registry.open(shop).await.unwrap();
+        registry
+    }
+
+    fn request(uri: &str, host: &str) -> Request<Body> {
+        Request::builder().uri(uri).header(HOST, host).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_routes_by_prefix_and_host() {
+        let registry = registry().await;
+        assert_eq!(registry.names(), vec!["shop"]);
+
+        let mut by_prefix = request("/p/shop/api/v1/data/posts?limit=5", "node.example.com");
+        assert!(registry.route(&mut by_prefix).is_some());
+        assert_eq!(by_prefix.uri(), "/api/v1/data/posts?limit=5");
+
+        let mut by_host = request("/api/v1/data/posts", "Shop.Example.com:8080");
+        assert!(registry.route(&mut by_host).is_some());
+        assert_eq!(by_host.uri(), "/api/v1/data/posts");
+
+        for (uri, host) in [
+            ("/api/v1/data/posts", "node.example.com"),
+            ("/p/blog/api/v1/data/posts", "node.example.com"),
+            ("/p/shopping/api/v1/data/posts", "node.example.com"),
+        ] {
+            let mut node = request(uri, host);
+            assert!(registry.route(&mut node).is_none(), "{}", uri);
+            assert_eq!(node.uri(), uri);
+        }
+
+        assert!(registry.close("shop"));
+        assert!(registry.route(&mut request("/p/shop/", "node.example.com")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_projects_run_with_node_services_and_tasks() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let cache = CacheService::new(CacheConfig::default()).await;
+        let (started, mut tasks_started) = tokio::sync::mpsc::unbounded_channel();
+        let tasks: ProjectTasks = Arc::new(move |project: &ApiState| {
+            // The task holds `running` until it is aborted
+            let (running, stopped) = tokio::sync::oneshot::channel::<()>();
+            started.send((project.db.config().namespace.clone(), stopped)).unwrap();
+            Box::pin(async move {
+                let task = tokio::spawn(async move {
+                    let _running = running;
+                    std::future::pending::<()>().await
+                });
+                Ok(vec![task])
+            })
+        });
+        let node = ApiState::new_minimal(cache, db.clone(), tempdir().unwrap().path().into())
+            .with_webauthn(Webauthn::new(edge_hive_auth::RelyingParty {
+                id: "example.com".to_string(),
+                name: "Example".to_string(),
+                origins: vec!["https://example.com".to_string()],
+            }))
+            .with_project_tasks(tasks);
+        let shop = db
+            .create_project(NewProject {
+                name: "shop".to_string(),
+                hosts: vec![],
+                issuer: None,
+                jwt_secret: None,
+            })
+            .await
+            .unwrap();
+
+        let registry = ProjectRegistry::new(&node);
+        let state = registry.open(shop.clone()).await.unwrap();
+        assert!(Arc::ptr_eq(state.webauthn.as_ref().unwrap(), node.webauthn.as_ref().unwrap()));
+        let (namespace, stopped) = tasks_started.recv().await.unwrap();
+        assert_eq!(namespace, shop.namespace());
+
+        assert!(registry.close("shop"));
+        assert!(stopped.await.is_err());
+    }
+}
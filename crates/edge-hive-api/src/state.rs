@@ -1,10 +1,17 @@
 //! API Gateway shared state
 
-use edge_hive_auth::{TokenGenerator, TokenValidator};
+use edge_hive_auth::client::ClientStore;
+use edge_hive_auth::{OidcClient, TokenGenerator, TokenValidator, Webauthn};
 use edge_hive_cache::CacheService;
-use edge_hive_db::DatabaseService;
+use edge_hive_db::{DatabaseService, Janitor, PolicySet};
+use edge_hive_identity::NodeIdentity;
 use edge_hive_mcp::AuthenticatedMCPServer;
 use edge_hive_realtime::{RealtimeServer, RealtimeServerConfig};
+use crate::branches::BranchRegistry;
+use crate::handlers::graphql::SchemaCache;
+use crate::jobs::JobOptions;
+use crate::mail::Mailer;
+use crate::projects::{ProjectRegistry, ProjectTasks};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -32,6 +39,39 @@ pub struct ApiState {
 
     /// MCP server
     pub mcp_server: Arc<AuthenticatedMCPServer>,
+
+    /// OAuth2 clients allowed to request MCP tokens
+    pub clients: ClientStore,
+
+    /// GraphQL schema generated from the data tables
+    pub graphql: SchemaCache,
+
+    /// Row-level policies of the data API
+    pub policies: Arc<PolicySet>,
+
+    /// Node identity, used to encrypt and decrypt database dumps
+    pub identity: Option<Arc<NodeIdentity>>,
+
+    /// Projects hosted by the node (`None` in the state of a project)
+    pub projects: Option<Arc<ProjectRegistry>>,
+
+    /// Branches of the database served under `/b/<branch>` (`None` in the state of a branch)
+    pub branches: Option<Arc<BranchRegistry>>,
+
+    /// How the jobs of hosted projects are run (`None`: only queued)
+    pub jobs: Option<JobOptions>,
+
+    /// Starts the background tasks of hosted projects (`None`: none)
+    pub project_tasks: Option<ProjectTasks>,
+
+    /// Sends verification and password reset messages (`None`: no email)
+    pub mailer: Option<Arc<Mailer>>,
+
+    /// Identity providers users can log in with (`None`: none)
+    pub oidc: Option<Arc<OidcClient>>,
+
+    /// Relying party of passkey logins (`None`: passkeys disabled)
+    pub webauthn: Option<Arc<Webauthn>>,
 }
 
 impl ApiState {
@@ -45,7 +85,11 @@ impl ApiState {
         token_generator: TokenGenerator,
         token_validator: TokenValidator,
     ) -> Self {
-        let mcp_server = Arc::new(AuthenticatedMCPServer::new(token_validator.clone()));
+        let policies = Arc::new(PolicySet::default());
+        let mcp_server = Arc::new(
+            AuthenticatedMCPServer::new(token_validator.clone())
+                .with_data(db.clone(), policies.clone()),
+        );
         Self {
             cache: Arc::new(Mutex::new(cache)),
             db,
@@ -54,9 +98,98 @@ impl ApiState {
             token_generator: Arc::new(token_generator),
             token_validator: Arc::new(token_validator),
             mcp_server,
+            clients: ClientStore::new(),
+            graphql: SchemaCache::default(),
+            policies,
+            identity: None,
+            projects: None,
+            branches: None,
+            jobs: None,
+            project_tasks: None,
+            mailer: None,
+            oidc: None,
+            webauthn: None,
         }
     }
 
+    /// Use the given row-level policies for the data API
+    ///
+    /// The MCP data tools are rebuilt so they enforce the same policies.
+    pub fn with_policies(mut self, policies: PolicySet) -> Self {
+        self.policies = Arc::new(policies);
+        self.mcp_server = Arc::new(
+            AuthenticatedMCPServer::new(self.token_validator.as_ref().clone())
+                .with_data(self.db.clone(), self.policies.clone()),
+        );
+        self
+    }
+
+    /// Use the node identity for encrypted database dumps
+    pub fn with_identity(mut self, identity: NodeIdentity) -> Self {
+        self.identity = Some(Arc::new(identity));
+        self
+    }
+
+    /// Serve the given projects next to the node's own API
+    pub fn with_projects(mut self, projects: ProjectRegistry) -> Self {
+        self.projects = Some(Arc::new(projects));
+        self
+    }
+
+    /// Run the jobs of projects opened from now on with these options
+    ///
+    /// The node's own worker is started by whoever serves the node.
+    pub fn with_jobs(mut self, options: JobOptions) -> Self {
+        self.jobs = Some(options);
+        self
+    }
+
+    /// Start the background tasks of projects opened from now on with `tasks`
+    ///
+    /// The node's own tasks are started by whoever serves the node.
+    pub fn with_project_tasks(mut self, tasks: ProjectTasks) -> Self {
+        self.project_tasks = Some(tasks);
+        self
+    }
+
+    /// Send the messages of the auth flows with `mailer`
+    pub fn with_mailer(mut self, mailer: Mailer) -> Self {
+        self.mailer = Some(Arc::new(mailer));
+        self
+    }
+
+    /// Let users log in with the providers of `oidc`
+    pub fn with_oidc(mut self, oidc: OidcClient) -> Self {
+        self.oidc = Some(Arc::new(oidc));
+        self
+    }
+
+    /// Let users register passkeys with and log in with them
+    pub fn with_webauthn(mut self, webauthn: Webauthn) -> Self {
+        self.webauthn = Some(Arc::new(webauthn));
+        self
+    }
+
+    /// Let `janitor` drop the cached queries of the tables it purges rows from
+    pub fn clear_purged(&self, janitor: Janitor) -> Janitor {
+        let cache = self.cache.clone();
+        janitor.on_purge(move |table| {
+            let cache = cache.clone();
+            Box::pin(async move {
+                crate::handlers::data::clear_table_cache(&cache, table.as_str()).await;
+            })
+        })
+    }
+
+    /// Serve the branches of the database under `/b/<branch>`
+    ///
+    /// Call last: branches are served with the policies, identity, jobs, mail,
+    /// login providers and passkeys set so far.
+    pub fn with_branches(mut self) -> Self {
+        self.branches = Some(Arc::new(BranchRegistry::new(&self)));
+        self
+    }
+
     /// Convenience constructor for tests / minimal setups.
     pub fn new_minimal(cache: CacheService, db: Arc<DatabaseService>, data_dir: PathBuf) -> Self {
         let token_secret = "some-secret-for-testing";
@@ -15,8 +15,7 @@ use edge_hive_api::handlers::auth::RegisterResponse;
 async fn setup_test_env() -> ApiState {
     let cache = edge_hive_cache::CacheService::new(CacheConfig::default()).await;
     let dir = tempdir().unwrap();
-    let db_path = dir.path().join("test.db");
-    let db = Arc::new(DatabaseService::new(&db_path).await.unwrap());
+    let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
     ApiState::new_minimal(cache, db, dir.path().to_path_buf())
 }
 
@@ -44,12 +43,12 @@ async fn test_register_success() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
 
-    let body = body::to_bytes(response.into_body()).await.unwrap();
+    let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
     let res: RegisterResponse = serde_json::from_slice(&body).unwrap();
     assert_eq!(res.email, "test@test.com");
-    assert_eq!(res.message, "User registered successfully");
+    assert_eq!(res.message, "Registration received");
 }
 
 #[tokio::test]
@@ -98,7 +97,11 @@ async fn test_register_duplicate_email() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::CONFLICT);
+    // Answered like a new registration, so addresses cannot be probed
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let res: RegisterResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(res.message, "Registration received");
 }
 
 #[tokio::test]
@@ -171,4 +174,3 @@ async fn test_auth_middleware_valid_token() {
     // In create_router, we don't apply an AuthLayer globally yet, so it returns OK anyway.
     assert_eq!(response.status(), StatusCode::OK);
 }
-}
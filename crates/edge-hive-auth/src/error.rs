@@ -26,6 +26,15 @@ pub enum AuthError {
     #[error("JWT error: {0}")]
     JwtError(#[from] jsonwebtoken::errors::Error),
 
+    #[error("Unknown identity provider: {0}")]
+    UnknownProvider(String),
+
+    #[error("Identity provider error: {0}")]
+    Provider(String),
+
+    #[error("WebAuthn error: {0}")]
+    Webauthn(String),
+
     #[error("Database error: {0}")]
     DatabaseError(String),
 
@@ -23,6 +23,9 @@ pub struct JwtClaims {
     /// Custom: Node ID that issued the token
     #[serde(skip_serializing_if = "Option::is_none")]
     pub node_id: Option<String>,
+    /// Custom: Role of the subject (`user`, `admin`, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
 }
 
 impl JwtClaims {
@@ -45,9 +48,33 @@ impl JwtClaims {
             jti: uuid::Uuid::new_v4().to_string(),
             scopes,
             node_id,
+            role: None,
         }
     }
 
+    /// Set the role of the subject
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Issue the token for another audience than the API
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.aud = audience.into();
+        self
+    }
+
+    /// Expire the token `ttl` after it was issued
+    pub fn expires_in(mut self, ttl: Duration) -> Self {
+        self.exp = self.iat + ttl.num_seconds();
+        self
+    }
+
+    /// Role of the subject, `user` when the token carries none
+    pub fn role(&self) -> &str {
+        self.role.as_deref().unwrap_or("user")
+    }
+
     /// Check if token has expired
     pub fn is_expired(&self) -> bool {
         let now = Utc::now().timestamp();
@@ -66,6 +93,7 @@ impl JwtClaims {
 }
 
 /// JWT cryptographic keys
+#[derive(Clone)]
 pub struct JwtKeys {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
@@ -89,6 +117,7 @@ impl JwtKeys {
 }
 
 /// JWT token generator
+#[derive(Clone)]
 pub struct TokenGenerator {
     keys: JwtKeys,
     issuer: String,
@@ -102,6 +131,11 @@ impl TokenGenerator {
         }
     }
 
+    /// Issuer of the generated tokens
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
     /// Generate JWT access token
     pub fn generate_token(
         &self,
@@ -115,6 +149,17 @@ impl TokenGenerator {
             .map_err(AuthError::from)
     }
 
+    /// Generate JWT access token for a user with a role
+    pub fn generate_user_token(
+        &self,
+        user_id: String,
+        role: String,
+        scopes: Vec<String>,
+    ) -> Result<String> {
+        let claims = JwtClaims::new(user_id, self.issuer.clone(), scopes, None).with_role(role);
+        self.generate_token_from_claims(&claims)
+    }
+
     /// Generate JWT access token from existing claims (useful for tests)
     pub fn generate_token_from_claims(&self, claims: &JwtClaims) -> Result<String> {
         encode(&Header::default(), claims, &self.keys.encoding_key).map_err(AuthError::from)
@@ -122,6 +167,7 @@ impl TokenGenerator {
 }
 
 /// JWT token validator
+#[derive(Clone)]
 pub struct TokenValidator {
     keys: JwtKeys,
     issuer: String,
@@ -135,11 +181,21 @@ impl TokenValidator {
         }
     }
 
+    /// Issuer accepted by this validator
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
     /// Validate and decode JWT token
     pub fn validate_token(&self, token: &str) -> Result<JwtClaims> {
+        self.validate_token_for(token, "mcp")
+    }
+
+    /// Validate and decode a token issued for `audience`
+    pub fn validate_token_for(&self, token: &str, audience: &str) -> Result<JwtClaims> {
         let mut validation = Validation::default();
         validation.set_issuer(&[&self.issuer]);
-        validation.set_audience(&["mcp"]);
+        validation.set_audience(&[audience]);
 
         let token_data: TokenData<JwtClaims> = decode(
             token,
@@ -212,4 +268,45 @@ mod tests {
 
         assert_eq!(claims.sub, "test-client");
     }
+
+    #[test]
+    fn test_role_claim() {
+        let secret = JwtKeys::generate_secret();
+        let issuer = "https://test-node:8080".to_string();
+
+        let generator = TokenGenerator::new(&secret, issuer.clone());
+        let validator = TokenValidator::new(&secret, issuer);
+
+        let token = generator
+            .generate_user_token("users:alice".to_string(), "admin".to_string(), vec![])
+            .unwrap();
+        assert_eq!(validator.validate_token(&token).unwrap().role(), "admin");
+
+        let token = generator
+            .generate_token("test-client".to_string(), vec![], None)
+            .unwrap();
+        let claims = validator.validate_token(&token).unwrap();
+        assert_eq!(claims.role, None);
+        assert_eq!(claims.role(), "user");
+    }
+
+    #[test]
+    fn test_audience_tokens() {
+        let secret = JwtKeys::generate_secret();
+        let issuer = "https://test-node:8080".to_string();
+
+        let generator = TokenGenerator::new(&secret, issuer.clone());
+        let validator = TokenValidator::new(&secret, issuer.clone());
+
+        let claims = JwtClaims::new("users:alice".to_string(), issuer, vec![], None)
+            .with_audience("reset_password")
+            .expires_in(Duration::minutes(30));
+        let token = generator.generate_token_from_claims(&claims).unwrap();
+
+        let validated = validator.validate_token_for(&token, "reset_password").unwrap();
+        assert_eq!(validated.exp, validated.iat + 30 * 60);
+        // Not an access token
+        assert!(validator.validate_token(&token).is_err());
+        assert!(validator.validate_token_for(&token, "verify_email").is_err());
+    }
 }
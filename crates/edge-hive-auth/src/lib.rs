@@ -1,11 +1,17 @@
 pub mod jwt;
 pub mod oauth2;
+pub mod oidc;
+pub mod totp;
+pub mod webauthn;
 pub mod middleware;
 pub mod client;
 pub mod error;
 
 pub use jwt::{JwtClaims, JwtKeys, TokenGenerator, TokenValidator};
 pub use oauth2::{OAuth2Config, ClientCredentials, AccessToken, TokenResponse};
+pub use oidc::{Authorization, ClaimMapping, OidcClient, OidcProvider, OidcUser};
+pub use totp::Totp;
+pub use webauthn::{Credential, RelyingParty, Webauthn};
 pub use error::{AuthError, Result};
 
 #[cfg(feature = "server")]
@@ -0,0 +1,352 @@
+//! OAuth2 / OpenID Connect login with external providers
+//!
+//! Authorization code flow with PKCE (RFC 7636): [`OidcClient::authorize`]
+//! builds the provider's login URL along with the `state` and code verifier
+//! the caller keeps until the provider redirects back, then
+//! [`OidcClient::login`] exchanges the code and maps the provider's userinfo
+//! to an [`OidcUser`]. Plain OAuth2 providers with a user endpoint (GitHub)
+//! work too, given a [`ClaimMapping`] for their field names.
+
+use crate::error::{AuthError, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Scopes requested when a provider lists none
+fn default_scopes() -> Vec<String> {
+    vec!["openid".to_string(), "email".to_string(), "profile".to_string()]
+}
+
+/// A provider users can log in with
+///
+/// The endpoints are listed in the provider's
+/// `/.well-known/openid-configuration`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcProvider {
+    pub client_id: String,
+    /// Sent with the code exchange; public clients rely on PKCE alone
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    /// Where the provider sends the user back with the code
+    pub redirect_uri: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+/// Userinfo fields holding each attribute of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub subject: String,
+    pub email: String,
+    /// Without this field, emails count as unverified
+    pub email_verified: String,
+    pub name: String,
+    pub picture: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: "email_verified".to_string(),
+            name: "name".to_string(),
+            picture: "picture".to_string(),
+        }
+    }
+}
+
+/// A user as described by a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidcUser {
+    pub provider: String,
+    /// Stable ID of the user at the provider
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+}
+
+/// A login started with a provider
+///
+/// `state` and `verifier` must be kept server-side until the provider
+/// redirects back; only `url` goes to the user.
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    pub url: String,
+    pub state: String,
+    pub verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenSet {
+    access_token: String,
+}
+
+/// Client of the configured providers
+#[derive(Clone)]
+pub struct OidcClient {
+    http: reqwest::Client,
+    providers: HashMap<String, OidcProvider>,
+}
+
+impl OidcClient {
+    pub fn new(providers: HashMap<String, OidcProvider>) -> Self {
+        let http = reqwest::Client::builder()
+            .user_agent("edge-hive")
+            .build()
+            .unwrap_or_default();
+        Self { http, providers }
+    }
+
+    /// Names of the configured providers
+    pub fn providers(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn provider(&self, name: &str) -> Result<&OidcProvider> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| AuthError::UnknownProvider(name.to_string()))
+    }
+
+    /// Start a login with `provider`
+    pub fn authorize(&self, provider: &str) -> Result<Authorization> {
+        let config = self.provider(provider)?;
+        let state = random_string(32);
+        let verifier = random_string(64);
+
+        let url = Url::parse_with_params(
+            &config.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", config.client_id.as_str()),
+                ("redirect_uri", config.redirect_uri.as_str()),
+                ("scope", config.scopes.join(" ").as_str()),
+                ("state", state.as_str()),
+                ("code_challenge", pkce_challenge(&verifier).as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| AuthError::Internal(format!("invalid authorization endpoint: {}", e)))?;
+
+        Ok(Authorization {
+            url: url.into(),
+            state,
+            verifier,
+        })
+    }
+
+    /// Finish a login: exchange the code and fetch the user
+    pub async fn login(&self, provider: &str, code: &str, verifier: &str) -> Result<OidcUser> {
+        let config = self.provider(provider)?;
+        let access_token = self.exchange_code(config, code, verifier).await?;
+        let claims = self.userinfo(config, &access_token).await?;
+        map_claims(provider, &config.claims, &claims)
+    }
+
+    async fn exchange_code(
+        &self,
+        config: &OidcProvider,
+        code: &str,
+        verifier: &str,
+    ) -> Result<String> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", verifier),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response = self
+            .http
+            .post(&config.token_endpoint)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(AuthError::Provider(format!("code exchange failed ({}): {}", status, body)));
+        }
+        let tokens: TokenSet = response.json().await.map_err(provider_error)?;
+        Ok(tokens.access_token)
+    }
+
+    async fn userinfo(&self, config: &OidcProvider, access_token: &str) -> Result<Value> {
+        let response = self
+            .http
+            .get(&config.userinfo_endpoint)
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if !response.status().is_success() {
+            let message = format!("userinfo request failed ({})", response.status());
+            return Err(AuthError::Provider(message));
+        }
+        response.json().await.map_err(provider_error)
+    }
+}
+
+fn provider_error(e: reqwest::Error) -> AuthError {
+    AuthError::Provider(e.to_string())
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// S256 code challenge of a PKCE verifier
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn map_claims(provider: &str, mapping: &ClaimMapping, claims: &Value) -> Result<OidcUser> {
+    let text = |field: &str| match claims.get(field) {
+        Some(Value::String(value)) if !value.is_empty() => Some(value.clone()),
+        Some(Value::Number(value)) => Some(value.to_string()),
+        _ => None,
+    };
+    let subject = text(&mapping.subject).ok_or_else(|| {
+        AuthError::Provider(format!("userinfo has no '{}' claim", mapping.subject))
+    })?;
+    let email_verified = match claims.get(&mapping.email_verified) {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+
+    Ok(OidcUser {
+        provider: provider.to_string(),
+        subject,
+        email: text(&mapping.email),
+        email_verified,
+        name: text(&mapping.name),
+        picture: text(&mapping.picture),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    /// Challenge of the code the mock provider handed out
+    type Challenge = Arc<Mutex<Option<String>>>;
+
+    async fn token(
+        State(challenge): State<Challenge>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> std::result::Result<Json<Value>, StatusCode> {
+        let expected = challenge.lock().unwrap().clone();
+        let verifier = form.get("code_verifier").ok_or(StatusCode::BAD_REQUEST)?;
+        if form.get("code").map(String::as_str) != Some("the-code")
+            || expected.as_deref() != Some(pkce_challenge(verifier).as_str())
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Json(json!({ "access_token": "the-token", "token_type": "Bearer" })))
+    }
+
+    async fn userinfo(headers: HeaderMap) -> std::result::Result<Json<Value>, StatusCode> {
+        if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Bearer the-token")
+        {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!({ "id": 42, "email": "ada@example.com", "login": "ada" })))
+    }
+
+    async fn mock_provider(challenge: Challenge) -> String {
+        let app = Router::new()
+            .route("/token", post(token))
+            .route("/userinfo", get(userinfo))
+            .with_state(challenge);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        base
+    }
+
+    fn provider(base: &str) -> OidcProvider {
+        OidcProvider {
+            client_id: "edge-hive".to_string(),
+            client_secret: None,
+            authorization_endpoint: format!("{}/authorize", base),
+            token_endpoint: format!("{}/token", base),
+            userinfo_endpoint: format!("{}/userinfo", base),
+            redirect_uri: "http://localhost:8080/callback".to_string(),
+            scopes: vec!["user:email".to_string()],
+            claims: ClaimMapping {
+                subject: "id".to_string(),
+                name: "login".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // Example of RFC 7636, appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert_eq!(pkce_challenge(verifier), "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[tokio::test]
+    async fn test_authorize_and_login() {
+        let challenge = Challenge::default();
+        let base = mock_provider(challenge.clone()).await;
+        let client = OidcClient::new(HashMap::from([("github".to_string(), provider(&base))]));
+
+        let authorization = client.authorize("github").unwrap();
+        let url = Url::parse(&authorization.url).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["state"], authorization.state);
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["redirect_uri"], "http://localhost:8080/callback");
+        *challenge.lock().unwrap() = Some(params["code_challenge"].clone());
+
+        let user = client.login("github", "the-code", &authorization.verifier).await.unwrap();
+        assert_eq!(user.subject, "42");
+        assert_eq!(user.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(user.name.as_deref(), Some("ada"));
+        assert!(!user.email_verified);
+
+        let result = client.login("github", "the-code", "wrong-verifier").await;
+        assert!(matches!(result, Err(AuthError::Provider(_))));
+        let result = client.login("gitlab", "the-code", &authorization.verifier).await;
+        assert!(matches!(result, Err(AuthError::UnknownProvider(_))));
+    }
+}
@@ -0,0 +1,192 @@
+//! Time-based one-time passwords (RFC 6238)
+//!
+//! Uses the parameters every authenticator app supports: HMAC-SHA1, six
+//! digits, 30 second steps. [`Totp::verify`] tolerates one step of clock
+//! skew either way and returns the step it matched, so callers can refuse a
+//! code that was already used.
+
+use crate::error::{AuthError, Result};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+
+/// Digits of a code
+pub const DIGITS: usize = 6;
+
+/// Seconds a code stays current
+pub const PERIOD: u64 = 30;
+
+/// Steps of clock skew accepted either way
+const SKEW: u64 = 1;
+
+/// Size of generated secrets (the HMAC-SHA1 block recommended by RFC 4226)
+const SECRET_BYTES: usize = 20;
+
+/// Characters of recovery codes, without the easily confused `0`, `1`, `l` and `o`
+const RECOVERY_ALPHABET: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyz";
+
+/// A TOTP secret
+#[derive(Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    /// A new random secret
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self { secret }
+    }
+
+    /// A secret in the base32 form shown by authenticator apps
+    pub fn from_base32(secret: &str) -> Result<Self> {
+        let secret: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect::<String>()
+            .to_ascii_uppercase();
+        match BASE32_NOPAD.decode(secret.as_bytes()) {
+            Ok(secret) if !secret.is_empty() => Ok(Self { secret }),
+            _ => Err(AuthError::InvalidToken("invalid TOTP secret".to_string())),
+        }
+    }
+
+    /// The secret in base32, for manual entry
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.secret)
+    }
+
+    /// `otpauth://` URI of the secret, the payload of enrollment QR codes
+    pub fn uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.to_base32(),
+            percent_encode(issuer),
+            DIGITS,
+            PERIOD
+        )
+    }
+
+    /// Code of the step `unix_time` falls in
+    pub fn code_at(&self, unix_time: u64) -> String {
+        self.code(unix_time / PERIOD)
+    }
+
+    /// Step of `code` if it is current at `unix_time`
+    pub fn verify(&self, code: &str, unix_time: u64) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let current = unix_time / PERIOD;
+        (current.saturating_sub(SKEW)..=current + SKEW).find(|step| {
+            // Compare every digit so timing does not reveal the first mismatch
+            self.code(*step)
+                .bytes()
+                .zip(code.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+        })
+    }
+
+    fn code(&self, step: u64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC takes any key size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Dynamic truncation (RFC 4226, section 5.3)
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!("{:0width$}", value % 10u32.pow(DIGITS as u32), width = DIGITS)
+    }
+}
+
+/// Random single-use codes for when the authenticator is lost, as `xxxxx-xxxxx`
+pub fn recovery_codes(count: usize) -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let mut pick = || RECOVERY_ALPHABET[rng.gen_range(0..RECOVERY_ALPHABET.len())] as char;
+    (0..count)
+        .map(|_| {
+            let first: String = (0..5).map(|_| pick()).collect();
+            let second: String = (0..5).map(|_| pick()).collect();
+            format!("{}-{}", first, second)
+        })
+        .collect()
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_secret() -> Totp {
+        Totp {
+            secret: b"12345678901234567890".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // SHA1 vectors of RFC 6238, appendix B, truncated to six digits
+        let totp = rfc_secret();
+        assert_eq!(totp.code_at(59), "287082");
+        assert_eq!(totp.code_at(1111111109), "081804");
+        assert_eq!(totp.code_at(1111111111), "050471");
+        assert_eq!(totp.code_at(1234567890), "005924");
+        assert_eq!(totp.code_at(2000000000), "279037");
+    }
+
+    #[test]
+    fn test_verify_with_skew() {
+        let totp = rfc_secret();
+        let now = 1111111111;
+        assert_eq!(totp.verify("050471", now), Some(now / PERIOD));
+        assert_eq!(totp.verify("050 471", now + PERIOD), Some(now / PERIOD));
+        assert_eq!(totp.verify("050471", now + 2 * PERIOD), None);
+        assert_eq!(totp.verify("123456", now), None);
+        assert_eq!(totp.verify("05047", now), None);
+    }
+
+    #[test]
+    fn test_secret_round_trip_and_uri() {
+        let totp = Totp::generate();
+        let secret = totp.to_base32();
+        let parsed = Totp::from_base32(&secret.to_lowercase()).unwrap();
+        assert_eq!(parsed.code_at(59), totp.code_at(59));
+        assert!(Totp::from_base32("not base32!").is_err());
+
+        let uri = totp.uri("Edge Hive", "ada@example.com");
+        assert!(uri.starts_with("otpauth://totp/Edge%20Hive:ada@example.com?secret="));
+        assert!(uri.contains(&format!("secret={}&issuer=Edge%20Hive", secret)));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = recovery_codes(10);
+        assert_eq!(codes.len(), 10);
+        assert!(codes.iter().all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 10);
+    }
+}
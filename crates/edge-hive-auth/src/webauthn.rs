@@ -0,0 +1,584 @@
+//! WebAuthn passkeys
+//!
+//! Relying party side of the registration and authentication ceremonies, for
+//! passkeys used as the only factor: user presence and user verification are
+//! both required. Attestation statements are not checked (options ask for
+//! `none`), so a credential is trusted as what it is, a key pair bound to
+//! this relying party. ES256 and EdDSA keys are supported, which covers
+//! platform and roaming authenticators.
+
+use crate::error::{AuthError, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::value::Value as Cbor;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// COSE algorithm of ECDSA with P-256 and SHA-256
+const ES256: i64 = -7;
+
+/// COSE algorithm of Ed25519
+const EDDSA: i64 = -8;
+
+/// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED: u8 = 0x40;
+
+/// Milliseconds clients give the user to complete a ceremony
+const TIMEOUT_MS: u64 = 300_000;
+
+/// The site passkeys are registered with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelyingParty {
+    /// Domain passkeys are bound to, e.g. `example.com`
+    pub id: String,
+    /// Name shown by authenticators
+    pub name: String,
+    /// Origins ceremonies may run on, e.g. `https://app.example.com`
+    pub origins: Vec<String>,
+}
+
+/// Credential created by `navigator.credentials.create()`, in its JSON form
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationResponse {
+    /// Credential ID, base64url
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// Assertion returned by `navigator.credentials.get()`, in its JSON form
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationResponse {
+    /// Credential ID, base64url
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    /// User handle of a discoverable credential
+    #[serde(rename = "userHandle", default, skip_serializing_if = "Option::is_none")]
+    pub user_handle: Option<String>,
+}
+
+/// A registered credential
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    /// Credential ID, base64url
+    pub id: String,
+    /// COSE public key, base64url
+    pub public_key: String,
+    /// Signature counter of the authenticator (0 if it keeps none)
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// Credential ID and COSE public key, when registering
+    attested: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+enum PublicKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    EdDsa(ed25519_dalek::VerifyingKey),
+}
+
+/// Relying party of the ceremonies
+#[derive(Debug, Clone)]
+pub struct Webauthn {
+    rp: RelyingParty,
+}
+
+impl Webauthn {
+    pub fn new(rp: RelyingParty) -> Self {
+        Self { rp }
+    }
+
+    /// A new random challenge, base64url
+    pub fn challenge() -> String {
+        let mut challenge = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        URL_SAFE_NO_PAD.encode(challenge)
+    }
+
+    /// `publicKey` options of `navigator.credentials.create()`
+    ///
+    /// `exclude` lists the credential IDs the user already registered, so an
+    /// authenticator does not register twice.
+    pub fn registration_options(
+        &self,
+        challenge: &str,
+        user_handle: &str,
+        user_name: &str,
+        exclude: &[String],
+    ) -> Value {
+        json!({
+            "challenge": challenge,
+            "rp": { "id": self.rp.id, "name": self.rp.name },
+            "user": { "id": user_handle, "name": user_name, "displayName": user_name },
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": ES256 },
+                { "type": "public-key", "alg": EDDSA },
+            ],
+            "timeout": TIMEOUT_MS,
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "requireResidentKey": true,
+                "userVerification": "required",
+            },
+            "excludeCredentials": descriptors(exclude),
+        })
+    }
+
+    /// `publicKey` options of `navigator.credentials.get()`
+    ///
+    /// Without `allow`ed credentials, the authenticator offers the passkeys
+    /// it holds for this relying party.
+    pub fn authentication_options(&self, challenge: &str, allow: &[String]) -> Value {
+        json!({
+            "challenge": challenge,
+            "rpId": self.rp.id,
+            "timeout": TIMEOUT_MS,
+            "userVerification": "required",
+            "allowCredentials": descriptors(allow),
+        })
+    }
+
+    /// Check a new credential answering `challenge`
+    pub fn register(&self, challenge: &str, response: &RegistrationResponse) -> Result<Credential> {
+        self.check_client_data(&response.response.client_data_json, "webauthn.create", challenge)?;
+
+        let attestation: Cbor = read_cbor(&decode(&response.response.attestation_object)?)?;
+        let auth_data = attestation
+            .as_map()
+            .and_then(|entries| {
+                entries.iter().find(|(key, _)| key.as_text() == Some("authData"))
+            })
+            .and_then(|(_, value)| value.as_bytes())
+            .ok_or_else(|| invalid("attestation object has no authenticator data"))?;
+        let data = self.check_authenticator_data(auth_data)?;
+
+        let (id, public_key) =
+            data.attested.ok_or_else(|| invalid("no attested credential data"))?;
+        PublicKey::from_cose(&public_key)?;
+        let id = URL_SAFE_NO_PAD.encode(id);
+        if id != response.id {
+            return Err(invalid("credential ID mismatch"));
+        }
+
+        Ok(Credential {
+            id,
+            public_key: URL_SAFE_NO_PAD.encode(public_key),
+            sign_count: data.sign_count,
+        })
+    }
+
+    /// Check an assertion of `credential` answering `challenge`, returning the
+    /// authenticator's new signature counter
+    ///
+    /// Fails when the counter did not move forward, a sign of a cloned
+    /// authenticator, unless the authenticator keeps no counter.
+    pub fn authenticate(
+        &self,
+        challenge: &str,
+        response: &AuthenticationResponse,
+        credential: &Credential,
+    ) -> Result<u32> {
+        if response.id != credential.id {
+            return Err(invalid("credential ID mismatch"));
+        }
+        let client_data = &response.response.client_data_json;
+        self.check_client_data(client_data, "webauthn.get", challenge)?;
+        let auth_data = decode(&response.response.authenticator_data)?;
+        let data = self.check_authenticator_data(&auth_data)?;
+
+        let mut message = auth_data;
+        message.extend_from_slice(&Sha256::digest(decode(client_data)?));
+        let key = PublicKey::from_cose(&decode(&credential.public_key)?)?;
+        key.verify(&message, &decode(&response.response.signature)?)?;
+
+        let counted = data.sign_count != 0 || credential.sign_count != 0;
+        if counted && data.sign_count <= credential.sign_count {
+            return Err(invalid("signature counter went backwards, authenticator may be cloned"));
+        }
+        Ok(data.sign_count)
+    }
+
+    fn check_client_data(&self, client_data_json: &str, kind: &str, challenge: &str) -> Result<()> {
+        let client_data = parse_client_data(client_data_json)?;
+        if client_data.kind != kind {
+            return Err(invalid("wrong ceremony"));
+        }
+        if client_data.challenge != challenge {
+            return Err(invalid("wrong challenge"));
+        }
+        if client_data.cross_origin || !self.rp.origins.contains(&client_data.origin) {
+            return Err(invalid(&format!("origin {} not allowed", client_data.origin)));
+        }
+        Ok(())
+    }
+
+    fn check_authenticator_data(&self, bytes: &[u8]) -> Result<AuthenticatorData> {
+        let data = parse_authenticator_data(bytes)?;
+        if data.rp_id_hash != Sha256::digest(self.rp.id.as_bytes()).as_slice() {
+            return Err(invalid("credential of another relying party"));
+        }
+        if data.flags & FLAG_USER_PRESENT == 0 || data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(invalid("user not verified"));
+        }
+        Ok(data)
+    }
+}
+
+/// Challenge a ceremony response answers, to find the ceremony it belongs to
+pub fn client_challenge(client_data_json: &str) -> Result<String> {
+    Ok(parse_client_data(client_data_json)?.challenge)
+}
+
+/// WebAuthn user handle of a user ID
+pub fn user_handle(user_id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(user_id)
+}
+
+fn descriptors(ids: &[String]) -> Vec<Value> {
+    ids.iter().map(|id| json!({ "type": "public-key", "id": id })).collect()
+}
+
+fn invalid(message: &str) -> AuthError {
+    AuthError::Webauthn(message.to_string())
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid("invalid base64url"))
+}
+
+fn read_cbor(bytes: &[u8]) -> Result<Cbor> {
+    ciborium::de::from_reader(bytes).map_err(|e| invalid(&format!("invalid CBOR: {}", e)))
+}
+
+fn parse_client_data(client_data_json: &str) -> Result<ClientData> {
+    serde_json::from_slice(&decode(client_data_json)?)
+        .map_err(|e| invalid(&format!("invalid client data: {}", e)))
+}
+
+fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData> {
+    let truncated = || invalid("truncated authenticator data");
+    if bytes.len() < 37 {
+        return Err(truncated());
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+
+    let attested = if flags & FLAG_ATTESTED != 0 {
+        // AAGUID (16 bytes), credential ID length (2), credential ID, COSE key
+        let rest = &bytes[37..];
+        let len = rest.get(16..18).ok_or_else(truncated)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        let id = rest.get(18..18 + len).ok_or_else(truncated)?;
+        let key = &rest[18 + len..];
+        // Extensions may follow the key
+        let mut cursor = std::io::Cursor::new(key);
+        ciborium::de::from_reader::<Cbor, _>(&mut cursor)
+            .map_err(|e| invalid(&format!("invalid credential public key: {}", e)))?;
+        Some((id.to_vec(), key[..cursor.position() as usize].to_vec()))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].to_vec(),
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+impl PublicKey {
+    fn from_cose(bytes: &[u8]) -> Result<Self> {
+        let key = read_cbor(bytes)?;
+        let entries = key.as_map().ok_or_else(|| invalid("COSE key is not a map"))?;
+        let field = |label: i64| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_integer().map(i128::from) == Some(label as i128))
+                .map(|(_, value)| value)
+        };
+        let int = |label: i64| field(label).and_then(Cbor::as_integer).map(i128::from);
+        let bytes = |label: i64| field(label).and_then(Cbor::as_bytes);
+
+        // kty (1), alg (3), crv (-1), x (-2), y (-3)
+        match (int(1), int(3), int(-1)) {
+            (Some(2), Some(alg), Some(1)) if alg == ES256 as i128 => {
+                let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+                    return Err(invalid("incomplete P-256 key"));
+                };
+                let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                    .map(PublicKey::Es256)
+                    .map_err(|_| invalid("invalid P-256 key"))
+            }
+            (Some(1), Some(alg), Some(6)) if alg == EDDSA as i128 => {
+                let x: [u8; 32] = bytes(-2)
+                    .and_then(|x| x.as_slice().try_into().ok())
+                    .ok_or_else(|| invalid("invalid Ed25519 key"))?;
+                ed25519_dalek::VerifyingKey::from_bytes(&x)
+                    .map(PublicKey::EdDsa)
+                    .map_err(|_| invalid("invalid Ed25519 key"))
+            }
+            _ => Err(invalid("unsupported key type")),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let bad_signature = || invalid("bad signature");
+        match self {
+            PublicKey::Es256(key) => {
+                use p256::ecdsa::signature::Verifier;
+                let signature =
+                    p256::ecdsa::Signature::from_der(signature).map_err(|_| bad_signature())?;
+                let signature = signature.normalize_s().unwrap_or(signature);
+                key.verify(message, &signature).map_err(|_| bad_signature())
+            }
+            PublicKey::EdDsa(key) => {
+                use ed25519_dalek::Verifier;
+                let signature =
+                    ed25519_dalek::Signature::from_slice(signature).map_err(|_| bad_signature())?;
+                key.verify(message, &signature).map_err(|_| bad_signature())
+            }
+        }
+    }
+}
+
+/// A software authenticator, for tests of the ceremonies
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    /// Authenticator holding one ES256 passkey
+    #[derive(Clone)]
+    pub struct SoftAuthenticator {
+        rp_id: String,
+        origin: String,
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        user_handle: Option<String>,
+        /// Signature counter, bumped by every assertion
+        pub sign_count: u32,
+    }
+
+    impl SoftAuthenticator {
+        pub fn new(rp_id: &str, origin: &str) -> Self {
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut credential_id);
+            Self {
+                rp_id: rp_id.to_string(),
+                origin: origin.to_string(),
+                key: SigningKey::random(&mut rand::rngs::OsRng),
+                credential_id,
+                user_handle: None,
+                sign_count: 0,
+            }
+        }
+
+        pub fn credential_id(&self) -> String {
+            URL_SAFE_NO_PAD.encode(&self.credential_id)
+        }
+
+        /// Answer the `publicKey` options of `navigator.credentials.create()`
+        pub fn create(&mut self, options: &Value) -> RegistrationResponse {
+            self.user_handle = options["user"]["id"].as_str().map(str::to_string);
+            let point = p256::PublicKey::from(self.key.verifying_key()).to_encoded_point(false);
+            let cose = cbor(&Cbor::Map(vec![
+                (Cbor::from(1), Cbor::from(2)),
+                (Cbor::from(3), Cbor::from(ES256)),
+                (Cbor::from(-1), Cbor::from(1)),
+                (Cbor::from(-2), Cbor::Bytes(point.x().unwrap().to_vec())),
+                (Cbor::from(-3), Cbor::Bytes(point.y().unwrap().to_vec())),
+            ]));
+
+            let mut auth_data = self.auth_data(FLAG_ATTESTED);
+            auth_data.extend_from_slice(&[0u8; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            auth_data.extend_from_slice(&cose);
+            let attestation = cbor(&Cbor::Map(vec![
+                (Cbor::from("fmt"), Cbor::from("none")),
+                (Cbor::from("attStmt"), Cbor::Map(vec![])),
+                (Cbor::from("authData"), Cbor::Bytes(auth_data)),
+            ]));
+
+            RegistrationResponse {
+                id: self.credential_id(),
+                response: AttestationResponse {
+                    client_data_json: self.client_data("webauthn.create", options),
+                    attestation_object: URL_SAFE_NO_PAD.encode(attestation),
+                },
+            }
+        }
+
+        /// Answer the `publicKey` options of `navigator.credentials.get()`
+        pub fn get(&mut self, options: &Value) -> AuthenticationResponse {
+            self.sign_count += 1;
+            let auth_data = self.auth_data(0);
+            let client_data_json = self.client_data("webauthn.get", options);
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(decode(&client_data_json).unwrap()));
+            let signature: Signature = self.key.sign(&message);
+
+            AuthenticationResponse {
+                id: self.credential_id(),
+                response: AssertionResponse {
+                    client_data_json,
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    signature: URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+                    user_handle: self.user_handle.clone(),
+                },
+            }
+        }
+
+        fn auth_data(&self, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(FLAG_USER_PRESENT | FLAG_USER_VERIFIED | flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        fn client_data(&self, kind: &str, options: &Value) -> String {
+            let client_data = json!({
+                "type": kind,
+                "challenge": options["challenge"],
+                "origin": self.origin,
+                "crossOrigin": false,
+            });
+            URL_SAFE_NO_PAD.encode(client_data.to_string())
+        }
+    }
+
+    fn cbor(value: &Cbor) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::SoftAuthenticator;
+    use super::*;
+
+    const ORIGIN: &str = "https://app.example.com";
+
+    fn webauthn() -> Webauthn {
+        Webauthn::new(RelyingParty {
+            id: "example.com".to_string(),
+            name: "Edge Hive".to_string(),
+            origins: vec![ORIGIN.to_string()],
+        })
+    }
+
+    fn register(webauthn: &Webauthn, authenticator: &mut SoftAuthenticator) -> Credential {
+        let challenge = Webauthn::challenge();
+        let options =
+            webauthn.registration_options(&challenge, &user_handle("ada"), "ada@example.com", &[]);
+        let response = authenticator.create(&options);
+        assert_eq!(client_challenge(&response.response.client_data_json).unwrap(), challenge);
+        webauthn.register(&challenge, &response).unwrap()
+    }
+
+    #[test]
+    fn test_register_and_authenticate() {
+        let webauthn = webauthn();
+        let mut authenticator = SoftAuthenticator::new("example.com", ORIGIN);
+        let mut credential = register(&webauthn, &mut authenticator);
+        assert_eq!(credential.id, authenticator.credential_id());
+        assert_eq!(credential.sign_count, 0);
+
+        for expected in 1..=2 {
+            let challenge = Webauthn::challenge();
+            let options = webauthn.authentication_options(&challenge, &[]);
+            let response = authenticator.get(&options);
+            credential.sign_count =
+                webauthn.authenticate(&challenge, &response, &credential).unwrap();
+            assert_eq!(credential.sign_count, expected);
+        }
+
+        // An answer to another challenge, or signed by another key, is refused
+        let options = webauthn.authentication_options(&Webauthn::challenge(), &[]);
+        let response = authenticator.get(&options);
+        let result = webauthn.authenticate(&Webauthn::challenge(), &response, &credential);
+        assert!(matches!(result, Err(AuthError::Webauthn(_))));
+        let other = register(&webauthn, &mut SoftAuthenticator::new("example.com", ORIGIN));
+        let forged = Credential {
+            id: credential.id.clone(),
+            ..other
+        };
+        let challenge = Webauthn::challenge();
+        let response = authenticator.get(&webauthn.authentication_options(&challenge, &[]));
+        assert!(webauthn.authenticate(&challenge, &response, &forged).is_err());
+    }
+
+    #[test]
+    fn test_cloned_authenticator_is_refused() {
+        let webauthn = webauthn();
+        let mut authenticator = SoftAuthenticator::new("example.com", ORIGIN);
+        let mut credential = register(&webauthn, &mut authenticator);
+        let mut clone = authenticator.clone();
+
+        let challenge = Webauthn::challenge();
+        let response = authenticator.get(&webauthn.authentication_options(&challenge, &[]));
+        credential.sign_count = webauthn.authenticate(&challenge, &response, &credential).unwrap();
+
+        let challenge = Webauthn::challenge();
+        let response = clone.get(&webauthn.authentication_options(&challenge, &[]));
+        assert!(webauthn.authenticate(&challenge, &response, &credential).is_err());
+    }
+
+    #[test]
+    fn test_foreign_origins_and_relying_parties() {
+        let webauthn = webauthn();
+        let challenge = Webauthn::challenge();
+        let options = webauthn.registration_options(&challenge, "ada", "ada", &[]);
+
+        let evil = "https://example.evil";
+        let phishing = SoftAuthenticator::new("example.com", evil).create(&options);
+        assert!(webauthn.register(&challenge, &phishing).is_err());
+        let other_rp = SoftAuthenticator::new("evil.com", ORIGIN).create(&options);
+        assert!(webauthn.register(&challenge, &other_rp).is_err());
+        let valid = SoftAuthenticator::new("example.com", ORIGIN).create(&options);
+        assert!(webauthn.register(&Webauthn::challenge(), &valid).is_err());
+        assert!(webauthn.register(&challenge, &valid).is_ok());
+    }
+}
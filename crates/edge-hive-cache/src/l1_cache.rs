@@ -43,28 +43,34 @@ impl L1Cache {
         self.cache.invalidate(key).await;
     }
 
-    /// Delete keys matching a pattern (basic implementation)
+    /// Delete keys matching a pattern (`prefix*`, `*suffix` or an exact key)
     pub async fn delete_pattern(&self, pattern: &str) -> u64 {
-        let mut count = 0u64;
-
-        // Simple pattern matching (prefix, suffix, contains)
-        if pattern.ends_with('*') {
-            // Prefix match: "user:*"
-            let _prefix = &pattern[..pattern.len() - 1];
-
-            // Note: moka doesn't expose keys directly, so we can't iterate
-            // This is a limitation. For production, consider using a separate index.
-            debug!("Pattern deletion not fully implemented for L1 cache: {}", pattern);
-        } else if pattern.starts_with('*') {
-            // Suffix match: "*:cached"
-            debug!("Pattern deletion not fully implemented for L1 cache: {}", pattern);
+        // Held across awaits, so the matcher has to be `Send`
+        type Matcher = Box<dyn Fn(&str) -> bool + Send + Sync>;
+        let matches: Matcher = if let Some(prefix) = pattern.strip_suffix('*') {
+            let prefix = prefix.to_string();
+            Box::new(move |key| key.starts_with(&prefix))
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            let suffix = suffix.to_string();
+            Box::new(move |key| key.ends_with(&suffix))
         } else {
-            // Exact match
             self.cache.invalidate(pattern).await;
-            count = 1;
+            return 1;
+        };
+
+        let keys: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(key, _)| matches(key.as_str()))
+            .map(|(key, _)| key)
+            .collect();
+
+        for key in &keys {
+            self.cache.invalidate(key.as_str()).await;
         }
 
-        count
+        debug!("Deleted {} keys matching pattern: {}", keys.len(), pattern);
+        keys.len() as u64
     }
 
     /// Clear all entries
@@ -136,4 +142,18 @@ mod tests {
         assert_eq!(cache.get("key1").await, None);
         assert_eq!(cache.get("key2").await, None);
     }
+
+    #[tokio::test]
+    async fn test_l1_delete_prefix_pattern() {
+        let cache = L1Cache::new(1000, 60);
+
+        cache.set("data:posts:query:a".to_string(), b"1".to_vec()).await;
+        cache.set("data:posts:query:b".to_string(), b"2".to_vec()).await;
+        cache.set("data:users:query:a".to_string(), b"3".to_vec()).await;
+
+        assert_eq!(cache.delete_pattern("data:posts:query*").await, 2);
+        assert_eq!(cache.get("data:posts:query:a").await, None);
+        assert_eq!(cache.get("data:posts:query:b").await, None);
+        assert_eq!(cache.get("data:users:query:a").await, Some(b"3".to_vec()));
+    }
 }
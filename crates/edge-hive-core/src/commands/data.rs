@@ -0,0 +1,200 @@
+//! Table export and import commands
+
+use crate::config::Config;
+use anyhow::{Context, Result};
+use clap::Args;
+use edge_hive_db::{
+    ColumnMap, DatabaseService, ExportOptions, ImportOptions, ImportReport, ListQuery, Table,
+    TransferFormat,
+};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct DataArgs {
+    #[command(subcommand)]
+    pub command: DataCommands,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DataCommands {
+    /// Export a table as CSV, NDJSON or Parquet
+    Export {
+        /// Table to export
+        table: String,
+
+        /// File to write (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// csv, ndjson or parquet (defaults to the output extension, then ndjson)
+        #[arg(short, long)]
+        format: Option<String>,
+
+        /// Fields to export and their column names, e.g. `title:headline,views`
+        #[arg(short, long)]
+        columns: Option<String>,
+
+        /// Filters and sorting as in the data API, e.g. `views=gt.10` or `order=views.desc`
+        #[arg(long = "where", value_name = "PARAM=VALUE")]
+        params: Vec<String>,
+    },
+
+    /// Import a CSV, NDJSON or Parquet file into a table
+    Import {
+        /// Table to import into
+        table: String,
+
+        /// File to read
+        input: PathBuf,
+
+        /// csv, ndjson or parquet (defaults to the input extension)
+        #[arg(short, long)]
+        format: Option<String>,
+
+        /// Columns to import and their field names, e.g. `headline:title,views`
+        #[arg(short, long)]
+        columns: Option<String>,
+
+        /// Field holding the record key; existing records are updated
+        #[arg(short, long)]
+        key: Option<String>,
+
+        /// Check every row without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Run the data command
+pub async fn run(args: DataArgs, data_dir: &Path) -> Result<()> {
+    let config = Config::load(&data_dir.join("config").to_string_lossy())?;
+    let db = DatabaseService::connect(config.database.to_db_config(data_dir)).await?;
+    super::db::unlock_encryption(&db, &config.database.encryption, data_dir).await?;
+
+    let result = match args.command {
+        DataCommands::Export {
+            table,
+            output,
+            format,
+            columns,
+            params,
+        } => run_export(&db, &table, output, format, columns, params).await,
+        DataCommands::Import {
+            table,
+            input,
+            format,
+            columns,
+            key,
+            dry_run,
+        } => run_import(&db, &table, &input, format, columns, key, dry_run).await,
+    };
+
+    db.shutdown().await?;
+    result
+}
+
+/// Format given on the command line, or else the one of the file extension
+fn transfer_format(format: Option<String>, path: Option<&Path>) -> Result<Option<TransferFormat>> {
+    let format = format.or_else(|| {
+        path.and_then(Path::extension)
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+    });
+    Ok(format.map(|format| format.parse()).transpose()?)
+}
+
+fn column_map(columns: Option<String>) -> Result<Option<ColumnMap>> {
+    Ok(columns.map(|columns| columns.parse()).transpose()?)
+}
+
+async fn run_export(
+    db: &DatabaseService,
+    table: &str,
+    output: Option<PathBuf>,
+    format: Option<String>,
+    columns: Option<String>,
+    params: Vec<String>,
+) -> Result<()> {
+    let params = params
+        .iter()
+        .map(|param| {
+            param
+                .split_once('=')
+                .with_context(|| format!("expected PARAM=VALUE, got '{}'", param))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let query = ListQuery::from_params(Table::new(table)?, params)?;
+    let options = ExportOptions {
+        format: transfer_format(format, output.as_deref())?.unwrap_or(TransferFormat::Ndjson),
+        columns: column_map(columns)?,
+    };
+
+    let writer: Box<dyn std::io::Write + Send> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+    let rows = db.export_table(query, options).write_to(db, writer).await?;
+
+    // Status goes to stderr so the export can be piped from stdout
+    match output {
+        Some(path) => eprintln!("📤 Exported {} rows of '{}' to {}", rows, table, path.display()),
+        None => eprintln!("📤 Exported {} rows of '{}'", rows, table),
+    }
+    Ok(())
+}
+
+async fn run_import(
+    db: &DatabaseService,
+    table: &str,
+    input: &Path,
+    format: Option<String>,
+    columns: Option<String>,
+    key: Option<String>,
+    dry_run: bool,
+) -> Result<()> {
+    let table = Table::new(table)?;
+    let format = transfer_format(format, Some(input))?
+        .context("cannot tell the file format, pass --format")?;
+    let options = ImportOptions {
+        format,
+        columns: column_map(columns)?,
+        key,
+        dry_run,
+    };
+
+    let reader = BufReader::new(File::open(input)?);
+    let report = db
+        .import_table(&table, reader, &options, |report| {
+            eprintln!(
+                "⏳ {} rows: {} created, {} updated, {} failed",
+                report.rows, report.created, report.updated, report.failed
+            );
+        })
+        .await?;
+
+    print_report(&table, &report);
+    Ok(())
+}
+
+fn print_report(table: &Table, report: &ImportReport) {
+    if report.dry_run {
+        println!("🔍 Dry run of the import into '{}' (nothing was written):", table);
+    } else {
+        println!("📥 Imported into '{}':", table);
+    }
+    println!("  Rows:    {}", report.rows);
+    println!("  Created: {}", report.created);
+    println!("  Updated: {}", report.updated);
+    println!("  Failed:  {}", report.failed);
+
+    for error in &report.errors {
+        println!("  ❌ row {}: {}", error.row, error.message);
+        for violation in &error.violations {
+            println!("       {}: {}", violation.path, violation.message);
+        }
+    }
+    if report.failed as usize > report.errors.len() {
+        println!("  … and {} more failed rows", report.failed as usize - report.errors.len());
+    }
+}
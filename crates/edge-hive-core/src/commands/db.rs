@@ -0,0 +1,381 @@
+//! Database management commands
+
+use crate::config::{Config, EncryptionConfig};
+use anyhow::Result;
+use clap::Args;
+use edge_hive_db::{BranchDiff, DatabaseService, DiffKind, SchemaChange, Table};
+use edge_hive_identity::NodeIdentity;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub command: DbCommands,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DbCommands {
+    /// Manage schema migrations
+    Migrate(MigrateCommand),
+
+    /// Write a dump of the whole database
+    Dump {
+        /// File to write (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Encrypt the dump to this node's identity
+        #[arg(long)]
+        encrypt: bool,
+    },
+
+    /// Replace the database with the contents of a dump
+    Restore {
+        /// Dump file; encrypted dumps are decrypted with this node's identity
+        input: PathBuf,
+    },
+
+    /// Add a data key for encrypted fields and re-encrypt every value with it
+    RotateKey,
+
+    /// Manage branches: copies of the database for preview environments
+    Branch(BranchCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct BranchCommand {
+    /// Branch the database of this project instead of the node's
+    #[arg(short, long, global = true)]
+    pub project: Option<String>,
+
+    #[command(subcommand)]
+    pub action: BranchAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum BranchAction {
+    /// Copy the database into a new branch
+    Create {
+        /// Branch name (a-z, 0-9 and '-')
+        name: String,
+    },
+
+    /// List the branches
+    List,
+
+    /// Show how the schema and record counts of a branch differ
+    Diff { name: String },
+
+    /// Apply the schema changes of a branch to the database
+    Merge {
+        name: String,
+
+        /// Also remove definitions missing on the branch (drops such tables)
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Drop a branch and its data
+    Delete { name: String },
+}
+
+#[derive(Args, Debug)]
+pub struct MigrateCommand {
+    #[command(subcommand)]
+    pub action: MigrateAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Show applied and pending migrations
+    Status,
+
+    /// Apply pending migrations
+    Up {
+        /// Stop after this version (defaults to the latest)
+        #[arg(long)]
+        to: Option<u32>,
+    },
+
+    /// Revert the most recently applied migrations
+    Down {
+        /// Number of migrations to revert
+        #[arg(short, long, default_value_t = 1)]
+        steps: u32,
+    },
+}
+
+/// Run the db command
+pub async fn run(args: DbArgs, data_dir: &Path) -> Result<()> {
+    let config = Config::load(&data_dir.join("config").to_string_lossy())?;
+    let db = DatabaseService::connect(config.database.to_db_config(data_dir)).await?;
+
+    let result = match args.command {
+        DbCommands::Migrate(cmd) => run_migrate(&db, cmd.action).await,
+        DbCommands::Dump { output, encrypt } => run_dump(&db, data_dir, output, encrypt).await,
+        DbCommands::Restore { input } => run_restore(&db, data_dir, &input).await,
+        DbCommands::RotateKey => run_rotate_key(&db, &config.database.encryption, data_dir).await,
+        DbCommands::Branch(cmd) => run_branch(&db, cmd).await,
+    };
+
+    db.shutdown().await?;
+    result
+}
+
+async fn run_migrate(db: &DatabaseService, action: MigrateAction) -> Result<()> {
+    match action {
+        MigrateAction::Status => {
+            let status = db.migration_status().await?;
+
+            println!("📐 Schema migrations:");
+            println!();
+            for migration in status {
+                let state = match (&migration.applied_at, migration.checksum_ok) {
+                    (Some(_), false) => "⚠️  modified",
+                    (Some(_), true) => "✅ applied",
+                    (None, _) => "⏳ pending",
+                };
+                let applied_at = migration
+                    .applied_at
+                    .map(|at| at.to_string())
+                    .unwrap_or_default();
+                println!(
+                    "  {:04}  {:<24} {:<12} {}",
+                    migration.version, migration.name, state, applied_at
+                );
+            }
+        }
+        MigrateAction::Up { to } => {
+            let applied = db.migrate_up(to).await?;
+            if applied.is_empty() {
+                println!("✅ Schema is up to date");
+            } else {
+                for version in applied {
+                    println!("⬆️  Applied migration {:04}", version);
+                }
+            }
+        }
+        MigrateAction::Down { steps } => {
+            let reverted = db.migrate_down(steps).await?;
+            if reverted.is_empty() {
+                println!("Nothing to revert");
+            } else {
+                for version in reverted {
+                    println!("⬇️  Reverted migration {:04}", version);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_branch(db: &DatabaseService, cmd: BranchCommand) -> Result<()> {
+    let project = match &cmd.project {
+        Some(name) => {
+            let project = db
+                .get_project(name)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No project named '{}'", name))?;
+            Some(db.open_project(&project).await?)
+        }
+        None => None,
+    };
+    let db = project.as_ref().unwrap_or(db);
+
+    match cmd.action {
+        BranchAction::Create { name } => {
+            let branch = db.create_branch(&name).await?;
+            println!(
+                "🌿 Created branch '{}' at schema version {}",
+                branch.name, branch.schema_version
+            );
+        }
+        BranchAction::List => {
+            let branches = db.branches().await?;
+            if branches.is_empty() {
+                println!("No branches");
+            }
+            for branch in branches {
+                let merged = branch
+                    .merged_at
+                    .map(|at| format!(", merged {}", at.format("%Y-%m-%d %H:%M")))
+                    .unwrap_or_default();
+                println!(
+                    "  {:<24} created {}{}",
+                    branch.name,
+                    branch.created_at.format("%Y-%m-%d %H:%M"),
+                    merged
+                );
+            }
+        }
+        BranchAction::Diff { name } => {
+            let diff = db
+                .diff_branch(&name)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No branch named '{}'", name))?;
+            print_diff(&diff);
+        }
+        BranchAction::Merge { name, remove } => {
+            let changes = db
+                .merge_branch(&name, remove)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No branch named '{}'", name))?;
+            for change in &changes {
+                println!("  {}", describe_change(change));
+            }
+            println!("🔀 Merged {} schema changes from '{}'", changes.len(), name);
+        }
+        BranchAction::Delete { name } => {
+            if db.delete_branch(&name).await? {
+                println!("🗑️  Deleted branch '{}'", name);
+            } else {
+                anyhow::bail!("No branch named '{}'", name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn describe_change(change: &SchemaChange) -> String {
+    let sign = match change.kind {
+        DiffKind::Added => "+",
+        DiffKind::Removed => "-",
+        DiffKind::Changed => "~",
+    };
+    let item = serde_json::to_value(change.item)
+        .ok()
+        .and_then(|item| item.as_str().map(str::to_string))
+        .unwrap_or_default();
+    match &change.table {
+        Some(table) => format!("{} {} {} on {}", sign, item, change.name, table),
+        None => format!("{} {} {}", sign, item, change.name),
+    }
+}
+
+fn print_diff(diff: &BranchDiff) {
+    println!("🌿 Branch '{}':", diff.branch);
+    if diff.base_version != diff.branch_version {
+        println!(
+            "  ⚠️  schema version {} on the branch, {} on the base",
+            diff.branch_version, diff.base_version
+        );
+    }
+    if diff.schema.is_empty() {
+        println!("  Schema: no changes");
+    } else {
+        println!("  Schema:");
+        for change in &diff.schema {
+            println!("    {}", describe_change(change));
+            if let Some(definition) = &change.branch {
+                println!("        {}", definition);
+            }
+        }
+    }
+    if !diff.rows.is_empty() {
+        println!("  Records (base → branch):");
+        for count in &diff.rows {
+            println!("    {:<24} {} → {}", count.table, count.base, count.branch);
+        }
+    }
+}
+
+fn load_identity(data_dir: &Path) -> Result<NodeIdentity> {
+    let identity_path = data_dir.join("identity.key");
+    if !identity_path.exists() {
+        anyhow::bail!("No identity at {}. Run 'edge-hive init' first.", identity_path.display());
+    }
+    Ok(NodeIdentity::load(&identity_path, None)?)
+}
+
+/// Mark the configured encrypted fields and unlock their data keys
+///
+/// Returns `false` when no field is encrypted.
+pub(crate) async fn unlock_encryption(
+    db: &DatabaseService,
+    config: &EncryptionConfig,
+    data_dir: &Path,
+) -> Result<bool> {
+    if config.fields.is_empty() {
+        return Ok(false);
+    }
+    for (table, fields) in &config.fields {
+        db.encrypt_fields(&Table::new(table.as_str())?, fields)?;
+    }
+
+    let identity = load_identity(data_dir)?.age_identity()?;
+    let recipients = config
+        .recipients
+        .iter()
+        .map(|r| edge_hive_db::encryption::parse_recipient(r))
+        .collect::<Result<Vec<_>, _>>()?;
+    db.unlock_keys(&identity, &recipients).await?;
+    Ok(true)
+}
+
+async fn run_rotate_key(
+    db: &DatabaseService,
+    config: &EncryptionConfig,
+    data_dir: &Path,
+) -> Result<()> {
+    if !unlock_encryption(db, config, data_dir).await? {
+        anyhow::bail!("No encrypted fields configured ([database.encryption.fields])");
+    }
+
+    let version = db.rotate_key().await?;
+    println!("🔑 Added data key {}", version);
+    let rewritten = db.reencrypt().await?;
+    println!("✅ Re-encrypted {} records", rewritten);
+    Ok(())
+}
+
+async fn run_dump(
+    db: &DatabaseService,
+    data_dir: &Path,
+    output: Option<PathBuf>,
+    encrypt: bool,
+) -> Result<()> {
+    let writer: Box<dyn std::io::Write + Send> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+
+    let header = if encrypt {
+        let recipient = load_identity(data_dir)?.age_recipient()?;
+        db.export_encrypted(writer, &recipient).await?
+    } else {
+        db.export(writer).await?
+    };
+
+    // Status goes to stderr so the dump can be piped from stdout
+    if let Some(path) = output {
+        eprintln!(
+            "💾 Dumped schema version {} to {}{}",
+            header.schema_version,
+            path.display(),
+            if encrypt { " (encrypted)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+async fn run_restore(db: &DatabaseService, data_dir: &Path, input: &Path) -> Result<()> {
+    let mut dump = Vec::new();
+    File::open(input)?.read_to_end(&mut dump)?;
+
+    let header = if edge_hive_db::dump::is_encrypted(&dump) {
+        let identity = load_identity(data_dir)?.age_identity()?;
+        db.import_encrypted(dump.as_slice(), &identity).await?
+    } else {
+        db.import(dump.as_slice()).await?
+    };
+
+    println!(
+        "✅ Restored dump from {} (schema version {})",
+        header.created_at, header.schema_version
+    );
+    Ok(())
+}
@@ -0,0 +1,263 @@
+//! Background job commands
+//!
+//! Jobs are only queued here; the worker of a running server runs them.
+
+use crate::config::Config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use edge_hive_db::{DatabaseService, Job, JobQuery, JobStatus, NewJob, NewJobSchedule};
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Args, Debug)]
+pub struct JobsArgs {
+    /// Manage the jobs of this project instead of the node's
+    #[arg(short, long, global = true)]
+    pub project: Option<String>,
+
+    #[command(subcommand)]
+    pub command: JobsCommands,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum JobsCommands {
+    /// List jobs, newest first
+    List {
+        /// queued, running, succeeded or dead
+        #[arg(short, long)]
+        status: Option<String>,
+
+        #[arg(long)]
+        handler: Option<String>,
+
+        #[arg(short, long, default_value_t = 20)]
+        limit: u64,
+    },
+
+    /// Queue a job
+    Enqueue {
+        /// `query`, `function:<edge function>` or a handler of the server
+        handler: String,
+
+        /// JSON payload
+        #[arg(long)]
+        payload: Option<String>,
+
+        /// Higher runs first
+        #[arg(long, default_value_t = 0)]
+        priority: i64,
+
+        /// When the job is due (RFC 3339, defaults to now)
+        #[arg(long)]
+        run_at: Option<DateTime<Utc>>,
+
+        /// Attempts before the job is dead-lettered
+        #[arg(long)]
+        max_attempts: Option<u32>,
+    },
+
+    /// Show a job
+    Show { id: String },
+
+    /// Run a queued or dead-lettered job right away
+    Retry { id: String },
+
+    /// Remove a job that is not running
+    Delete { id: String },
+
+    /// Manage cron schedules
+    Schedule(ScheduleArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ScheduleArgs {
+    #[command(subcommand)]
+    pub command: ScheduleCommands,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ScheduleCommands {
+    /// Queue a job whenever a cron expression fires
+    Add {
+        name: String,
+
+        /// Cron expression, e.g. "0 3 * * *" (minute hour day month weekday)
+        cron: String,
+
+        handler: String,
+
+        /// JSON payload
+        #[arg(long)]
+        payload: Option<String>,
+
+        #[arg(long, default_value_t = 0)]
+        priority: i64,
+
+        #[arg(long)]
+        max_attempts: Option<u32>,
+    },
+
+    /// List the schedules
+    List,
+
+    /// Remove a schedule
+    Remove { name: String },
+}
+
+/// Run the jobs command
+pub async fn run(args: JobsArgs, data_dir: &Path) -> Result<()> {
+    let config = Config::load(&data_dir.join("config").to_string_lossy())?;
+    let db = DatabaseService::connect(config.database.to_db_config(data_dir)).await?;
+
+    let result = run_on(&db, args).await;
+
+    db.shutdown().await?;
+    result
+}
+
+async fn run_on(db: &DatabaseService, args: JobsArgs) -> Result<()> {
+    let project = match &args.project {
+        Some(name) => {
+            let project = db
+                .get_project(name)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No project named '{}'", name))?;
+            Some(db.open_project(&project).await?)
+        }
+        None => None,
+    };
+    let db = project.as_ref().unwrap_or(db);
+
+    match args.command {
+        JobsCommands::List {
+            status,
+            handler,
+            limit,
+        } => {
+            let query = JobQuery {
+                status: status.map(|status| status.parse::<JobStatus>()).transpose()?,
+                handler,
+                limit,
+            };
+            let jobs = db.jobs(&query).await?;
+            if jobs.is_empty() {
+                println!("No jobs");
+            }
+            for job in &jobs {
+                println!(
+                    "  {:<22} {:<24} {:<10} attempts {}  due {}",
+                    job.id,
+                    job.handler,
+                    job.status.as_str(),
+                    job.attempts,
+                    job.run_at.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+        }
+        JobsCommands::Enqueue {
+            handler,
+            payload,
+            priority,
+            run_at,
+            max_attempts,
+        } => {
+            let job = NewJob {
+                handler,
+                payload: parse_payload(payload)?,
+                priority,
+                run_at,
+                max_attempts,
+            };
+            let job = db.enqueue_job(job).await?;
+            println!("📥 Queued job {} ({})", job.id, job.handler);
+        }
+        JobsCommands::Show { id } => {
+            let job = db
+                .job(&id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No job with ID '{}'", id))?;
+            print_job(&job)?;
+        }
+        JobsCommands::Retry { id } => {
+            let job = db
+                .retry_job(&id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("No queued or dead job with ID '{}'", id))?;
+            println!("🔁 Job {} will run again", job.id);
+        }
+        JobsCommands::Delete { id } => {
+            if !db.delete_job(&id).await? {
+                anyhow::bail!("No job with ID '{}'", id);
+            }
+            println!("🗑️  Deleted job {}", id);
+        }
+        JobsCommands::Schedule(schedule) => run_schedule(db, schedule.command).await?,
+    }
+
+    Ok(())
+}
+
+async fn run_schedule(db: &DatabaseService, command: ScheduleCommands) -> Result<()> {
+    match command {
+        ScheduleCommands::Add {
+            name,
+            cron,
+            handler,
+            payload,
+            priority,
+            max_attempts,
+        } => {
+            let schedule = NewJobSchedule {
+                name,
+                cron,
+                handler,
+                payload: parse_payload(payload)?,
+                priority,
+                max_attempts,
+            };
+            let schedule = db.create_job_schedule(schedule).await?;
+            println!(
+                "⏰ Added schedule '{}', next run at {}",
+                schedule.name,
+                schedule.next_run_at.format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+        ScheduleCommands::List => {
+            let schedules = db.job_schedules().await?;
+            if schedules.is_empty() {
+                println!("No schedules");
+            }
+            for schedule in schedules {
+                let next = if schedule.active {
+                    schedule.next_run_at.format("%Y-%m-%d %H:%M:%S").to_string()
+                } else {
+                    "never".to_string()
+                };
+                println!(
+                    "  {:<20} {:<16} {:<24} next {}",
+                    schedule.name, schedule.cron, schedule.handler, next
+                );
+            }
+        }
+        ScheduleCommands::Remove { name } => {
+            if !db.delete_job_schedule(&name).await? {
+                anyhow::bail!("No schedule named '{}'", name);
+            }
+            println!("🗑️  Removed schedule '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn parse_payload(payload: Option<String>) -> Result<Value> {
+    match payload {
+        Some(payload) => serde_json::from_str(&payload).context("Payload is not valid JSON"),
+        None => Ok(Value::Null),
+    }
+}
+
+fn print_job(job: &Job) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(job)?);
+    Ok(())
+}
@@ -9,3 +9,6 @@ pub mod plugin;
 pub mod mcp;
 pub mod auth;
 pub mod cloud;
+pub mod db;
+pub mod data;
+pub mod jobs;
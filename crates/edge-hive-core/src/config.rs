@@ -1,7 +1,16 @@
 //! Configuration module for Edge Hive
 
+use edge_hive_api::jobs::JobOptions;
+use edge_hive_api::mail::{MailError, MailLinks, Mailer, SmtpSecurity, SmtpSettings};
+use edge_hive_auth::{OidcProvider, RelyingParty};
+use edge_hive_db::filter::Field;
+use edge_hive_db::{
+    DbConfig, RetentionRule, RetryPolicy, StorageEngine, TablePolicy, VectorField,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Main configuration structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +25,38 @@ pub struct Config {
     pub tunnel: TunnelConfig,
     /// Database configuration
     pub database: DatabaseConfig,
+    /// Outbound webhook delivery
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    /// Background job worker
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    /// Outgoing email of the auth flows
+    #[serde(default)]
+    pub mail: MailConfig,
+    /// Identity providers users can log in with, keyed by name
+    ///
+    /// ```toml
+    /// [oidc.google]
+    /// client_id = "..."
+    /// client_secret = "..."
+    /// authorization_endpoint = "https://accounts.google.com/o/oauth2/v2/auth"
+    /// token_endpoint = "https://oauth2.googleapis.com/token"
+    /// userinfo_endpoint = "https://openidconnect.googleapis.com/v1/userinfo"
+    /// redirect_uri = "https://example.com/api/v1/auth/oauth/google/callback"
+    /// ```
+    #[serde(default)]
+    pub oidc: HashMap<String, OidcProvider>,
+    /// Site users register passkeys with (passkeys are disabled without it)
+    ///
+    /// ```toml
+    /// [webauthn]
+    /// id = "example.com"
+    /// name = "Example"
+    /// origins = ["https://example.com"]
+    /// ```
+    #[serde(default)]
+    pub webauthn: Option<RelyingParty>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,12 +97,311 @@ pub struct TunnelConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
-    /// Database path
+    /// Storage engine: "rocksdb" (default, needs the `rocksdb` feature) or
+    /// "memory" (tests only)
+    #[serde(default)]
+    pub engine: StorageEngine,
+    /// Database path (relative paths are resolved against the data directory)
     pub path: PathBuf,
     /// Database namespace
     pub namespace: String,
     /// Database name
     pub database: String,
+    /// Row-level policies of the data API, keyed by table
+    ///
+    /// ```toml
+    /// [database.policies.posts]
+    /// select = "published = true OR owner = $identity.sub"
+    /// create = "'posts:write' IN $identity.scopes"
+    /// ```
+    #[serde(default)]
+    pub policies: HashMap<String, TablePolicy>,
+    /// Full-text searchable fields of the data API, keyed by table
+    ///
+    /// ```toml
+    /// [database.search]
+    /// posts = ["title", "body"]
+    /// ```
+    #[serde(default)]
+    pub search: HashMap<String, Vec<Field>>,
+    /// Vector fields of the data API, keyed by table
+    ///
+    /// ```toml
+    /// [[database.vectors.chunks]]
+    /// field = "embedding"
+    /// dimension = 384
+    /// index = "hnsw"       # or "mtree"
+    /// distance = "cosine"  # or "euclidean", "manhattan"
+    /// ```
+    #[serde(default)]
+    pub vectors: HashMap<String, Vec<VectorField>>,
+    /// Tables whose writes are recorded in the change log
+    ///
+    /// Tables with a webhook are captured anyway.
+    #[serde(default)]
+    pub changes: Vec<String>,
+    /// Tables keeping the history of their records (soft deletes, `as_of` reads)
+    #[serde(default)]
+    pub versioned: Vec<String>,
+    /// Retention rules, keyed by table (expired sessions are always purged)
+    ///
+    /// ```toml
+    /// [database.retention.metrics]
+    /// field = "recorded_at"
+    /// max_age = "30d"
+    /// max_rows = 1000000
+    /// ```
+    #[serde(default)]
+    pub retention: HashMap<String, RetentionRule>,
+    /// How often the retention rules are applied
+    #[serde(default = "default_retention_interval_secs")]
+    pub retention_interval_secs: u64,
+    /// Fields encrypted at rest
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Encrypted fields, keyed by table (of `users`, only `name`; of `_mfa`, `secret`)
+    ///
+    /// ```toml
+    /// [database.encryption.fields]
+    /// patients = ["ssn", "notes.body"]
+    /// ```
+    #[serde(default)]
+    pub fields: HashMap<String, Vec<Field>>,
+    /// age recipients the data keys are wrapped to besides the node identity,
+    /// e.g. an offline backup key
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+fn default_retention_interval_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Attempts before a delivery is dead-lettered
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt, doubled after each further one
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Longest delay between two attempts
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: u64,
+    /// How often due deliveries are looked for
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Timeout of one delivery request
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    8
+}
+
+fn default_base_delay_ms() -> u64 {
+    5_000
+}
+
+fn default_max_delay_secs() -> u64 {
+    3600
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_secs: default_max_delay_secs(),
+            poll_interval_ms: default_poll_interval_ms(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+impl WebhookConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: Duration::from_millis(self.base_delay_ms),
+            max_delay: Duration::from_secs(self.max_delay_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobsConfig {
+    /// Attempts before a job without its own limit is dead-lettered
+    #[serde(default = "default_job_attempts")]
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt, doubled after each further one
+    #[serde(default = "default_job_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Longest delay between two attempts
+    #[serde(default = "default_max_delay_secs")]
+    pub max_delay_secs: u64,
+    /// How long a job may run before it is considered lost and run again
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+    /// How often due jobs are looked for
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Jobs run concurrently by one worker
+    #[serde(default = "default_job_batch_size")]
+    pub batch_size: u64,
+}
+
+fn default_job_attempts() -> u32 {
+    5
+}
+
+fn default_job_base_delay_ms() -> u64 {
+    10_000
+}
+
+fn default_lease_secs() -> u64 {
+    300
+}
+
+fn default_job_batch_size() -> u64 {
+    10
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_job_attempts(),
+            base_delay_ms: default_job_base_delay_ms(),
+            max_delay_secs: default_max_delay_secs(),
+            lease_secs: default_lease_secs(),
+            poll_interval_ms: default_poll_interval_ms(),
+            batch_size: default_job_batch_size(),
+        }
+    }
+}
+
+impl JobsConfig {
+    pub fn options(&self) -> JobOptions {
+        JobOptions {
+            retry: RetryPolicy {
+                max_attempts: self.max_attempts.max(1),
+                base_delay: Duration::from_millis(self.base_delay_ms),
+                max_delay: Duration::from_secs(self.max_delay_secs),
+            },
+            lease: Duration::from_secs(self.lease_secs.max(1)),
+            poll_interval: Duration::from_millis(self.poll_interval_ms.max(1)),
+            batch_size: self.batch_size.max(1),
+        }
+    }
+}
+
+/// SMTP relay of verification and password reset messages
+///
+/// ```toml
+/// [mail]
+/// enabled = true
+/// host = "smtp.example.com"
+/// port = 587
+/// security = "starttls"
+/// username = "edge-hive"
+/// password = "..."
+/// from = "Edge Hive <noreply@example.com>"
+/// reset_password_url = "https://app.example.com/reset-password"
+/// ```
+///
+/// Templates in `<data dir>/mail-templates/` replace the built-in ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// Without it, users register without a verification message
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// `tls`, `starttls` or `none`
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    /// Target of verification links, the API's `verify-email` endpoint
+    #[serde(default = "default_verify_email_url")]
+    pub verify_email_url: String,
+    /// Page where users choose a new password, posting to `reset-password`
+    #[serde(default = "default_reset_password_url")]
+    pub reset_password_url: String,
+}
+
+fn default_smtp_port() -> u16 {
+    465
+}
+
+fn default_mail_from() -> String {
+    "Edge Hive <noreply@localhost>".to_string()
+}
+
+fn default_verify_email_url() -> String {
+    MailLinks::default().verify_email
+}
+
+fn default_reset_password_url() -> String {
+    MailLinks::default().reset_password
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: String::new(),
+            port: default_smtp_port(),
+            security: SmtpSecurity::default(),
+            username: None,
+            password: None,
+            from: default_mail_from(),
+            verify_email_url: default_verify_email_url(),
+            reset_password_url: default_reset_password_url(),
+        }
+    }
+}
+
+impl MailConfig {
+    /// Mailer of the node, `None` when email is disabled
+    pub fn mailer(&self, data_dir: &Path) -> Result<Option<Mailer>, MailError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let settings = SmtpSettings {
+            host: self.host.clone(),
+            port: self.port,
+            security: self.security,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            from: self.from.clone(),
+        };
+        let links = MailLinks {
+            verify_email: self.verify_email_url.clone(),
+            reset_password: self.reset_password_url.clone(),
+        };
+        Mailer::new(&settings)?
+            .with_links(links)
+            .with_templates(&data_dir.join("mail-templates"))
+            .map(Some)
+    }
 }
 
 impl Default for Config {
@@ -86,10 +426,42 @@ impl Default for Config {
                 cf_token: None,
             },
             database: DatabaseConfig {
-                path: PathBuf::from("~/.edge-hive/data"),
+                engine: StorageEngine::default(),
+                path: PathBuf::from("edge-hive.db"),
                 namespace: "edge_hive".into(),
                 database: "main".into(),
+                policies: HashMap::new(),
+                search: HashMap::new(),
+                vectors: HashMap::new(),
+                changes: vec![],
+                versioned: vec![],
+                retention: HashMap::new(),
+                retention_interval_secs: default_retention_interval_secs(),
+                encryption: EncryptionConfig::default(),
             },
+            webhooks: WebhookConfig::default(),
+            jobs: JobsConfig::default(),
+            mail: MailConfig::default(),
+            oidc: HashMap::new(),
+            webauthn: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Build the storage options for `edge_hive_db::DatabaseService::open`
+    pub fn to_db_config(&self, data_dir: &Path) -> DbConfig {
+        let path = if self.path.is_absolute() {
+            self.path.clone()
+        } else {
+            data_dir.join(&self.path)
+        };
+
+        DbConfig {
+            engine: self.engine,
+            path,
+            namespace: self.namespace.clone(),
+            database: self.database.clone(),
         }
     }
 }
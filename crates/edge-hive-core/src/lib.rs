@@ -1,4 +1,6 @@
 pub mod server;
 pub mod auth;
+pub mod config;
 pub mod tls;
 pub mod commands;
+pub mod webhooks;
@@ -7,6 +7,7 @@ mod config;
 mod auth;
 mod server;
 mod tls;
+mod webhooks;
 pub mod commands {
     pub mod init;
     pub mod serve;
@@ -18,6 +19,9 @@ pub mod commands {
     pub mod mcp; // Added MCP module
     pub mod auth; // OAuth2 client management
     pub mod cloud;
+    pub mod db;
+    pub mod data;
+    pub mod jobs;
 }
 
 #[derive(Parser, Debug)]
@@ -66,6 +70,15 @@ enum Commands {
 
     /// Manage cloud nodes
     Cloud(commands::cloud::CloudArgs),
+
+    /// Manage the node database
+    Db(commands::db::DbArgs),
+
+    /// Export and import tables
+    Data(commands::data::DataArgs),
+
+    /// Queue and inspect background jobs
+    Jobs(commands::jobs::JobsArgs),
 }
 
 #[tokio::main]
@@ -107,6 +120,9 @@ async fn main() -> anyhow::Result<()> {
         Commands::Auth(a) => commands::auth::run(a, &data_dir).await?, // OAuth2 management
         Commands::Ping(a) => commands::ping::run(a).await?,
         Commands::Cloud(a) => commands::cloud::handle_cloud_command(a).await?,
+        Commands::Db(a) => commands::db::run(a, &data_dir).await?,
+        Commands::Data(a) => commands::data::run(a, &data_dir).await?,
+        Commands::Jobs(a) => commands::jobs::run(a, &data_dir).await?,
     }
 
     Ok(())
@@ -477,11 +477,104 @@ pub async fn run(
 
     // API gateway components (shared DB + cache + realtime)
     let cache = edge_hive_cache::CacheService::new(edge_hive_cache::CacheConfig::default()).await;
-    let db_path = data_dir.join("edge-hive.db");
-    let db = Arc::new(edge_hive_db::DatabaseService::new(&db_path).await?);
+    let node_config = crate::config::Config::load(&data_dir.join("config").to_string_lossy())?;
+    let db_config = node_config.database.to_db_config(&data_dir);
+    let db = Arc::new(edge_hive_db::DatabaseService::open(db_config).await?);
+    let db_owner = db.clone();
     let realtime = edge_hive_realtime::RealtimeServer::new(edge_hive_realtime::RealtimeServerConfig::default())
         .with_db(db.clone());
-    let api_state = edge_hive_api::ApiState::new(cache, db, realtime, data_dir.clone());
+    let policies = edge_hive_db::PolicySet::new(node_config.database.policies.clone())?;
+    for (table, fields) in &node_config.database.search {
+        db.define_search(&edge_hive_db::Table::new(table.as_str())?, fields).await?;
+    }
+    for (table, fields) in &node_config.database.vectors {
+        db.define_vectors(&edge_hive_db::Table::new(table.as_str())?, fields).await?;
+    }
+    for table in &node_config.database.changes {
+        db.capture_changes(&edge_hive_db::Table::new(table.as_str())?).await?;
+    }
+    for table in &node_config.database.versioned {
+        db.enable_versioning(&edge_hive_db::Table::new(table.as_str())?).await?;
+    }
+    let encryption = &node_config.database.encryption;
+    if crate::commands::db::unlock_encryption(&db, encryption, &data_dir).await? {
+        // Finish an interrupted rotation and encrypt values written before their field was marked
+        let db = db.clone();
+        tokio::spawn(async move {
+            match db.reencrypt().await {
+                Ok(0) => {}
+                Ok(rewritten) => tracing::info!("Re-encrypted {} records", rewritten),
+                Err(e) => tracing::warn!("Re-encryption failed: {}", e),
+            }
+        });
+    }
+    let dispatcher = crate::webhooks::WebhookDispatcher::new(db.clone(), &node_config.webhooks)?;
+    let retention_interval =
+        std::time::Duration::from_secs(node_config.database.retention_interval_secs.max(1));
+    let janitor = edge_hive_db::Janitor::new(
+        db.clone(),
+        &node_config.database.retention,
+        retention_interval,
+    )?;
+    // Projects capture the same changes and run a dispatcher and janitor of their own
+    let project_tasks: edge_hive_api::projects::ProjectTasks = {
+        let dispatcher = dispatcher.with_db(db.clone());
+        let changes = node_config.database.changes.clone();
+        let retention = node_config.database.retention.clone();
+        Arc::new(move |project: &edge_hive_api::ApiState| {
+            let db = project.db.clone();
+            let janitor = edge_hive_db::Janitor::new(db.clone(), &retention, retention_interval)
+                .map(|janitor| project.clear_purged(janitor));
+            let dispatcher = dispatcher.with_db(db.clone());
+            let changes = changes.clone();
+            Box::pin(async move {
+                for table in &changes {
+                    db.capture_changes(&edge_hive_db::Table::new(table.as_str())?).await?;
+                }
+                Ok(vec![tokio::spawn(dispatcher.run()), tokio::spawn(janitor?.run())])
+            })
+        })
+    };
+    let dispatcher = tokio::spawn(dispatcher.run());
+    let api_state = edge_hive_api::ApiState::new(cache, db, realtime, data_dir.clone())
+        .with_policies(policies);
+    let janitor = tokio::spawn(api_state.clear_purged(janitor).run());
+    let identity_path = data_dir.join("identity.key");
+    let api_state = match edge_hive_identity::NodeIdentity::load(&identity_path, None) {
+        Ok(identity) => api_state.with_identity(identity),
+        Err(e) => {
+            tracing::warn!("Node identity unavailable, encrypted dumps disabled: {}", e);
+            api_state
+        }
+    };
+    let api_state = api_state
+        .with_jobs(node_config.jobs.options())
+        .with_project_tasks(project_tasks);
+    let api_state = match node_config.mail.mailer(&data_dir)? {
+        Some(mailer) => {
+            info!("📧 Sending email through {}", node_config.mail.host);
+            api_state.with_mailer(mailer)
+        }
+        None => api_state,
+    };
+    let api_state = if node_config.oidc.is_empty() {
+        api_state
+    } else {
+        let oidc = edge_hive_auth::OidcClient::new(node_config.oidc.clone());
+        info!("🔑 Login with identity providers: {}", oidc.providers().join(", "));
+        api_state.with_oidc(oidc)
+    };
+    let api_state = match &node_config.webauthn {
+        Some(rp) => {
+            info!("🔑 Passkey login for {}", rp.id);
+            api_state.with_webauthn(edge_hive_auth::Webauthn::new(rp.clone()))
+        }
+        None => api_state,
+    };
+    let jobs = edge_hive_api::jobs::JobWorker::new(&api_state, node_config.jobs.options());
+    let jobs = jobs.spawn();
+    let projects = edge_hive_api::projects::ProjectRegistry::load(&api_state).await?;
+    let api_state = api_state.with_projects(projects).with_branches();
     let api_router = edge_hive_api::create_router(api_state);
 
     // MINIMAL TEST - Remove api_router temporarily to isolate issue
@@ -521,7 +614,15 @@ pub async fn run(
         info!("🔐 OAuth2 token endpoint: https://{}/mcp/auth/token", display_addr);
         info!("⚠️  Using self-signed certificate (for testing only)");
 
+        let handle = axum_server::Handle::new();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            shutdown_handle.graceful_shutdown(Some(Duration::from_secs(10)));
+        });
+
         axum_server::bind_rustls(addr, tls_config)
+            .handle(handle)
             .serve(app.into_make_service())
             .await?;
     } else {
@@ -531,8 +632,31 @@ pub async fn run(
         info!("💡 Tip: Use --https for HTTPS/TLS support");
 
         let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, app).await?;
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+    }
+
+    // Release every handle on the database so the engine can flush and close cleanly
+    drop(api_router);
+    dispatcher.abort();
+    let _ = dispatcher.await;
+    janitor.abort();
+    let _ = janitor.await;
+    jobs.stop().await;
+    match Arc::try_unwrap(db_owner) {
+        Ok(db) => db.shutdown().await?,
+        Err(_) => tracing::warn!("Database still in use at shutdown, skipping clean close"),
     }
 
     Ok(())
 }
+
+/// Resolve when the process receives Ctrl+C
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::warn!("Failed to listen for shutdown signal: {}", e);
+        std::future::pending::<()>().await;
+    }
+    info!("🛑 Shutdown signal received, stopping server...");
+}
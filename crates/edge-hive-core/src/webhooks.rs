@@ -0,0 +1,267 @@
+//! Delivery of outbound webhooks
+//!
+//! The dispatcher polls the database: it fans new changes out to the
+//! webhooks subscribed to them, then POSTs every due delivery. Each request
+//! is signed so receivers can check it came from this node:
+//!
+//! ```text
+//! X-Edge-Hive-Timestamp: 1700000000
+//! X-Edge-Hive-Signature: sha256=<hex HMAC-SHA256 of "{timestamp}.{body}">
+//! ```
+//!
+//! keyed by the webhook secret. Any 2xx response counts as delivered.
+
+use crate::config::WebhookConfig;
+use edge_hive_db::{DatabaseService, DbError, DueDelivery, RetryPolicy};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+pub const SIGNATURE_HEADER: &str = "X-Edge-Hive-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Edge-Hive-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-Edge-Hive-Delivery";
+pub const EVENT_HEADER: &str = "X-Edge-Hive-Event";
+
+/// Changes fanned out and deliveries sent per poll
+const BATCH_SIZE: u64 = 100;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Value of the signature header for a delivery body
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(mac(secret, timestamp, body).finalize().into_bytes()))
+}
+
+/// Check a signature header value in constant time
+pub fn verify(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature.strip_prefix("sha256=").and_then(|h| hex::decode(h).ok()) else {
+        return false;
+    };
+    mac(secret, timestamp, body).verify_slice(&digest).is_ok()
+}
+
+/// Background task sending webhook deliveries
+pub struct WebhookDispatcher {
+    db: Arc<DatabaseService>,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+    poll_interval: Duration,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: Arc<DatabaseService>, config: &WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+
+        Ok(Self {
+            db,
+            client,
+            policy: config.retry_policy(),
+            poll_interval: Duration::from_millis(config.poll_interval_ms),
+        })
+    }
+
+    /// Dispatcher sending the deliveries of another database the same way
+    pub fn with_db(&self, db: Arc<DatabaseService>) -> Self {
+        Self {
+            db,
+            client: self.client.clone(),
+            policy: self.policy,
+            poll_interval: self.poll_interval,
+        }
+    }
+
+    /// Fan out new changes and attempt every due delivery once
+    ///
+    /// Returns the number of deliveries attempted.
+    pub async fn tick(&self) -> Result<usize, DbError> {
+        self.db.fan_out_changes(BATCH_SIZE).await?;
+
+        let due = self.db.due_deliveries(BATCH_SIZE).await?;
+        for delivery in &due {
+            match self.send(delivery).await {
+                Ok(()) => self.db.record_delivery_success(&delivery.delivery.id).await?,
+                Err(error) => {
+                    let status = self
+                        .db
+                        .record_delivery_failure(&delivery.delivery, &error, &self.policy)
+                        .await?;
+                    debug!(
+                        "Webhook delivery {} failed ({}), now {}",
+                        delivery.delivery.id,
+                        error,
+                        status.as_str()
+                    );
+                }
+            }
+        }
+        Ok(due.len())
+    }
+
+    async fn send(&self, delivery: &DueDelivery) -> Result<(), String> {
+        let body = serde_json::to_vec(&delivery.payload()).map_err(|e| e.to_string())?;
+        let timestamp = chrono::Utc::now().timestamp();
+
+        let response = self
+            .client
+            .post(&delivery.webhook.url)
+            .header("Content-Type", "application/json")
+            .header(SIGNATURE_HEADER, sign(&delivery.webhook.secret, timestamp, &body))
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(DELIVERY_HEADER, delivery.delivery.id.as_str())
+            .header(EVENT_HEADER, delivery.event())
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("HTTP {}", response.status()))
+        }
+    }
+
+    /// Poll until the task is dropped
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.tick().await {
+                warn!("Webhook dispatch failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+    use edge_hive_db::{DeliveryQuery, DeliveryStatus, NewWebhook, Table};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Clone)]
+    struct Receiver {
+        status: StatusCode,
+        received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        receiver.received.lock().unwrap().push((headers, body));
+        receiver.status
+    }
+
+    /// Start a local receiver answering `status`; returns its URL
+    async fn start_receiver(status: StatusCode) -> (String, Receiver) {
+        let receiver = Receiver {
+            status,
+            received: Arc::new(Mutex::new(Vec::new())),
+        };
+        let app = axum::Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, receiver)
+    }
+
+    async fn setup(
+        url: String,
+        config: &WebhookConfig,
+    ) -> (Arc<DatabaseService>, WebhookDispatcher) {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        db.create_webhook(NewWebhook {
+            table: Table::new("posts").unwrap(),
+            actions: vec![],
+            url,
+            secret: Some("s3cret".to_string()),
+        })
+        .await
+        .unwrap();
+        let dispatcher = WebhookDispatcher::new(db.clone(), config).unwrap();
+        (db, dispatcher)
+    }
+
+    #[test]
+    fn test_signature_roundtrip() {
+        let signature = sign("key", 1700000000, b"{}");
+        assert!(verify("key", 1700000000, b"{}", &signature));
+        assert!(!verify("key", 1700000001, b"{}", &signature));
+        assert!(!verify("other", 1700000000, b"{}", &signature));
+        assert!(!verify("key", 1700000000, b"{}", "sha256=zz"));
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_signed() {
+        let (url, receiver) = start_receiver(StatusCode::NO_CONTENT).await;
+        let (db, dispatcher) = setup(url, &WebhookConfig::default()).await;
+
+        let table = Table::new("posts").unwrap();
+        db.create_record(&table, json!({"title": "hello"})).await.unwrap();
+        assert_eq!(dispatcher.tick().await.unwrap(), 1);
+        assert_eq!(dispatcher.tick().await.unwrap(), 0);
+
+        let received = receiver.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        assert!(verify("s3cret", timestamp, body, signature));
+        assert_eq!(headers[EVENT_HEADER], "posts.create");
+
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["change"]["after"]["title"], "hello");
+        assert_eq!(payload["delivery"], headers[DELIVERY_HEADER].to_str().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failing_deliveries_are_dead_lettered() {
+        let (url, receiver) = start_receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let config = WebhookConfig {
+            max_attempts: 3,
+            base_delay_ms: 0,
+            ..Default::default()
+        };
+        let (db, dispatcher) = setup(url, &config).await;
+
+        let table = Table::new("posts").unwrap();
+        db.create_record(&table, json!({"title": "hello"})).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(dispatcher.tick().await.unwrap(), 1);
+        }
+        assert_eq!(dispatcher.tick().await.unwrap(), 0);
+        assert_eq!(receiver.received.lock().unwrap().len(), 3);
+
+        let dead = db
+            .deliveries(&DeliveryQuery {
+                status: Some(DeliveryStatus::Dead),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 3);
+        assert_eq!(dead[0].last_error.as_deref(), Some("HTTP 500 Internal Server Error"));
+    }
+}
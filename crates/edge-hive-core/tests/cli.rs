@@ -70,3 +70,69 @@ fn test_serve_command_help() -> TestResult {
         .stdout(predicate::str::contains("Start the Edge Hive server"));
     Ok(())
 }
+
+// Each command is its own process, so the store has to persist between them
+#[cfg(feature = "rocksdb")]
+#[test]
+fn test_db_migrate_up_and_status() -> TestResult {
+    let temp_dir = tempdir()?;
+    let data_dir = temp_dir.path();
+
+    let mut up_cmd = Command::cargo_bin("edge-hive-core")?;
+    up_cmd.args(["db", "migrate", "up"])
+        .env("EDGE_HIVE_DATA_DIR", data_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Applied migration 0001"));
+
+    let mut status_cmd = Command::cargo_bin("edge-hive-core")?;
+    status_cmd.args(["db", "migrate", "status"])
+        .env("EDGE_HIVE_DATA_DIR", data_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("initial_schema"))
+        .stdout(predicate::str::contains("applied"));
+
+    Ok(())
+}
+
+#[cfg(feature = "rocksdb")]
+#[test]
+fn test_db_dump_and_restore() -> TestResult {
+    let temp_dir = tempdir()?;
+    let data_dir = temp_dir.path();
+    let dump_path = data_dir.join("backup.surql");
+
+    let mut dump_cmd = Command::cargo_bin("edge-hive-core")?;
+    dump_cmd.args(["db", "dump", "--output"])
+        .arg(&dump_path)
+        .env("EDGE_HIVE_DATA_DIR", data_dir)
+        .assert()
+        .success();
+    assert!(std::fs::read_to_string(&dump_path)?.starts_with("-- edge-hive-dump "));
+
+    let mut restore_cmd = Command::cargo_bin("edge-hive-core")?;
+    restore_cmd.args(["db", "restore"])
+        .arg(&dump_path)
+        .env("EDGE_HIVE_DATA_DIR", data_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Restored dump"));
+
+    Ok(())
+}
+
+#[cfg(not(feature = "rocksdb"))]
+#[test]
+fn test_db_commands_need_rocksdb() -> TestResult {
+    let temp_dir = tempdir()?;
+
+    let mut cmd = Command::cargo_bin("edge-hive-core")?;
+    cmd.args(["db", "migrate", "status"])
+        .env("EDGE_HIVE_DATA_DIR", temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no RocksDB support"));
+
+    Ok(())
+}
@@ -42,8 +42,7 @@ async fn spawn_test_server() -> TestServer {
 
     // API gateway components (cache + db + realtime)
     let cache = edge_hive_cache::CacheService::new(edge_hive_cache::CacheConfig::default()).await;
-    let db_path = data_dir.join("edge-hive.db");
-    let db = Arc::new(edge_hive_db::DatabaseService::new(&db_path).await.expect("db"));
+    let db = Arc::new(edge_hive_db::DatabaseService::new_in_memory().await.expect("db"));
     let realtime = edge_hive_realtime::RealtimeServer::new(edge_hive_realtime::RealtimeServerConfig::default())
         .with_db(db.clone());
     let api_state = edge_hive_api::ApiState::new(cache, db, realtime, data_dir.clone());
@@ -16,8 +16,7 @@ async fn setup_test_app() -> axum::Router {
     let temp_dir = tempdir().unwrap();
     let data_dir = temp_dir.path().to_path_buf();
     let cache = CacheService::new(Default::default()).await;
-    let db_path = data_dir.join("test.db");
-    let db = Arc::new(DatabaseService::new(&db_path).await.unwrap());
+    let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
     let realtime = RealtimeServer::new(Default::default());
     let state = ApiState::new(cache, db, realtime, data_dir);
     create_router(state)
@@ -0,0 +1,140 @@
+//! API keys: long-lived credentials for servers and scripts
+//!
+//! A key stands for a role, like the role claim of a token. Only a SHA-256
+//! hash of the key is stored (`_api_keys`); the key itself is returned once,
+//! when it is created. Every project has its own keys, since they live in
+//! the project's database.
+
+use crate::changes::parse_rows;
+use crate::query::BoundQuery;
+use crate::webhook::generate_secret;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Start of every API key, so leaked keys are easy to spot
+pub const API_KEY_PREFIX: &str = "ehk_";
+
+const API_KEY_FIELDS: &str =
+    "record::id(id) AS id, name, role, prefix, <string> created_at AS created_at";
+
+/// A stored API key, without the key itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Role granted to callers using the key
+    pub role: String,
+    /// First characters of the key, to tell keys apart
+    pub prefix: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fields of a new API key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    #[serde(default = "default_role")]
+    pub role: String,
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+/// A key just created, the only time the key itself is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+impl DatabaseService {
+    /// Create an API key
+    pub async fn create_api_key(&self, api_key: NewApiKey) -> Result<CreatedApiKey, DbError> {
+        let key = format!("{}{}", API_KEY_PREFIX, generate_secret());
+        let content = json!({
+            "name": api_key.name,
+            "role": api_key.role,
+            "prefix": &key[..API_KEY_PREFIX.len() + 6],
+            "hash": hash_key(&key),
+        });
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE _api_keys CONTENT $content); \
+             RETURN SELECT {} FROM $created }}",
+            API_KEY_FIELDS
+        ))
+        .bind("content", content);
+
+        let api_key = parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("API key creation returned no record".to_string()))?;
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    /// Every API key, oldest first
+    pub async fn api_keys(&self) -> Result<Vec<ApiKey>, DbError> {
+        let query = BoundQuery::new(format!("SELECT {} FROM _api_keys", API_KEY_FIELDS));
+        let mut keys: Vec<ApiKey> = parse_rows(self.execute(query).await?)?;
+        keys.sort_by_key(|k| k.created_at);
+        Ok(keys)
+    }
+
+    /// Revoke an API key; `false` if it did not exist
+    pub async fn delete_api_key(&self, id: &str) -> Result<bool, DbError> {
+        let query = BoundQuery::new("DELETE type::thing('_api_keys', $id) RETURN BEFORE")
+            .bind("id", id);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// The stored key matching `key`, if it is valid
+    pub async fn verify_api_key(&self, key: &str) -> Result<Option<ApiKey>, DbError> {
+        if !key.starts_with(API_KEY_PREFIX) {
+            return Ok(None);
+        }
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM _api_keys WHERE hash = $hash",
+            API_KEY_FIELDS
+        ))
+        .bind("hash", hash_key(key));
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let created = db
+            .create_api_key(NewApiKey {
+                name: "backend".to_string(),
+                role: "admin".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(created.key.starts_with(API_KEY_PREFIX));
+        assert!(created.key.starts_with(&created.api_key.prefix));
+
+        let verified = db.verify_api_key(&created.key).await.unwrap();
+        assert_eq!(verified.as_ref(), Some(&created.api_key));
+        assert_eq!(db.verify_api_key("ehk_wrong").await.unwrap(), None);
+        assert_eq!(db.api_keys().await.unwrap(), vec![created.api_key.clone()]);
+
+        assert!(db.delete_api_key(&created.api_key.id).await.unwrap());
+        assert!(!db.delete_api_key(&created.api_key.id).await.unwrap());
+        assert_eq!(db.verify_api_key(&created.key).await.unwrap(), None);
+    }
+}
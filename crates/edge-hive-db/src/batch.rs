@@ -0,0 +1,410 @@
+//! Atomic batches of data API writes
+//!
+//! A batch is an ordered list of create/update/delete operations that runs in
+//! a single transaction under the caller's row-level policies. An update or
+//! delete that matches no visible record aborts the whole batch, so a batch
+//! either applies completely or not at all.
+//!
+//! Tables with a JSON Schema are validated before the transaction starts; the
+//! paths of the violations point into the batch request
+//! (`/operations/{index}/data/...`).
+
+use crate::policy::{ScopedDatabase, TablePolicy, IDENTITY_VAR};
+use crate::query::{BoundQuery, RecordId, Table};
+use crate::DbError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Largest number of operations accepted in one batch
+pub const MAX_BATCH_OPERATIONS: usize = 100;
+
+/// One write of a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOp {
+    Create { table: Table, data: Value },
+    Update { table: Table, id: String, data: Value },
+    Delete { table: Table, id: String },
+}
+
+impl BatchOp {
+    pub fn table(&self) -> &Table {
+        match self {
+            BatchOp::Create { table, .. }
+            | BatchOp::Update { table, .. }
+            | BatchOp::Delete { table, .. } => table,
+        }
+    }
+}
+
+/// Outcome of one operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Created,
+    Updated,
+    Deleted,
+    /// This operation made the batch fail
+    Failed,
+    /// Undone because another operation failed
+    RolledBack,
+}
+
+/// Result of one operation, in request order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub table: Table,
+    pub status: BatchStatus,
+    /// Created or updated record, or the record as it was before deletion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a whole batch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchOutcome {
+    /// Whether the transaction committed
+    pub committed: bool,
+    pub results: Vec<BatchResult>,
+}
+
+/// Patch of an update: the operation's data without the owner field
+fn update_patch(policy: &TablePolicy, data: &Value) -> Value {
+    let mut patch = data.clone();
+    if let (Some(owner), Some(object)) = (&policy.owner_field, patch.as_object_mut()) {
+        object.remove(owner.as_str());
+    }
+    patch
+}
+
+impl ScopedDatabase<'_> {
+    /// Run `ops` atomically as the caller
+    ///
+    /// A failed transaction is reported in the outcome rather than as an error.
+    /// Operations on tables the caller can never reach (system tables) are
+    /// rejected before anything runs.
+    pub async fn batch(&self, ops: Vec<BatchOp>) -> Result<BatchOutcome, DbError> {
+        if ops.len() > MAX_BATCH_OPERATIONS {
+            return Err(DbError::InvalidQuery(format!(
+                "a batch holds at most {} operations",
+                MAX_BATCH_OPERATIONS
+            )));
+        }
+
+        self.validate_batch(&ops).await?;
+
+        let statements = ops
+            .iter()
+            .enumerate()
+            .map(|(index, op)| self.batch_statement(index, op))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match self.db.transaction(statements).await {
+            Ok(rows) => {
+                let results = ops
+                    .iter()
+                    .zip(rows)
+                    .enumerate()
+                    .map(|(index, (op, rows))| {
+                        Ok(BatchResult {
+                            index,
+                            table: op.table().clone(),
+                            status: match op {
+                                BatchOp::Create { .. } => BatchStatus::Created,
+                                BatchOp::Update { .. } => BatchStatus::Updated,
+                                BatchOp::Delete { .. } => BatchStatus::Deleted,
+                            },
+                            record: rows
+                                .into_iter()
+                                .next()
+                                .map(|record| self.db.open_record(op.table(), record))
+                                .transpose()?,
+                            error: None,
+                        })
+                    })
+                    .collect::<Result<_, DbError>>()?;
+
+                Ok(BatchOutcome {
+                    committed: true,
+                    results,
+                })
+            }
+            Err(DbError::Transaction { index, message }) => {
+                let results = ops
+                    .iter()
+                    .enumerate()
+                    .map(|(i, op)| BatchResult {
+                        index: i,
+                        table: op.table().clone(),
+                        status: if i == index {
+                            BatchStatus::Failed
+                        } else {
+                            BatchStatus::RolledBack
+                        },
+                        record: None,
+                        error: (i == index).then(|| message.clone()),
+                    })
+                    .collect();
+
+                Ok(BatchOutcome {
+                    committed: false,
+                    results,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Content of a created record: the operation's data plus the owner field
+    fn create_content(
+        &self,
+        index: usize,
+        policy: &TablePolicy,
+        data: &Value,
+    ) -> Result<Value, DbError> {
+        let mut content = data.clone();
+        let object = content.as_object_mut().ok_or_else(|| {
+            DbError::InvalidQuery(format!("operation {}: data must be an object", index))
+        })?;
+        if let Some(owner) = &policy.owner_field {
+            object.insert(owner.to_string(), Value::String(self.identity.sub.clone()));
+        }
+        Ok(content)
+    }
+
+    /// Check every create and update against its table's schema
+    ///
+    /// Updates are checked against the current record; records the batch
+    /// itself creates or changes earlier are not taken into account. Records
+    /// the caller may not update are left to fail inside the transaction, so
+    /// a violation never tells them anything about a record they cannot see.
+    async fn validate_batch(&self, ops: &[BatchOp]) -> Result<(), DbError> {
+        let mut violations = Vec::new();
+        for (index, op) in ops.iter().enumerate() {
+            let result = match op {
+                BatchOp::Create { table, data } => {
+                    let policy = self.policies.policy(table)?;
+                    let content = self.create_content(index, policy, data)?;
+                    self.db.validate_record(table, &content).await
+                }
+                BatchOp::Update { table, id, data } => {
+                    let policy = self.policies.policy(table)?;
+                    let id = RecordId::new(table.clone(), id.as_str())?;
+                    let patch = update_patch(policy, data);
+                    match self.updatable(&id).await? {
+                        Some(current) => self.db.validate_merge(table, &current, &patch).await,
+                        // Fails inside the transaction instead
+                        None => Ok(()),
+                    }
+                }
+                BatchOp::Delete { .. } => Ok(()),
+            };
+
+            match result {
+                Err(DbError::Validation(found)) => {
+                    let prefix = format!("/operations/{}/data", index);
+                    violations.extend(found.into_iter().map(|v| v.nested(&prefix)));
+                }
+                other => other?,
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(DbError::Validation(violations))
+        }
+    }
+
+    /// Block statement for operation `index`, using variables suffixed with the index
+    fn batch_statement(&self, index: usize, op: &BatchOp) -> Result<BoundQuery, DbError> {
+        let policy = self.policies.policy(op.table())?;
+        let (table, data) = (format!("t{}", index), format!("d{}", index));
+        let (key, rows) = (format!("k{}", index), format!("r{}", index));
+
+        let statement = match op {
+            BatchOp::Create { table: t, data: content } => {
+                let content = self.db.seal(t, self.create_content(index, policy, content)?)?;
+
+                // A block only returns the value of its last statement, not of a CREATE
+                BoundQuery::new(format!(
+                    "{{ IF array::len((SELECT VALUE true FROM [${data}] WHERE ({rule}))) = 0 \
+                     {{ THROW 'permission denied' }}; \
+                     LET ${rows} = (CREATE type::table(${table}) CONTENT ${data}); ${rows} }}",
+                    rule = policy.rule(&policy.create),
+                ))
+                .bind(table, t.as_str())
+                .bind(data, content)
+            }
+            BatchOp::Update { table: t, id, data: patch } => {
+                let id = RecordId::new(t.clone(), id.as_str())?;
+                let patch = self.db.seal(t, update_patch(policy, patch))?;
+
+                BoundQuery::new(format!(
+                    "{{ LET ${rows} = (UPDATE type::thing(${table}, ${key}) MERGE ${data} WHERE ({rule})); \
+                     IF array::len(${rows}) = 0 {{ THROW 'record not found' }}; ${rows} }}",
+                    rule = policy.rule(&policy.update),
+                ))
+                .bind(table, id.table.as_str())
+                .bind(key, id.key)
+                .bind(data, patch)
+            }
+            BatchOp::Delete { table: t, id } => {
+                let id = RecordId::new(t.clone(), id.as_str())?;
+
+                BoundQuery::new(format!(
+                    "{{ LET ${rows} = (DELETE type::thing(${table}, ${key}) WHERE ({rule}) RETURN BEFORE); \
+                     IF array::len(${rows}) = 0 {{ THROW 'record not found' }}; ${rows} }}",
+                    rule = policy.rule(&policy.delete),
+                ))
+                .bind(table, id.table.as_str())
+                .bind(key, id.key)
+            }
+        };
+
+        Ok(statement.bind(IDENTITY_VAR, self.identity.to_value()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+    use crate::DatabaseService;
+
+    fn ops(value: Value) -> Vec<BatchOp> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_batch_op_format() {
+        let ops = ops(serde_json::json!([
+            {"op": "create", "table": "posts", "data": {"title": "a"}},
+            {"op": "update", "table": "posts", "id": "p1", "data": {"title": "b"}},
+            {"op": "delete", "table": "posts", "id": "p1"},
+        ]));
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops[2].table().as_str(), "posts");
+
+        let invalid: Result<Vec<BatchOp>, _> =
+            serde_json::from_value(serde_json::json!([{"op": "create", "table": "a b", "data": {}}]));
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_batch_commits_all_operations() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let identity = Identity::new("users:alice", "user", vec![]);
+        let scoped = db.scoped(&policies, &identity);
+
+        let outcome = scoped
+            .batch(ops(serde_json::json!([
+                {"op": "create", "table": "orders", "data": {"total": 10}},
+                {"op": "create", "table": "order_items", "data": {"sku": "A"}},
+            ])))
+            .await
+            .unwrap();
+
+        assert!(outcome.committed);
+        assert_eq!(outcome.results[0].status, BatchStatus::Created);
+        assert_eq!(outcome.results[1].record.as_ref().unwrap()["owner"], "users:alice");
+        assert_eq!(db.select_records(&Table::new("orders").unwrap()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_rolls_back_on_failure() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let identity = Identity::new("users:alice", "user", vec![]);
+        let scoped = db.scoped(&policies, &identity);
+
+        let outcome = scoped
+            .batch(ops(serde_json::json!([
+                {"op": "create", "table": "orders", "data": {"total": 10}},
+                {"op": "update", "table": "orders", "id": "missing", "data": {"total": 0}},
+            ])))
+            .await
+            .unwrap();
+
+        assert!(!outcome.committed);
+        assert_eq!(outcome.results[0].status, BatchStatus::RolledBack);
+        assert_eq!(outcome.results[1].status, BatchStatus::Failed);
+        assert!(outcome.results[1].error.as_ref().unwrap().contains("record not found"));
+        assert!(db.select_records(&Table::new("orders").unwrap()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batch_rejects_system_tables() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let identity = Identity::new("users:alice", "user", vec![]);
+
+        let result = db
+            .scoped(&policies, &identity)
+            .batch(ops(serde_json::json!([
+                {"op": "delete", "table": "sessions", "id": "s1"},
+            ])))
+            .await;
+        assert!(matches!(result, Err(DbError::PermissionDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_validated_before_running() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("orders").unwrap();
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"total": {"type": "number", "minimum": 0}}
+        });
+        db.set_table_schema(&table, &schema).await.unwrap();
+
+        let policies = PolicySet::default();
+        let identity = Identity::new("users:alice", "user", vec![]);
+        let result = db
+            .scoped(&policies, &identity)
+            .batch(ops(serde_json::json!([
+                {"op": "create", "table": "orders", "data": {"total": 10}},
+                {"op": "create", "table": "orders", "data": {"total": -1}},
+            ])))
+            .await;
+
+        let Err(DbError::Validation(violations)) = result else {
+            panic!("expected a validation error, got {:?}", result);
+        };
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "/operations/1/data/total");
+        assert!(db.select_records(&table).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batch_validation_ignores_invisible_records() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("orders").unwrap();
+        let policies = PolicySet::default();
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let id = RecordId::new(table.clone(), "o1").unwrap();
+        db.scoped(&policies, &bob)
+            .create_record_at(&id, serde_json::json!({"note": "bob's"}))
+            .await
+            .unwrap();
+        let schema = serde_json::json!({"type": "object", "required": ["total"]});
+        db.set_table_schema(&table, &schema).await.unwrap();
+
+        // Bob's record misses `total`, which must not show through a violation
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let outcome = db
+            .scoped(&policies, &alice)
+            .batch(ops(serde_json::json!([
+                {"op": "update", "table": "orders", "id": "o1", "data": {"note": "mine"}},
+            ])))
+            .await
+            .unwrap();
+
+        assert!(!outcome.committed);
+        assert!(outcome.results[0].error.as_ref().unwrap().contains("record not found"));
+    }
+}
@@ -0,0 +1,578 @@
+//! Database branches for preview environments
+//!
+//! A branch is a copy of a database (the node's or a project's) kept in a
+//! database of its own, `branch_<name>`, next to it in the same namespace. It
+//! is created from a dump, so it starts with the schema, table settings, data
+//! and migration history of its base and then evolves on its own.
+//!
+//! [`DatabaseService::diff_branch`] compares the schema of a branch with its
+//! base: tables, fields, indexes, events, analyzers, functions, params and
+//! the table settings kept in `config` (JSON Schemas, search, versioning).
+//! [`DatabaseService::merge_branch`] applies those changes to the base. Data
+//! is never merged.
+
+use crate::changes::parse_rows;
+use crate::describe::definitions;
+use crate::project::validate_label;
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError, ListQuery};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+
+const BRANCH_FIELDS: &str = "name, schema_version, <string> created_at AS created_at, \
+     (IF merged_at THEN <string> merged_at END) AS merged_at";
+
+/// A copy of a database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Branch {
+    pub name: String,
+    /// Latest migration applied on the base when the branch was created
+    pub schema_version: u32,
+    pub created_at: DateTime<Utc>,
+    /// When the branch's schema was last merged into its base
+    pub merged_at: Option<DateTime<Utc>>,
+}
+
+impl Branch {
+    /// SurrealDB database holding the branch
+    pub fn database(&self) -> String {
+        branch_database(&self.name)
+    }
+}
+
+/// Database of a validated branch name
+fn branch_database(name: &str) -> String {
+    format!("branch_{}", name.replace('-', "_"))
+}
+
+/// Kind of schema definition, in the order they are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaItem {
+    Analyzer,
+    Function,
+    Param,
+    Table,
+    Field,
+    Index,
+    Event,
+    /// Table setting stored in `config`
+    Setting,
+}
+
+/// How a definition differs between a branch and its base
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    /// Only on the branch
+    Added,
+    /// Only on the base
+    Removed,
+    Changed,
+}
+
+/// One definition that differs between a branch and its base
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaChange {
+    pub kind: DiffKind,
+    pub item: SchemaItem,
+    /// Table of a field, index or event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    pub name: String,
+    /// Definition on the base (a JSON value for settings)
+    pub base: Option<String>,
+    /// Definition on the branch (a JSON value for settings)
+    pub branch: Option<String>,
+}
+
+impl SchemaChange {
+    /// Statement applying this change to the base; `index` keeps variables unique
+    fn statement(&self, index: usize) -> Result<BoundQuery, DbError> {
+        let key = format!("key{}", index);
+        let value = format!("value{}", index);
+
+        if self.item == SchemaItem::Setting {
+            return Ok(match &self.branch {
+                Some(setting) => {
+                    let setting: Value = serde_json::from_str(setting)
+                        .map_err(|e| DbError::Serialization(e.to_string()))?;
+                    let sql = format!(
+                        "UPSERT type::thing('config', ${0}) CONTENT {{ key: ${0}, value: ${1} }}",
+                        key, value
+                    );
+                    BoundQuery::new(sql).bind(&value, setting)
+                }
+                None => BoundQuery::new(format!("DELETE type::thing('config', ${})", key)),
+            }
+            .bind(&key, self.name.as_str()));
+        }
+
+        let sql = match (&self.branch, &self.table) {
+            (Some(definition), _) => overwrite(definition),
+            (None, Some(table)) => format!(
+                "REMOVE {} IF EXISTS {} ON {}",
+                self.keyword(),
+                self.name,
+                table
+            ),
+            (None, None) => format!("REMOVE {} IF EXISTS {}", self.keyword(), self.removed_name()),
+        };
+        Ok(BoundQuery::new(sql))
+    }
+
+    fn keyword(&self) -> &'static str {
+        match self.item {
+            SchemaItem::Analyzer => "ANALYZER",
+            SchemaItem::Function => "FUNCTION",
+            SchemaItem::Param => "PARAM",
+            SchemaItem::Table => "TABLE",
+            SchemaItem::Field => "FIELD",
+            SchemaItem::Index => "INDEX",
+            SchemaItem::Event => "EVENT",
+            SchemaItem::Setting => "",
+        }
+    }
+
+    fn removed_name(&self) -> String {
+        match self.item {
+            SchemaItem::Function => format!("fn::{}", self.name),
+            SchemaItem::Param => format!("${}", self.name),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Turn `DEFINE <KIND> ...` into `DEFINE <KIND> OVERWRITE ...`
+fn overwrite(definition: &str) -> String {
+    let Some(rest) = definition.strip_prefix("DEFINE ") else {
+        return definition.to_string();
+    };
+    match rest.split_once(' ') {
+        Some((kind, rest)) => format!("DEFINE {} OVERWRITE {}", kind, rest),
+        None => definition.to_string(),
+    }
+}
+
+/// Record counts of a data table that differ between a branch and its base
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowCount {
+    pub table: String,
+    pub base: u64,
+    pub branch: u64,
+}
+
+/// Differences between a branch and its base
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchDiff {
+    pub branch: String,
+    /// Latest migration applied on the base
+    pub base_version: u32,
+    /// Latest migration applied on the branch
+    pub branch_version: u32,
+    pub schema: Vec<SchemaChange>,
+    /// Data tables whose record count differs
+    pub rows: Vec<RowCount>,
+}
+
+type Definitions = BTreeMap<(SchemaItem, Option<String>, String), String>;
+
+fn diff_definitions(base: &Definitions, branch: &Definitions) -> Vec<SchemaChange> {
+    let keys: BTreeSet<_> = base.keys().chain(branch.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (on_base, on_branch) = (base.get(key), branch.get(key));
+            let kind = match (on_base, on_branch) {
+                (None, Some(_)) => DiffKind::Added,
+                (Some(_), None) => DiffKind::Removed,
+                (Some(a), Some(b)) if a != b => DiffKind::Changed,
+                _ => return None,
+            };
+            let (item, table, name) = key.clone();
+            Some(SchemaChange {
+                kind,
+                item,
+                table,
+                name,
+                base: on_base.cloned(),
+                branch: on_branch.cloned(),
+            })
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    /// Copy this database into a new branch
+    pub async fn create_branch(&self, name: &str) -> Result<Branch, DbError> {
+        self.ensure_not_branch("create branches")?;
+        validate_label("branch", name)?;
+        if self.get_branch(name).await?.is_some() {
+            return Err(DbError::InvalidQuery(format!("branch '{}' already exists", name)));
+        }
+
+        let mut dump = Vec::new();
+        let header = self.export(&mut dump).await?;
+        let branch = self.branch_session(name)?;
+        let copied = async {
+            branch.import(dump.as_slice()).await?;
+            // Branches of the base are not branches of the copy
+            branch.execute(BoundQuery::new("DELETE _branches")).await
+        }
+        .await;
+        if let Err(e) = copied {
+            self.remove_branch_database(name).await?;
+            return Err(e);
+        }
+
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE type::thing('_branches', $name) \
+             CONTENT {{ name: $name, schema_version: $version }}); \
+             RETURN SELECT {} FROM $created }}",
+            BRANCH_FIELDS
+        ))
+        .bind("name", name)
+        .bind("version", header.schema_version);
+
+        let branch = parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Branch creation returned no record".to_string()))?;
+        info!("🌿 Created branch '{}'", name);
+        Ok(branch)
+    }
+
+    /// Every branch of this database, by name
+    pub async fn branches(&self) -> Result<Vec<Branch>, DbError> {
+        let query =
+            BoundQuery::new(format!("SELECT {} FROM _branches ORDER BY name", BRANCH_FIELDS));
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// A branch by name
+    pub async fn get_branch(&self, name: &str) -> Result<Option<Branch>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM type::thing('_branches', $name)",
+            BRANCH_FIELDS
+        ))
+        .bind("name", name);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Drop a branch and its data; `false` if it did not exist
+    pub async fn delete_branch(&self, name: &str) -> Result<bool, DbError> {
+        if self.get_branch(name).await?.is_none() {
+            return Ok(false);
+        }
+
+        self.remove_branch_database(name).await?;
+        self.execute(BoundQuery::new("DELETE type::thing('_branches', $name)").bind("name", name))
+            .await?;
+        Ok(true)
+    }
+
+    /// Open a branch of this database, applying pending migrations
+    ///
+    /// The branch encrypts the same fields as this service and its copied data
+    /// keys are unlocked with the same identity.
+    pub async fn open_branch(&self, branch: &Branch) -> Result<Self, DbError> {
+        let service = self.branch_session(&branch.name)?;
+        service.migrate_up(None).await?;
+        service.inherit_keys(self).await?;
+        Ok(service)
+    }
+
+    /// Name of the branch this service was opened for, `None` for a main database
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// Compare the schema and record counts of a branch with this database
+    pub async fn diff_branch(&self, name: &str) -> Result<Option<BranchDiff>, DbError> {
+        let Some(branch) = self.get_branch(name).await? else {
+            return Ok(None);
+        };
+        let branch_db = self.open_branch(&branch).await?;
+
+        let base_definitions = self.schema_definitions().await?;
+        let branch_definitions = branch_db.schema_definitions().await?;
+
+        let tables: BTreeSet<Table> = self
+            .data_tables()
+            .await?
+            .into_iter()
+            .chain(branch_db.data_tables().await?)
+            .map(|(table, _)| table)
+            .collect();
+        let mut rows = Vec::new();
+        for table in tables {
+            let count = ListQuery::new(table.clone());
+            let (base, branch) =
+                (self.count_records(&count).await?, branch_db.count_records(&count).await?);
+            if base != branch {
+                rows.push(RowCount { table: table.to_string(), base, branch });
+            }
+        }
+
+        Ok(Some(BranchDiff {
+            branch: branch.name,
+            base_version: self.schema_version().await?,
+            branch_version: branch_db.schema_version().await?,
+            schema: diff_definitions(&base_definitions, &branch_definitions),
+            rows,
+        }))
+    }
+
+    /// Apply the schema changes of a branch to this database
+    ///
+    /// Definitions added or changed on the branch are applied; definitions
+    /// missing on the branch are only removed here when `remove` is set, which
+    /// drops such tables together with their records. Both sides must be at
+    /// the same migration. Returns the applied changes, or `None` if the
+    /// branch does not exist.
+    pub async fn merge_branch(
+        &self,
+        name: &str,
+        remove: bool,
+    ) -> Result<Option<Vec<SchemaChange>>, DbError> {
+        let Some(diff) = self.diff_branch(name).await? else {
+            return Ok(None);
+        };
+        if diff.base_version != diff.branch_version {
+            return Err(DbError::Migration(format!(
+                "branch '{}' is at schema version {} but its base at {}; migrate both first",
+                name, diff.branch_version, diff.base_version
+            )));
+        }
+
+        let (mut removals, mut changes): (Vec<_>, Vec<_>) = diff
+            .schema
+            .into_iter()
+            .filter(|change| remove || change.kind != DiffKind::Removed)
+            .partition(|change| change.kind == DiffKind::Removed);
+        // Dependencies first when defining, dependents first when removing
+        changes.sort_by_key(|change| change.item);
+        removals.sort_by_key(|change| std::cmp::Reverse(change.item));
+        changes.extend(removals);
+
+        let mut statements = changes
+            .iter()
+            .enumerate()
+            .map(|(index, change)| change.statement(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        statements.push(
+            BoundQuery::new("UPDATE type::thing('_branches', $branch) SET merged_at = time::now()")
+                .bind("branch", name),
+        );
+        self.transaction(statements).await?;
+
+        info!("🔀 Merged {} schema changes of branch '{}'", changes.len(), name);
+        Ok(Some(changes))
+    }
+
+    /// Service on a branch's database, without migrating it
+    fn branch_session(&self, name: &str) -> Result<Self, DbError> {
+        self.ensure_not_branch("open branches")?;
+        validate_label("branch", name)?;
+
+        let config = self
+            .config
+            .clone()
+            .with_namespace(self.config.namespace.clone(), branch_database(name));
+        Ok(self.sibling(config, self.project.clone(), Some(name.to_string())))
+    }
+
+    async fn remove_branch_database(&self, name: &str) -> Result<(), DbError> {
+        // Databases cannot be bound, but validated names are safe to inline
+        validate_label("branch", name)?;
+        self.execute(BoundQuery::new(format!(
+            "REMOVE DATABASE IF EXISTS {}",
+            branch_database(name)
+        )))
+        .await?;
+        Ok(())
+    }
+
+    fn ensure_not_branch(&self, action: &str) -> Result<(), DbError> {
+        match &self.branch {
+            None => Ok(()),
+            Some(branch) => Err(DbError::PermissionDenied(format!(
+                "branch '{}' cannot {}",
+                branch, action
+            ))),
+        }
+    }
+
+    /// Latest applied migration
+    async fn schema_version(&self) -> Result<u32, DbError> {
+        Ok(self.applied_migrations().await?.iter().map(|m| m.version).max().unwrap_or(0))
+    }
+
+    /// Definitions of the data tables and database-wide items, and table settings
+    async fn schema_definitions(&self) -> Result<Definitions, DbError> {
+        let mut schema = Definitions::new();
+
+        let info = self.info("INFO FOR DB".to_string()).await?;
+        for (item, section) in [
+            (SchemaItem::Analyzer, "analyzers"),
+            (SchemaItem::Function, "functions"),
+            (SchemaItem::Param, "params"),
+        ] {
+            for (name, statement) in definitions(&info, section) {
+                schema.insert((item, None, name), statement);
+            }
+        }
+
+        for (table, statement) in self.data_tables().await? {
+            let info = self.info(format!("INFO FOR TABLE {}", table)).await?;
+            for (item, section) in [
+                (SchemaItem::Field, "fields"),
+                (SchemaItem::Index, "indexes"),
+                (SchemaItem::Event, "events"),
+            ] {
+                for (name, statement) in definitions(&info, section) {
+                    schema.insert((item, Some(table.to_string()), name), statement);
+                }
+            }
+            schema.insert((SchemaItem::Table, None, table.to_string()), statement);
+        }
+
+        for row in self.execute(BoundQuery::new("SELECT key, value FROM config")).await? {
+            if let Some(key) = row.get("key").and_then(Value::as_str) {
+                let value = row.get("value").cloned().unwrap_or(Value::Null);
+                schema.insert((SchemaItem::Setting, None, key.to_string()), value.to_string());
+            }
+        }
+
+        Ok(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn seeded() -> DatabaseService {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.execute(BoundQuery::new(
+            "DEFINE TABLE posts SCHEMALESS; DEFINE FIELD title ON posts TYPE string",
+        ))
+        .await
+        .unwrap();
+        let posts = Table::new("posts").unwrap();
+        db.create_record(&posts, json!({"title": "hello"})).await.unwrap();
+        db
+    }
+
+    #[test]
+    fn test_overwrite() {
+        assert_eq!(
+            overwrite("DEFINE FIELD title ON posts TYPE string"),
+            "DEFINE FIELD OVERWRITE title ON posts TYPE string"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_branch_is_an_isolated_copy() {
+        let db = seeded().await;
+        let branch = db.create_branch("feature-x").await.unwrap();
+        assert_eq!(branch.database(), "branch_feature_x");
+        assert!(db.create_branch("feature-x").await.is_err());
+        assert_eq!(db.branches().await.unwrap(), vec![branch.clone()]);
+
+        let branch_db = db.open_branch(&branch).await.unwrap();
+        assert_eq!(branch_db.branch(), Some("feature-x"));
+        assert!(branch_db.create_branch("nested").await.is_err());
+        assert!(branch_db.branches().await.unwrap().is_empty());
+
+        let posts = Table::new("posts").unwrap();
+        assert_eq!(branch_db.select_records(&posts).await.unwrap().len(), 1);
+        branch_db.create_record(&posts, json!({"title": "draft"})).await.unwrap();
+        assert_eq!(db.select_records(&posts).await.unwrap().len(), 1);
+
+        drop(branch_db);
+        assert!(db.delete_branch("feature-x").await.unwrap());
+        assert!(!db.delete_branch("feature-x").await.unwrap());
+        assert!(db.branches().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_branch_leaves_the_caller_on_its_database() {
+        let db = seeded().await;
+        let branch = db.create_branch("feature-y").await.unwrap();
+        let branch_db = db.open_branch(&branch).await.unwrap();
+
+        let posts = Table::new("posts").unwrap();
+        let writes = (0..10).map(|i| {
+            let (db, branch_db, posts) = (&db, &branch_db, &posts);
+            async move {
+                let target = if i % 2 == 0 { db } else { branch_db };
+                target.create_record(posts, json!({"title": i.to_string()})).await.unwrap();
+            }
+        });
+        futures::future::join_all(writes).await;
+
+        let current = db.execute(BoundQuery::new("RETURN session::db()")).await.unwrap();
+        assert_eq!(current, vec![json!(db.config().database)]);
+        assert_eq!(db.select_records(&posts).await.unwrap().len(), 6);
+        assert_eq!(branch_db.select_records(&posts).await.unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_diff_and_merge_schema() {
+        let db = seeded().await;
+        let branch = db.create_branch("preview").await.unwrap();
+        let branch_db = db.open_branch(&branch).await.unwrap();
+        branch_db
+            .execute(BoundQuery::new(
+                "DEFINE FIELD OVERWRITE title ON posts TYPE string ASSERT $value != ''; \
+                 DEFINE FIELD views ON posts TYPE int DEFAULT 0; \
+                 DEFINE INDEX posts_views ON posts FIELDS views",
+            ))
+            .await
+            .unwrap();
+        branch_db.set_config("versioning.posts", &true).await.unwrap();
+        branch_db.execute(BoundQuery::new("DELETE posts")).await.unwrap();
+        db.execute(BoundQuery::new("DEFINE FIELD legacy ON posts TYPE string"))
+            .await
+            .unwrap();
+
+        let diff = db.diff_branch("preview").await.unwrap().unwrap();
+        assert_eq!(diff.base_version, diff.branch_version);
+        let summary: Vec<_> = diff
+            .schema
+            .iter()
+            .map(|c| (c.kind, c.item, c.name.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (DiffKind::Removed, SchemaItem::Field, "legacy"),
+                (DiffKind::Changed, SchemaItem::Field, "title"),
+                (DiffKind::Added, SchemaItem::Field, "views"),
+                (DiffKind::Added, SchemaItem::Index, "posts_views"),
+                (DiffKind::Added, SchemaItem::Setting, "versioning.posts"),
+            ]
+        );
+        assert_eq!(
+            diff.rows,
+            vec![RowCount { table: "posts".to_string(), base: 1, branch: 0 }]
+        );
+
+        let applied = db.merge_branch("preview", false).await.unwrap().unwrap();
+        assert_eq!(applied.len(), 4);
+        let diff = db.diff_branch("preview").await.unwrap().unwrap();
+        assert_eq!(diff.schema.len(), 1);
+        assert_eq!(diff.schema[0].kind, DiffKind::Removed);
+        assert!(db.get_branch("preview").await.unwrap().unwrap().merged_at.is_some());
+        assert!(db.is_versioned(&Table::new("posts").unwrap()).await.unwrap());
+
+        db.merge_branch("preview", true).await.unwrap().unwrap();
+        assert!(db.diff_branch("preview").await.unwrap().unwrap().schema.is_empty());
+        assert!(db.merge_branch("missing", false).await.unwrap().is_none());
+    }
+}
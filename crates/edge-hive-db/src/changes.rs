@@ -0,0 +1,223 @@
+//! Change data capture for data API tables
+//!
+//! [`DatabaseService::capture_changes`] defines a table event that appends
+//! every create, update and delete to the `_changes` log. The event runs in
+//! the same transaction as the write, so the log is complete whichever path
+//! wrote the record (data API, batches, edge functions).
+//!
+//! The log reads in write order (by timestamp, ties broken by the ULID of the
+//! change). Each change starts undispatched; the webhook fan-out (see
+//! `webhook.rs`) marks it dispatched once its deliveries exist.
+
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// Name of the table event writing to the log
+const EVENT: &str = "edge_hive_changes";
+
+/// Largest page of changes returned at once
+pub const MAX_CHANGES: u64 = 1000;
+
+/// Projection turning a `_changes` row into a [`Change`], plus the sort keys
+pub(crate) const CHANGE_FIELDS: &str = "record::id(id) AS id, `table`, action, record, \
+     before, after, <string> at AS at, at AS __at, id AS __id";
+
+/// Write order of the log
+pub(crate) const CHANGE_ORDER: &str = "ORDER BY __at, __id";
+
+/// Kind of write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl ChangeAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeAction::Create => "create",
+            ChangeAction::Update => "update",
+            ChangeAction::Delete => "delete",
+        }
+    }
+}
+
+/// One entry of the change log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// ULID of the change
+    pub id: String,
+    pub table: Table,
+    pub action: ChangeAction,
+    /// ID of the written record (`table:key`)
+    pub record: String,
+    /// Record before the write (updates and deletes)
+    #[serde(default)]
+    pub before: Option<Value>,
+    /// Record after the write (creates and updates)
+    #[serde(default)]
+    pub after: Option<Value>,
+    pub at: DateTime<Utc>,
+}
+
+/// A page of the change log, oldest first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeQuery {
+    /// Only changes of this table
+    pub table: Option<Table>,
+    /// Only changes after this change ID
+    pub after: Option<String>,
+    pub limit: u64,
+}
+
+/// Deserialize rows selected with one of the projections of this module
+pub(crate) fn parse_rows<T: DeserializeOwned>(rows: Vec<Value>) -> Result<Vec<T>, DbError> {
+    rows.into_iter()
+        .map(|row| {
+            serde_json::from_value(row).map_err(|e| DbError::Serialization(e.to_string()))
+        })
+        .collect()
+}
+
+impl DatabaseService {
+    /// Record every write to `table` in the change log
+    pub async fn capture_changes(&self, table: &Table) -> Result<(), DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+
+        // Event definitions need the table name itself, not a bound variable
+        let sql = format!(
+            "DEFINE EVENT OVERWRITE {} ON TABLE {} THEN {{ \
+             CREATE type::thing('_changes', rand::ulid()) CONTENT {{ \
+             \"table\": record::tb($value.id), action: string::lowercase($event), \
+             record: <string> $value.id, before: $before, after: $after, \
+             at: time::now(), dispatched: false }} }}",
+            EVENT, table
+        );
+        self.db().query(sql).await?.check()?;
+        Ok(())
+    }
+
+    /// Stop recording writes to `table`; the entries already logged are kept
+    pub async fn stop_capturing_changes(&self, table: &Table) -> Result<(), DbError> {
+        let sql = format!("REMOVE EVENT IF EXISTS {} ON TABLE {}", EVENT, table);
+        self.db().query(sql).await?.check()?;
+        Ok(())
+    }
+
+    /// Read the change log
+    pub async fn changes(&self, query: &ChangeQuery) -> Result<Vec<Change>, DbError> {
+        let (mut conditions, mut cursor) = (Vec::new(), "");
+        let mut statement = BoundQuery::new("").bind("limit", query.limit.clamp(1, MAX_CHANGES));
+        if let Some(table) = &query.table {
+            conditions.push("`table` = $table");
+            statement = statement.bind("table", table.as_str());
+        }
+        if let Some(after) = &query.after {
+            cursor = "LET $cursor = (SELECT at, id FROM ONLY type::thing('_changes', $after)); ";
+            conditions.push("(at > $cursor.at OR (at = $cursor.at AND id > $cursor.id))");
+            statement = statement.bind("after", after.as_str());
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        statement.sql = format!(
+            "{{ {}RETURN SELECT {} FROM _changes{} {} LIMIT $limit }}",
+            cursor, CHANGE_FIELDS, filter, CHANGE_ORDER
+        );
+
+        parse_rows(self.execute(statement).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::RecordId;
+    use serde_json::json;
+
+    async fn record_key(db: &DatabaseService) -> String {
+        let keys = db
+            .execute(BoundQuery::new("SELECT VALUE record::id(id) FROM posts"))
+            .await
+            .unwrap();
+        keys[0].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_logged_in_order() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.capture_changes(&table).await.unwrap();
+
+        db.create_record(&table, json!({"title": "a"})).await.unwrap();
+        let id = RecordId::new(table.clone(), record_key(&db).await).unwrap();
+        db.merge_record(&id, json!({"title": "b"})).await.unwrap();
+        db.delete_record(&id).await.unwrap();
+
+        let changes = db
+            .changes(&ChangeQuery {
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let actions: Vec<ChangeAction> = changes.iter().map(|c| c.action).collect();
+        assert_eq!(
+            actions,
+            vec![ChangeAction::Create, ChangeAction::Update, ChangeAction::Delete]
+        );
+        assert_eq!(changes[0].record, id.to_string());
+        assert!(changes[0].before.is_none());
+        assert_eq!(changes[1].before.as_ref().unwrap()["title"], "a");
+        assert_eq!(changes[1].after.as_ref().unwrap()["title"], "b");
+        assert!(changes[2].after.is_none());
+
+        // Paging continues after the last change seen
+        let rest = db
+            .changes(&ChangeQuery {
+                after: Some(changes[0].id.clone()),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_uncaptured_tables_are_not_logged() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.capture_changes(&table).await.unwrap();
+        db.stop_capturing_changes(&table).await.unwrap();
+
+        db.create_record(&table, json!({"title": "a"})).await.unwrap();
+        let other = Table::new("comments").unwrap();
+        db.create_record(&other, json!({"text": "b"})).await.unwrap();
+
+        let query = ChangeQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        assert!(db.changes(&query).await.unwrap().is_empty());
+
+        let system = Table::new("users").unwrap();
+        assert!(matches!(
+            db.capture_changes(&system).await,
+            Err(DbError::PermissionDenied(_))
+        ));
+    }
+}
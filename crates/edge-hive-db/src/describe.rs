@@ -0,0 +1,377 @@
+//! Introspection of the data tables
+//!
+//! [`DatabaseService::describe`] reads `INFO FOR DB` and `INFO FOR TABLE`,
+//! whose answers are the `DEFINE` statements of each table, field and index,
+//! and turns them into [`TableInfo`]s that clients can generate forms or
+//! typed SDKs from. System tables are left out, like everywhere else in the
+//! data API.
+//!
+//! Schemaless tables have no defined fields; their shape is whatever their
+//! JSON Schema (see `schema.rs`) says, which is included when there is one.
+
+use crate::filter::ListQuery;
+use crate::policy::ScopedDatabase;
+use crate::query::Table;
+use crate::{DatabaseService, DbError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Keywords ending the expression of a `DEFINE FIELD ... DEFAULT` clause
+const FIELD_CLAUSES: &[&str] =
+    &["READONLY", "VALUE", "ASSERT", "REFERENCE", "PERMISSIONS", "COMMENT"];
+
+/// Keywords ending the field list of a `DEFINE INDEX` statement
+const INDEX_CLAUSES: &[&str] = &["UNIQUE", "SEARCH", "HNSW", "MTREE", "COMMENT", "CONCURRENTLY"];
+
+/// Shape of one data table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableInfo {
+    pub name: String,
+    /// Whether records may only hold the defined fields
+    pub schemafull: bool,
+    pub fields: Vec<FieldInfo>,
+    pub indexes: Vec<IndexInfo>,
+    /// Number of records (only those visible to the caller, when scoped)
+    pub count: u64,
+    /// JSON Schema validating the table's records, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<Value>,
+    /// Whether the table keeps the history of its records
+    pub versioned: bool,
+}
+
+/// A defined field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldInfo {
+    /// Field path (`address.city`, `tags[*]`)
+    pub name: String,
+    /// SurrealQL type (`string`, `option<datetime>`, ...); `None` for `any`
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Default value expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    pub readonly: bool,
+}
+
+/// Kind of index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    Standard,
+    Unique,
+    /// Full-text search
+    Search,
+    /// Vector index (HNSW)
+    Hnsw,
+    /// Vector index (M-tree)
+    Mtree,
+}
+
+/// A defined index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexInfo {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub kind: IndexKind,
+}
+
+/// Split a `DEFINE` statement into words, keeping quoted strings and type
+/// parameters (`array<string>`) in one piece
+fn tokens(statement: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let (mut depth, mut quote) = (0usize, None);
+
+    for c in statement.chars() {
+        match quote {
+            Some(end) => {
+                current.push(c);
+                if c == end {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '\'' | '"' | '`' => {
+                    quote = Some(c);
+                    current.push(c);
+                }
+                '⟨' => {
+                    quote = Some('⟩');
+                    current.push(c);
+                }
+                // `<` only opens a parameter list when it follows a type name
+                '<' | '(' | '[' | '{' if c != '<' || !current.is_empty() => {
+                    depth += 1;
+                    current.push(c);
+                }
+                '>' | ')' | ']' | '}' if depth > 0 => {
+                    depth -= 1;
+                    current.push(c);
+                }
+                c if c.is_whitespace() && depth == 0 => {
+                    if !current.is_empty() {
+                        tokens.push(std::mem::take(&mut current));
+                    }
+                }
+                c => current.push(c),
+            },
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// Name without its escaping (`` `table` `` or `⟨table⟩`)
+fn unescape(name: &str) -> String {
+    name.replace(['`', '⟨', '⟩'], "")
+}
+
+/// Words following `keyword`, up to the next of `clauses`
+fn clause<'a>(tokens: &'a [String], keyword: &str, clauses: &[&str]) -> Option<&'a [String]> {
+    let start = tokens.iter().position(|t| t == keyword)? + 1;
+    let end = tokens[start..]
+        .iter()
+        .position(|t| clauses.contains(&t.as_str()))
+        .map_or(tokens.len(), |end| start + end);
+    Some(&tokens[start..end])
+}
+
+fn parse_field(statement: &str) -> Option<FieldInfo> {
+    let tokens = tokens(statement);
+    let name = unescape(tokens.get(2)?);
+
+    let kind = clause(&tokens, "TYPE", &[])
+        .and_then(|words| words.first())
+        .filter(|kind| kind.as_str() != "any")
+        .cloned();
+    let default = clause(&tokens, "DEFAULT", FIELD_CLAUSES).map(|words| {
+        let words = match words.first() {
+            Some(first) if first == "ALWAYS" => &words[1..],
+            _ => words,
+        };
+        words.join(" ")
+    });
+
+    Some(FieldInfo {
+        name,
+        kind,
+        default,
+        readonly: tokens.iter().any(|t| t == "READONLY"),
+    })
+}
+
+fn parse_index(statement: &str) -> Option<IndexInfo> {
+    let tokens = tokens(statement);
+    let name = unescape(tokens.get(2)?);
+
+    let fields = clause(&tokens, "FIELDS", INDEX_CLAUSES)
+        .or_else(|| clause(&tokens, "COLUMNS", INDEX_CLAUSES))
+        .unwrap_or_default()
+        .join(" ")
+        .split(',')
+        .map(|field| unescape(field.trim()))
+        .filter(|field| !field.is_empty())
+        .collect();
+
+    let has = |keyword: &str| tokens.iter().any(|t| t == keyword);
+    let kind = if has("UNIQUE") {
+        IndexKind::Unique
+    } else if has("SEARCH") {
+        IndexKind::Search
+    } else if has("HNSW") {
+        IndexKind::Hnsw
+    } else if has("MTREE") {
+        IndexKind::Mtree
+    } else {
+        IndexKind::Standard
+    };
+
+    Some(IndexInfo { name, fields, kind })
+}
+
+/// `DEFINE` statements of an `INFO FOR ...` section, by name
+pub(crate) fn definitions(info: &Value, section: &str) -> Vec<(String, String)> {
+    let mut definitions: Vec<(String, String)> = info
+        .get(section)
+        .and_then(Value::as_object)
+        .map(|section| {
+            section
+                .iter()
+                .filter_map(|(name, statement)| {
+                    Some((name.clone(), statement.as_str()?.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    definitions.sort();
+    definitions
+}
+
+impl DatabaseService {
+    pub(crate) async fn info(&self, statement: String) -> Result<Value, DbError> {
+        let info: Option<Value> = self.db().query(statement).await?.take(0)?;
+        Ok(info.unwrap_or_default())
+    }
+
+    /// Data tables with their `DEFINE TABLE` statement, by name
+    pub(crate) async fn data_tables(&self) -> Result<Vec<(Table, String)>, DbError> {
+        let info = self.info("INFO FOR DB".to_string()).await?;
+        Ok(definitions(&info, "tables")
+            .into_iter()
+            .filter_map(|(name, statement)| Some((Table::new(name).ok()?, statement)))
+            .filter(|(table, _)| !table.is_system())
+            .collect())
+    }
+
+    async fn describe_table(
+        &self,
+        table: Table,
+        definition: &str,
+        count: Option<&ListQuery>,
+    ) -> Result<TableInfo, DbError> {
+        let info = self.info(format!("INFO FOR TABLE {}", table)).await?;
+
+        Ok(TableInfo {
+            schemafull: tokens(definition).iter().any(|t| t == "SCHEMAFULL"),
+            fields: definitions(&info, "fields")
+                .iter()
+                .filter_map(|(_, statement)| parse_field(statement))
+                .collect(),
+            indexes: definitions(&info, "indexes")
+                .iter()
+                .filter_map(|(_, statement)| parse_index(statement))
+                .collect(),
+            count: match count {
+                Some(count) => self.count_records(count).await?,
+                None => 0,
+            },
+            json_schema: self.table_schema(&table).await?,
+            versioned: self.is_versioned(&table).await?,
+            name: table.to_string(),
+        })
+    }
+
+    /// Tables, fields, indexes and record counts of the data tables
+    pub async fn describe(&self) -> Result<Vec<TableInfo>, DbError> {
+        let mut tables = Vec::new();
+        for (table, definition) in self.data_tables().await? {
+            let count = ListQuery::new(table.clone());
+            tables.push(self.describe_table(table, &definition, Some(&count)).await?);
+        }
+        Ok(tables)
+    }
+
+    /// Like [`describe`](Self::describe), without counting the records
+    ///
+    /// Every `count` is 0. This only reads definitions, so it stays cheap on
+    /// large tables.
+    pub async fn describe_shapes(&self) -> Result<Vec<TableInfo>, DbError> {
+        let mut tables = Vec::new();
+        for (table, definition) in self.data_tables().await? {
+            tables.push(self.describe_table(table, &definition, None).await?);
+        }
+        Ok(tables)
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// Like [`DatabaseService::describe`], counting only the records the caller may read
+    pub async fn describe(&self) -> Result<Vec<TableInfo>, DbError> {
+        let mut tables = Vec::new();
+        for (table, definition) in self.db.data_tables().await? {
+            let count = self.restrict(ListQuery::new(table.clone()))?;
+            tables.push(self.db.describe_table(table, &definition, Some(&count)).await?);
+        }
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_field_definitions() {
+        let field = parse_field(
+            "DEFINE FIELD `table` ON _webhooks TYPE option<record<a | b>> \
+             DEFAULT ALWAYS 'x y' READONLY ASSERT $value < 10 PERMISSIONS FULL",
+        )
+        .unwrap();
+        assert_eq!(field.name, "table");
+        assert_eq!(field.kind.as_deref(), Some("option<record<a | b>>"));
+        assert_eq!(field.default.as_deref(), Some("'x y'"));
+        assert!(field.readonly);
+
+        let any = parse_field("DEFINE FIELD meta ON posts FLEXIBLE TYPE any PERMISSIONS FULL");
+        assert_eq!(any.unwrap().kind, None);
+    }
+
+    #[test]
+    fn test_parse_index_definitions() {
+        let index = parse_index("DEFINE INDEX email ON users FIELDS email UNIQUE").unwrap();
+        assert_eq!(index.fields, vec!["email"]);
+        assert_eq!(index.kind, IndexKind::Unique);
+
+        let index = parse_index(
+            "DEFINE INDEX search ON posts FIELDS title, `body` SEARCH ANALYZER a BM25(1.2,0.75)",
+        )
+        .unwrap();
+        assert_eq!(index.fields, vec!["title", "body"]);
+        assert_eq!(index.kind, IndexKind::Search);
+
+        let index = parse_index("DEFINE INDEX v ON docs FIELDS embedding HNSW DIMENSION 3");
+        assert_eq!(index.unwrap().kind, IndexKind::Hnsw);
+    }
+
+    #[tokio::test]
+    async fn test_describe_data_tables() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.query(
+            "DEFINE TABLE books SCHEMAFULL; \
+             DEFINE FIELD title ON books TYPE string; \
+             DEFINE FIELD pages ON books TYPE option<int> DEFAULT 0; \
+             DEFINE INDEX books_title ON books FIELDS title UNIQUE;",
+        )
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+        let posts = Table::new("posts").unwrap();
+        let schema = json!({"type": "object"});
+        db.set_table_schema(&posts, &schema).await.unwrap();
+
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+        db.scoped(&policies, &alice).create_record(&posts, json!({"a": 1})).await.unwrap();
+
+        let tables = db.describe().await.unwrap();
+        let names: Vec<&str> = tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["books", "posts"]);
+
+        let books = &tables[0];
+        assert!(books.schemafull);
+        let fields: Vec<(&str, Option<&str>)> =
+            books.fields.iter().map(|f| (f.name.as_str(), f.kind.as_deref())).collect();
+        assert_eq!(fields, vec![("pages", Some("option<int>")), ("title", Some("string"))]);
+        assert_eq!(books.fields[0].default.as_deref(), Some("0"));
+        assert_eq!(books.indexes[0].kind, IndexKind::Unique);
+
+        assert!(!tables[1].schemafull);
+        assert_eq!(tables[1].count, 1);
+        assert_eq!(tables[1].json_schema, Some(schema));
+
+        let scoped = db.scoped(&policies, &bob).describe().await.unwrap();
+        assert_eq!(scoped[1].count, 0);
+
+        let shapes = db.describe_shapes().await.unwrap();
+        let uncounted: Vec<TableInfo> =
+            tables.into_iter().map(|table| TableInfo { count: 0, ..table }).collect();
+        assert_eq!(shapes, uncounted);
+    }
+}
@@ -0,0 +1,292 @@
+//! Logical dumps of the whole database
+//!
+//! A dump is a header line followed by SurrealDB's own SurrealQL export:
+//!
+//! ```text
+//! -- edge-hive-dump {"format":1,"schema_version":2,"created_at":"..."}
+//! OPTION IMPORT;
+//! ...
+//! ```
+//!
+//! The header records the dump format and the schema version of the node
+//! that wrote it, so a restore can refuse dumps it does not understand and
+//! bring older ones up to date by running the pending migrations.
+//!
+//! Dumps can be wrapped in an [age](https://age-encryption.org) envelope;
+//! nodes use the key derived from their identity (see
+//! `NodeIdentity::age_identity`).
+
+use crate::{migrations, DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use tracing::info;
+
+/// Current dump format
+pub const DUMP_FORMAT: u32 = 1;
+
+/// Prefix of the header line
+const HEADER_PREFIX: &str = "-- edge-hive-dump ";
+
+/// First bytes of an age-encrypted file
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Whether a dump is wrapped in an age envelope
+pub fn is_encrypted(dump: &[u8]) -> bool {
+    dump.starts_with(AGE_MAGIC)
+}
+
+/// Metadata written at the start of every dump
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DumpHeader {
+    /// Dump format version
+    pub format: u32,
+    /// Latest migration applied on the exporting node
+    pub schema_version: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DumpHeader {
+    fn parse(line: &str) -> Result<Self, DbError> {
+        let json = line
+            .trim_end()
+            .strip_prefix(HEADER_PREFIX)
+            .ok_or_else(|| DbError::Dump("not an edge-hive dump".to_string()))?;
+        let header: Self =
+            serde_json::from_str(json).map_err(|e| DbError::Dump(format!("bad header: {}", e)))?;
+
+        if header.format != DUMP_FORMAT {
+            return Err(DbError::Dump(format!("unsupported dump format {}", header.format)));
+        }
+        if header.schema_version > migrations::latest_version() {
+            return Err(DbError::Dump(format!(
+                "dump has schema version {}, this node only knows up to {}",
+                header.schema_version,
+                migrations::latest_version()
+            )));
+        }
+        Ok(header)
+    }
+}
+
+/// Prefix of the database a dump is tried on before it replaces the real one
+const STAGING_PREFIX: &str = "import_";
+
+/// Export statements that can be replayed on a fresh database
+///
+/// Defining an array field also defines its `[*]` element field, which the
+/// export lists again on its own; overwriting keeps the replay from failing.
+fn replayable(statements: &str) -> String {
+    statements
+        .lines()
+        .map(|line| match line.strip_prefix("DEFINE FIELD ") {
+            Some(rest) => format!("DEFINE FIELD OVERWRITE {}\n", rest),
+            None => format!("{}\n", line),
+        })
+        .collect()
+}
+
+fn io_error(e: std::io::Error) -> DbError {
+    DbError::Dump(e.to_string())
+}
+
+impl DatabaseService {
+    /// Write a dump of every table to `writer`
+    pub async fn export<W: Write>(&self, mut writer: W) -> Result<DumpHeader, DbError> {
+        let header = DumpHeader {
+            format: DUMP_FORMAT,
+            schema_version: self
+                .applied_migrations()
+                .await?
+                .iter()
+                .map(|m| m.version)
+                .max()
+                .unwrap_or(0),
+            created_at: Utc::now(),
+        };
+        let json = serde_json::to_string(&header)
+            .map_err(|e| DbError::Serialization(e.to_string()))?;
+        writeln!(writer, "{}{}", HEADER_PREFIX, json).map_err(io_error)?;
+
+        // The export takes the database selected on the session when it
+        // starts, so other services only wait for it to start
+        let mut export = {
+            let db = self.db().switch().await?;
+            db.export(()).await?
+        };
+        while let Some(chunk) = export.next().await {
+            writer.write_all(&chunk?).map_err(io_error)?;
+        }
+        writer.flush().map_err(io_error)?;
+
+        Ok(header)
+    }
+
+    /// Write a dump encrypted to `recipient`
+    pub async fn export_encrypted<W: Write>(
+        &self,
+        writer: W,
+        recipient: &age::x25519::Recipient,
+    ) -> Result<DumpHeader, DbError> {
+        let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient.clone())])
+            .ok_or_else(|| DbError::Dump("no recipient".to_string()))?;
+        let mut writer = encryptor
+            .wrap_output(writer)
+            .map_err(|e| DbError::Dump(e.to_string()))?;
+
+        let header = self.export(&mut writer).await?;
+        writer.finish().map_err(io_error)?;
+        Ok(header)
+    }
+
+    /// Replace the contents of the database with a dump
+    ///
+    /// The dump is first loaded into a staging database; only when that
+    /// succeeds is the current database removed and the dump loaded in its
+    /// place. Any migrations newer than the dump are then applied.
+    pub async fn import<R: Read>(&self, reader: R) -> Result<DumpHeader, DbError> {
+        let (header, statements) = {
+            let mut reader = BufReader::new(reader);
+            let mut line = String::new();
+            reader.read_line(&mut line).map_err(io_error)?;
+            let header = DumpHeader::parse(&line)?;
+
+            let mut statements = String::new();
+            reader.read_to_string(&mut statements).map_err(io_error)?;
+            (header, statements)
+        };
+
+        let database = &self.config.database;
+        if !crate::query::is_identifier(database) {
+            return Err(DbError::InvalidIdentifier(database.clone()));
+        }
+        let statements = replayable(&statements);
+        let staging = format!("{}{}", STAGING_PREFIX, database);
+
+        // A `USE` inside a query only lasts for that query, so the dump is
+        // tried on the staging database without switching the service
+        let db = self.db();
+        let staged = db
+            .query(format!("REMOVE DATABASE IF EXISTS {}; USE DB {};", staging, staging))
+            .query(statements.as_str())
+            .await
+            .and_then(crate::storage::Response::check);
+        db.query(format!("REMOVE DATABASE IF EXISTS {}", staging)).await?.check()?;
+        staged?;
+
+        db.query(format!("REMOVE DATABASE IF EXISTS {}", database)).await?.check()?;
+        db.query(statements).await?.check()?;
+
+        let applied = self.migrate_up(None).await?;
+        // The dump brings its own data keys
+        self.reload_keys().await?;
+        info!(
+            "💾 Restored dump from {} (schema {}, migrated {:?})",
+            header.created_at, header.schema_version, applied
+        );
+
+        Ok(header)
+    }
+
+    /// Restore a dump encrypted to `identity`
+    pub async fn import_encrypted<R: Read>(
+        &self,
+        reader: R,
+        identity: &age::x25519::Identity,
+    ) -> Result<DumpHeader, DbError> {
+        let mut plaintext = Vec::new();
+        {
+            let decryptor =
+                match age::Decryptor::new(reader).map_err(|e| DbError::Dump(e.to_string()))? {
+                    age::Decryptor::Recipients(d) => d,
+                    _ => return Err(DbError::Dump("dump is not encrypted to a key".to_string())),
+                };
+            let mut reader = decryptor
+                .decrypt(std::iter::once(identity as &dyn age::Identity))
+                .map_err(|e| DbError::Dump(e.to_string()))?;
+            reader.read_to_end(&mut plaintext).map_err(io_error)?;
+        }
+
+        self.import(plaintext.as_slice()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{BoundQuery, Table};
+
+    async fn seeded() -> DatabaseService {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("notes").unwrap();
+        for text in ["one", "two"] {
+            db.create_record(&table, serde_json::json!({"text": text})).await.unwrap();
+        }
+        db
+    }
+
+    async fn note_count(db: &DatabaseService) -> usize {
+        db.execute(BoundQuery::new("SELECT * FROM notes")).await.unwrap().len()
+    }
+
+    #[test]
+    fn test_header_is_validated() {
+        assert!(DumpHeader::parse("OPTION IMPORT;").is_err());
+
+        let newer = format!(
+            "{}{{\"format\":1,\"schema_version\":{},\"created_at\":\"2024-01-01T00:00:00Z\"}}",
+            HEADER_PREFIX,
+            migrations::latest_version() + 1
+        );
+        assert!(DumpHeader::parse(&newer).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_import_roundtrip() {
+        let source = seeded().await;
+        let mut dump = Vec::new();
+        let header = source.export(&mut dump).await.unwrap();
+        assert_eq!(header.schema_version, migrations::latest_version());
+        assert!(dump.starts_with(HEADER_PREFIX.as_bytes()));
+
+        let target = DatabaseService::new_in_memory().await.unwrap();
+        target
+            .create_record(&Table::new("notes").unwrap(), serde_json::json!({"text": "stale"}))
+            .await
+            .unwrap();
+        target.import(dump.as_slice()).await.unwrap();
+
+        assert_eq!(note_count(&target).await, 2);
+        assert!(target.migration_status().await.unwrap().iter().all(|m| m.applied_at.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_failed_import_keeps_the_database() {
+        let target = seeded().await;
+        let mut dump = Vec::new();
+        target.export(&mut dump).await.unwrap();
+        dump.extend_from_slice(b"NOT SURREALQL;\n");
+
+        assert!(target.import(dump.as_slice()).await.is_err());
+        assert_eq!(note_count(&target).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_roundtrip() {
+        let source = seeded().await;
+        let key = age::x25519::Identity::generate();
+
+        let mut dump = Vec::new();
+        source.export_encrypted(&mut dump, &key.to_public()).await.unwrap();
+        assert!(is_encrypted(&dump));
+        assert!(!dump.windows(HEADER_PREFIX.len()).any(|w| w == HEADER_PREFIX.as_bytes()));
+
+        let target = DatabaseService::new_in_memory().await.unwrap();
+        let other = age::x25519::Identity::generate();
+        assert!(target.import_encrypted(dump.as_slice(), &other).await.is_err());
+
+        target.import_encrypted(dump.as_slice(), &key).await.unwrap();
+        assert_eq!(note_count(&target).await, 2);
+    }
+}
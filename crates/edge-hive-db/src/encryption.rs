@@ -0,0 +1,579 @@
+//! Field-level encryption
+//!
+//! Fields marked with [`DatabaseService::encrypt_fields`] are encrypted with
+//! XChaCha20-Poly1305 before they are written and decrypted when they are
+//! read back through this service, so callers only ever see plaintext. An
+//! encrypted value is stored as a string:
+//!
+//! ```text
+//! $enc:v<key version>:<base64 of nonce and ciphertext>
+//! ```
+//!
+//! holding the JSON of the original value, bound to its `table.field`.
+//!
+//! Data keys live in `_keys`, each wrapped with age to the node identity and
+//! any extra recipients (e.g. an offline backup key). They are unwrapped once
+//! by [`DatabaseService::unlock_keys`]; the newest key encrypts new values.
+//! [`DatabaseService::rotate_key`] adds a key and
+//! [`DatabaseService::reencrypt`] rewrites the values still under an older
+//! one. Old keys are kept, since record history may still use them.
+//!
+//! Encrypted fields cannot be filtered, sorted, searched or used in row
+//! policies, and the change log and live queries carry their ciphertext
+//! (see [`crate::ScopedDatabase::readable`]).
+
+use crate::filter::Field;
+use crate::query::{BoundQuery, RecordId, Table};
+use crate::user::StoredUser;
+use crate::{DatabaseService, DbError};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Start of every encrypted value
+const ENVELOPE_PREFIX: &str = "$enc:v";
+
+const NONCE_LEN: usize = 24;
+
+/// Rows re-encrypted per statement
+const REENCRYPT_BATCH: u64 = 200;
+
+/// Fields of system tables that may be encrypted (the others are looked up by value)
+const SYSTEM_FIELDS: &[(&str, &[&str])] = &[("users", &["name"]), ("_mfa", &["secret"])];
+
+/// Unwrapped data keys and the fields they protect
+#[derive(Default)]
+pub(crate) struct Keyring {
+    keys: HashMap<u32, Key>,
+    /// Version encrypting new values (the newest key)
+    active: Option<u32>,
+    /// Identity the keys were unlocked with, kept to reload them after a restore
+    identity: Option<age::x25519::Identity>,
+    /// Recipients new keys are wrapped to besides the identity
+    extra: Vec<age::x25519::Recipient>,
+    fields: HashMap<Table, Vec<Field>>,
+}
+
+/// A stored data key
+#[derive(Deserialize)]
+struct WrappedKey {
+    version: u32,
+    wrapped: String,
+}
+
+fn encryption_error(e: impl std::fmt::Display) -> DbError {
+    DbError::Encryption(e.to_string())
+}
+
+fn is_envelope(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s.starts_with(ENVELOPE_PREFIX))
+}
+
+fn wrap(key: &Key, recipients: &[age::x25519::Recipient]) -> Result<String, DbError> {
+    let recipients = recipients
+        .iter()
+        .map(|r| Box::new(r.clone()) as Box<dyn age::Recipient + Send>)
+        .collect();
+    let encryptor = age::Encryptor::with_recipients(recipients)
+        .ok_or_else(|| encryption_error("no recipient"))?;
+
+    let mut wrapped = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut wrapped).map_err(encryption_error)?;
+    writer.write_all(key.as_slice()).map_err(encryption_error)?;
+    writer.finish().map_err(encryption_error)?;
+    Ok(STANDARD_NO_PAD.encode(wrapped))
+}
+
+fn unwrap(wrapped: &str, identity: &age::x25519::Identity) -> Result<Key, DbError> {
+    let wrapped = STANDARD_NO_PAD.decode(wrapped).map_err(encryption_error)?;
+    let decryptor = match age::Decryptor::new(wrapped.as_slice()).map_err(encryption_error)? {
+        age::Decryptor::Recipients(d) => d,
+        _ => return Err(encryption_error("data key is not wrapped to a key")),
+    };
+    let mut reader = decryptor
+        .decrypt(std::iter::once(identity as &dyn age::Identity))
+        .map_err(encryption_error)?;
+
+    let mut key = Vec::new();
+    reader.read_to_end(&mut key).map_err(encryption_error)?;
+    if key.len() != 32 {
+        return Err(encryption_error("data key has the wrong length"));
+    }
+    Ok(*Key::from_slice(&key))
+}
+
+/// Parse an age recipient (`age1...`) to wrap data keys to
+pub fn parse_recipient(recipient: &str) -> Result<age::x25519::Recipient, DbError> {
+    recipient
+        .trim()
+        .parse()
+        .map_err(|e| encryption_error(format!("invalid age recipient '{}': {}", recipient, e)))
+}
+
+/// Value at a dotted path, for in-place changes
+fn lookup_mut<'a>(record: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    path.split('.').try_fold(record, |value, key| value.get_mut(key))
+}
+
+impl Keyring {
+    fn seal(&self, table: &Table, field: &Field, value: &Value) -> Result<String, DbError> {
+        let version = self.active.ok_or_else(|| encryption_error("data keys are locked"))?;
+        let cipher = XChaCha20Poly1305::new(&self.keys[&version]);
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(value).map_err(encryption_error)?;
+        let aad = format!("{}.{}", table, field);
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(encryption_error)?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}{}:{}", ENVELOPE_PREFIX, version, STANDARD_NO_PAD.encode(sealed)))
+    }
+
+    fn open(&self, table: &Table, field: &Field, envelope: &str) -> Result<Value, DbError> {
+        let invalid =
+            || encryption_error(format!("invalid encrypted value in {}.{}", table, field));
+        let (version, sealed) = envelope
+            .strip_prefix(ENVELOPE_PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or_else(invalid)?;
+        let version: u32 = version.parse().map_err(|_| invalid())?;
+        let key = self.keys.get(&version).ok_or_else(|| {
+            encryption_error(format!("data key {} is not unlocked", version))
+        })?;
+
+        let sealed = STANDARD_NO_PAD.decode(sealed).map_err(|_| invalid())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = format!("{}.{}", table, field);
+        let plaintext = XChaCha20Poly1305::new(key)
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(&plaintext).map_err(encryption_error)
+    }
+}
+
+impl DatabaseService {
+    /// Encrypt `fields` of `table` from now on
+    ///
+    /// Existing plaintext values stay readable and are encrypted the next time
+    /// they are written or by [`DatabaseService::reencrypt`].
+    pub fn encrypt_fields(&self, table: &Table, fields: &[Field]) -> Result<(), DbError> {
+        let allowed = SYSTEM_FIELDS.iter().find(|(name, _)| *name == table.as_str());
+        if let Some((_, allowed)) = allowed {
+            if let Some(field) = fields.iter().find(|f| !allowed.contains(&f.as_str())) {
+                return Err(DbError::InvalidQuery(format!(
+                    "field '{}' of {} cannot be encrypted",
+                    field, table
+                )));
+            }
+        } else if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+        if let Some(id) = fields.iter().find(|f| f.root() == "id") {
+            return Err(DbError::InvalidQuery(format!("field '{}' cannot be encrypted", id)));
+        }
+
+        let mut keyring = self.keyring.write().unwrap_or_else(|e| e.into_inner());
+        keyring.fields.insert(table.clone(), fields.to_vec());
+        Ok(())
+    }
+
+    /// Encrypted fields of `table`
+    pub fn encrypted_fields(&self, table: &Table) -> Vec<Field> {
+        let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+        keyring.fields.get(table).cloned().unwrap_or_default()
+    }
+
+    /// Unwrap the data keys with `identity`, creating the first one if needed
+    ///
+    /// New keys are wrapped to `identity` and every extra recipient. Returns
+    /// the version of the key encrypting new values.
+    pub async fn unlock_keys(
+        &self,
+        identity: &age::x25519::Identity,
+        recipients: &[age::x25519::Recipient],
+    ) -> Result<u32, DbError> {
+        let rows = self
+            .execute(BoundQuery::new("SELECT version, wrapped FROM _keys ORDER BY version"))
+            .await?;
+
+        let mut keys = HashMap::new();
+        for row in rows {
+            let key: WrappedKey = serde_json::from_value(row)
+                .map_err(|e| DbError::Serialization(e.to_string()))?;
+            keys.insert(key.version, unwrap(&key.wrapped, identity)?);
+        }
+
+        {
+            let mut keyring = self.keyring.write().unwrap_or_else(|e| e.into_inner());
+            keyring.active = keys.keys().max().copied();
+            keyring.keys = keys;
+            keyring.identity = Some(identity.clone());
+            keyring.extra = recipients.to_vec();
+        }
+
+        let active = self.keyring.read().unwrap_or_else(|e| e.into_inner()).active;
+        match active {
+            Some(version) => Ok(version),
+            None => self.rotate_key().await,
+        }
+    }
+
+    /// Add a new data key, used for every value encrypted from now on
+    ///
+    /// Values under older keys stay readable; [`DatabaseService::reencrypt`]
+    /// moves them to the new key.
+    pub async fn rotate_key(&self) -> Result<u32, DbError> {
+        let (version, key, wrapped) = {
+            let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+            let identity = keyring
+                .identity
+                .as_ref()
+                .ok_or_else(|| encryption_error("data keys are locked"))?;
+            let recipients: Vec<_> = std::iter::once(identity.to_public())
+                .chain(keyring.extra.iter().cloned())
+                .collect();
+            let version = keyring.keys.keys().max().map_or(1, |v| v + 1);
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            (version, key, wrap(&key, &recipients)?)
+        };
+
+        let query = BoundQuery::new("CREATE _keys CONTENT { version: $version, wrapped: $wrapped }")
+            .bind("version", version)
+            .bind("wrapped", wrapped);
+        self.execute(query).await?;
+
+        let mut keyring = self.keyring.write().unwrap_or_else(|e| e.into_inner());
+        keyring.keys.insert(version, key);
+        keyring.active = Some(version);
+        Ok(version)
+    }
+
+    /// Encrypt the same fields as `base`, unlocking this database's own data
+    /// keys with the identity `base` was unlocked with
+    pub(crate) async fn inherit_keys(&self, base: &DatabaseService) -> Result<(), DbError> {
+        let (fields, unlocked) = {
+            let keyring = base.keyring.read().unwrap_or_else(|e| e.into_inner());
+            let unlocked =
+                keyring.identity.clone().map(|identity| (identity, keyring.extra.clone()));
+            (keyring.fields.clone(), unlocked)
+        };
+        self.keyring.write().unwrap_or_else(|e| e.into_inner()).fields = fields;
+
+        if let Some((identity, extra)) = unlocked {
+            self.unlock_keys(&identity, &extra).await?;
+        }
+        Ok(())
+    }
+
+    /// Unwrap the data keys again, e.g. after a dump replaced them
+    pub(crate) async fn reload_keys(&self) -> Result<(), DbError> {
+        let unlocked = {
+            let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+            keyring.identity.clone().map(|identity| (identity, keyring.extra.clone()))
+        };
+        if let Some((identity, extra)) = unlocked {
+            self.unlock_keys(&identity, &extra).await?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt every plaintext or outdated value of the encrypted fields
+    /// with the current key, returning the number of rewritten records
+    pub async fn reencrypt(&self) -> Result<u64, DbError> {
+        let (tables, active) = {
+            let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+            let active = keyring.active.ok_or_else(|| encryption_error("data keys are locked"))?;
+            (keyring.fields.clone(), active)
+        };
+
+        let current = format!("{}{}:", ENVELOPE_PREFIX, active);
+        let mut rewritten = 0;
+        for (table, fields) in tables {
+            let stale: Vec<String> = fields
+                .iter()
+                .map(|f| {
+                    format!(
+                        "({0} != NONE AND {0} != NULL AND !(type::is::string({0}) \
+                         AND string::starts_with({0}, $current)))",
+                        f
+                    )
+                })
+                .collect();
+            let query = BoundQuery::new(format!(
+                "SELECT *, <string> record::id(id) AS __key FROM type::table($table) \
+                 WHERE {} LIMIT $batch",
+                stale.join(" OR ")
+            ))
+            .bind("table", table.as_str())
+            .bind("current", current.as_str())
+            .bind("batch", REENCRYPT_BATCH);
+
+            loop {
+                let rows = self.execute(query.clone()).await?;
+                for mut row in rows.iter().cloned() {
+                    let key = row["__key"].as_str().unwrap_or_default().to_string();
+                    let id = RecordId::new(table.clone(), key)?;
+                    self.open_fields(&table, &mut row, &fields)?;
+                    self.seal_fields(&table, &mut row, &fields)?;
+
+                    let patch: Map<String, Value> = fields
+                        .iter()
+                        .filter_map(|f| {
+                            row.get(f.root()).map(|v| (f.root().to_string(), v.clone()))
+                        })
+                        .collect();
+                    self.execute(BoundQuery::merge(&id, Value::Object(patch))).await?;
+                    rewritten += 1;
+                }
+                if (rows.len() as u64) < REENCRYPT_BATCH {
+                    break;
+                }
+            }
+        }
+        Ok(rewritten)
+    }
+
+    fn seal_fields(
+        &self,
+        table: &Table,
+        record: &mut Value,
+        fields: &[Field],
+    ) -> Result<(), DbError> {
+        let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+        for field in fields {
+            if let Some(value) = lookup_mut(record, field.as_str()) {
+                if !value.is_null() && !is_envelope(value) {
+                    *value = Value::String(keyring.seal(table, field, value)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn open_fields(
+        &self,
+        table: &Table,
+        record: &mut Value,
+        fields: &[Field],
+    ) -> Result<(), DbError> {
+        let keyring = self.keyring.read().unwrap_or_else(|e| e.into_inner());
+        for field in fields {
+            if let Some(value) = lookup_mut(record, field.as_str()) {
+                if let Value::String(envelope) = value {
+                    if envelope.starts_with(ENVELOPE_PREFIX) {
+                        *value = keyring.open(table, field, envelope)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encrypt the encrypted fields of content or a patch about to be written
+    pub(crate) fn seal(&self, table: &Table, mut content: Value) -> Result<Value, DbError> {
+        let fields = self.encrypted_fields(table);
+        if !fields.is_empty() {
+            self.seal_fields(table, &mut content, &fields)?;
+        }
+        Ok(content)
+    }
+
+    /// Decrypt the encrypted fields of a record read from `table`
+    pub(crate) fn open_record(&self, table: &Table, mut record: Value) -> Result<Value, DbError> {
+        let fields = self.encrypted_fields(table);
+        if !fields.is_empty() {
+            self.open_fields(table, &mut record, &fields)?;
+        }
+        Ok(record)
+    }
+
+    pub(crate) fn open_rows(&self, table: &Table, rows: Vec<Value>) -> Result<Vec<Value>, DbError> {
+        rows.into_iter().map(|row| self.open_record(table, row)).collect()
+    }
+
+    /// Encrypt the encrypted fields of a user about to be written
+    pub(crate) fn seal_user(&self, user: &StoredUser) -> Result<StoredUser, DbError> {
+        let users = Table::new("users")?;
+        let mut user = user.clone();
+        if let Some(name) = user.name.take() {
+            let sealed = self.seal(&users, serde_json::json!({ "name": name }))?;
+            user.name = sealed["name"].as_str().map(str::to_string);
+        }
+        Ok(user)
+    }
+
+    /// Decrypt the encrypted fields of a user read back
+    pub(crate) fn open_user(
+        &self,
+        user: Option<StoredUser>,
+    ) -> Result<Option<StoredUser>, DbError> {
+        let users = Table::new("users")?;
+        user.map(|mut user| {
+            if let Some(name) = user.name.take() {
+                let opened = self.open_record(&users, serde_json::json!({ "name": name }))?;
+                user.name = opened["name"].as_str().map(str::to_string);
+            }
+            Ok(user)
+        })
+        .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+    use chrono::Utc;
+    use serde_json::json;
+
+    async fn unlocked() -> (DatabaseService, age::x25519::Identity, Table) {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let identity = age::x25519::Identity::generate();
+        let table = Table::new("patients").unwrap();
+        db.encrypt_fields(&table, &[Field::new("ssn").unwrap(), Field::new("notes.body").unwrap()])
+            .unwrap();
+        assert_eq!(db.unlock_keys(&identity, &[]).await.unwrap(), 1);
+        (db, identity, table)
+    }
+
+    async fn stored(db: &DatabaseService, id: &RecordId) -> Value {
+        let query = BoundQuery::new("SELECT * FROM type::thing($table, $key)")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str());
+        db.execute(query).await.unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_fields_are_encrypted_at_rest() {
+        let (db, _, table) = unlocked().await;
+        let id = RecordId::new(table.clone(), "p1").unwrap();
+        let created = db
+            .create_record_at(&id, json!({"name": "Ada", "ssn": "123", "notes": {"body": [1]}}))
+            .await
+            .unwrap();
+        assert_eq!(created["ssn"], "123");
+
+        let raw = stored(&db, &id).await;
+        assert_eq!(raw["name"], "Ada");
+        assert!(raw["ssn"].as_str().unwrap().starts_with("$enc:v1:"));
+        assert!(is_envelope(&raw["notes"]["body"]));
+
+        let record = db.select_record(&id).await.unwrap().unwrap();
+        assert_eq!(record["notes"]["body"], json!([1]));
+
+        // A ciphertext moved to another field does not decrypt
+        let mut moved = raw.clone();
+        moved["notes"]["body"] = raw["ssn"].clone();
+        assert!(db.open_record(&table, moved).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scoped_reads_and_writes_are_transparent() {
+        let (db, _, table) = unlocked().await;
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let scoped = db.scoped(&policies, &alice);
+
+        let created = scoped.create_record(&table, json!({"ssn": "1"})).await.unwrap();
+        let key = created["id"].as_str().unwrap().split_once(':').unwrap().1.to_string();
+        let id = RecordId::new(table.clone(), key).unwrap();
+        let merged = scoped.merge_record(&id, json!({"ssn": "2"})).await.unwrap().unwrap();
+        assert_eq!(merged["ssn"], "2");
+        assert!(is_envelope(&stored(&db, &id).await["ssn"]));
+
+        let page = scoped.list_records(crate::ListQuery::new(table.clone())).await.unwrap();
+        assert_eq!(page.rows[0]["ssn"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_rotation_reencrypts_existing_rows() {
+        let (db, identity, table) = unlocked().await;
+        for i in 0..3 {
+            let id = RecordId::new(table.clone(), format!("p{}", i)).unwrap();
+            db.create_record_at(&id, json!({"ssn": i})).await.unwrap();
+        }
+        // Written before the field was marked
+        let legacy = RecordId::new(table.clone(), "legacy").unwrap();
+        db.execute(BoundQuery::create_at(&legacy, json!({"ssn": "old"}))).await.unwrap();
+
+        assert_eq!(db.rotate_key().await.unwrap(), 2);
+        assert_eq!(db.reencrypt().await.unwrap(), 4);
+        assert_eq!(db.reencrypt().await.unwrap(), 0);
+        let raw = stored(&db, &RecordId::new(table.clone(), "p2").unwrap()).await;
+        assert!(raw["ssn"].as_str().unwrap().starts_with("$enc:v2:"));
+
+        // A restarted node unwraps both keys
+        let reopened = DatabaseService {
+            session: db.session.clone(),
+            config: db.config.clone(),
+            keyring: Default::default(),
+            project: None,
+            branch: None,
+        };
+        reopened.encrypt_fields(&table, &[Field::new("ssn").unwrap()]).unwrap();
+        assert!(reopened.select_record(&legacy).await.is_err());
+        assert_eq!(reopened.unlock_keys(&identity, &[]).await.unwrap(), 2);
+        assert_eq!(reopened.select_record(&legacy).await.unwrap().unwrap()["ssn"], "old");
+
+        let stranger = age::x25519::Identity::generate();
+        assert!(reopened.unlock_keys(&stranger, &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_user_names_and_rules() {
+        let (db, _, _) = unlocked().await;
+        let users = Table::new("users").unwrap();
+        assert!(db.encrypt_fields(&users, &[Field::new("email").unwrap()]).is_err());
+        assert!(db.encrypt_fields(&Table::new("_keys").unwrap(), &[]).is_err());
+        db.encrypt_fields(&users, &[Field::new("name").unwrap()]).unwrap();
+
+        let user = StoredUser {
+            id: None,
+            email: "ada@example.com".into(),
+            name: Some("Ada".into()),
+            password_hash: "hash".into(),
+            provider: None,
+            provider_id: None,
+            avatar_url: None,
+            role: "user".into(),
+            email_verified: false,
+            created_at: Utc::now().into(),
+            updated_at: Utc::now().into(),
+        };
+        let created = db.create_user(&user).await.unwrap();
+        assert_eq!(created.name.as_deref(), Some("Ada"));
+        let raw = db.execute(BoundQuery::new("SELECT VALUE name FROM users")).await.unwrap();
+        assert!(is_envelope(&raw[0]));
+
+        let found = db.get_user_by_email("ada@example.com").await.unwrap().unwrap();
+        assert_eq!(found.name.as_deref(), Some("Ada"));
+    }
+}
@@ -0,0 +1,795 @@
+//! PostgREST-style list queries
+//!
+//! A request such as `?age=gt.18&order=created_at.desc&select=id,name&limit=50`
+//! is parsed into a [`ListQuery`] and rendered to SurrealQL with every value
+//! bound as a variable. Field names are validated identifiers, which makes
+//! them the only user input that reaches the statement text.
+//!
+//! Pagination is keyset based: each page ends with an opaque cursor holding
+//! the ordering values of its last row, so later pages stay stable while
+//! records are inserted or deleted.
+//!
+//! On versioned tables, `as_of` reads the rows as they were at a point in time
+//! from the record history instead of the live table.
+
+use crate::policy::IDENTITY_VAR;
+use crate::query::{is_identifier, BoundQuery, Table};
+use crate::DbError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Rows returned when no `limit` is given
+pub const DEFAULT_LIMIT: u64 = 100;
+
+/// Upper bound for `limit`
+pub const MAX_LIMIT: u64 = 1000;
+
+/// Extra projection carrying the keyset of each row (stripped from results)
+const CURSOR_FIELD: &str = "__cursor";
+
+/// A validated field path (`name`, `address.city`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Field(String);
+
+impl Field {
+    pub fn new(path: impl Into<String>) -> Result<Self, DbError> {
+        let path = path.into();
+        if path.split('.').all(is_identifier) {
+            Ok(Self(path))
+        } else {
+            Err(DbError::InvalidIdentifier(path))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Top-level field the path starts with
+    pub fn root(&self) -> &str {
+        self.0.split('.').next().unwrap_or(&self.0)
+    }
+
+    fn is_id(&self) -> bool {
+        self.0 == "id"
+    }
+}
+
+impl TryFrom<String> for Field {
+    type Error = DbError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<Field> for String {
+    fn from(field: Field) -> Self {
+        field.0
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Comparison operator of a filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterOp {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// Case-sensitive pattern match, `*` matches any run of characters
+    Like,
+    /// Case-insensitive `Like`
+    Ilike,
+    /// Membership in a list: `in.(a,b,c)`
+    In,
+    /// `is.null`, `is.true` or `is.false`
+    Is,
+}
+
+impl FilterOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => "eq",
+            FilterOp::Neq => "neq",
+            FilterOp::Gt => "gt",
+            FilterOp::Gte => "gte",
+            FilterOp::Lt => "lt",
+            FilterOp::Lte => "lte",
+            FilterOp::Like => "like",
+            FilterOp::Ilike => "ilike",
+            FilterOp::In => "in",
+            FilterOp::Is => "is",
+        }
+    }
+
+    /// SurrealQL operator for the plain comparison operators
+    fn operator(&self) -> &'static str {
+        match self {
+            FilterOp::Eq | FilterOp::Is => "=",
+            FilterOp::Neq => "!=",
+            FilterOp::Gt => ">",
+            FilterOp::Gte => ">=",
+            FilterOp::Lt => "<",
+            FilterOp::Lte => "<=",
+            FilterOp::In => "INSIDE",
+            FilterOp::Like | FilterOp::Ilike => unreachable!("pattern operators render as functions"),
+        }
+    }
+}
+
+impl FromStr for FilterOp {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "eq" => FilterOp::Eq,
+            "neq" => FilterOp::Neq,
+            "gt" => FilterOp::Gt,
+            "gte" => FilterOp::Gte,
+            "lt" => FilterOp::Lt,
+            "lte" => FilterOp::Lte,
+            "like" => FilterOp::Like,
+            "ilike" => FilterOp::Ilike,
+            "in" => FilterOp::In,
+            "is" => FilterOp::Is,
+            other => return Err(DbError::InvalidQuery(format!("Unknown operator '{}'", other))),
+        })
+    }
+}
+
+/// A single `field=[not.]op.value` condition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: Field,
+    pub op: FilterOp,
+    pub value: Value,
+    pub negated: bool,
+}
+
+impl Filter {
+    pub fn new(field: Field, op: FilterOp, value: impl Into<Value>) -> Self {
+        Self {
+            field,
+            op,
+            value: value.into(),
+            negated: false,
+        }
+    }
+
+    /// Parse the right-hand side of `field=[not.]op.value`
+    ///
+    /// Values are typed the way they read: `18` is a number, `true` a bool,
+    /// `null` is null and anything else a string. Wrap a value in double quotes
+    /// to force a string (`eq."18"`).
+    pub fn parse(field: Field, expr: &str) -> Result<Self, DbError> {
+        let (negated, expr) = match expr.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, expr),
+        };
+        let (op, raw) = expr.split_once('.').ok_or_else(|| {
+            DbError::InvalidQuery(format!("Expected operator.value for '{}'", field))
+        })?;
+        let op: FilterOp = op.parse()?;
+
+        let value = match op {
+            FilterOp::In => {
+                let items = raw
+                    .strip_prefix('(')
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .ok_or_else(|| {
+                        DbError::InvalidQuery(format!("'in' expects a list like (a,b) for '{}'", field))
+                    })?;
+                Value::Array(split_list(items).map(parse_value).collect())
+            }
+            FilterOp::Is => match raw {
+                "null" => Value::Null,
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => {
+                    return Err(DbError::InvalidQuery(format!(
+                        "'is' expects null, true or false for '{}'",
+                        field
+                    )))
+                }
+            },
+            FilterOp::Like | FilterOp::Ilike => Value::String(raw.to_string()),
+            _ => parse_value(raw),
+        };
+
+        Ok(Self {
+            field,
+            op,
+            value,
+            negated,
+        })
+    }
+
+    /// Render the condition, binding its value as `$name`
+    pub(crate) fn render(&self, name: &str, vars: &mut Map<String, Value>) -> String {
+        let field = self.field.as_str();
+        let condition = match self.op {
+            FilterOp::Is if self.value.is_null() => format!("({0} IS NONE OR {0} IS NULL)", field),
+            FilterOp::Like | FilterOp::Ilike => {
+                let pattern = self.value.as_str().unwrap_or_default();
+                let regex = like_to_regex(pattern, self.op == FilterOp::Ilike);
+                vars.insert(name.to_string(), Value::String(regex));
+                format!("string::matches(<string> ({} ?? ''), ${})", field, name)
+            }
+            op => {
+                vars.insert(name.to_string(), self.value.clone());
+                format!("{} {} ${}", field, op.operator(), name)
+            }
+        };
+
+        if self.negated {
+            format!("!({})", condition)
+        } else {
+            condition
+        }
+    }
+
+    /// Canonical `field=[not.]op.value` form
+    fn normalized(&self) -> String {
+        format!(
+            "{}={}{}.{}",
+            self.field,
+            if self.negated { "not." } else { "" },
+            self.op.as_str(),
+            self.value
+        )
+    }
+}
+
+/// Sort direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Asc => "asc",
+            Direction::Desc => "desc",
+        }
+    }
+}
+
+/// One `field[.asc|.desc]` entry of `order=`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    pub field: Field,
+    pub direction: Direction,
+}
+
+impl Order {
+    pub fn asc(field: Field) -> Self {
+        Self {
+            field,
+            direction: Direction::Asc,
+        }
+    }
+
+    pub fn desc(field: Field) -> Self {
+        Self {
+            field,
+            direction: Direction::Desc,
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, DbError> {
+        match s.rsplit_once('.') {
+            Some((field, "asc")) => Ok(Self::asc(Field::new(field)?)),
+            Some((field, "desc")) => Ok(Self::desc(Field::new(field)?)),
+            _ => Ok(Self::asc(Field::new(s)?)),
+        }
+    }
+}
+
+/// Opaque keyset cursor: the ordering values of the last row of a page
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor(Vec<Value>);
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Value::Array(self.0.clone()).to_string())
+    }
+
+    pub fn decode(s: &str) -> Result<Self, DbError> {
+        let invalid = || DbError::InvalidQuery("Invalid cursor".to_string());
+        let bytes = URL_SAFE_NO_PAD.decode(s).map_err(|_| invalid())?;
+        match serde_json::from_slice(&bytes).map_err(|_| invalid())? {
+            Value::Array(values) => Ok(Self(values)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// One page of a [`ListQuery`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Page {
+    pub rows: Vec<Value>,
+    /// Number of rows matching the filters, across all pages
+    pub total: u64,
+    /// Cursor of the next page (`None` on the last page)
+    pub next_cursor: Option<String>,
+}
+
+/// A filtered, sorted and paginated `SELECT` over one table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListQuery {
+    pub table: Table,
+    /// Projected fields (empty selects every field)
+    pub select: Vec<Field>,
+    /// Conditions, combined with `AND`
+    pub filters: Vec<Filter>,
+    pub order: Vec<Order>,
+    pub limit: u64,
+    pub offset: u64,
+    pub cursor: Option<Cursor>,
+    /// Read the table as it was at this time (versioned tables only)
+    pub as_of: Option<DateTime<Utc>>,
+    /// Row policy added by [`crate::policy::ScopedDatabase`]
+    pub(crate) guard: Option<Guard>,
+}
+
+/// Trusted row predicate plus the identity it is evaluated against
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Guard {
+    pub predicate: String,
+    pub identity: Value,
+}
+
+impl ListQuery {
+    pub fn new(table: Table) -> Self {
+        Self {
+            table,
+            select: Vec::new(),
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            cursor: None,
+            as_of: None,
+            guard: None,
+        }
+    }
+
+    /// Build a query from URL query parameters
+    ///
+    /// `select`, `order`, `limit`, `offset`, `cursor` and `as_of` are reserved;
+    /// every other parameter is a filter on the field of the same name.
+    pub fn from_params<I, K, V>(table: Table, params: I) -> Result<Self, DbError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut query = Self::new(table);
+
+        for (key, value) in params {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            match key {
+                "select" if value.trim() == "*" => query.select.clear(),
+                "select" => {
+                    query.select = split_list(value).map(Field::new).collect::<Result<_, _>>()?
+                }
+                "order" => {
+                    query.order = split_list(value).map(Order::parse).collect::<Result<_, _>>()?
+                }
+                "limit" => query.limit = parse_count(key, value)?.clamp(1, MAX_LIMIT),
+                "offset" => query.offset = parse_count(key, value)?,
+                "cursor" => query.cursor = Some(Cursor::decode(value)?),
+                "as_of" => query.as_of = Some(parse_time(key, value)?),
+                field => query.filters.push(Filter::parse(Field::new(field)?, value)?),
+            }
+        }
+
+        if let Some(cursor) = &query.cursor {
+            if cursor.0.len() != query.keyset().len() {
+                return Err(DbError::InvalidQuery(
+                    "Cursor does not match the requested order".to_string(),
+                ));
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// Canonical form, equal for requests that select the same page
+    pub fn normalized(&self) -> String {
+        let mut parts = Vec::new();
+
+        if !self.select.is_empty() {
+            let mut select: Vec<&str> = self.select.iter().map(Field::as_str).collect();
+            select.sort_unstable();
+            select.dedup();
+            parts.push(format!("select={}", select.join(",")));
+        }
+
+        let mut filters: Vec<String> = self.filters.iter().map(Filter::normalized).collect();
+        filters.sort();
+        parts.extend(filters);
+
+        if !self.order.is_empty() {
+            let order: Vec<String> = self
+                .order
+                .iter()
+                .map(|o| format!("{}.{}", o.field, o.direction.as_str()))
+                .collect();
+            parts.push(format!("order={}", order.join(",")));
+        }
+
+        parts.push(format!("limit={}", self.limit));
+        parts.push(format!("offset={}", self.offset));
+        if let Some(cursor) = &self.cursor {
+            parts.push(format!("cursor={}", cursor.encode()));
+        }
+        if let Some(as_of) = &self.as_of {
+            parts.push(format!("as_of={}", as_of.to_rfc3339()));
+        }
+        // Guarded pages differ per caller
+        if let Some(guard) = &self.guard {
+            parts.push(format!("identity={}", guard.identity));
+        }
+
+        parts.join("&")
+    }
+
+    /// Requested order followed by `id` as a tie-breaker
+    fn keyset(&self) -> Vec<Order> {
+        let mut keyset = self.order.clone();
+        if !keyset.iter().any(|o| o.field.is_id()) {
+            keyset.push(Order::asc(Field("id".to_string())));
+        }
+        keyset
+    }
+
+    /// `WHERE` clause for the filters (and cursor, if any)
+    fn render_where(&self, keyset: &[Order], vars: &mut Map<String, Value>) -> String {
+        let mut conditions: Vec<String> = self
+            .filters
+            .iter()
+            .enumerate()
+            .map(|(i, filter)| filter.render(&format!("f{}", i), vars))
+            .collect();
+
+        if let Some(guard) = &self.guard {
+            vars.insert(IDENTITY_VAR.into(), guard.identity.clone());
+            conditions.push(format!("({})", guard.predicate));
+        }
+
+        if let Some(cursor) = &self.cursor {
+            conditions.push(render_after(keyset, cursor, vars));
+        }
+
+        if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        }
+    }
+
+    /// Rows the query reads from: the live table, or its history at `as_of`
+    fn render_source(&self, vars: &mut Map<String, Value>) -> String {
+        vars.insert("table".into(), self.table.as_str().into());
+        match &self.as_of {
+            Some(as_of) => {
+                vars.insert("as_of".into(), as_of.to_rfc3339().into());
+                format!("({})", crate::history::AS_OF_SOURCE)
+            }
+            None => "type::table($table)".to_string(),
+        }
+    }
+
+    /// Statement selecting one page (plus one row to detect a next page)
+    pub(crate) fn page_query(&self) -> BoundQuery {
+        let keyset = self.keyset();
+        let mut vars = Map::new();
+        let source = self.render_source(&mut vars);
+        vars.insert("limit".into(), (self.limit + 1).into());
+        vars.insert("start".into(), self.offset.into());
+
+        let projection = if self.select.is_empty() {
+            "*".to_string()
+        } else {
+            // Ordered fields must be part of the selection
+            let mut fields: Vec<&str> = self.select.iter().map(Field::as_str).collect();
+            for order in &keyset {
+                if !fields.contains(&order.field.as_str()) {
+                    fields.push(order.field.as_str());
+                }
+            }
+            fields.join(", ")
+        };
+
+        let cursor_values: Vec<String> = keyset
+            .iter()
+            .map(|o| {
+                if o.field.is_id() {
+                    "record::id(id)".to_string()
+                } else {
+                    o.field.to_string()
+                }
+            })
+            .collect();
+
+        let order_by: Vec<String> = keyset
+            .iter()
+            .map(|o| format!("{} {}", o.field, o.direction.as_str().to_uppercase()))
+            .collect();
+
+        let sql = format!(
+            "SELECT {}, [{}] AS {} FROM {}{} ORDER BY {} LIMIT $limit START $start",
+            projection,
+            cursor_values.join(", "),
+            CURSOR_FIELD,
+            source,
+            self.render_where(&keyset, &mut vars),
+            order_by.join(", "),
+        );
+
+        BoundQuery::new(sql).bind_all(vars)
+    }
+
+    /// Statement counting every row that matches the filters
+    pub(crate) fn count_query(&self) -> BoundQuery {
+        let mut vars = Map::new();
+        let source = self.render_source(&mut vars);
+
+        let unpaged = Self {
+            cursor: None,
+            ..self.clone()
+        };
+        let sql = format!(
+            "SELECT count() AS total FROM {}{} GROUP ALL",
+            source,
+            unpaged.render_where(&[], &mut vars)
+        );
+
+        BoundQuery::new(sql).bind_all(vars)
+    }
+
+    /// Turn the rows of [`ListQuery::page_query`] into a page
+    pub(crate) fn to_page(&self, mut rows: Vec<Value>, total: u64) -> Page {
+        let has_more = rows.len() as u64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let mut next_cursor = None;
+        let last = rows.len().saturating_sub(1);
+        for (i, row) in rows.iter_mut().enumerate() {
+            let Some(object) = row.as_object_mut() else {
+                continue;
+            };
+
+            let keyset = object.remove(CURSOR_FIELD);
+            if has_more && i == last {
+                if let Some(Value::Array(values)) = keyset {
+                    next_cursor = Some(Cursor(values).encode());
+                }
+            }
+
+            // Drop fields that were only selected for ordering
+            if !self.select.is_empty() {
+                object.retain(|key, _| self.select.iter().any(|f| f.root() == key));
+            }
+        }
+
+        Page {
+            rows,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+/// Keyset condition selecting the rows after `cursor`
+fn render_after(keyset: &[Order], cursor: &Cursor, vars: &mut Map<String, Value>) -> String {
+    let mut terms = Vec::new();
+
+    for (i, order) in keyset.iter().enumerate() {
+        let mut parts = Vec::new();
+        for (j, previous) in keyset[..i].iter().enumerate() {
+            parts.push(format!("{} = {}", previous.field, cursor_operand(previous, j)));
+        }
+        let comparison = match order.direction {
+            Direction::Asc => ">",
+            Direction::Desc => "<",
+        };
+        parts.push(format!("{} {} {}", order.field, comparison, cursor_operand(order, i)));
+        terms.push(format!("({})", parts.join(" AND ")));
+    }
+
+    for (i, value) in cursor.0.iter().enumerate() {
+        vars.insert(format!("c{}", i), value.clone());
+    }
+
+    format!("({})", terms.join(" OR "))
+}
+
+fn cursor_operand(order: &Order, index: usize) -> String {
+    if order.field.is_id() {
+        format!("type::thing($table, $c{})", index)
+    } else {
+        format!("$c{}", index)
+    }
+}
+
+fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+pub(crate) fn parse_count(key: &str, value: &str) -> Result<u64, DbError> {
+    value
+        .parse()
+        .map_err(|_| DbError::InvalidQuery(format!("'{}' must be a non-negative integer", key)))
+}
+
+/// Type a raw URL value: quoted strings, null, booleans, numbers, else string
+fn parse_time(key: &str, value: &str) -> Result<DateTime<Utc>, DbError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| DbError::InvalidQuery(format!("'{}' must be an RFC 3339 timestamp", key)))
+}
+
+fn parse_value(raw: &str) -> Value {
+    if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+        return Value::String(raw[1..raw.len() - 1].to_string());
+    }
+
+    match raw {
+        "null" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+
+    if let Ok(int) = raw.parse::<i64>() {
+        return int.into();
+    }
+    match raw.parse::<f64>() {
+        Ok(float) if float.is_finite() => float.into(),
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Translate a `*` wildcard pattern into an anchored regular expression
+fn like_to_regex(pattern: &str, case_insensitive: bool) -> String {
+    let mut regex = String::from(if case_insensitive { "(?i)^" } else { "^" });
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            c if "\\.+?()|[]{}^$".contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posts() -> Table {
+        Table::new("posts").unwrap()
+    }
+
+    #[test]
+    fn test_parse_postgrest_params() {
+        let query = ListQuery::from_params(
+            posts(),
+            [
+                ("age", "gt.18"),
+                ("name", "not.eq.\"42\""),
+                ("order", "created_at.desc,address.city"),
+                ("select", "id,name"),
+                ("limit", "50"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(query.filters[0].op, FilterOp::Gt);
+        assert_eq!(query.filters[0].value, serde_json::json!(18));
+        assert!(query.filters[1].negated);
+        assert_eq!(query.filters[1].value, serde_json::json!("42"));
+        assert_eq!(query.order[0], Order::desc(Field::new("created_at").unwrap()));
+        assert_eq!(query.order[1], Order::asc(Field::new("address.city").unwrap()));
+        assert_eq!(query.select.len(), 2);
+        assert_eq!(query.limit, 50);
+    }
+
+    #[test]
+    fn test_as_of_reads_history() {
+        let query = ListQuery::from_params(posts(), [("as_of", "2024-05-01T12:00:00+02:00")])
+            .unwrap();
+        assert_eq!(query.as_of.unwrap().to_rfc3339(), "2024-05-01T10:00:00+00:00");
+        assert!(query.filters.is_empty());
+        assert!(query.page_query().sql.contains("FROM (SELECT VALUE data FROM _history"));
+        assert!(query.normalized().contains("as_of=2024-05-01T10:00:00+00:00"));
+
+        let invalid = ListQuery::from_params(posts(), [("as_of", "yesterday")]);
+        assert!(matches!(invalid, Err(DbError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn test_rejects_unsafe_input() {
+        assert!(ListQuery::from_params(posts(), [("age; DELETE posts", "eq.1")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("age", "between.1")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("select", "id,count()")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("order", "name;DROP")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("limit", "-1")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("cursor", "not-a-cursor")]).is_err());
+        assert!(ListQuery::from_params(posts(), [("tags", "in.a,b")]).is_err());
+    }
+
+    #[test]
+    fn test_limit_is_clamped() {
+        let query = ListQuery::from_params(posts(), [("limit", "100000")]).unwrap();
+        assert_eq!(query.limit, MAX_LIMIT);
+    }
+
+    #[test]
+    fn test_values_are_bound() {
+        let query = ListQuery::from_params(
+            posts(),
+            [("title", "eq.'; REMOVE TABLE users; --"), ("tags", "in.(a,b)")],
+        )
+        .unwrap();
+        let bound = query.page_query();
+
+        assert!(!bound.sql.contains("REMOVE"));
+        assert!(bound.sql.contains("title = $f0"));
+        assert!(bound.sql.contains("tags INSIDE $f1"));
+        assert_eq!(bound.vars["f1"], serde_json::json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_like_becomes_anchored_regex() {
+        assert_eq!(like_to_regex("*.rs", false), "^.*\\.rs$");
+        assert_eq!(like_to_regex("Ab*", true), "(?i)^Ab.*$");
+    }
+
+    #[test]
+    fn test_normalized_ignores_parameter_order() {
+        let a = ListQuery::from_params(posts(), [("b", "eq.2"), ("a", "eq.1"), ("select", "x,y")])
+            .unwrap();
+        let b = ListQuery::from_params(posts(), [("select", "y,x"), ("a", "eq.1"), ("b", "eq.2")])
+            .unwrap();
+        let c = ListQuery::from_params(posts(), [("a", "eq.1"), ("b", "eq.3")]).unwrap();
+
+        assert_eq!(a.normalized(), b.normalized());
+        assert_ne!(a.normalized(), c.normalized());
+    }
+
+    #[test]
+    fn test_cursor_roundtrip_and_shape() {
+        let cursor = Cursor(vec![serde_json::json!("2024-01-01"), serde_json::json!("abc")]);
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        let params = [("order", "created_at.desc".to_string()), ("cursor", cursor.encode())];
+        assert!(ListQuery::from_params(posts(), params).is_ok());
+
+        // One ordering value plus the id is expected
+        let params = [("cursor", cursor.encode())];
+        assert!(ListQuery::from_params(posts(), params).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Record history for versioned data tables
+//!
+//! Versioning is opt-in per table ([`DatabaseService::enable_versioning`]).
+//! A table event then keeps every version of every record in `_history`: the
+//! record as written, the action, when, and who wrote it (`$identity.sub` of
+//! the write, unset for writes made outside the data API).
+//!
+//! Each version is valid from `at` until the next version of the record, so
+//! the table can be read as it was at any time. Deleting a record of a
+//! versioned table is a soft delete: the record leaves the live table but its
+//! last version stays in the history, from where it can be restored.
+
+use crate::changes::{parse_rows, ChangeAction};
+use crate::policy::{ScopedDatabase, IDENTITY_VAR};
+use crate::query::{BoundQuery, RecordId, Table};
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Name of the table event writing the history
+const EVENT: &str = "edge_hive_history";
+
+/// Prefix of the `config` keys marking versioned tables
+const CONFIG_PREFIX: &str = "versioning.";
+
+const VERSION_FIELDS: &str = "version, action, data, author, <string> at AS at, \
+     (IF until THEN <string> until END) AS until";
+
+/// Rows of `$table` as they were at `$as_of`
+pub(crate) const AS_OF_SOURCE: &str = "SELECT VALUE data FROM _history \
+     WHERE `table` = $table AND action != 'delete' AND at <= <datetime> $as_of \
+     AND (until = NONE OR until > <datetime> $as_of)";
+
+fn config_key(table: &Table) -> String {
+    format!("{}{}", CONFIG_PREFIX, table)
+}
+
+/// One version of a record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordVersion {
+    /// 1 for the first write, incremented by every later one
+    pub version: u32,
+    pub action: ChangeAction,
+    /// The record as written (as it was before a delete)
+    pub data: Value,
+    /// Subject of the writer, when known
+    #[serde(default)]
+    pub author: Option<String>,
+    pub at: DateTime<Utc>,
+    /// When the next version replaced this one
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl RecordVersion {
+    /// The record without its `id`, ready to be written again
+    fn content(&self) -> Value {
+        let mut content = self.data.clone();
+        if let Some(object) = content.as_object_mut() {
+            object.remove("id");
+        }
+        content
+    }
+}
+
+impl DatabaseService {
+    /// Keep the history of every record of `table` from now on
+    pub async fn enable_versioning(&self, table: &Table) -> Result<(), DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+
+        // Event definitions need the table name itself, not a bound variable
+        let sql = format!(
+            "DEFINE EVENT OVERWRITE {} ON TABLE {} THEN {{ \
+             LET $at = time::now(); \
+             LET $previous = (SELECT VALUE id FROM _history WHERE record = $value.id); \
+             UPDATE _history SET until = $at WHERE record = $value.id AND until = NONE; \
+             CREATE _history CONTENT {{ \
+             \"table\": record::tb($value.id), record: $value.id, \
+             version: array::len($previous) + 1, action: string::lowercase($event), \
+             data: IF $event = 'DELETE' THEN $before ELSE $after END, \
+             author: $identity.sub, at: $at }} }}",
+            EVENT, table
+        );
+        self.db().query(sql).await?.check()?;
+
+        self.set_config(&config_key(table), &true).await
+    }
+
+    /// Stop versioning `table`; the history recorded so far is kept
+    pub async fn disable_versioning(&self, table: &Table) -> Result<(), DbError> {
+        let sql = format!("REMOVE EVENT IF EXISTS {} ON TABLE {}", EVENT, table);
+        self.db().query(sql).await?.check()?;
+
+        let query = BoundQuery::new("DELETE type::thing('config', $key)")
+            .bind("key", config_key(table));
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Whether `table` keeps a history
+    pub async fn is_versioned(&self, table: &Table) -> Result<bool, DbError> {
+        Ok(self.get_config::<bool>(&config_key(table)).await?.unwrap_or(false))
+    }
+
+    /// Every version of a record, oldest first
+    pub async fn history(&self, id: &RecordId) -> Result<Vec<RecordVersion>, DbError> {
+        let mut versions = self.versions(id).await?;
+        for version in &mut versions {
+            version.data = self.open_record(&id.table, std::mem::take(&mut version.data))?;
+        }
+        Ok(versions)
+    }
+
+    /// Every version of a record as stored, with encrypted fields left encrypted
+    async fn versions(&self, id: &RecordId) -> Result<Vec<RecordVersion>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM _history WHERE record = type::thing($table, $key) ORDER BY version",
+            VERSION_FIELDS
+        ))
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str());
+
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// Last version of a record, if the record is currently deleted
+    async fn deleted_version(&self, id: &RecordId) -> Result<Option<RecordVersion>, DbError> {
+        Ok(self
+            .versions(id)
+            .await?
+            .pop()
+            .filter(|version| version.action == ChangeAction::Delete))
+    }
+
+    /// Write a deleted record back under its own ID
+    async fn recreate(
+        &self,
+        id: &RecordId,
+        version: &RecordVersion,
+        identity: Value,
+    ) -> Result<Value, DbError> {
+        let query = BoundQuery::new("CREATE type::thing($table, $key) CONTENT $content")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str())
+            .bind("content", version.content())
+            .bind(IDENTITY_VAR, identity);
+
+        let restored = self
+            .execute(query)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Record restore returned no record".to_string()))?;
+        self.open_record(&id.table, restored)
+    }
+
+    /// Bring back a soft-deleted record; `None` if it is not deleted
+    pub async fn restore_record(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        match self.deleted_version(id).await? {
+            Some(version) => self.recreate(id, &version, Value::Null).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// History of a record whose latest version the caller may read
+    ///
+    /// Only the versions the caller may read are returned, so rows they could
+    /// not see before an owner change stay hidden. Returns `None` when the
+    /// record has no history or is not visible to the caller.
+    pub async fn history(&self, id: &RecordId) -> Result<Option<Vec<RecordVersion>>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+        let rule = policy.rule(&policy.select);
+        let history = self.db.history(id).await?;
+
+        match history.last() {
+            Some(latest) if self.allows(&rule, &latest.data).await? => {}
+            _ => return Ok(None),
+        }
+
+        let mut readable = Vec::with_capacity(history.len());
+        for version in history {
+            if self.allows(&rule, &version.data).await? {
+                readable.push(version);
+            }
+        }
+        Ok(Some(readable))
+    }
+
+    /// Restore a soft-deleted record the caller could have deleted
+    ///
+    /// Returns `None` when the record is not deleted or not visible to the caller.
+    pub async fn restore_record(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+
+        match self.db.deleted_version(id).await? {
+            Some(version) if self.allows(&policy.rule(&policy.delete), &version.data).await? => {
+                let restored = self.db.recreate(id, &version, self.identity.to_value()).await?;
+                Ok(Some(restored))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::ListQuery;
+    use crate::policy::{Identity, PolicySet};
+    use serde_json::json;
+
+    async fn versioned_post(db: &DatabaseService, identity: &Identity) -> RecordId {
+        let table = Table::new("posts").unwrap();
+        db.enable_versioning(&table).await.unwrap();
+
+        let policies = PolicySet::default();
+        db.scoped(&policies, identity)
+            .create_record(&table, json!({"title": "v1"}))
+            .await
+            .unwrap();
+        let keys = db
+            .execute(BoundQuery::new("SELECT VALUE record::id(id) FROM posts"))
+            .await
+            .unwrap();
+        RecordId::new(table, keys[0].as_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_versions_keep_author_and_data() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let id = versioned_post(&db, &alice).await;
+
+        let scoped = db.scoped(&policies, &alice);
+        scoped.merge_record(&id, json!({"title": "v2"})).await.unwrap();
+        scoped.delete_record(&id).await.unwrap();
+
+        let history = db.history(&id).await.unwrap();
+        let versions: Vec<(u32, ChangeAction)> =
+            history.iter().map(|v| (v.version, v.action)).collect();
+        assert_eq!(
+            versions,
+            vec![(1, ChangeAction::Create), (2, ChangeAction::Update), (3, ChangeAction::Delete)]
+        );
+        assert!(history.iter().all(|v| v.author.as_deref() == Some("users:alice")));
+        assert_eq!(history[2].data["title"], "v2");
+        assert_eq!(history[0].until, Some(history[1].at));
+        assert!(history[2].until.is_none());
+
+        let bob = Identity::new("users:bob", "user", vec![]);
+        assert!(db.scoped(&policies, &bob).history(&id).await.unwrap().is_none());
+        assert_eq!(scoped.history(&id).await.unwrap().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_history_hides_versions_of_a_previous_owner() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let id = versioned_post(&db, &alice).await;
+
+        let handover = BoundQuery::new(
+            "UPDATE type::thing($table, $key) SET owner = 'users:bob', title = 'v2'",
+        )
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str());
+        db.execute(handover).await.unwrap();
+        assert_eq!(db.history(&id).await.unwrap().len(), 2);
+
+        let history = db.scoped(&policies, &bob).history(&id).await.unwrap().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].data["title"], "v2");
+        assert!(db.scoped(&policies, &alice).history(&id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_soft_deleted_records_can_be_restored() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let id = versioned_post(&db, &alice).await;
+
+        // Nothing to restore while the record exists
+        assert!(db.scoped(&policies, &alice).restore_record(&id).await.unwrap().is_none());
+
+        db.scoped(&policies, &alice).delete_record(&id).await.unwrap();
+        assert!(db.select_record(&id).await.unwrap().is_none());
+        assert!(db.scoped(&policies, &bob).restore_record(&id).await.unwrap().is_none());
+
+        let restored = db.scoped(&policies, &alice).restore_record(&id).await.unwrap().unwrap();
+        assert_eq!(restored["title"], "v1");
+        assert_eq!(restored["owner"], "users:alice");
+        assert_eq!(db.history(&id).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_list_as_of_reads_past_versions() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let id = versioned_post(&db, &alice).await;
+        let scoped = db.scoped(&policies, &alice);
+
+        let first = db.history(&id).await.unwrap()[0].at;
+        scoped.merge_record(&id, json!({"title": "v2"})).await.unwrap();
+        scoped.delete_record(&id).await.unwrap();
+
+        let mut query = ListQuery::new(id.table.clone());
+        query.as_of = Some(first);
+        let page = scoped.list_records(query.clone()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.rows[0]["title"], "v1");
+
+        query.as_of = Some(Utc::now());
+        assert_eq!(scoped.list_records(query.clone()).await.unwrap().total, 0);
+
+        let bob = Identity::new("users:bob", "user", vec![]);
+        query.as_of = Some(first);
+        assert_eq!(db.scoped(&policies, &bob).list_records(query).await.unwrap().total, 0);
+    }
+
+    #[tokio::test]
+    async fn test_as_of_needs_a_versioned_table() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let mut query = ListQuery::new(Table::new("posts").unwrap());
+        query.as_of = Some(Utc::now());
+        assert!(matches!(db.list_records(&query).await, Err(DbError::InvalidQuery(_))));
+
+        let table = Table::new("posts").unwrap();
+        db.enable_versioning(&table).await.unwrap();
+        assert!(db.is_versioned(&table).await.unwrap());
+        db.disable_versioning(&table).await.unwrap();
+        assert!(!db.is_versioned(&table).await.unwrap());
+    }
+}
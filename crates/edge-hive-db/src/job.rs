@@ -0,0 +1,709 @@
+//! Durable background jobs
+//!
+//! A job names a handler, carries a JSON payload and becomes due at its
+//! `run_at`. Workers claim due jobs, highest priority first, with a lease:
+//! a job whose lease expires before its worker reports back (the worker
+//! crashed or hung) is due again, so no job is lost. A failed attempt is
+//! retried with exponential backoff until the job runs out of attempts and
+//! is dead-lettered, where it stays until an admin retries it.
+//!
+//! A [`JobSchedule`] enqueues a job whenever its cron expression fires.
+//! Missed firings (the node was down) are caught up with a single job.
+
+use crate::changes::parse_rows;
+use crate::project::validate_label;
+use crate::query::BoundQuery;
+use crate::webhook::RetryPolicy;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Largest page of jobs returned at once
+pub const MAX_JOBS: u64 = 1000;
+
+const JOB_FIELDS: &str = "record::id(id) AS id, handler, payload, priority, status, \
+     <string> run_at AS run_at, attempts, max_attempts, lease_owner, \
+     (IF lease_expires_at THEN <string> lease_expires_at END) AS lease_expires_at, \
+     last_error, result, (IF schedule THEN record::id(schedule) END) AS schedule, \
+     <string> created_at AS created_at, \
+     (IF finished_at THEN <string> finished_at END) AS finished_at";
+
+const SCHEDULE_FIELDS: &str = "name, cron, handler, payload, priority, max_attempts, active, \
+     <string> next_run_at AS next_run_at, \
+     (IF last_run_at THEN <string> last_run_at END) AS last_run_at, \
+     <string> created_at AS created_at";
+
+/// State of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for `run_at`
+    Queued,
+    /// Leased by a worker
+    Running,
+    Succeeded,
+    /// Gave up after the last attempt
+    Dead,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobStatus::Queued),
+            "running" => Ok(JobStatus::Running),
+            "succeeded" => Ok(JobStatus::Succeeded),
+            "dead" => Ok(JobStatus::Dead),
+            _ => Err(DbError::InvalidQuery(format!("unknown job status '{}'", s))),
+        }
+    }
+}
+
+/// A queued, running or finished job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// Name of the handler running the job
+    pub handler: String,
+    #[serde(default)]
+    pub payload: Value,
+    /// Higher runs first
+    pub priority: i64,
+    pub status: JobStatus,
+    /// When the job is due (its next attempt, after a failure)
+    pub run_at: DateTime<Utc>,
+    /// Attempts started so far
+    pub attempts: u32,
+    /// Attempts before the job is dead-lettered (the worker's default when missing)
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Worker holding the lease of a running job
+    #[serde(default)]
+    pub lease_owner: Option<String>,
+    #[serde(default)]
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Output of the handler, once succeeded
+    #[serde(default)]
+    pub result: Option<Value>,
+    /// Schedule that enqueued the job
+    #[serde(default)]
+    pub schedule: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Fields of a new job
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct NewJob {
+    pub handler: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default)]
+    pub priority: i64,
+    /// When the job is due (right away when missing)
+    #[serde(default)]
+    pub run_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+}
+
+/// Filters of a job listing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobQuery {
+    pub status: Option<JobStatus>,
+    pub handler: Option<String>,
+    pub limit: u64,
+}
+
+/// A job enqueued on a cron schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobSchedule {
+    pub name: String,
+    /// Cron expression, with or without a leading seconds field
+    pub cron: String,
+    pub handler: String,
+    #[serde(default)]
+    pub payload: Value,
+    pub priority: i64,
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// `false` once the expression never fires again
+    pub active: bool,
+    pub next_run_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fields of a new schedule
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct NewJobSchedule {
+    pub name: String,
+    pub cron: String,
+    pub handler: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+}
+
+/// Parse a cron expression
+///
+/// Five fields (`min hour day month weekday`) run at second 0; six or seven
+/// fields start with seconds (and end with years).
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, DbError> {
+    let expression = expression.trim();
+    let full = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&full)
+        .map_err(|e| DbError::InvalidQuery(format!("invalid cron '{}': {}", expression, e)))
+}
+
+/// First time a cron expression fires after `after`
+pub fn next_cron_run(
+    expression: &str,
+    after: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, DbError> {
+    Ok(parse_cron(expression)?.after(&after).next())
+}
+
+fn validate_handler(handler: &str) -> Result<(), DbError> {
+    if handler.is_empty() || handler.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(DbError::InvalidQuery(format!("invalid job handler '{}'", handler)));
+    }
+    Ok(())
+}
+
+fn validate_attempts(max_attempts: Option<u32>) -> Result<(), DbError> {
+    if max_attempts == Some(0) {
+        return Err(DbError::InvalidQuery("max_attempts must be at least 1".to_string()));
+    }
+    Ok(())
+}
+
+fn lease_text(lease: Duration) -> String {
+    format!("{}ms", lease.as_millis().max(1))
+}
+
+impl DatabaseService {
+    /// Queue a job
+    pub async fn enqueue_job(&self, job: NewJob) -> Result<Job, DbError> {
+        validate_handler(&job.handler)?;
+        validate_attempts(job.max_attempts)?;
+
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE _jobs CONTENT {{ handler: $handler, payload: $payload, \
+             priority: $priority, status: 'queued', run_at: <datetime> $run_at, attempts: 0, \
+             max_attempts: $max_attempts, created_at: time::now() }}); \
+             RETURN SELECT {} FROM $created }}",
+            JOB_FIELDS
+        ))
+        .bind("handler", job.handler)
+        .bind("payload", job.payload)
+        .bind("priority", job.priority)
+        .bind("run_at", job.run_at.unwrap_or_else(Utc::now).to_rfc3339())
+        .bind("max_attempts", job.max_attempts);
+
+        parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Job creation returned no record".to_string()))
+    }
+
+    /// A job by ID
+    pub async fn job(&self, id: &str) -> Result<Option<Job>, DbError> {
+        let query =
+            BoundQuery::new(format!("SELECT {} FROM type::thing('_jobs', $id)", JOB_FIELDS))
+                .bind("id", id);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// List jobs, newest first
+    pub async fn jobs(&self, query: &JobQuery) -> Result<Vec<Job>, DbError> {
+        let mut conditions = Vec::new();
+        let mut statement = BoundQuery::new("").bind("limit", query.limit.clamp(1, MAX_JOBS));
+        if let Some(status) = &query.status {
+            conditions.push("status = $status");
+            statement = statement.bind("status", status.as_str());
+        }
+        if let Some(handler) = &query.handler {
+            conditions.push("handler = $handler");
+            statement = statement.bind("handler", handler.as_str());
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        statement.sql = format!(
+            "SELECT {}, created_at AS __created FROM _jobs{} \
+             ORDER BY __created DESC LIMIT $limit",
+            JOB_FIELDS, filter
+        );
+
+        parse_rows(self.execute(statement).await?)
+    }
+
+    /// Remove a job that is not running; `false` if it did not exist
+    pub async fn delete_job(&self, id: &str) -> Result<bool, DbError> {
+        match self.job(id).await? {
+            None => Ok(false),
+            Some(job) if job.status == JobStatus::Running => {
+                Err(DbError::InvalidQuery(format!("job '{}' is running", id)))
+            }
+            Some(_) => {
+                let query = BoundQuery::new(
+                    "DELETE type::thing('_jobs', $id) WHERE status != 'running' RETURN BEFORE",
+                )
+                .bind("id", id);
+                Ok(!self.execute(query).await?.is_empty())
+            }
+        }
+    }
+
+    /// Run a queued or dead job right away, with a fresh attempt budget
+    pub async fn retry_job(&self, id: &str) -> Result<Option<Job>, DbError> {
+        let query = BoundQuery::new(format!(
+            "{{ LET $updated = (UPDATE type::thing('_jobs', $id) \
+             SET status = 'queued', attempts = 0, run_at = time::now(), finished_at = NONE \
+             WHERE status IN ['queued', 'dead']); \
+             RETURN SELECT {} FROM $updated }}",
+            JOB_FIELDS
+        ))
+        .bind("id", id);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Lease up to `limit` due jobs to `worker`, highest priority first
+    ///
+    /// Jobs whose lease expired are due again, unless they used up their
+    /// attempts (`max_attempts` is the default of jobs without their own
+    /// limit): those are dead-lettered instead. A job is leased to one
+    /// worker even when several claim at once; a claim that conflicts with
+    /// another one leases nothing and the worker tries again on its next poll.
+    pub async fn claim_jobs(
+        &self,
+        worker: &str,
+        lease: Duration,
+        max_attempts: u32,
+        limit: u64,
+    ) -> Result<Vec<Job>, DbError> {
+        let query = BoundQuery::new(format!(
+            "{{ UPDATE _jobs SET status = 'dead', last_error = 'lease expired', \
+             finished_at = time::now(), lease_owner = NONE, lease_expires_at = NONE \
+             WHERE status = 'running' AND lease_expires_at < time::now() \
+             AND attempts >= (max_attempts ?? $max_attempts); \
+             LET $due = (SELECT id, priority, run_at FROM _jobs \
+             WHERE (status = 'queued' AND run_at <= time::now()) \
+             OR (status = 'running' AND lease_expires_at < time::now()) \
+             ORDER BY priority DESC, run_at LIMIT $limit).id; \
+             LET $leased = (UPDATE $due SET status = 'running', attempts += 1, \
+             lease_owner = $worker, lease_expires_at = time::now() + <duration> $lease \
+             WHERE (status = 'queued' AND run_at <= time::now()) \
+             OR (status = 'running' AND lease_expires_at < time::now())); \
+             RETURN SELECT {} FROM $leased }}",
+            JOB_FIELDS
+        ))
+        .bind("worker", worker)
+        .bind("lease", lease_text(lease))
+        .bind("max_attempts", max_attempts)
+        .bind("limit", limit);
+
+        match self.execute(query).await {
+            Ok(rows) => parse_rows(rows),
+            Err(e) if e.is_retryable_conflict() => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record the result of a job leased to `worker`; `false` if it lost the lease
+    pub async fn complete_job(
+        &self,
+        id: &str,
+        worker: &str,
+        result: Value,
+    ) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_jobs', $id) SET status = 'succeeded', result = $result, \
+             last_error = NONE, finished_at = time::now(), lease_owner = NONE, \
+             lease_expires_at = NONE WHERE status = 'running' AND lease_owner = $worker",
+        )
+        .bind("id", id)
+        .bind("worker", worker)
+        .bind("result", result);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Record a failed attempt, scheduling a retry or dead-lettering the job
+    ///
+    /// Without a `policy` the failure is final. Returns `None` if `worker`
+    /// lost the lease.
+    pub async fn fail_job(
+        &self,
+        job: &Job,
+        worker: &str,
+        error: &str,
+        policy: Option<&RetryPolicy>,
+    ) -> Result<Option<JobStatus>, DbError> {
+        let (status, next) = match policy {
+            Some(policy) if job.attempts < job.max_attempts.unwrap_or(policy.max_attempts) => {
+                let delay = chrono::Duration::from_std(policy.backoff(job.attempts))
+                    .map_err(|e| DbError::InvalidQuery(e.to_string()))?;
+                (JobStatus::Queued, Utc::now() + delay)
+            }
+            _ => (JobStatus::Dead, job.run_at),
+        };
+
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_jobs', $id) SET status = $status, last_error = $error, \
+             run_at = <datetime> $next, lease_owner = NONE, lease_expires_at = NONE, \
+             finished_at = IF $status = 'dead' THEN time::now() END \
+             WHERE status = 'running' AND lease_owner = $worker",
+        )
+        .bind("id", job.id.as_str())
+        .bind("worker", worker)
+        .bind("status", status.as_str())
+        .bind("error", error)
+        .bind("next", next.to_rfc3339());
+
+        let updated = !self.execute(query).await?.is_empty();
+        Ok(updated.then_some(status))
+    }
+
+    /// Add a schedule enqueuing a job whenever `cron` fires
+    pub async fn create_job_schedule(
+        &self,
+        schedule: NewJobSchedule,
+    ) -> Result<JobSchedule, DbError> {
+        validate_label("schedule", &schedule.name)?;
+        validate_handler(&schedule.handler)?;
+        validate_attempts(schedule.max_attempts)?;
+        let next = next_cron_run(&schedule.cron, Utc::now())?.ok_or_else(|| {
+            DbError::InvalidQuery(format!("cron '{}' never fires", schedule.cron))
+        })?;
+        if self.job_schedule(&schedule.name).await?.is_some() {
+            return Err(DbError::InvalidQuery(format!(
+                "schedule '{}' already exists",
+                schedule.name
+            )));
+        }
+
+        let content = json!({
+            "name": schedule.name,
+            "cron": schedule.cron.trim(),
+            "handler": schedule.handler,
+            "payload": schedule.payload,
+            "priority": schedule.priority,
+            "max_attempts": schedule.max_attempts,
+            "active": true,
+        });
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE type::thing('_job_schedules', $content.name) \
+             CONTENT $content); \
+             LET $stamped = (UPDATE $created.id \
+             SET next_run_at = <datetime> $next, created_at = time::now()); \
+             RETURN SELECT {} FROM $stamped }}",
+            SCHEDULE_FIELDS
+        ))
+        .bind("content", content)
+        .bind("next", next.to_rfc3339());
+
+        parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Schedule creation returned no record".to_string()))
+    }
+
+    /// Every schedule, by name
+    pub async fn job_schedules(&self) -> Result<Vec<JobSchedule>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM _job_schedules ORDER BY name",
+            SCHEDULE_FIELDS
+        ));
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// A schedule by name
+    pub async fn job_schedule(&self, name: &str) -> Result<Option<JobSchedule>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM type::thing('_job_schedules', $name)",
+            SCHEDULE_FIELDS
+        ))
+        .bind("name", name);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Remove a schedule, keeping the jobs it enqueued; `false` if it did not exist
+    pub async fn delete_job_schedule(&self, name: &str) -> Result<bool, DbError> {
+        let query =
+            BoundQuery::new("DELETE type::thing('_job_schedules', $name) RETURN BEFORE")
+                .bind("name", name);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Enqueue a job for every schedule that fired
+    ///
+    /// Returns the number of jobs enqueued. Each firing is enqueued once,
+    /// even with several workers polling.
+    pub async fn enqueue_scheduled_jobs(&self) -> Result<usize, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM _job_schedules WHERE active = true AND next_run_at <= time::now()",
+            SCHEDULE_FIELDS
+        ));
+        let due: Vec<JobSchedule> = parse_rows(self.execute(query).await?)?;
+
+        let mut enqueued = 0;
+        for schedule in due {
+            let next = next_cron_run(&schedule.cron, Utc::now())?;
+            // Advancing the schedule and creating its job is one statement, so
+            // a firing claimed by another worker is skipped here
+            let query = BoundQuery::new(
+                "{ LET $schedule = type::thing('_job_schedules', $name); \
+                 LET $fired = (UPDATE $schedule SET last_run_at = time::now(), \
+                 next_run_at = IF $active THEN <datetime> $next ELSE next_run_at END, \
+                 active = $active WHERE active = true AND next_run_at <= time::now()); \
+                 IF $fired { CREATE _jobs CONTENT { handler: $fired[0].handler, \
+                 payload: $fired[0].payload, priority: $fired[0].priority, status: 'queued', \
+                 run_at: time::now(), attempts: 0, max_attempts: $fired[0].max_attempts, \
+                 schedule: $schedule, created_at: time::now() } }; \
+                 RETURN $fired.id }",
+            )
+            .bind("name", schedule.name.as_str())
+            .bind("next", next.map(|next| next.to_rfc3339()))
+            .bind("active", next.is_some());
+
+            enqueued += self.execute(query).await?.len();
+        }
+        Ok(enqueued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const WORKER: &str = "worker-1";
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn new_job(handler: &str, priority: i64) -> NewJob {
+        NewJob {
+            handler: handler.to_string(),
+            payload: json!({"n": priority}),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cron_expressions() {
+        let after = DateTime::parse_from_rfc3339("2025-01-01T10:07:30Z").unwrap();
+        let after = after.with_timezone(&Utc);
+        let next = next_cron_run("*/15 * * * *", after).unwrap().unwrap();
+        assert_eq!(next.to_rfc3339(), "2025-01-01T10:15:00+00:00");
+        let next = next_cron_run("30 0 0 * * *", after).unwrap().unwrap();
+        assert_eq!(next.to_rfc3339(), "2025-01-02T00:00:30+00:00");
+        assert!(parse_cron("every minute").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_claimed_by_priority_and_completed() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let low = db.enqueue_job(new_job("noop", 0)).await.unwrap();
+        let high = db.enqueue_job(new_job("noop", 10)).await.unwrap();
+        let later = NewJob {
+            run_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..new_job("noop", 20)
+        };
+        db.enqueue_job(later).await.unwrap();
+        assert!(db.enqueue_job(new_job("", 0)).await.is_err());
+
+        let claimed = db.claim_jobs(WORKER, LEASE, 3, 1).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, high.id);
+        assert_eq!(claimed[0].status, JobStatus::Running);
+        assert_eq!(claimed[0].attempts, 1);
+        assert_eq!(claimed[0].lease_owner.as_deref(), Some(WORKER));
+
+        // Leased jobs are not claimed twice
+        let claimed = db.claim_jobs("worker-2", LEASE, 3, 10).await.unwrap();
+        assert_eq!(claimed.iter().map(|j| &j.id).collect::<Vec<_>>(), vec![&low.id]);
+
+        assert!(!db.complete_job(&high.id, "worker-2", json!(null)).await.unwrap());
+        assert!(db.complete_job(&high.id, WORKER, json!({"ok": true})).await.unwrap());
+        let done = db.job(&high.id).await.unwrap().unwrap();
+        assert_eq!(done.status, JobStatus::Succeeded);
+        assert_eq!(done.result, Some(json!({"ok": true})));
+        assert!(done.finished_at.is_some());
+
+        let running = db
+            .jobs(&JobQuery {
+                status: Some(JobStatus::Running),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(running.len(), 1);
+        assert!(db.delete_job(&low.id).await.is_err());
+        assert!(db.delete_job(&high.id).await.unwrap());
+        assert!(!db.delete_job(&high.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_claims_lease_a_job_once() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+
+        for _ in 0..10 {
+            let job = db.enqueue_job(new_job("noop", 0)).await.unwrap();
+            let (first, second, third) = tokio::join!(
+                db.claim_jobs(WORKER, LEASE, 3, 10),
+                db.claim_jobs("worker-2", LEASE, 3, 10),
+                db.claim_jobs("worker-3", LEASE, 3, 10),
+            );
+            let leased = first.unwrap().len() + second.unwrap().len() + third.unwrap().len();
+            assert!(leased <= 1);
+
+            // A round where every claim conflicted leaves the job for the next poll
+            if leased == 0 {
+                assert_eq!(db.claim_jobs(WORKER, LEASE, 3, 10).await.unwrap().len(), 1);
+            }
+            let owner = db.job(&job.id).await.unwrap().unwrap().lease_owner.unwrap();
+            assert!(db.complete_job(&job.id, &owner, json!(null)).await.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_commit_conflicts_are_retryable() {
+        let detail = surrealdb::error::Db::QueryNotExecutedDetail {
+            message: "Failed to commit transaction due to a read or write conflict. \
+                      This transaction can be retried"
+                .to_string(),
+        };
+        assert!(DbError::from(surrealdb::Error::Db(detail)).is_retryable_conflict());
+        let retryable = surrealdb::Error::Db(surrealdb::error::Db::TxRetryable);
+        assert!(DbError::from(retryable).is_retryable_conflict());
+        assert!(!DbError::Query("syntax error".to_string()).is_retryable_conflict());
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_retried_then_dead_lettered() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let job = NewJob {
+            max_attempts: Some(2),
+            ..new_job("flaky", 0)
+        };
+        let job = db.enqueue_job(job).await.unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        };
+
+        let claimed = db.claim_jobs(WORKER, LEASE, 5, 10).await.unwrap().remove(0);
+        let status = db.fail_job(&claimed, WORKER, "boom", Some(&policy)).await.unwrap();
+        assert_eq!(status, Some(JobStatus::Queued));
+
+        let claimed = db.claim_jobs(WORKER, LEASE, 5, 10).await.unwrap().remove(0);
+        assert_eq!(claimed.attempts, 2);
+        let status = db.fail_job(&claimed, WORKER, "boom", Some(&policy)).await.unwrap();
+        assert_eq!(status, Some(JobStatus::Dead));
+        assert!(db.claim_jobs(WORKER, LEASE, 5, 10).await.unwrap().is_empty());
+
+        let dead = db.job(&job.id).await.unwrap().unwrap();
+        assert_eq!(dead.last_error.as_deref(), Some("boom"));
+        let retried = db.retry_job(&job.id).await.unwrap().unwrap();
+        assert_eq!(retried.status, JobStatus::Queued);
+        assert_eq!(retried.attempts, 0);
+
+        // Without a policy the first failure is final
+        let claimed = db.claim_jobs(WORKER, LEASE, 5, 10).await.unwrap().remove(0);
+        let status = db.fail_job(&claimed, WORKER, "unknown handler", None).await.unwrap();
+        assert_eq!(status, Some(JobStatus::Dead));
+    }
+
+    #[tokio::test]
+    async fn test_expired_leases_make_jobs_due_again() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let job = db.enqueue_job(new_job("slow", 0)).await.unwrap();
+
+        let lease = Duration::from_millis(1);
+        db.claim_jobs(WORKER, lease, 2, 10).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let claimed = db.claim_jobs("worker-2", lease, 2, 10).await.unwrap();
+        assert_eq!(claimed[0].attempts, 2);
+        assert_eq!(claimed[0].lease_owner.as_deref(), Some("worker-2"));
+        // The first worker lost its lease
+        assert!(!db.complete_job(&job.id, WORKER, json!(null)).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(db.claim_jobs(WORKER, lease, 2, 10).await.unwrap().is_empty());
+        let dead = db.job(&job.id).await.unwrap().unwrap();
+        assert_eq!(dead.status, JobStatus::Dead);
+        assert_eq!(dead.last_error.as_deref(), Some("lease expired"));
+    }
+
+    #[tokio::test]
+    async fn test_schedules_enqueue_jobs_once_per_firing() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let schedule = db
+            .create_job_schedule(NewJobSchedule {
+                name: "nightly".to_string(),
+                cron: "0 3 * * *".to_string(),
+                handler: "cleanup".to_string(),
+                payload: json!({"days": 30}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(schedule.next_run_at > Utc::now());
+        assert_eq!(db.enqueue_scheduled_jobs().await.unwrap(), 0);
+
+        db.execute(BoundQuery::new("UPDATE _job_schedules SET next_run_at = time::now() - 1m"))
+            .await
+            .unwrap();
+        assert_eq!(db.enqueue_scheduled_jobs().await.unwrap(), 1);
+        assert_eq!(db.enqueue_scheduled_jobs().await.unwrap(), 0);
+
+        let job = db.claim_jobs(WORKER, LEASE, 3, 10).await.unwrap().remove(0);
+        assert_eq!(job.handler, "cleanup");
+        assert_eq!(job.payload, json!({"days": 30}));
+        assert_eq!(job.schedule.as_deref(), Some("nightly"));
+
+        let schedule = db.job_schedule("nightly").await.unwrap().unwrap();
+        assert!(schedule.last_run_at.is_some());
+        assert!(schedule.next_run_at > Utc::now());
+        assert!(db.delete_job_schedule("nightly").await.unwrap());
+        assert!(db.job_schedules().await.unwrap().is_empty());
+    }
+}
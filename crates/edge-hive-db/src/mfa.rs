@@ -0,0 +1,241 @@
+//! Multi-factor authentication
+//!
+//! Each enrolled user has one `_mfa` record holding their TOTP secret. The
+//! secret goes through field encryption, so listing `_mfa = ["secret"]`
+//! among the encrypted fields keeps it sealed at rest. The record also holds
+//! the last step a code was accepted for, so a code works at most once.
+//! Recovery codes are kept as hashes only, one `_recovery_codes` row each.
+
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use serde_json::json;
+
+/// Config key of the roles that must use MFA
+const REQUIRED_ROLES_KEY: &str = "mfa_required_roles";
+
+/// TOTP secret of a user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpEnrollment {
+    /// Base32 secret
+    pub secret: String,
+    /// Whether the user proved their authenticator has the secret
+    pub confirmed: bool,
+}
+
+impl DatabaseService {
+    /// Start enrolling `user` with a new secret, replacing an unconfirmed one
+    ///
+    /// Returns `false`, changing nothing, when the user already confirmed a
+    /// secret.
+    pub async fn save_totp_secret(&self, user: &str, secret: &str) -> Result<bool, DbError> {
+        if self.totp_enrollment(user).await?.is_some_and(|totp| totp.confirmed) {
+            return Ok(false);
+        }
+        let sealed = self.seal(&mfa_table()?, json!({ "secret": secret }))?;
+        let query = BoundQuery::new(
+            "UPSERT type::thing('_mfa', $user) CONTENT { secret: $secret, confirmed: false, \
+             created_at: time::now() }",
+        )
+        .bind("user", user)
+        .bind("secret", sealed["secret"].clone());
+
+        self.execute(query).await?;
+        Ok(true)
+    }
+
+    /// The TOTP secret of `user`, if they enrolled
+    pub async fn totp_enrollment(&self, user: &str) -> Result<Option<TotpEnrollment>, DbError> {
+        let query = BoundQuery::new("SELECT secret, confirmed FROM type::thing('_mfa', $user)")
+            .bind("user", user);
+        let Some(row) = self.execute(query).await?.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let row = self.open_record(&mfa_table()?, row)?;
+        Ok(Some(TotpEnrollment {
+            secret: row["secret"].as_str().unwrap_or_default().to_string(),
+            confirmed: row["confirmed"].as_bool().unwrap_or(false),
+        }))
+    }
+
+    /// Finish enrolling `user` with the code of `step`, storing their recovery codes
+    ///
+    /// Returns `false` if there is no unconfirmed secret to confirm.
+    pub async fn confirm_totp(
+        &self,
+        user: &str,
+        step: u64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_mfa', $user) SET confirmed = true, last_step = $step, \
+             confirmed_at = time::now() WHERE confirmed = false RETURN BEFORE",
+        )
+        .bind("user", user)
+        .bind("step", step as i64);
+        if self.execute(query).await?.is_empty() {
+            return Ok(false);
+        }
+
+        self.replace_recovery_codes(user, recovery_code_hashes).await?;
+        Ok(true)
+    }
+
+    /// Accept a code of `step` for `user`, unless a code of it or a later step was
+    /// already accepted
+    pub async fn use_totp_step(&self, user: &str, step: u64) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_mfa', $user) SET last_step = $step \
+             WHERE confirmed = true AND (last_step = NONE OR last_step < $step) RETURN BEFORE",
+        )
+        .bind("user", user)
+        .bind("step", step as i64);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Replace the recovery codes of `user` with the codes of these hashes
+    pub async fn replace_recovery_codes(
+        &self,
+        user: &str,
+        code_hashes: &[String],
+    ) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "DELETE _recovery_codes WHERE user = $user; \
+             FOR $hash IN $hashes { CREATE _recovery_codes CONTENT { user: $user, \
+             code_hash: $hash, created_at: time::now() } }",
+        )
+        .bind("user", user)
+        .bind("hashes", code_hashes.to_vec());
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Spend the recovery code of `code_hash`, returning whether it was unused
+    pub async fn redeem_recovery_code(&self, user: &str, code_hash: &str) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE _recovery_codes SET used_at = time::now() \
+             WHERE user = $user AND code_hash = $hash AND used_at = NONE RETURN BEFORE",
+        )
+        .bind("user", user)
+        .bind("hash", code_hash);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Unused recovery codes of `user`
+    pub async fn recovery_codes_left(&self, user: &str) -> Result<usize, DbError> {
+        let query = BoundQuery::new(
+            "SELECT count() FROM _recovery_codes WHERE user = $user AND used_at = NONE GROUP ALL",
+        )
+        .bind("user", user);
+
+        let rows = self.execute(query).await?;
+        Ok(rows.first().and_then(|row| row["count"].as_u64()).unwrap_or(0) as usize)
+    }
+
+    /// Remove the TOTP secret and recovery codes of `user`
+    pub async fn disable_mfa(&self, user: &str) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "DELETE type::thing('_mfa', $user); DELETE _recovery_codes WHERE user = $user",
+        )
+        .bind("user", user);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Roles whose users must log in with a second factor
+    pub async fn mfa_required_roles(&self) -> Result<Vec<String>, DbError> {
+        Ok(self.get_config(REQUIRED_ROLES_KEY).await?.unwrap_or_default())
+    }
+
+    /// Require a second factor of the users of `roles` (and only them)
+    pub async fn set_mfa_required_roles(&self, roles: &[String]) -> Result<(), DbError> {
+        self.set_config(REQUIRED_ROLES_KEY, &roles).await
+    }
+}
+
+fn mfa_table() -> Result<Table, DbError> {
+    Table::new("_mfa")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Field;
+
+    fn hashes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_totp_enrollment() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        assert_eq!(db.totp_enrollment("alice").await.unwrap(), None);
+        assert!(db.save_totp_secret("alice", "FIRST").await.unwrap());
+        assert!(db.save_totp_secret("alice", "SECOND").await.unwrap());
+        // Unconfirmed secrets accept no codes
+        assert!(!db.use_totp_step("alice", 10).await.unwrap());
+
+        assert!(db.confirm_totp("alice", 10, &hashes(&["a", "b"])).await.unwrap());
+        assert!(!db.confirm_totp("alice", 11, &[]).await.unwrap());
+        let totp = db.totp_enrollment("alice").await.unwrap().unwrap();
+        assert_eq!(totp.secret, "SECOND");
+        assert!(totp.confirmed);
+        assert!(!db.save_totp_secret("alice", "THIRD").await.unwrap());
+
+        // A step is accepted once, and never after a later one
+        assert!(!db.use_totp_step("alice", 10).await.unwrap());
+        assert!(db.use_totp_step("alice", 12).await.unwrap());
+        assert!(!db.use_totp_step("alice", 11).await.unwrap());
+
+        db.disable_mfa("alice").await.unwrap();
+        assert_eq!(db.totp_enrollment("alice").await.unwrap(), None);
+        assert_eq!(db.recovery_codes_left("alice").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_totp_secrets_can_be_encrypted_at_rest() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let mfa = mfa_table().unwrap();
+        assert!(db.encrypt_fields(&mfa, &[Field::new("last_step").unwrap()]).is_err());
+        db.encrypt_fields(&mfa, &[Field::new("secret").unwrap()]).unwrap();
+        db.unlock_keys(&age::x25519::Identity::generate(), &[]).await.unwrap();
+
+        assert!(db.save_totp_secret("alice", "SECRET").await.unwrap());
+        assert!(db.confirm_totp("alice", 10, &[]).await.unwrap());
+        let raw = db
+            .execute(BoundQuery::new("SELECT VALUE secret FROM _mfa"))
+            .await
+            .unwrap();
+        assert!(raw[0].as_str().unwrap().starts_with("$enc:v1:"));
+        assert_eq!(db.totp_enrollment("alice").await.unwrap().unwrap().secret, "SECRET");
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes_are_single_use() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.replace_recovery_codes("alice", &hashes(&["a", "b"])).await.unwrap();
+        db.replace_recovery_codes("bob", &hashes(&["c"])).await.unwrap();
+        assert_eq!(db.recovery_codes_left("alice").await.unwrap(), 2);
+
+        assert!(db.redeem_recovery_code("alice", "a").await.unwrap());
+        assert!(!db.redeem_recovery_code("alice", "a").await.unwrap());
+        assert!(!db.redeem_recovery_code("alice", "c").await.unwrap());
+        assert_eq!(db.recovery_codes_left("alice").await.unwrap(), 1);
+
+        db.replace_recovery_codes("alice", &hashes(&["d"])).await.unwrap();
+        assert!(!db.redeem_recovery_code("alice", "b").await.unwrap());
+        assert!(db.redeem_recovery_code("alice", "d").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_required_roles() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        assert!(db.mfa_required_roles().await.unwrap().is_empty());
+        db.set_mfa_required_roles(&["admin".to_string()]).await.unwrap();
+        assert_eq!(db.mfa_required_roles().await.unwrap(), vec!["admin".to_string()]);
+    }
+}
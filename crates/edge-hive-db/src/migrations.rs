@@ -0,0 +1,355 @@
+//! Versioned schema migrations
+//!
+//! Every migration has a number, an `up` script and a `down` script. Applied
+//! migrations are recorded in the `_migrations` table together with a
+//! checksum of their `up` script, so an edited migration is detected instead
+//! of silently diverging from what the database actually contains.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A single schema migration
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Monotonically increasing version number
+    pub version: u32,
+    /// Short human-readable name
+    pub name: &'static str,
+    /// SurrealQL applied when migrating up
+    pub up: &'static str,
+    /// SurrealQL applied when migrating down
+    pub down: &'static str,
+}
+
+impl Migration {
+    /// SHA-256 checksum of the `up` script
+    pub fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.up.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Record stored in the `_migrations` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: surrealdb::sql::Datetime,
+}
+
+/// Status of a known migration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    /// When the migration was applied (`None` if pending)
+    pub applied_at: Option<surrealdb::sql::Datetime>,
+    /// False when the applied checksum no longer matches the migration source
+    pub checksum_ok: bool,
+}
+
+impl MigrationStatus {
+    pub fn is_applied(&self) -> bool {
+        self.applied_at.is_some()
+    }
+}
+
+/// Bookkeeping table, created before any migration runs
+pub(crate) const MIGRATIONS_TABLE: &str = r#"
+    DEFINE TABLE IF NOT EXISTS _migrations SCHEMAFULL;
+    DEFINE FIELD IF NOT EXISTS version ON _migrations TYPE int;
+    DEFINE FIELD IF NOT EXISTS name ON _migrations TYPE string;
+    DEFINE FIELD IF NOT EXISTS checksum ON _migrations TYPE string;
+    DEFINE FIELD IF NOT EXISTS applied_at ON _migrations TYPE datetime;
+"#;
+
+/// All migrations, ordered by version
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    name: "initial_schema",
+    // `IF NOT EXISTS` lets databases created before migrations existed adopt this version
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS peer SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS peer_id ON peer TYPE string;
+        DEFINE FIELD IF NOT EXISTS name ON peer TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS addresses ON peer TYPE array;
+        DEFINE FIELD IF NOT EXISTS last_seen ON peer TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS capabilities ON peer TYPE int;
+        DEFINE INDEX IF NOT EXISTS peer_id_idx ON peer FIELDS peer_id UNIQUE;
+
+        DEFINE TABLE IF NOT EXISTS users SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS email ON users TYPE string ASSERT string::is::email($value);
+        DEFINE FIELD IF NOT EXISTS password_hash ON users TYPE string;
+        DEFINE FIELD IF NOT EXISTS provider ON users TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS provider_id ON users TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS name ON users TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS avatar_url ON users TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS created_at ON users TYPE datetime DEFAULT time::now();
+        DEFINE FIELD IF NOT EXISTS updated_at ON users TYPE datetime DEFAULT time::now();
+        DEFINE FIELD IF NOT EXISTS email_verified ON users TYPE bool DEFAULT false;
+        DEFINE FIELD IF NOT EXISTS role ON users TYPE string DEFAULT 'user';
+        DEFINE INDEX IF NOT EXISTS users_email ON users COLUMNS email UNIQUE;
+        DEFINE INDEX IF NOT EXISTS users_provider ON users COLUMNS provider, provider_id UNIQUE;
+
+        DEFINE TABLE IF NOT EXISTS config SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS key ON config TYPE string;
+        DEFINE FIELD IF NOT EXISTS value ON config TYPE any;
+        DEFINE INDEX IF NOT EXISTS key_idx ON config FIELDS key UNIQUE;
+
+        DEFINE TABLE IF NOT EXISTS task SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS title ON task TYPE string;
+        DEFINE FIELD IF NOT EXISTS description ON task TYPE string;
+        DEFINE FIELD IF NOT EXISTS status ON task TYPE string;
+        DEFINE FIELD IF NOT EXISTS priority ON task TYPE string;
+        DEFINE FIELD IF NOT EXISTS due_date ON task TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS created_at ON task TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS assignee ON task TYPE option<string>;
+
+        DEFINE TABLE IF NOT EXISTS sessions SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS user_id ON sessions TYPE record<users>;
+        DEFINE FIELD IF NOT EXISTS refresh_token_hash ON sessions TYPE string;
+        DEFINE FIELD IF NOT EXISTS device_info ON sessions TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS ip_address ON sessions TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS created_at ON sessions TYPE datetime DEFAULT time::now();
+        DEFINE FIELD IF NOT EXISTS expires_at ON sessions TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS revoked ON sessions TYPE bool DEFAULT false;
+        DEFINE INDEX IF NOT EXISTS sessions_user ON sessions COLUMNS user_id;
+        DEFINE INDEX IF NOT EXISTS sessions_token ON sessions COLUMNS refresh_token_hash UNIQUE;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS sessions;
+        REMOVE TABLE IF EXISTS task;
+        REMOVE TABLE IF EXISTS config;
+        REMOVE TABLE IF EXISTS users;
+        REMOVE TABLE IF EXISTS peer;
+    "#,
+}, Migration {
+    version: 2,
+    name: "search_analyzer",
+    // Shared by every full-text index of the data API (see `search.rs`)
+    up: r#"
+        DEFINE ANALYZER IF NOT EXISTS edge_hive_search
+            TOKENIZERS blank, class, punct
+            FILTERS lowercase, ascii, snowball(english);
+    "#,
+    down: r#"
+        REMOVE ANALYZER IF EXISTS edge_hive_search;
+    "#,
+}, Migration {
+    version: 3,
+    name: "change_log_and_webhooks",
+    // Rows are written by table events (see `changes.rs`) and the webhook dispatcher
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _changes SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS changes_dispatched ON _changes FIELDS dispatched;
+
+        DEFINE TABLE IF NOT EXISTS _webhooks SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS `table` ON _webhooks TYPE string;
+        DEFINE FIELD IF NOT EXISTS actions ON _webhooks TYPE array<string>;
+        DEFINE FIELD IF NOT EXISTS url ON _webhooks TYPE string;
+        DEFINE FIELD IF NOT EXISTS secret ON _webhooks TYPE string;
+        DEFINE FIELD IF NOT EXISTS active ON _webhooks TYPE bool DEFAULT true;
+        DEFINE FIELD IF NOT EXISTS created_at ON _webhooks TYPE datetime DEFAULT time::now();
+        DEFINE INDEX IF NOT EXISTS webhooks_table ON _webhooks FIELDS `table`;
+
+        DEFINE TABLE IF NOT EXISTS _webhook_deliveries SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS webhook ON _webhook_deliveries TYPE record<_webhooks>;
+        DEFINE FIELD IF NOT EXISTS change ON _webhook_deliveries TYPE record<_changes>;
+        DEFINE FIELD IF NOT EXISTS status ON _webhook_deliveries TYPE string;
+        DEFINE FIELD IF NOT EXISTS attempts ON _webhook_deliveries TYPE int DEFAULT 0;
+        DEFINE FIELD IF NOT EXISTS next_attempt_at ON _webhook_deliveries TYPE datetime;
+        DEFINE FIELD IF NOT EXISTS last_error ON _webhook_deliveries TYPE option<string>;
+        DEFINE FIELD IF NOT EXISTS created_at ON _webhook_deliveries TYPE datetime DEFAULT time::now();
+        DEFINE FIELD IF NOT EXISTS delivered_at ON _webhook_deliveries TYPE option<datetime>;
+        DEFINE INDEX IF NOT EXISTS deliveries_due ON _webhook_deliveries FIELDS status, next_attempt_at;
+        DEFINE INDEX IF NOT EXISTS deliveries_webhook ON _webhook_deliveries FIELDS webhook;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _webhook_deliveries;
+        REMOVE TABLE IF EXISTS _webhooks;
+        REMOVE TABLE IF EXISTS _changes;
+    "#,
+}, Migration {
+    version: 4,
+    name: "record_history",
+    // Rows are written by table events of versioned tables (see `history.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _history SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS history_record ON _history FIELDS record, version;
+        DEFINE INDEX IF NOT EXISTS history_table ON _history FIELDS `table`, at;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _history;
+    "#,
+}, Migration {
+    version: 5,
+    name: "encryption_keys",
+    // Wrapped data keys of encrypted fields (see `encryption.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _keys SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS version ON _keys TYPE int;
+        DEFINE FIELD IF NOT EXISTS wrapped ON _keys TYPE string;
+        DEFINE FIELD IF NOT EXISTS created_at ON _keys TYPE datetime DEFAULT time::now();
+        DEFINE INDEX IF NOT EXISTS keys_version ON _keys FIELDS version UNIQUE;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _keys;
+    "#,
+}, Migration {
+    version: 6,
+    name: "projects_and_api_keys",
+    // Projects are only registered on the node (see `project.rs` and `api_key.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _projects SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON _projects TYPE string;
+        DEFINE FIELD IF NOT EXISTS hosts ON _projects TYPE array<string>;
+        DEFINE FIELD IF NOT EXISTS issuer ON _projects TYPE string;
+        DEFINE FIELD IF NOT EXISTS jwt_secret ON _projects TYPE string;
+        DEFINE FIELD IF NOT EXISTS created_at ON _projects TYPE datetime DEFAULT time::now();
+
+        DEFINE TABLE IF NOT EXISTS _api_keys SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON _api_keys TYPE string;
+        DEFINE FIELD IF NOT EXISTS role ON _api_keys TYPE string;
+        DEFINE FIELD IF NOT EXISTS prefix ON _api_keys TYPE string;
+        DEFINE FIELD IF NOT EXISTS hash ON _api_keys TYPE string;
+        DEFINE FIELD IF NOT EXISTS created_at ON _api_keys TYPE datetime DEFAULT time::now();
+        DEFINE INDEX IF NOT EXISTS api_keys_hash ON _api_keys FIELDS hash UNIQUE;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _api_keys;
+        REMOVE TABLE IF EXISTS _projects;
+    "#,
+}, Migration {
+    version: 7,
+    name: "branches",
+    // Branches of this database (see `branch.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _branches SCHEMAFULL;
+        DEFINE FIELD IF NOT EXISTS name ON _branches TYPE string;
+        DEFINE FIELD IF NOT EXISTS schema_version ON _branches TYPE int;
+        DEFINE FIELD IF NOT EXISTS created_at ON _branches TYPE datetime DEFAULT time::now();
+        DEFINE FIELD IF NOT EXISTS merged_at ON _branches TYPE option<datetime>;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _branches;
+    "#,
+}, Migration {
+    version: 8,
+    name: "jobs",
+    // Payloads are arbitrary JSON, so both tables are schemaless (see `job.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _jobs SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS jobs_due ON _jobs FIELDS status, run_at;
+        DEFINE INDEX IF NOT EXISTS jobs_lease ON _jobs FIELDS status, lease_expires_at;
+
+        DEFINE TABLE IF NOT EXISTS _job_schedules SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS job_schedules_due ON _job_schedules FIELDS active, next_run_at;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _job_schedules;
+        REMOVE TABLE IF EXISTS _jobs;
+    "#,
+}, Migration {
+    version: 9,
+    name: "session_families",
+    // Refresh tokens rotate within a family (see `session.rs`)
+    up: r#"
+        DEFINE FIELD IF NOT EXISTS family ON sessions TYPE string DEFAULT rand::uuid::v4();
+        DEFINE FIELD IF NOT EXISTS rotated_at ON sessions TYPE option<datetime>;
+        DEFINE INDEX IF NOT EXISTS sessions_family ON sessions COLUMNS family;
+        UPDATE sessions SET family = rand::uuid::v4() WHERE family = NONE;
+    "#,
+    down: r#"
+        REMOVE INDEX IF EXISTS sessions_family ON sessions;
+        REMOVE FIELD IF EXISTS rotated_at ON sessions;
+        REMOVE FIELD IF EXISTS family ON sessions;
+    "#,
+}, Migration {
+    version: 10,
+    name: "one_time_tokens",
+    // Single-use records of email verification and password reset tokens
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _one_time_tokens SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS one_time_tokens_user ON _one_time_tokens FIELDS user, purpose;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _one_time_tokens;
+    "#,
+}, Migration {
+    version: 11,
+    name: "oauth_states",
+    // Logins waiting for an identity provider's redirect (see `oauth_state.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _oauth_states SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS oauth_states_expiry ON _oauth_states FIELDS expires_at;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _oauth_states;
+    "#,
+}, Migration {
+    version: 12,
+    name: "mfa",
+    // TOTP secrets and hashed recovery codes (see `mfa.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _mfa SCHEMALESS;
+        DEFINE TABLE IF NOT EXISTS _recovery_codes SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS recovery_codes_user ON _recovery_codes FIELDS user, code_hash;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _recovery_codes;
+        REMOVE TABLE IF EXISTS _mfa;
+    "#,
+}, Migration {
+    version: 13,
+    name: "passkeys",
+    // WebAuthn credentials and pending ceremonies (see `passkey.rs`)
+    up: r#"
+        DEFINE TABLE IF NOT EXISTS _passkeys SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS passkeys_user ON _passkeys FIELDS user;
+        DEFINE TABLE IF NOT EXISTS _webauthn_challenges SCHEMALESS;
+        DEFINE INDEX IF NOT EXISTS webauthn_challenges_expiry ON _webauthn_challenges
+            FIELDS expires_at;
+    "#,
+    down: r#"
+        REMOVE TABLE IF EXISTS _webauthn_challenges;
+        REMOVE TABLE IF EXISTS _passkeys;
+    "#,
+}, Migration {
+    version: 14,
+    name: "schemaless_config",
+    // Schemafull tables drop the nested content of `value`, which holds the
+    // table schemas, search and vector fields and the MFA policy
+    up: r#"
+        DEFINE TABLE OVERWRITE config SCHEMALESS;
+    "#,
+    down: r#"
+        DEFINE TABLE OVERWRITE config SCHEMAFULL;
+    "#,
+}];
+
+/// Latest schema version known to this build
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_strictly_ordered() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+        assert_eq!(MIGRATIONS.first().map(|m| m.version), Some(1));
+    }
+
+    #[test]
+    fn test_checksum_is_stable() {
+        let migration = MIGRATIONS[0];
+        assert_eq!(migration.checksum(), migration.checksum());
+        assert_eq!(migration.checksum().len(), 64);
+    }
+}
@@ -0,0 +1,92 @@
+//! Pending logins with external identity providers
+//!
+//! Between sending a user to a provider and the provider sending them back,
+//! the login's `state` and PKCE verifier wait here. A state can be taken
+//! once, so a replayed redirect finds nothing.
+
+use crate::query::BoundQuery;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+
+/// A login waiting for the provider's redirect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthState {
+    /// Value of the `state` parameter
+    pub state: String,
+    pub provider: String,
+    pub verifier: String,
+}
+
+impl DatabaseService {
+    /// Keep a pending login until `expires_at`
+    ///
+    /// Expired logins nobody came back for are dropped on the way.
+    pub async fn save_oauth_state(
+        &self,
+        login: &OAuthState,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "DELETE _oauth_states WHERE expires_at <= time::now(); \
+             CREATE type::thing('_oauth_states', $state) CONTENT { provider: $provider, \
+             verifier: $verifier, expires_at: <datetime> $expires_at }",
+        )
+        .bind("state", login.state.as_str())
+        .bind("provider", login.provider.as_str())
+        .bind("verifier", login.verifier.as_str())
+        .bind("expires_at", expires_at.to_rfc3339());
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Remove and return the pending login of `state`, unless it expired
+    pub async fn take_oauth_state(&self, state: &str) -> Result<Option<OAuthState>, DbError> {
+        let query = BoundQuery::new(
+            "DELETE type::thing('_oauth_states', $state) WHERE expires_at > time::now() \
+             RETURN BEFORE",
+        )
+        .bind("state", state);
+
+        let rows = self.execute(query).await?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+
+        let text = |field: &str| row[field].as_str().unwrap_or_default().to_string();
+        Ok(Some(OAuthState {
+            state: state.to_string(),
+            provider: text("provider"),
+            verifier: text("verifier"),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn login(state: &str) -> OAuthState {
+        OAuthState {
+            state: state.to_string(),
+            provider: "github".to_string(),
+            verifier: "verifier".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_states_are_taken_once() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let expires_at = Utc::now() + Duration::minutes(10);
+        db.save_oauth_state(&login("abc"), expires_at).await.unwrap();
+
+        assert_eq!(db.take_oauth_state("abc").await.unwrap(), Some(login("abc")));
+        assert_eq!(db.take_oauth_state("abc").await.unwrap(), None);
+        assert_eq!(db.take_oauth_state("unknown").await.unwrap(), None);
+
+        let expired = Utc::now() - Duration::minutes(1);
+        db.save_oauth_state(&login("old"), expired).await.unwrap();
+        assert_eq!(db.take_oauth_state("old").await.unwrap(), None);
+    }
+}
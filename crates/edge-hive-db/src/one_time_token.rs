@@ -0,0 +1,175 @@
+//! One-time tokens of the auth flows
+//!
+//! Email verification and password reset links carry a signed token; the
+//! signature proves where the token came from, the record kept here makes
+//! it single-use. Redeeming a token also spends every other token the user
+//! holds for the same purpose, so only the latest link ever works once.
+
+use crate::query::BoundQuery;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What a one-time token allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    /// Second step of a login, once the password was checked
+    MfaChallenge,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify_email",
+            TokenPurpose::ResetPassword => "reset_password",
+            TokenPurpose::MfaChallenge => "mfa_challenge",
+        }
+    }
+}
+
+impl DatabaseService {
+    /// Record the token `jti` of `user`, redeemable until `expires_at`
+    pub async fn issue_one_time_token(
+        &self,
+        jti: &str,
+        purpose: TokenPurpose,
+        user: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "CREATE type::thing('_one_time_tokens', $jti) CONTENT { purpose: $purpose, \
+             user: $user, expires_at: <datetime> $expires_at, created_at: time::now() }",
+        )
+        .bind("jti", jti)
+        .bind("purpose", purpose.as_str())
+        .bind("user", user)
+        .bind("expires_at", expires_at.to_rfc3339());
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Spend the token `jti`, returning its user
+    ///
+    /// `None` if the token is unknown, expired, already spent or was issued
+    /// for another purpose.
+    pub async fn redeem_one_time_token(
+        &self,
+        jti: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<String>, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_one_time_tokens', $jti) SET used_at = time::now() \
+             WHERE purpose = $purpose AND used_at = NONE AND expires_at > time::now() \
+             RETURN BEFORE",
+        )
+        .bind("jti", jti)
+        .bind("purpose", purpose.as_str());
+
+        let rows = self.execute(query).await?;
+        let Some(user) = rows.first().and_then(|row| row["user"].as_str()) else {
+            return Ok(None);
+        };
+
+        let siblings = BoundQuery::new(
+            "UPDATE _one_time_tokens SET used_at = time::now() \
+             WHERE user = $user AND purpose = $purpose AND used_at = NONE",
+        )
+        .bind("user", user)
+        .bind("purpose", purpose.as_str());
+        self.execute(siblings).await?;
+
+        Ok(Some(user.to_string()))
+    }
+
+    /// User of the token `jti` if it can still be redeemed, without spending it
+    pub async fn check_one_time_token(
+        &self,
+        jti: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<String>, DbError> {
+        let query = BoundQuery::new(
+            "SELECT user FROM type::thing('_one_time_tokens', $jti) \
+             WHERE purpose = $purpose AND used_at = NONE AND expires_at > time::now()",
+        )
+        .bind("jti", jti)
+        .bind("purpose", purpose.as_str());
+
+        let rows = self.execute(query).await?;
+        Ok(rows.first().and_then(|row| row["user"].as_str()).map(str::to_string))
+    }
+
+    /// Count a failed attempt with the token `jti`, spending it at `max_attempts`
+    pub async fn fail_one_time_token(&self, jti: &str, max_attempts: u32) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_one_time_tokens', $jti) SET attempts += 1; \
+             UPDATE type::thing('_one_time_tokens', $jti) SET used_at = time::now() \
+             WHERE attempts >= $max AND used_at = NONE",
+        )
+        .bind("jti", jti)
+        .bind("max", max_attempts);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn in_an_hour() -> DateTime<Utc> {
+        Utc::now() + Duration::hours(1)
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_single_use() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let reset = TokenPurpose::ResetPassword;
+        db.issue_one_time_token("jti-1", reset, "alice", in_an_hour()).await.unwrap();
+
+        let redeemed = db.redeem_one_time_token("jti-1", TokenPurpose::VerifyEmail).await;
+        assert_eq!(redeemed.unwrap(), None);
+        let redeemed = db.redeem_one_time_token("jti-1", reset).await.unwrap();
+        assert_eq!(redeemed.as_deref(), Some("alice"));
+        assert_eq!(db.redeem_one_time_token("jti-1", reset).await.unwrap(), None);
+        assert_eq!(db.redeem_one_time_token("unknown", reset).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_superseded_tokens() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let reset = TokenPurpose::ResetPassword;
+        let expired = Utc::now() - Duration::minutes(1);
+        db.issue_one_time_token("expired", reset, "alice", expired).await.unwrap();
+        assert_eq!(db.redeem_one_time_token("expired", reset).await.unwrap(), None);
+
+        db.issue_one_time_token("first", reset, "alice", in_an_hour()).await.unwrap();
+        db.issue_one_time_token("second", reset, "alice", in_an_hour()).await.unwrap();
+        db.issue_one_time_token("bob", reset, "bob", in_an_hour()).await.unwrap();
+        assert!(db.redeem_one_time_token("second", reset).await.unwrap().is_some());
+        assert_eq!(db.redeem_one_time_token("first", reset).await.unwrap(), None);
+        assert!(db.redeem_one_time_token("bob", reset).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_attempts_spend_token() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let mfa = TokenPurpose::MfaChallenge;
+        db.issue_one_time_token("jti-1", mfa, "alice", in_an_hour()).await.unwrap();
+
+        db.fail_one_time_token("jti-1", 2).await.unwrap();
+        let checked = db.check_one_time_token("jti-1", mfa).await.unwrap();
+        assert_eq!(checked.as_deref(), Some("alice"));
+        let reset = TokenPurpose::ResetPassword;
+        assert_eq!(db.check_one_time_token("jti-1", reset).await.unwrap(), None);
+
+        db.fail_one_time_token("jti-1", 2).await.unwrap();
+        assert_eq!(db.check_one_time_token("jti-1", mfa).await.unwrap(), None);
+        assert_eq!(db.redeem_one_time_token("jti-1", mfa).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! WebAuthn passkeys of users
+//!
+//! Each passkey is a `_passkeys` record keyed by its credential ID, and a
+//! user may hold any number of them. Ceremonies in flight keep their
+//! challenge in `_webauthn_challenges` until the authenticator answers; a
+//! challenge can be answered once.
+
+use crate::changes::parse_rows;
+use crate::query::BoundQuery;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const PASSKEY_FIELDS: &str = "record::id(id) AS id, user, name, public_key, sign_count, \
+     <string> created_at AS created_at, \
+     (IF last_used_at THEN <string> last_used_at END) AS last_used_at";
+
+/// Attempts at registering a passkey when it conflicts with concurrent writes
+const ADD_ATTEMPTS: usize = 3;
+
+/// A registered passkey
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passkey {
+    /// Credential ID, base64url
+    pub id: String,
+    pub user: String,
+    /// Label given at registration, e.g. the device
+    #[serde(default)]
+    pub name: Option<String>,
+    /// COSE public key, base64url
+    pub public_key: String,
+    /// Last signature counter of the authenticator
+    pub sign_count: u32,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A passkey to register
+#[derive(Debug, Clone)]
+pub struct NewPasskey {
+    pub id: String,
+    pub user: String,
+    pub name: Option<String>,
+    pub public_key: String,
+    pub sign_count: u32,
+}
+
+/// Ceremony a WebAuthn challenge belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebauthnCeremony {
+    Register,
+    Authenticate,
+}
+
+impl WebauthnCeremony {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebauthnCeremony::Register => "register",
+            WebauthnCeremony::Authenticate => "authenticate",
+        }
+    }
+}
+
+/// A ceremony waiting for the authenticator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebauthnChallenge {
+    pub challenge: String,
+    pub ceremony: WebauthnCeremony,
+    /// User the ceremony is for (`None`: whoever holds a passkey)
+    pub user: Option<String>,
+}
+
+impl DatabaseService {
+    /// Keep a pending ceremony until `expires_at`
+    ///
+    /// Expired ceremonies nobody answered are dropped on the way.
+    pub async fn save_webauthn_challenge(
+        &self,
+        challenge: &WebauthnChallenge,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "DELETE _webauthn_challenges WHERE expires_at <= time::now(); \
+             CREATE type::thing('_webauthn_challenges', $challenge) CONTENT { \
+             ceremony: $ceremony, user: $user, expires_at: <datetime> $expires_at }",
+        )
+        .bind("challenge", challenge.challenge.as_str())
+        .bind("ceremony", challenge.ceremony.as_str())
+        .bind("user", challenge.user.clone())
+        .bind("expires_at", expires_at.to_rfc3339());
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Remove and return the pending `ceremony` of `challenge`, unless it expired
+    pub async fn take_webauthn_challenge(
+        &self,
+        challenge: &str,
+        ceremony: WebauthnCeremony,
+    ) -> Result<Option<WebauthnChallenge>, DbError> {
+        let query = BoundQuery::new(
+            "DELETE type::thing('_webauthn_challenges', $challenge) \
+             WHERE ceremony = $ceremony AND expires_at > time::now() RETURN BEFORE",
+        )
+        .bind("challenge", challenge)
+        .bind("ceremony", ceremony.as_str());
+
+        let rows = self.execute(query).await?;
+        Ok(rows.first().map(|row| WebauthnChallenge {
+            challenge: challenge.to_string(),
+            ceremony,
+            user: row["user"].as_str().map(str::to_string),
+        }))
+    }
+
+    /// Register a passkey; `None` if its credential ID is already registered
+    pub async fn add_passkey(&self, passkey: &NewPasskey) -> Result<Option<Passkey>, DbError> {
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE type::thing('_passkeys', $id) CONTENT {{ user: $user, \
+             name: $name, public_key: $public_key, sign_count: $sign_count, \
+             created_at: time::now() }}); \
+             RETURN SELECT {} FROM $created }}",
+            PASSKEY_FIELDS
+        ))
+        .bind("id", passkey.id.as_str())
+        .bind("user", passkey.user.as_str())
+        .bind("name", passkey.name.clone())
+        .bind("public_key", passkey.public_key.as_str())
+        .bind("sign_count", passkey.sign_count);
+
+        // The record ID is the credential ID, so the database settles concurrent
+        // registrations: the loser conflicts, then finds the winner's record
+        let mut attempts = 1;
+        loop {
+            match self.execute(query.clone()).await {
+                Ok(rows) => return Ok(parse_rows(rows)?.into_iter().next()),
+                Err(e) if e.is_duplicate() => return Ok(None),
+                Err(e) if e.is_retryable_conflict() && attempts < ADD_ATTEMPTS => attempts += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// A passkey by credential ID
+    pub async fn passkey(&self, id: &str) -> Result<Option<Passkey>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM type::thing('_passkeys', $id)",
+            PASSKEY_FIELDS
+        ))
+        .bind("id", id);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Passkeys of `user`, oldest first
+    pub async fn passkeys(&self, user: &str) -> Result<Vec<Passkey>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {}, created_at AS __created FROM _passkeys WHERE user = $user \
+             ORDER BY __created",
+            PASSKEY_FIELDS
+        ))
+        .bind("user", user);
+
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// Record a login with the passkey `id`, moving its counter from `sign_count`
+    /// to `new_sign_count`
+    ///
+    /// Returns `false` if another login moved the counter first.
+    pub async fn use_passkey(
+        &self,
+        id: &str,
+        sign_count: u32,
+        new_sign_count: u32,
+    ) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_passkeys', $id) SET sign_count = $new_sign_count, \
+             last_used_at = time::now() WHERE sign_count = $sign_count RETURN BEFORE",
+        )
+        .bind("id", id)
+        .bind("sign_count", sign_count)
+        .bind("new_sign_count", new_sign_count);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Remove the passkey `id` of `user`, returning whether it existed
+    pub async fn delete_passkey(&self, user: &str, id: &str) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "DELETE type::thing('_passkeys', $id) WHERE user = $user RETURN BEFORE",
+        )
+        .bind("id", id)
+        .bind("user", user);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Remove every passkey of `user`
+    pub async fn delete_passkeys(&self, user: &str) -> Result<(), DbError> {
+        let query = BoundQuery::new("DELETE _passkeys WHERE user = $user").bind("user", user);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn new_passkey(id: &str, user: &str) -> NewPasskey {
+        NewPasskey {
+            id: id.to_string(),
+            user: user.to_string(),
+            name: Some("Phone".to_string()),
+            public_key: "key".to_string(),
+            sign_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passkeys_per_user() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let first = db.add_passkey(&new_passkey("cred-1", "alice")).await.unwrap().unwrap();
+        assert_eq!(first.name.as_deref(), Some("Phone"));
+        assert_eq!(first.last_used_at, None);
+        db.add_passkey(&new_passkey("cred-2", "alice")).await.unwrap().unwrap();
+        db.add_passkey(&new_passkey("cred-3", "bob")).await.unwrap().unwrap();
+        assert_eq!(db.add_passkey(&new_passkey("cred-1", "bob")).await.unwrap(), None);
+
+        let ids: Vec<String> =
+            db.passkeys("alice").await.unwrap().into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec!["cred-1", "cred-2"]);
+
+        assert!(!db.delete_passkey("bob", "cred-1").await.unwrap());
+        assert!(db.delete_passkey("alice", "cred-1").await.unwrap());
+        assert_eq!(db.passkey("cred-1").await.unwrap(), None);
+
+        db.delete_passkeys("alice").await.unwrap();
+        assert!(db.passkeys("alice").await.unwrap().is_empty());
+        assert_eq!(db.passkeys("bob").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_registrations_of_a_credential() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        for round in 0..10 {
+            let id = format!("cred-{}", round);
+            let (alice, bob) = (new_passkey(&id, "alice"), new_passkey(&id, "bob"));
+            let (alice, bob) = tokio::join!(db.add_passkey(&alice), db.add_passkey(&bob));
+            let added = [alice.unwrap(), bob.unwrap()];
+            assert_eq!(added.iter().flatten().count(), 1, "round {}", round);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counter_moves_once() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.add_passkey(&new_passkey("cred-1", "alice")).await.unwrap();
+
+        assert!(db.use_passkey("cred-1", 0, 5).await.unwrap());
+        assert!(!db.use_passkey("cred-1", 0, 6).await.unwrap());
+        let passkey = db.passkey("cred-1").await.unwrap().unwrap();
+        assert_eq!(passkey.sign_count, 5);
+        assert!(passkey.last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_challenges_are_taken_once() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let challenge = WebauthnChallenge {
+            challenge: "abc".to_string(),
+            ceremony: WebauthnCeremony::Register,
+            user: Some("alice".to_string()),
+        };
+        let expires_at = Utc::now() + Duration::minutes(5);
+        db.save_webauthn_challenge(&challenge, expires_at).await.unwrap();
+
+        let authenticate = WebauthnCeremony::Authenticate;
+        assert_eq!(db.take_webauthn_challenge("abc", authenticate).await.unwrap(), None);
+        let taken = db.take_webauthn_challenge("abc", WebauthnCeremony::Register).await.unwrap();
+        assert_eq!(taken, Some(challenge));
+        let taken = db.take_webauthn_challenge("abc", WebauthnCeremony::Register).await.unwrap();
+        assert_eq!(taken, None);
+    }
+}
@@ -0,0 +1,467 @@
+//! Row-level security for the data API
+//!
+//! Each table has a [`TablePolicy`]: one SurrealQL predicate per action,
+//! written like the `WHERE` part of a `DEFINE TABLE ... PERMISSIONS` clause.
+//! The caller's identity is bound as `$identity` (`sub`, `role`, `scopes`),
+//! so a predicate such as `owner = $identity.sub` is evaluated per row.
+//!
+//! SurrealDB's own `PERMISSIONS` only apply to record-level sessions, and the
+//! embedded engine has a single shared session, so the predicates are added
+//! to every statement instead. They come from the node configuration and are
+//! trusted; request input is still only ever bound.
+//!
+//! Tables without a policy are owner-scoped: rows carry an `owner` field set
+//! to the creator's subject, and only the owner (or an `admin`) can see or
+//! change them. System tables are never reachable, whatever the policy says.
+
+use crate::filter::{Field, Guard, ListQuery, Page};
+use crate::query::{BoundQuery, RecordId, Table};
+use crate::{DatabaseService, DbError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Name of the variable holding the caller's [`Identity`]
+pub(crate) const IDENTITY_VAR: &str = "identity";
+
+/// Who is running a query, bound as `$identity`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// Subject of the caller's token
+    pub sub: String,
+    /// Role of the caller (`user`, `admin`, ...)
+    pub role: String,
+    /// Scopes granted to the caller's token
+    pub scopes: Vec<String>,
+}
+
+impl Identity {
+    pub fn new(sub: impl Into<String>, role: impl Into<String>, scopes: Vec<String>) -> Self {
+        Self {
+            sub: sub.into(),
+            role: role.into(),
+            scopes,
+        }
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+/// Access rules for one table
+///
+/// A missing rule falls back to "owner or admin" (or "admin only" when the
+/// table has no owner field). Use `"true"` to allow everyone and `"false"` to
+/// deny everyone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TablePolicy {
+    /// Rows the caller may read
+    pub select: Option<String>,
+    /// Whether the caller may create a record, evaluated on the new record
+    pub create: Option<String>,
+    /// Rows the caller may update
+    pub update: Option<String>,
+    /// Rows the caller may delete
+    pub delete: Option<String>,
+    /// Top-level field set to `$identity.sub` on create and never changed by updates
+    pub owner_field: Option<Field>,
+}
+
+impl Default for TablePolicy {
+    fn default() -> Self {
+        Self {
+            select: None,
+            create: Some("true".to_string()),
+            update: None,
+            delete: None,
+            owner_field: Some(Field::new("owner").expect("valid field name")),
+        }
+    }
+}
+
+impl TablePolicy {
+    fn fallback(&self) -> String {
+        match &self.owner_field {
+            Some(owner) => format!("{} = $identity.sub OR $identity.role = 'admin'", owner),
+            None => "$identity.role = 'admin'".to_string(),
+        }
+    }
+
+    pub(crate) fn rule(&self, rule: &Option<String>) -> String {
+        rule.clone().unwrap_or_else(|| self.fallback())
+    }
+}
+
+/// Policies of every table exposed through the data API
+#[derive(Debug, Clone, Default)]
+pub struct PolicySet {
+    tables: HashMap<Table, TablePolicy>,
+    default: TablePolicy,
+}
+
+impl PolicySet {
+    /// Build from configured policies keyed by table name
+    pub fn new(policies: HashMap<String, TablePolicy>) -> Result<Self, DbError> {
+        let tables = policies
+            .into_iter()
+            .map(|(name, policy)| Ok((Table::new(name)?, policy)))
+            .collect::<Result<_, DbError>>()?;
+
+        Ok(Self {
+            tables,
+            default: TablePolicy::default(),
+        })
+    }
+
+    /// Policy of a table; system tables are always denied
+    pub fn policy(&self, table: &Table) -> Result<&TablePolicy, DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+        Ok(self.tables.get(table).unwrap_or(&self.default))
+    }
+}
+
+/// Database access on behalf of a caller
+pub struct ScopedDatabase<'a> {
+    pub(crate) db: &'a DatabaseService,
+    pub(crate) policies: &'a PolicySet,
+    pub(crate) identity: &'a Identity,
+}
+
+impl DatabaseService {
+    /// Run data API operations as `identity`, under `policies`
+    pub fn scoped<'a>(
+        &'a self,
+        policies: &'a PolicySet,
+        identity: &'a Identity,
+    ) -> ScopedDatabase<'a> {
+        ScopedDatabase {
+            db: self,
+            policies,
+            identity,
+        }
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// Add the table's select policy to a list query
+    pub fn restrict(&self, mut query: ListQuery) -> Result<ListQuery, DbError> {
+        let policy = self.policies.policy(&query.table)?;
+        query.guard = Some(Guard {
+            predicate: policy.rule(&policy.select),
+            identity: self.identity.to_value(),
+        });
+        Ok(query)
+    }
+
+    /// List the rows the caller may read
+    pub async fn list_records(&self, query: ListQuery) -> Result<Page, DbError> {
+        let query = self.restrict(query)?;
+        self.db.list_records(&query).await
+    }
+
+    /// Create a record owned by the caller
+    pub async fn create_record(&self, table: &Table, content: Value) -> Result<Value, DbError> {
+        let content = self.creatable(table, content).await?;
+        self.insert(table, BoundQuery::create(table, content)).await
+    }
+
+    /// Create a record owned by the caller under a chosen key
+    pub async fn create_record_at(&self, id: &RecordId, content: Value) -> Result<Value, DbError> {
+        let content = self.creatable(&id.table, content).await?;
+        self.insert(&id.table, BoundQuery::create_at(id, content)).await
+    }
+
+    /// Set the owner of new content and check the caller may create it
+    async fn creatable(&self, table: &Table, mut content: Value) -> Result<Value, DbError> {
+        let policy = self.policies.policy(table)?;
+        let object = content.as_object_mut().ok_or_else(|| {
+            DbError::InvalidQuery("record content must be an object".to_string())
+        })?;
+        if let Some(owner) = &policy.owner_field {
+            object.insert(owner.to_string(), Value::String(self.identity.sub.clone()));
+        }
+
+        if !self.allows(&policy.rule(&policy.create), &content).await? {
+            return Err(DbError::PermissionDenied(format!(
+                "cannot create records in '{}'",
+                table
+            )));
+        }
+
+        self.db.validate_record(table, &content).await?;
+        self.db.seal(table, content)
+    }
+
+    async fn insert(&self, table: &Table, query: BoundQuery) -> Result<Value, DbError> {
+        // The identity is bound so table events (record history) see the author
+        let query = query.bind(IDENTITY_VAR, self.identity.to_value());
+        let created = self
+            .db
+            .execute(query)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Record creation returned no record".to_string()))?;
+        self.db.open_record(table, created)
+    }
+
+    /// Whether `rule` holds for `record`, which need not be stored
+    pub(crate) async fn allows(&self, rule: &str, record: &Value) -> Result<bool, DbError> {
+        let check = BoundQuery::new(format!("SELECT VALUE true FROM [$record] WHERE ({})", rule))
+            .bind("record", record.clone())
+            .bind(IDENTITY_VAR, self.identity.to_value());
+        Ok(!self.db.execute(check).await?.is_empty())
+    }
+
+    /// Whether the caller may read `record` of `table`, e.g. a change pushed to them
+    pub async fn can_read(&self, table: &Table, record: &Value) -> Result<bool, DbError> {
+        let policy = self.policies.policy(table)?;
+        self.allows(&policy.rule(&policy.select), record).await
+    }
+
+    /// A stored `record` of `table` as the caller gets to see it, decrypted
+    ///
+    /// `None` when the caller may not read it. For rows that did not come
+    /// through this service, such as live query notifications.
+    pub async fn readable(&self, table: &Table, record: Value) -> Result<Option<Value>, DbError> {
+        if !self.can_read(table, &record).await? {
+            return Ok(None);
+        }
+        self.db.open_record(table, record).map(Some)
+    }
+
+    /// A record the caller may read, decrypted
+    ///
+    /// Records the caller may not read are reported as missing.
+    pub async fn select_record(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+        let query = BoundQuery::new(format!(
+            "SELECT * FROM type::thing($table, $key) WHERE ({})",
+            policy.rule(&policy.select)
+        ))
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str())
+        .bind(IDENTITY_VAR, self.identity.to_value());
+
+        let record = self.db.execute(query).await?.into_iter().next();
+        record.map(|record| self.db.open_record(&id.table, record)).transpose()
+    }
+
+    /// Merge fields into a record the caller may update
+    ///
+    /// Returns `None` when the record does not exist or is not visible to the caller.
+    pub async fn merge_record(
+        &self,
+        id: &RecordId,
+        mut patch: Value,
+    ) -> Result<Option<Value>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+        if let (Some(owner), Some(object)) = (&policy.owner_field, patch.as_object_mut()) {
+            object.remove(owner.as_str());
+        }
+
+        if self.db.table_schema(&id.table).await?.is_some() {
+            match self.updatable(id).await? {
+                Some(current) => self.db.validate_merge(&id.table, &current, &patch).await?,
+                None => return Ok(None),
+            }
+        }
+
+        let query = BoundQuery::new(format!(
+            "UPDATE type::thing($table, $key) MERGE $patch WHERE ({})",
+            policy.rule(&policy.update)
+        ))
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str())
+        .bind("patch", self.db.seal(&id.table, patch)?)
+        .bind(IDENTITY_VAR, self.identity.to_value());
+
+        let merged = self.db.execute(query).await?.into_iter().next();
+        merged.map(|record| self.db.open_record(&id.table, record)).transpose()
+    }
+
+    /// A record the caller may update, as it is now
+    ///
+    /// Records the caller may not update are reported as missing.
+    pub(crate) async fn updatable(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+        let query = BoundQuery::new(format!(
+            "SELECT * FROM type::thing($table, $key) WHERE ({})",
+            policy.rule(&policy.update)
+        ))
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str())
+        .bind(IDENTITY_VAR, self.identity.to_value());
+
+        let current = self.db.execute(query).await?.into_iter().next();
+        current.map(|record| self.db.open_record(&id.table, record)).transpose()
+    }
+
+    /// Delete a record the caller may delete, returning it
+    pub async fn delete_record(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        let policy = self.policies.policy(&id.table)?;
+
+        let query = BoundQuery::new(format!(
+            "DELETE type::thing($table, $key) WHERE ({}) RETURN BEFORE",
+            policy.rule(&policy.delete)
+        ))
+        .bind("table", id.table.as_str())
+        .bind("key", id.key.as_str())
+        .bind(IDENTITY_VAR, self.identity.to_value());
+
+        let deleted = self.db.execute(query).await?.into_iter().next();
+        deleted.map(|record| self.db.open_record(&id.table, record)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alice() -> Identity {
+        Identity::new("users:alice", "user", vec![])
+    }
+
+    fn bob() -> Identity {
+        Identity::new("users:bob", "user", vec![])
+    }
+
+    #[test]
+    fn test_system_tables_are_denied() {
+        let policies = PolicySet::new(HashMap::from([(
+            "users".to_string(),
+            TablePolicy {
+                select: Some("true".to_string()),
+                ..Default::default()
+            },
+        )]))
+        .unwrap();
+
+        for table in ["users", "sessions", "_migrations"] {
+            let table = Table::new(table).unwrap();
+            assert!(matches!(policies.policy(&table), Err(DbError::PermissionDenied(_))));
+        }
+    }
+
+    #[test]
+    fn test_guard_is_part_of_cache_key() {
+        let policies = PolicySet::default();
+        let query = ListQuery::new(Table::new("posts").unwrap());
+
+        let identities = [alice(), bob()];
+        let keys: Vec<String> = identities
+            .iter()
+            .map(|identity| {
+                let guard = Guard {
+                    predicate: policies.default.fallback(),
+                    identity: identity.to_value(),
+                };
+                ListQuery {
+                    guard: Some(guard),
+                    ..query.clone()
+                }
+                .normalized()
+            })
+            .collect();
+
+        assert_ne!(keys[0], keys[1]);
+    }
+
+    #[tokio::test]
+    async fn test_owner_scoped_by_default() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let (alice, bob) = (alice(), bob());
+        let table = Table::new("notes").unwrap();
+
+        let created = db
+            .scoped(&policies, &alice)
+            .create_record(&table, serde_json::json!({"text": "mine", "owner": "users:bob"}))
+            .await
+            .unwrap();
+        assert_eq!(created["owner"], "users:alice");
+
+        let mine = db
+            .scoped(&policies, &alice)
+            .list_records(ListQuery::new(table.clone()))
+            .await
+            .unwrap();
+        assert_eq!(mine.total, 1);
+
+        let theirs = db
+            .scoped(&policies, &bob)
+            .list_records(ListQuery::new(table.clone()))
+            .await
+            .unwrap();
+        assert_eq!(theirs.total, 0);
+
+        let keys = db
+            .execute(BoundQuery::new("SELECT VALUE record::id(id) FROM notes"))
+            .await
+            .unwrap();
+        let id = RecordId::new(table, keys[0].as_str().unwrap()).unwrap();
+
+        let bob_db = db.scoped(&policies, &bob);
+        assert!(!bob_db.can_read(&id.table, &created).await.unwrap());
+        assert!(bob_db.select_record(&id).await.unwrap().is_none());
+        let patch = serde_json::json!({"text": "x"});
+        assert!(bob_db.merge_record(&id, patch).await.unwrap().is_none());
+        assert!(bob_db.delete_record(&id).await.unwrap().is_none());
+
+        let alice_db = db.scoped(&policies, &alice);
+        let updated = alice_db
+            .merge_record(&id, serde_json::json!({"text": "edited", "owner": "users:bob"}))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated["owner"], "users:alice");
+        assert!(alice_db.delete_record(&id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_configured_policy() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::new(HashMap::from([(
+            "posts".to_string(),
+            TablePolicy {
+                select: Some("published = true OR owner = $identity.sub".to_string()),
+                create: Some("'posts:write' IN $identity.scopes".to_string()),
+                ..Default::default()
+            },
+        )]))
+        .unwrap();
+        let table = Table::new("posts").unwrap();
+
+        let denied = db
+            .scoped(&policies, &alice())
+            .create_record(&table, serde_json::json!({"published": true}))
+            .await;
+        assert!(matches!(denied, Err(DbError::PermissionDenied(_))));
+
+        let writer = Identity::new("users:alice", "user", vec!["posts:write".to_string()]);
+        let writer_db = db.scoped(&policies, &writer);
+        writer_db
+            .create_record(&table, serde_json::json!({"published": true}))
+            .await
+            .unwrap();
+        writer_db
+            .create_record(&table, serde_json::json!({"published": false}))
+            .await
+            .unwrap();
+
+        let page = db
+            .scoped(&policies, &bob())
+            .list_records(ListQuery::new(table))
+            .await
+            .unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.rows[0]["published"], true);
+    }
+}
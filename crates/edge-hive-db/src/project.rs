@@ -0,0 +1,292 @@
+//! Projects: independent backends hosted by one node
+//!
+//! Projects are registered in the node database (`_projects`). Each one keeps
+//! its data in its own SurrealDB namespace of the same store, opened with
+//! [`DatabaseService::open_project`], and has its own JWT issuer and secret.
+
+use crate::changes::parse_rows;
+use crate::query::BoundQuery;
+use crate::webhook::generate_secret;
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Longest project name (one DNS label)
+pub const MAX_PROJECT_NAME: usize = 63;
+
+const PROJECT_FIELDS: &str =
+    "name, hosts, issuer, jwt_secret, <string> created_at AS created_at";
+
+/// A backend hosted by the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub name: String,
+    /// Host names whose requests are routed to this project
+    pub hosts: Vec<String>,
+    /// Issuer of the project's tokens
+    pub issuer: String,
+    /// HMAC secret of the project's tokens
+    pub jwt_secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Project {
+    /// SurrealDB namespace holding the project's data
+    pub fn namespace(&self) -> String {
+        project_namespace(&self.name)
+    }
+}
+
+/// Fields of a new project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewProject {
+    pub name: String,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Token issuer (`edge-hive:<name>` when missing)
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Token secret (generated when missing)
+    #[serde(default)]
+    pub jwt_secret: Option<String>,
+}
+
+/// Check that `name` can name a project
+///
+/// Names are used as URL path segments and host labels: lowercase ASCII
+/// letters, digits and `-`, starting with a letter.
+pub fn validate_project_name(name: &str) -> Result<(), DbError> {
+    validate_label("project", name)
+}
+
+/// Check that `name` is a DNS label, naming a `kind` of thing
+pub(crate) fn validate_label(kind: &str, name: &str) -> Result<(), DbError> {
+    let valid = name.len() <= MAX_PROJECT_NAME
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && !name.ends_with('-')
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(DbError::InvalidIdentifier(format!(
+            "'{}' is not a valid {} name (use a-z, 0-9 and '-')",
+            name, kind
+        )))
+    }
+}
+
+/// Namespace of a validated project name
+fn project_namespace(name: &str) -> String {
+    format!("project_{}", name.replace('-', "_"))
+}
+
+impl DatabaseService {
+    /// Register a project
+    ///
+    /// Fails if the name or one of the hosts is already taken.
+    pub async fn create_project(&self, project: NewProject) -> Result<Project, DbError> {
+        self.ensure_node("create projects")?;
+        validate_project_name(&project.name)?;
+        let hosts: Vec<String> = project.hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+
+        let taken = self.projects().await?.into_iter().find(|existing| {
+            existing.name == project.name || existing.hosts.iter().any(|h| hosts.contains(h))
+        });
+        if let Some(existing) = taken {
+            return Err(DbError::InvalidQuery(format!(
+                "project name or host already used by '{}'",
+                existing.name
+            )));
+        }
+
+        let issuer = project.issuer.unwrap_or_else(|| format!("edge-hive:{}", project.name));
+        let content = json!({
+            "name": project.name,
+            "hosts": hosts,
+            "issuer": issuer,
+            "jwt_secret": project.jwt_secret.unwrap_or_else(generate_secret),
+        });
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE type::thing('_projects', $content.name) CONTENT $content); \
+             RETURN SELECT {} FROM $created }}",
+            PROJECT_FIELDS
+        ))
+        .bind("content", content);
+
+        parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Project creation returned no record".to_string()))
+    }
+
+    /// Every project, by name
+    pub async fn projects(&self) -> Result<Vec<Project>, DbError> {
+        let query =
+            BoundQuery::new(format!("SELECT {} FROM _projects ORDER BY name", PROJECT_FIELDS));
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// A project by name
+    pub async fn get_project(&self, name: &str) -> Result<Option<Project>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM type::thing('_projects', $name)",
+            PROJECT_FIELDS
+        ))
+        .bind("name", name);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Unregister a project and drop its data; `false` if it did not exist
+    pub async fn delete_project(&self, name: &str) -> Result<bool, DbError> {
+        self.ensure_node("delete projects")?;
+        let Some(project) = self.get_project(name).await? else {
+            return Ok(false);
+        };
+
+        // Namespaces cannot be bound, but validated names are safe to inline
+        validate_project_name(&project.name)?;
+        let query = BoundQuery::new(format!(
+            "REMOVE NAMESPACE IF EXISTS {}; DELETE type::thing('_projects', $name);",
+            project.namespace()
+        ))
+        .bind("name", name);
+        self.execute(query).await?;
+        Ok(true)
+    }
+
+    /// Open the database of a project, applying pending migrations
+    ///
+    /// The returned service shares the node's store; drop it before shutting
+    /// the node's service down.
+    pub async fn open_project(&self, project: &Project) -> Result<Self, DbError> {
+        self.ensure_node("open projects")?;
+        validate_project_name(&project.name)?;
+
+        let config = self.config.clone().with_namespace(project.namespace(), "main");
+        let service = self.sibling(config, Some(project.name.clone()), None);
+        service.migrate_up(None).await?;
+        Ok(service)
+    }
+
+    /// Name of the project this service was opened for, `None` for the node
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    fn ensure_node(&self, action: &str) -> Result<(), DbError> {
+        if self.project.is_none() && self.branch.is_none() {
+            Ok(())
+        } else {
+            Err(DbError::PermissionDenied(format!(
+                "only the node's main database can {}",
+                action
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Table;
+    use serde_json::json;
+
+    fn new_project(name: &str, hosts: &[&str]) -> NewProject {
+        NewProject {
+            name: name.to_string(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            issuer: None,
+            jwt_secret: None,
+        }
+    }
+
+    #[test]
+    fn test_project_names() {
+        for name in ["shop", "my-app", "a1"] {
+            assert!(validate_project_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", "1app", "App", "my_app", "app-", "a.b", &"a".repeat(64)] {
+            assert!(validate_project_name(name).is_err(), "{}", name);
+        }
+        assert_eq!(project_namespace("my-app"), "project_my_app");
+    }
+
+    #[tokio::test]
+    async fn test_create_and_delete_projects() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let shop = db.create_project(new_project("shop", &["Shop.Example.com"])).await.unwrap();
+        assert_eq!(shop.hosts, vec!["shop.example.com"]);
+        assert_eq!(shop.issuer, "edge-hive:shop");
+        assert!(!shop.jwt_secret.is_empty());
+
+        assert!(db.create_project(new_project("shop", &[])).await.is_err());
+        assert!(db.create_project(new_project("blog", &["shop.example.com"])).await.is_err());
+        db.create_project(new_project("blog", &[])).await.unwrap();
+
+        let names: Vec<_> = db.projects().await.unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["blog", "shop"]);
+        assert_eq!(db.get_project("shop").await.unwrap(), Some(shop));
+
+        assert!(db.delete_project("shop").await.unwrap());
+        assert!(!db.delete_project("shop").await.unwrap());
+        assert!(db.get_project("shop").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_projects_are_isolated() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let shop = db.create_project(new_project("shop", &[])).await.unwrap();
+        let blog = db.create_project(new_project("blog", &[])).await.unwrap();
+        let shop_db = db.open_project(&shop).await.unwrap();
+        let blog_db = db.open_project(&blog).await.unwrap();
+        assert_eq!(shop_db.project(), Some("shop"));
+        assert!(shop_db.open_project(&blog).await.is_err());
+
+        let notes = Table::new("notes").unwrap();
+        shop_db.create_record(&notes, json!({"text": "shop"})).await.unwrap();
+        db.create_record(&notes, json!({"text": "node"})).await.unwrap();
+
+        let texts = |rows: Vec<serde_json::Value>| -> Vec<String> {
+            rows.iter().map(|r| r["text"].as_str().unwrap().to_string()).collect()
+        };
+        assert_eq!(texts(shop_db.select_records(&notes).await.unwrap()), vec!["shop"]);
+        assert_eq!(texts(db.select_records(&notes).await.unwrap()), vec!["node"]);
+        assert!(blog_db.select_records(&notes).await.unwrap().is_empty());
+
+        // Projects are registered on the node only
+        assert!(shop_db.projects().await.unwrap().is_empty());
+
+        drop(shop_db);
+        db.delete_project("shop").await.unwrap();
+        let shop = db.create_project(new_project("shop", &[])).await.unwrap();
+        let shop_db = db.open_project(&shop).await.unwrap();
+        assert!(shop_db.select_records(&notes).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_projects_do_not_wait_for_each_other() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let shop = db.create_project(new_project("shop", &[])).await.unwrap();
+        let shop_db = db.open_project(&shop).await.unwrap();
+        let notes = Table::new("notes").unwrap();
+
+        // A project holding the session, as while an export starts, does not
+        // hold up queries of the node
+        let switched = shop_db.db().switch().await.unwrap();
+        let write = db.create_record(&notes, json!({"text": "node"}));
+        tokio::time::timeout(std::time::Duration::from_secs(5), write).await.unwrap().unwrap();
+        drop(switched);
+
+        let writes = (0..20).map(|i| {
+            let target = if i % 2 == 0 { &db } else { &shop_db };
+            target.create_record(&notes, json!({"text": i}))
+        });
+        for created in futures::future::join_all(writes).await {
+            created.unwrap();
+        }
+        assert_eq!(db.select_records(&notes).await.unwrap().len(), 11);
+        assert_eq!(shop_db.select_records(&notes).await.unwrap().len(), 10);
+    }
+}
@@ -0,0 +1,213 @@
+//! Parameterized query layer
+//!
+//! Values never get spliced into SurrealQL text: they travel as bound
+//! variables, and tables/records are addressed through `type::table` and
+//! `type::thing`. Table names are still validated so callers cannot reach
+//! arbitrary tables by accident.
+
+use crate::DbError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// A validated table name (`[A-Za-z_][A-Za-z0-9_]*`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Table(String);
+
+impl Table {
+    pub fn new(name: impl Into<String>) -> Result<Self, DbError> {
+        let name = name.into();
+        if is_identifier(&name) {
+            Ok(Self(name))
+        } else {
+            Err(DbError::InvalidIdentifier(name))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Tables that back the node itself (users, sessions, migrations, ...)
+    pub fn is_system(&self) -> bool {
+        self.0.starts_with('_') || SYSTEM_TABLES.contains(&self.0.as_str())
+    }
+}
+
+/// Whether `s` is a plain SurrealQL identifier (`[A-Za-z_][A-Za-z0-9_]*`)
+pub(crate) fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Tables owned by Edge Hive itself
+pub const SYSTEM_TABLES: &[&str] = &["users", "sessions", "config", "peer", "task"];
+
+impl TryFrom<String> for Table {
+    type Error = DbError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<Table> for String {
+    fn from(table: Table) -> Self {
+        table.0
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A typed record identifier (`table:key`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecordId {
+    pub table: Table,
+    pub key: String,
+}
+
+impl RecordId {
+    pub fn new(table: Table, key: impl Into<String>) -> Result<Self, DbError> {
+        let key = key.into();
+        if key.is_empty() {
+            return Err(DbError::InvalidIdentifier(format!("{}:", table)));
+        }
+        Ok(Self { table, key })
+    }
+
+    /// Convert to a SurrealDB `Thing`
+    pub fn to_thing(&self) -> surrealdb::sql::Thing {
+        surrealdb::sql::Thing::from((self.table.as_str(), self.key.as_str()))
+    }
+}
+
+impl FromStr for RecordId {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (table, key) = s
+            .split_once(':')
+            .ok_or_else(|| DbError::InvalidIdentifier(s.to_string()))?;
+        Self::new(Table::new(table)?, key)
+    }
+}
+
+impl fmt::Display for RecordId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.table, self.key)
+    }
+}
+
+/// A SurrealQL statement plus its bound variables
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BoundQuery {
+    pub sql: String,
+    #[serde(default)]
+    pub vars: Map<String, Value>,
+}
+
+impl BoundQuery {
+    pub fn new(sql: impl Into<String>) -> Self {
+        Self {
+            sql: sql.into(),
+            vars: Map::new(),
+        }
+    }
+
+    /// Bind a variable, referenced as `$name` in the statement
+    pub fn bind(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Bind every variable of a JSON object
+    pub fn bind_all(mut self, vars: Map<String, Value>) -> Self {
+        self.vars.extend(vars);
+        self
+    }
+
+    /// `SELECT * FROM <table>`
+    pub fn select_all(table: &Table) -> Self {
+        Self::new("SELECT * FROM type::table($table)").bind("table", table.as_str())
+    }
+
+    /// `SELECT * FROM <table>:<key>`
+    pub fn select(id: &RecordId) -> Self {
+        Self::new("SELECT * FROM type::thing($table, $key)")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str())
+    }
+
+    /// `CREATE <table> CONTENT <content>`
+    pub fn create(table: &Table, content: Value) -> Self {
+        Self::new("CREATE type::table($table) CONTENT $content")
+            .bind("table", table.as_str())
+            .bind("content", content)
+    }
+
+    /// `CREATE <table>:<key> CONTENT <content>` (fails if the record exists)
+    pub fn create_at(id: &RecordId, content: Value) -> Self {
+        Self::new("CREATE type::thing($table, $key) CONTENT $content")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str())
+            .bind("content", content)
+    }
+
+    /// `UPDATE <table>:<key> MERGE <patch>` (missing records are not created)
+    pub fn merge(id: &RecordId, patch: Value) -> Self {
+        Self::new("UPDATE type::thing($table, $key) MERGE $patch")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str())
+            .bind("patch", patch)
+    }
+
+    /// `DELETE <table>:<key>`, returning the deleted record
+    pub fn delete(id: &RecordId) -> Self {
+        Self::new("DELETE type::thing($table, $key) RETURN BEFORE")
+            .bind("table", id.table.as_str())
+            .bind("key", id.key.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_validation() {
+        assert!(Table::new("posts").is_ok());
+        assert!(Table::new("_migrations").is_ok());
+        assert!(Table::new("").is_err());
+        assert!(Table::new("1posts").is_err());
+        assert!(Table::new("posts; DELETE users").is_err());
+        assert!(Table::new("users").unwrap().is_system());
+        assert!(!Table::new("posts").unwrap().is_system());
+    }
+
+    #[test]
+    fn test_record_id_parsing() {
+        let id: RecordId = "posts:abc-123".parse().unwrap();
+        assert_eq!(id.table.as_str(), "posts");
+        assert_eq!(id.key, "abc-123");
+        assert_eq!(id.to_string(), "posts:abc-123");
+
+        assert!("posts".parse::<RecordId>().is_err());
+        assert!("posts:".parse::<RecordId>().is_err());
+        assert!("bad table:1".parse::<RecordId>().is_err());
+    }
+
+    #[test]
+    fn test_values_are_bound_not_inlined() {
+        let table = Table::new("posts").unwrap();
+        let query = BoundQuery::create(&table, serde_json::json!({"title": "'; REMOVE TABLE users; --"}));
+        assert!(!query.sql.contains("REMOVE"));
+        assert_eq!(query.vars["table"], "posts");
+    }
+}
@@ -0,0 +1,416 @@
+//! Per-table data retention
+//!
+//! A [`RetentionRule`] bounds a table by age (rows whose timestamp field is
+//! older than `max_age`), by size (only the newest `max_rows` rows are kept),
+//! or both. The [`Janitor`] applies every rule periodically and keeps count
+//! of what it purged. Whoever caches rows can follow its purges with
+//! [`Janitor::on_purge`].
+//!
+//! Rows are deleted in batches so one sweep never holds a long transaction.
+//! The change log is special: a change still needed by a webhook delivery
+//! that has not succeeded yet is never purged.
+
+use crate::filter::Field;
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Rows deleted per statement
+const PURGE_BATCH: u64 = 1000;
+
+/// Table of the change log (see `changes.rs`)
+const CHANGES_TABLE: &str = "_changes";
+
+/// Limits on the rows kept in one table
+///
+/// ```toml
+/// [database.retention.metrics]
+/// field = "recorded_at"   # datetime field, defaults to created_at
+/// max_age = "30d"         # s, m, h, d or w
+/// max_rows = 1000000
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionRule {
+    /// Datetime field the age of a row is measured from, and the order in
+    /// which rows beyond `max_rows` are dropped
+    #[serde(default = "default_field")]
+    pub field: Field,
+    /// Rows older than this are purged
+    #[serde(default, with = "duration_text", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Duration>,
+    /// Only the newest rows are kept beyond this count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rows: Option<u64>,
+}
+
+fn default_field() -> Field {
+    Field::new("created_at").expect("valid field")
+}
+
+impl RetentionRule {
+    /// Sessions are dropped as soon as they expire
+    pub fn expired_sessions() -> Self {
+        Self {
+            field: Field::new("expires_at").expect("valid field"),
+            max_age: Some(Duration::ZERO),
+            max_rows: None,
+        }
+    }
+
+    fn check(&self, table: &Table) -> Result<(), DbError> {
+        if self.max_age.is_none() && self.max_rows.is_none() {
+            return Err(DbError::InvalidQuery(format!(
+                "retention rule of '{}' needs max_age or max_rows",
+                table
+            )));
+        }
+        Ok(())
+    }
+
+    /// Extra condition on purged rows of `table`
+    fn guard(table: &Table) -> &'static str {
+        if table.as_str() == CHANGES_TABLE {
+            // Pending and dead-lettered deliveries still read their change
+            " AND dispatched = true AND id NOT IN \
+             (SELECT VALUE change FROM _webhook_deliveries WHERE status != 'delivered')"
+        } else {
+            ""
+        }
+    }
+
+    /// Statement deleting one batch of expired rows, returning their IDs
+    fn expired_batch(&self, table: &Table, max_age: Duration) -> BoundQuery {
+        BoundQuery::new(format!(
+            "{{ LET $expired = (SELECT VALUE id FROM type::table($table) \
+             WHERE {} < time::now() - <duration> $max_age{} LIMIT $batch); \
+             DELETE $expired; RETURN $expired }}",
+            self.field,
+            Self::guard(table)
+        ))
+        .bind("table", table.as_str())
+        .bind("max_age", format!("{}s", max_age.as_secs()))
+        .bind("batch", PURGE_BATCH)
+    }
+
+    /// Statement deleting one batch of the oldest rows beyond `max_rows`
+    fn overflow_batch(&self, table: &Table, max_rows: u64) -> BoundQuery {
+        BoundQuery::new(format!(
+            "{{ LET $overflow = (SELECT id, {0} FROM type::table($table) WHERE true{1} \
+             ORDER BY {0} DESC START $keep LIMIT $batch).id; \
+             DELETE $overflow; RETURN $overflow }}",
+            self.field,
+            Self::guard(table)
+        ))
+        .bind("table", table.as_str())
+        .bind("keep", max_rows)
+        .bind("batch", PURGE_BATCH)
+    }
+}
+
+/// Durations written as a number and a unit: `90s`, `15m`, `12h`, `30d`, `2w`
+mod duration_text {
+    use super::*;
+
+    pub fn parse(text: &str) -> Result<Duration, String> {
+        let text = text.trim();
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (count, unit) = text.split_at(split);
+        let count: u64 = count.parse().map_err(|_| format!("invalid duration '{}'", text))?;
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86_400,
+            "w" => 604_800,
+            _ => return Err(format!("invalid duration unit in '{}'", text)),
+        };
+        Ok(Duration::from_secs(count.saturating_mul(unit)))
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(duration) => s.serialize_str(&format!("{}s", duration.as_secs())),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|text| parse(&text).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+impl DatabaseService {
+    /// Apply a retention rule to a table now, returning the number of purged rows
+    pub async fn purge(&self, table: &Table, rule: &RetentionRule) -> Result<u64, DbError> {
+        rule.check(table)?;
+
+        let mut purged = 0;
+        if let Some(max_age) = rule.max_age {
+            purged += self.purge_batches(|| rule.expired_batch(table, max_age)).await?;
+        }
+        if let Some(max_rows) = rule.max_rows {
+            purged += self.purge_batches(|| rule.overflow_batch(table, max_rows)).await?;
+        }
+        Ok(purged)
+    }
+
+    async fn purge_batches(&self, batch: impl Fn() -> BoundQuery) -> Result<u64, DbError> {
+        let mut purged = 0;
+        loop {
+            let deleted = self.execute(batch()).await?.len() as u64;
+            purged += deleted;
+            if deleted < PURGE_BATCH {
+                return Ok(purged);
+            }
+        }
+    }
+}
+
+/// Rows purged from one table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableRetention {
+    /// Rows purged since the node started
+    pub purged: u64,
+    /// Rows purged by the last sweep
+    pub last_purged: u64,
+    pub last_sweep: Option<DateTime<Utc>>,
+    /// Error of the last sweep, if it failed
+    pub last_error: Option<String>,
+}
+
+/// Counters of the janitor, per table
+#[derive(Debug, Default)]
+pub struct RetentionMetrics {
+    tables: Mutex<BTreeMap<Table, TableRetention>>,
+}
+
+impl RetentionMetrics {
+    fn record(&self, table: &Table, outcome: &Result<u64, DbError>) {
+        let mut tables = self.tables.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = tables.entry(table.clone()).or_default();
+        entry.last_sweep = Some(Utc::now());
+        match outcome {
+            Ok(purged) => {
+                entry.purged += purged;
+                entry.last_purged = *purged;
+                entry.last_error = None;
+            }
+            Err(e) => {
+                entry.last_purged = 0;
+                entry.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Counters of every table swept so far
+    pub fn snapshot(&self) -> BTreeMap<Table, TableRetention> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Called with every table a sweep purged rows from
+type PurgeHook = Box<dyn Fn(Table) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Background task enforcing retention rules
+pub struct Janitor {
+    db: Arc<DatabaseService>,
+    rules: Vec<(Table, RetentionRule)>,
+    interval: Duration,
+    metrics: Arc<RetentionMetrics>,
+    on_purge: Option<PurgeHook>,
+}
+
+impl Janitor {
+    /// Enforce `rules`, keyed by table name, every `interval`
+    ///
+    /// Expired sessions are purged too, unless `rules` has its own rule for
+    /// the `sessions` table.
+    pub fn new(
+        db: Arc<DatabaseService>,
+        rules: &HashMap<String, RetentionRule>,
+        interval: Duration,
+    ) -> Result<Self, DbError> {
+        let mut rules = rules
+            .iter()
+            .map(|(name, rule)| {
+                let table = Table::new(name.as_str())?;
+                rule.check(&table)?;
+                Ok((table, rule.clone()))
+            })
+            .collect::<Result<Vec<_>, DbError>>()?;
+        if !rules.iter().any(|(table, _)| table.as_str() == "sessions") {
+            rules.push((Table::new("sessions")?, RetentionRule::expired_sessions()));
+        }
+        rules.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(Self {
+            db,
+            rules,
+            interval,
+            metrics: Arc::default(),
+            on_purge: None,
+        })
+    }
+
+    /// Run `hook` with every table rows were purged from, once per sweep
+    pub fn on_purge(
+        mut self,
+        hook: impl Fn(Table) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_purge = Some(Box::new(hook));
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<RetentionMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Apply every rule once, returning the total number of purged rows
+    ///
+    /// A failing rule is recorded in the metrics and does not stop the others.
+    pub async fn sweep(&self) -> u64 {
+        let mut total = 0;
+        for (table, rule) in &self.rules {
+            let outcome = self.db.purge(table, rule).await;
+            match &outcome {
+                Ok(0) => {}
+                Ok(purged) => {
+                    info!("Retention purged {} rows from '{}'", purged, table);
+                    total += purged;
+                    if let Some(hook) = &self.on_purge {
+                        hook(table.clone()).await;
+                    }
+                }
+                Err(e) => warn!("Retention of '{}' failed: {}", table, e),
+            }
+            self.metrics.record(table, &outcome);
+        }
+        total
+    }
+
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.sweep().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::NewWebhook;
+
+    async fn logs(db: &DatabaseService, ages_in_days: &[u32]) -> Table {
+        for days in ages_in_days {
+            db.query_json(&format!("CREATE logs SET created_at = time::now() - {}d", days))
+                .await
+                .unwrap();
+        }
+        Table::new("logs").unwrap()
+    }
+
+    #[test]
+    fn test_rule_from_config() {
+        let rule: RetentionRule =
+            serde_json::from_value(serde_json::json!({"max_age": "30d"})).unwrap();
+        assert_eq!(rule.field.as_str(), "created_at");
+        assert_eq!(rule.max_age, Some(Duration::from_secs(30 * 86_400)));
+        assert!(duration_text::parse("3y").is_err());
+        assert!(duration_text::parse("d").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_by_age_and_count() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = logs(&db, &[0, 1, 2, 10, 20]).await;
+
+        let rule = RetentionRule {
+            field: default_field(),
+            max_age: Some(Duration::from_secs(7 * 86_400)),
+            max_rows: None,
+        };
+        assert_eq!(db.purge(&table, &rule).await.unwrap(), 2);
+
+        let rule = RetentionRule {
+            max_age: None,
+            max_rows: Some(1),
+            ..rule
+        };
+        assert_eq!(db.purge(&table, &rule).await.unwrap(), 2);
+        assert_eq!(db.select_records(&table).await.unwrap().len(), 1);
+
+        let empty = RetentionRule { max_rows: None, ..rule };
+        assert!(db.purge(&table, &empty).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_changes_with_pending_deliveries_are_kept() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let posts = Table::new("posts").unwrap();
+        db.create_webhook(NewWebhook {
+            table: posts.clone(),
+            actions: vec![],
+            url: "http://localhost:9/hook".to_string(),
+            secret: None,
+        })
+        .await
+        .unwrap();
+        db.create_record(&posts, serde_json::json!({"title": "a"})).await.unwrap();
+        db.fan_out_changes(10).await.unwrap();
+
+        let changes = Table::new(CHANGES_TABLE).unwrap();
+        let rule = RetentionRule {
+            field: Field::new("at").unwrap(),
+            max_age: Some(Duration::ZERO),
+            max_rows: None,
+        };
+        assert_eq!(db.purge(&changes, &rule).await.unwrap(), 0);
+
+        let due = db.due_deliveries(10).await.unwrap();
+        db.record_delivery_success(&due[0].delivery.id).await.unwrap();
+        assert_eq!(db.purge(&changes, &rule).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_janitor_records_metrics() {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let table = logs(&db, &[1, 5]).await;
+
+        let rules = HashMap::from([(
+            "logs".to_string(),
+            RetentionRule {
+                field: default_field(),
+                max_age: Some(Duration::from_secs(2 * 86_400)),
+                max_rows: None,
+            },
+        )]);
+        let purged_tables = Arc::new(Mutex::new(Vec::new()));
+        let janitor = Janitor::new(db.clone(), &rules, Duration::from_secs(60)).unwrap();
+        let janitor = janitor.on_purge({
+            let purged_tables = purged_tables.clone();
+            move |table| {
+                purged_tables.lock().unwrap().push(table);
+                Box::pin(async {})
+            }
+        });
+        assert_eq!(janitor.sweep().await, 1);
+        janitor.sweep().await;
+
+        let metrics = janitor.metrics().snapshot();
+        assert_eq!(metrics[&table].purged, 1);
+        assert_eq!(metrics[&table].last_purged, 0);
+        assert!(metrics.contains_key(&Table::new("sessions").unwrap()));
+        assert_eq!(*purged_tables.lock().unwrap(), vec![table]);
+    }
+}
@@ -0,0 +1,250 @@
+//! JSON Schema validation of data API records
+//!
+//! A table gets a schema with [`DatabaseService::set_table_schema`]; it is
+//! kept in the `config` table under `schema.{table}`. Tables without a schema
+//! stay schemaless.
+//!
+//! Records are validated as they will be stored: without their `id` and with
+//! the owner field set by the row-level policy. An update is checked against
+//! the record the merge produces, not the patch alone, so `required` keywords
+//! keep working for partial updates.
+
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Prefix of the `config` keys holding table schemas
+const CONFIG_PREFIX: &str = "schema.";
+
+fn config_key(table: &Table) -> String {
+    format!("{}{}", CONFIG_PREFIX, table)
+}
+
+/// A value of a record that does not match its table's schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    /// JSON Pointer to the offending value (empty for the record itself)
+    pub path: String,
+    pub message: String,
+}
+
+impl SchemaViolation {
+    /// The same violation, with its path nested under `prefix`
+    pub fn nested(self, prefix: &str) -> Self {
+        Self {
+            path: format!("{}{}", prefix, self.path),
+            message: self.message,
+        }
+    }
+}
+
+fn compile(schema: &Value) -> Result<JSONSchema, DbError> {
+    JSONSchema::compile(schema)
+        .map_err(|e| DbError::InvalidQuery(format!("invalid JSON Schema: {}", e)))
+}
+
+fn check(schema: &JSONSchema, record: &Value) -> Result<(), DbError> {
+    let mut record = record.clone();
+    if let Some(object) = record.as_object_mut() {
+        object.remove("id");
+    }
+
+    let violations: Vec<SchemaViolation> = match schema.validate(&record) {
+        Ok(()) => return Ok(()),
+        Err(errors) => errors
+            .map(|error| SchemaViolation {
+                path: error.instance_path.to_string(),
+                message: error.to_string(),
+            })
+            .collect(),
+    };
+    Err(DbError::Validation(violations))
+}
+
+/// Apply a `MERGE` patch the way SurrealDB does: objects are merged
+/// recursively, any other value replaces the previous one
+pub(crate) fn merge_patch(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(key) {
+                    Some(previous) => merge_patch(previous, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+impl DatabaseService {
+    /// Validate the records of `table` against `schema` from now on
+    ///
+    /// Existing records are not checked.
+    pub async fn set_table_schema(&self, table: &Table, schema: &Value) -> Result<(), DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+        compile(schema)?;
+
+        self.set_config(&config_key(table), schema).await
+    }
+
+    /// Schema of `table`, if it has one
+    pub async fn table_schema(&self, table: &Table) -> Result<Option<Value>, DbError> {
+        self.get_config(&config_key(table)).await
+    }
+
+    /// Every table schema, by table name
+    pub async fn table_schemas(&self) -> Result<BTreeMap<String, Value>, DbError> {
+        let query = BoundQuery::new(
+            "SELECT key, value FROM config WHERE string::starts_with(key, $prefix) ORDER BY key",
+        )
+        .bind("prefix", CONFIG_PREFIX);
+
+        Ok(self
+            .execute(query)
+            .await?
+            .into_iter()
+            .filter_map(|row| {
+                let table = row["key"].as_str()?.strip_prefix(CONFIG_PREFIX)?.to_string();
+                Some((table, row["value"].clone()))
+            })
+            .collect())
+    }
+
+    /// Make `table` schemaless again; `false` if it had no schema
+    pub async fn remove_table_schema(&self, table: &Table) -> Result<bool, DbError> {
+        let query = BoundQuery::new("DELETE type::thing('config', $key) RETURN BEFORE")
+            .bind("key", config_key(table));
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Check a record about to be created in `table`
+    pub async fn validate_record(&self, table: &Table, content: &Value) -> Result<(), DbError> {
+        match self.table_schema(table).await? {
+            Some(schema) => check(&compile(&schema)?, content),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the record produced by merging `patch` into `current`
+    pub async fn validate_merge(
+        &self,
+        table: &Table,
+        current: &Value,
+        patch: &Value,
+    ) -> Result<(), DbError> {
+        let mut merged = current.clone();
+        merge_patch(&mut merged, patch);
+        self.validate_record(table, &merged).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+    use crate::query::RecordId;
+    use serde_json::json;
+
+    fn posts_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["title"],
+            "properties": {
+                "title": {"type": "string", "minLength": 1},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut record = json!({"a": 1, "nested": {"x": 1, "y": 2}});
+        merge_patch(&mut record, &json!({"b": 2, "nested": {"y": 3}}));
+        assert_eq!(record, json!({"a": 1, "b": 2, "nested": {"x": 1, "y": 3}}));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_schema_is_rejected() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+
+        let result = db.set_table_schema(&table, &json!({"type": "no-such-type"})).await;
+        assert!(matches!(result, Err(DbError::InvalidQuery(_))));
+
+        let users = Table::new("users").unwrap();
+        let result = db.set_table_schema(&users, &posts_schema()).await;
+        assert!(matches!(result, Err(DbError::PermissionDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_reports_failing_paths() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.set_table_schema(&table, &posts_schema()).await.unwrap();
+
+        let result = db.create_record(&table, json!({"title": "", "tags": ["a", 1]})).await;
+        let Err(DbError::Validation(violations)) = result else {
+            panic!("expected a validation error, got {:?}", result);
+        };
+        let paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+        assert!(paths.contains(&"/title"));
+        assert!(paths.contains(&"/tags/1"));
+        assert!(db.select_records(&table).await.unwrap().is_empty());
+
+        db.create_record(&table, json!({"title": "ok"})).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_merge_is_validated_against_the_result() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.set_table_schema(&table, &posts_schema()).await.unwrap();
+
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let scoped = db.scoped(&policies, &alice);
+        scoped.create_record(&table, json!({"title": "first"})).await.unwrap();
+        let keys = db
+            .execute(BoundQuery::new("SELECT VALUE record::id(id) FROM posts"))
+            .await
+            .unwrap();
+        let id = RecordId::new(table.clone(), keys[0].as_str().unwrap()).unwrap();
+
+        // The patch lacks `title`, but the merged record has it
+        let updated = scoped.merge_record(&id, json!({"tags": ["rust"]})).await.unwrap();
+        assert_eq!(updated.unwrap()["tags"], json!(["rust"]));
+
+        let result = scoped.merge_record(&id, json!({"title": 42})).await;
+        assert!(matches!(result, Err(DbError::Validation(_))));
+
+        assert!(db.remove_table_schema(&table).await.unwrap());
+        assert!(!db.remove_table_schema(&table).await.unwrap());
+        assert!(scoped.merge_record(&id, json!({"title": 42})).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_table_schemas_lists_every_table() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        for name in ["posts", "comments"] {
+            let table = Table::new(name).unwrap();
+            db.set_table_schema(&table, &posts_schema()).await.unwrap();
+        }
+        db.set_config("search.posts", &["title"]).await.unwrap();
+
+        let schemas = db.table_schemas().await.unwrap();
+        assert_eq!(schemas.keys().collect::<Vec<_>>(), vec!["comments", "posts"]);
+        assert_eq!(schemas["posts"], posts_schema());
+    }
+}
@@ -0,0 +1,333 @@
+//! Full-text search over data API tables
+//!
+//! A table becomes searchable once its searchable fields are declared with
+//! [`DatabaseService::define_search`]: every field gets a BM25 `SEARCH` index
+//! using the shared `edge_hive_search` analyzer, and the field list is kept in
+//! the `config` table so queries know which indexes to match against.
+//!
+//! Results are ranked by the sum of the per-field scores and carry the
+//! matched terms of each field wrapped in `<mark>` tags.
+
+use crate::filter::{parse_count, Field, Guard, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::policy::{ScopedDatabase, IDENTITY_VAR};
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Analyzer defined by migration 2
+const ANALYZER: &str = "edge_hive_search";
+
+/// Extra projections carrying the rank and highlights (stripped from records)
+const SCORE_FIELD: &str = "__score";
+const HIGHLIGHTS_FIELD: &str = "__highlights";
+
+/// Key of the `config` record listing the searchable fields of `table`
+fn config_key(table: &Table) -> String {
+    format!("search.{}", table)
+}
+
+fn index_name(table: &Table, field: &Field) -> String {
+    format!("search_{}_{}", table, field.as_str().replace('.', "_"))
+}
+
+/// A ranked keyword search over one table
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub table: Table,
+    /// Search terms
+    pub text: String,
+    pub limit: u64,
+    pub offset: u64,
+    /// Row policy added by [`ScopedDatabase`]
+    pub(crate) guard: Option<Guard>,
+}
+
+/// One matching record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub record: Value,
+    /// BM25 score, summed over the searchable fields
+    pub score: f64,
+    /// Highlighted text of each searchable field
+    pub highlights: Map<String, Value>,
+}
+
+/// One page of search results, best match first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    /// Number of matching records, across all pages
+    pub total: u64,
+}
+
+impl SearchQuery {
+    pub fn new(table: Table, text: impl Into<String>) -> Self {
+        Self {
+            table,
+            text: text.into(),
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            guard: None,
+        }
+    }
+
+    /// Build a query from `q`, `limit` and `offset` URL parameters
+    pub fn from_params<I, K, V>(table: Table, params: I) -> Result<Self, DbError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut query = Self::new(table, "");
+
+        for (key, value) in params {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            match key {
+                "q" => query.text = value.trim().to_string(),
+                "limit" => query.limit = parse_count(key, value)?.clamp(1, MAX_LIMIT),
+                "offset" => query.offset = parse_count(key, value)?,
+                other => {
+                    return Err(DbError::InvalidQuery(format!(
+                        "unknown search parameter '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+
+        if query.text.is_empty() {
+            return Err(DbError::InvalidQuery("'q' must not be empty".to_string()));
+        }
+        Ok(query)
+    }
+
+    /// `WHERE` clause matching any searchable field (plus the row policy)
+    fn render_where(&self, fields: &[Field], vars: &mut Map<String, Value>) -> String {
+        vars.insert("q".into(), self.text.clone().into());
+
+        let matches: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| format!("{} @{}@ $q", field, i))
+            .collect();
+        let mut conditions = vec![format!("({})", matches.join(" OR "))];
+
+        if let Some(guard) = &self.guard {
+            vars.insert(IDENTITY_VAR.into(), guard.identity.clone());
+            conditions.push(format!("({})", guard.predicate));
+        }
+
+        format!(" WHERE {}", conditions.join(" AND "))
+    }
+
+    // The match operator needs the table name to pick its index, so the
+    // validated table name is written into these statements instead of bound
+    fn page_query(&self, fields: &[Field]) -> BoundQuery {
+        let mut vars = Map::new();
+        vars.insert("limit".into(), self.limit.into());
+        vars.insert("start".into(), self.offset.into());
+
+        let score: Vec<String> =
+            (0..fields.len()).map(|i| format!("(search::score({}) ?? 0)", i)).collect();
+        let highlights: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                format!("\"{}\": search::highlight('<mark>', '</mark>', {})", field, i)
+            })
+            .collect();
+
+        let sql = format!(
+            "SELECT *, {} AS {}, {{ {} }} AS {} FROM {}{} \
+             ORDER BY {} DESC LIMIT $limit START $start",
+            score.join(" + "),
+            SCORE_FIELD,
+            highlights.join(", "),
+            HIGHLIGHTS_FIELD,
+            self.table,
+            self.render_where(fields, &mut vars),
+            SCORE_FIELD,
+        );
+
+        BoundQuery::new(sql).bind_all(vars)
+    }
+
+    fn count_query(&self, fields: &[Field]) -> BoundQuery {
+        let mut vars = Map::new();
+        let sql = format!(
+            "SELECT count() AS total FROM {}{} GROUP ALL",
+            self.table,
+            self.render_where(fields, &mut vars)
+        );
+
+        BoundQuery::new(sql).bind_all(vars)
+    }
+}
+
+fn into_hit(mut row: Value) -> SearchHit {
+    let (score, highlights) = match row.as_object_mut() {
+        Some(object) => (
+            object.remove(SCORE_FIELD).and_then(|s| s.as_f64()).unwrap_or(0.0),
+            match object.remove(HIGHLIGHTS_FIELD) {
+                Some(Value::Object(highlights)) => highlights,
+                _ => Map::new(),
+            },
+        ),
+        None => (0.0, Map::new()),
+    };
+
+    SearchHit {
+        record: row,
+        score,
+        highlights,
+    }
+}
+
+impl DatabaseService {
+    /// Make `fields` of `table` searchable, replacing any previous declaration
+    pub async fn define_search(&self, table: &Table, fields: &[Field]) -> Result<(), DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+
+        let mut sql = String::new();
+        for previous in self.search_fields(table).await? {
+            if !fields.contains(&previous) {
+                sql.push_str(&format!(
+                    "REMOVE INDEX IF EXISTS {} ON TABLE {};\n",
+                    index_name(table, &previous),
+                    table
+                ));
+            }
+        }
+        for field in fields {
+            sql.push_str(&format!(
+                "DEFINE INDEX OVERWRITE {} ON TABLE {} FIELDS {} \
+                 SEARCH ANALYZER {} BM25 HIGHLIGHTS;\n",
+                index_name(table, field),
+                table,
+                field,
+                ANALYZER
+            ));
+        }
+        if !sql.is_empty() {
+            self.db().query(sql).await?.check()?;
+        }
+
+        self.set_config(&config_key(table), &fields).await
+    }
+
+    /// Searchable fields declared for `table`
+    pub async fn search_fields(&self, table: &Table) -> Result<Vec<Field>, DbError> {
+        Ok(self.get_config(&config_key(table)).await?.unwrap_or_default())
+    }
+
+    /// Run a ranked full-text search
+    pub async fn search(&self, query: &SearchQuery) -> Result<SearchPage, DbError> {
+        let fields = self.search_fields(&query.table).await?;
+        if fields.is_empty() {
+            return Err(DbError::InvalidQuery(format!(
+                "table '{}' has no searchable fields",
+                query.table
+            )));
+        }
+
+        let rows = self.execute(query.page_query(&fields)).await?;
+        let rows = self.open_rows(&query.table, rows)?;
+        let total = self
+            .execute(query.count_query(&fields))
+            .await?
+            .first()
+            .and_then(|row| row["total"].as_u64())
+            .unwrap_or(0);
+
+        Ok(SearchPage {
+            hits: rows.into_iter().map(into_hit).collect(),
+            total,
+        })
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// Search the rows the caller may read
+    pub async fn search(&self, mut query: SearchQuery) -> Result<SearchPage, DbError> {
+        let policy = self.policies.policy(&query.table)?;
+        query.guard = Some(Guard {
+            predicate: policy.rule(&policy.select),
+            identity: self.identity.to_value(),
+        });
+        self.db.search(&query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+
+    fn fields(names: &[&str]) -> Vec<Field> {
+        names.iter().map(|name| Field::new(*name).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_from_params() {
+        let table = Table::new("articles").unwrap();
+        let query =
+            SearchQuery::from_params(table.clone(), [("q", " rust "), ("limit", "5")]).unwrap();
+        assert_eq!(query.text, "rust");
+        assert_eq!(query.limit, 5);
+
+        assert!(SearchQuery::from_params(table.clone(), [("limit", "5")]).is_err());
+        assert!(SearchQuery::from_params(table, [("q", "x"), ("order", "id")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_and_highlights() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("articles").unwrap();
+        db.define_search(&table, &fields(&["title", "body"])).await.unwrap();
+
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let scoped = db.scoped(&policies, &alice);
+        for (title, body) in [
+            ("Rust in production", "Rust everywhere, rust all day"),
+            ("Gardening", "Tomatoes and rust-free tools"),
+            ("Cooking", "Nothing relevant"),
+        ] {
+            scoped
+                .create_record(&table, serde_json::json!({"title": title, "body": body}))
+                .await
+                .unwrap();
+        }
+
+        let page = scoped.search(SearchQuery::new(table.clone(), "rust")).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.hits[0].record["title"], "Rust in production");
+        assert!(page.hits[0].score >= page.hits[1].score);
+        assert!(page.hits[0].highlights["title"].as_str().unwrap().contains("<mark>"));
+        assert!(page.hits[0].record.get(SCORE_FIELD).is_none());
+
+        // Rows of other callers are not searchable
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let page = db.scoped(&policies, &bob).search(SearchQuery::new(table, "rust")).await;
+        assert_eq!(page.unwrap().total, 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_requires_declared_fields() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("articles").unwrap();
+
+        let result = db.search(&SearchQuery::new(table.clone(), "rust")).await;
+        assert!(matches!(result, Err(DbError::InvalidQuery(_))));
+
+        db.define_search(&table, &fields(&["title", "body"])).await.unwrap();
+        db.define_search(&table, &fields(&["title"])).await.unwrap();
+        assert_eq!(db.search_fields(&table).await.unwrap(), fields(&["title"]));
+    }
+}
@@ -1,15 +1,165 @@
-//! Session model for refresh tokens
+//! Refresh-token sessions
+//!
+//! Every refresh is a rotation: the presented token is marked rotated and a
+//! new session of the same family replaces it. Sessions descending from one
+//! login share a family, so when a rotated token is presented again (it was
+//! stolen, or the client was) the whole family is revoked and neither party
+//! can refresh anymore.
 
+use crate::{DatabaseService, DbError};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use surrealdb::sql::{Datetime, Thing};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Session information stored in the database
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredSession {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub id: Option<Thing>,
-    pub user_id: Thing,
+    pub id: Option<surrealdb::sql::Thing>,
+    pub user_id: surrealdb::sql::Thing,
     pub refresh_token_hash: String,
-    pub expires_at: Datetime,
-    pub created_at: Datetime,
-    pub updated_at: Datetime,
+    pub device_info: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: surrealdb::Datetime,
+    pub expires_at: surrealdb::Datetime,
+    pub revoked: bool,
+    /// Shared by the sessions rotated from one login (a new family when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    /// When the token was exchanged for the next session of the family
+    #[serde(default)]
+    pub rotated_at: Option<surrealdb::Datetime>,
+}
+
+/// Outcome of presenting a refresh token
+#[derive(Debug, Clone)]
+pub enum SessionRotation {
+    /// The token was current and this new session replaces it
+    Rotated(StoredSession),
+    /// The token had already been rotated, so its family is now revoked
+    Reused(StoredSession),
+    /// No live session has the token (unknown, revoked or expired)
+    Invalid,
+}
+
+impl DatabaseService {
+    /// Exchange the session of `token_hash` for one of `new_token_hash`
+    ///
+    /// Only one caller can rotate a session; a later attempt counts as reuse.
+    pub async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<SessionRotation, DbError> {
+        let mut result = self
+            .db()
+            .query(
+                "UPDATE sessions SET rotated_at = time::now() \
+                 WHERE refresh_token_hash = $token_hash AND rotated_at = NONE \
+                 AND revoked = false AND expires_at > time::now() RETURN BEFORE",
+            )
+            .bind(("token_hash", token_hash.to_string()))
+            .await?;
+        let current: Option<StoredSession> = result.take(0)?;
+
+        let Some(current) = current else {
+            return match self.get_session_by_token(token_hash).await? {
+                Some(session) if session.rotated_at.is_some() => {
+                    if let Some(family) = &session.family {
+                        self.revoke_session_family(family).await?;
+                    }
+                    Ok(SessionRotation::Reused(session))
+                }
+                _ => Ok(SessionRotation::Invalid),
+            };
+        };
+
+        let session = StoredSession {
+            id: None,
+            refresh_token_hash: new_token_hash.to_string(),
+            created_at: Utc::now().into(),
+            expires_at: expires_at.into(),
+            revoked: false,
+            rotated_at: None,
+            ..current
+        };
+        self.create_session(&session).await.map(SessionRotation::Rotated)
+    }
+
+    /// Revoke every session of a family, returning how many were live
+    pub async fn revoke_session_family(&self, family: &str) -> Result<u64, DbError> {
+        let mut result = self
+            .db()
+            .query("UPDATE sessions SET revoked = true WHERE family = $family AND revoked = false")
+            .bind(("family", family.to_string()))
+            .await?;
+        let revoked: Vec<StoredSession> = result.take(0)?;
+        Ok(revoked.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use surrealdb::sql::Thing;
+
+    fn new_session(token_hash: &str) -> StoredSession {
+        StoredSession {
+            id: None,
+            user_id: Thing::from(("users", "test-user")),
+            refresh_token_hash: token_hash.to_string(),
+            device_info: None,
+            ip_address: None,
+            created_at: Utc::now().into(),
+            expires_at: (Utc::now() + Duration::days(7)).into(),
+            revoked: false,
+            family: None,
+            rotated_at: None,
+        }
+    }
+
+    fn expiry() -> DateTime<Utc> {
+        Utc::now() + Duration::days(7)
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_family() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let first = db.create_session(&new_session("hash-1")).await.unwrap();
+        assert!(first.family.is_some());
+
+        let SessionRotation::Rotated(second) =
+            db.rotate_session("hash-1", "hash-2", expiry()).await.unwrap()
+        else {
+            panic!("expected a rotation");
+        };
+        assert_eq!(second.family, first.family);
+        assert_eq!(second.user_id, first.user_id);
+        assert_ne!(second.id, first.id);
+
+        let old = db.get_session_by_token("hash-1").await.unwrap().unwrap();
+        assert!(old.rotated_at.is_some());
+        assert!(!old.revoked);
+    }
+
+    #[tokio::test]
+    async fn test_reuse_revokes_family() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.create_session(&new_session("hash-1")).await.unwrap();
+        db.create_session(&new_session("other")).await.unwrap();
+        db.rotate_session("hash-1", "hash-2", expiry()).await.unwrap();
+
+        let outcome = db.rotate_session("hash-1", "hash-3", expiry()).await.unwrap();
+        assert!(matches!(outcome, SessionRotation::Reused(_)));
+        let current = db.get_session_by_token("hash-2").await.unwrap().unwrap();
+        assert!(current.revoked);
+        let outcome = db.rotate_session("hash-2", "hash-3", expiry()).await.unwrap();
+        assert!(matches!(outcome, SessionRotation::Invalid));
+
+        // Other logins are untouched
+        let other = db.get_session_by_token("other").await.unwrap().unwrap();
+        assert!(!other.revoked);
+        let outcome = db.rotate_session("unknown", "hash-4", expiry()).await.unwrap();
+        assert!(matches!(outcome, SessionRotation::Invalid));
+    }
 }
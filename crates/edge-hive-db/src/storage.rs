@@ -0,0 +1,304 @@
+//! Storage engine selection and crash-safe open
+//!
+//! The node keeps its data in an embedded RocksDB store. The in-memory engine
+//! is only available as an explicit test mode: builds without the `rocksdb`
+//! feature refuse to open a store unless it is asked for.
+
+use crate::DbError;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::ops::Deref;
+use std::path::PathBuf;
+use surrealdb::engine::local::{Db, Mem};
+use surrealdb::opt::QueryResult;
+use surrealdb::Surreal;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::info;
+#[cfg(feature = "rocksdb")]
+use {std::path::Path, std::time::Duration, surrealdb::engine::local::RocksDb, tracing::warn};
+
+/// How many times to retry opening a store whose lock is still held
+#[cfg(feature = "rocksdb")]
+const OPEN_RETRIES: u32 = 10;
+
+/// Delay between open retries
+#[cfg(feature = "rocksdb")]
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Storage backend for the embedded SurrealDB instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    /// Durable on-disk RocksDB store (needs the `rocksdb` feature)
+    #[default]
+    RocksDb,
+    /// Volatile in-memory store (tests only)
+    Memory,
+}
+
+/// Options used to open a [`crate::DatabaseService`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbConfig {
+    /// Storage engine
+    pub engine: StorageEngine,
+    /// Directory of the on-disk store (ignored for the in-memory engine)
+    pub path: PathBuf,
+    /// SurrealDB namespace
+    pub namespace: String,
+    /// SurrealDB database
+    pub database: String,
+}
+
+impl DbConfig {
+    /// Store of the default engine at `path` using the default namespace and database
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            engine: StorageEngine::default(),
+            path: path.into(),
+            namespace: "edge_hive".into(),
+            database: "main".into(),
+        }
+    }
+
+    /// Volatile in-memory store (tests only)
+    pub fn in_memory() -> Self {
+        Self {
+            engine: StorageEngine::Memory,
+            path: PathBuf::new(),
+            namespace: "edge_hive".into(),
+            database: "main".into(),
+        }
+    }
+
+    /// Use a different namespace and database
+    pub fn with_namespace(mut self, namespace: impl Into<String>, database: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self.database = database.into();
+        self
+    }
+
+    /// Marker file present while the store is open; left behind after a crash
+    pub(crate) fn open_marker(&self) -> PathBuf {
+        self.path.with_extension("open")
+    }
+}
+
+/// The connection to a store, shared by the services using it
+///
+/// SurrealDB keeps one session per connection, and clones of the handle
+/// share it. The node, its projects and its branches use different
+/// namespaces and databases of one store, so rather than switching the
+/// session, every query starts with a `USE` of its database: a `USE` inside
+/// a query only lasts for that query, and services never wait for each
+/// other. Only the few calls that read the session itself, such as an
+/// export, select their database on it, and take turns while they do.
+pub(crate) struct Session {
+    db: Surreal<Db>,
+    /// Held while a call runs on the database selected on the session
+    switch: Mutex<()>,
+}
+
+impl Session {
+    /// Session on `db`, starting with the namespace and database of `config`
+    pub(crate) async fn new(db: Surreal<Db>, config: &DbConfig) -> Result<Self, DbError> {
+        db.use_ns(config.namespace.as_str()).use_db(config.database.as_str()).await?;
+        Ok(Self {
+            db,
+            switch: Mutex::new(()),
+        })
+    }
+
+    /// The connection as seen from `namespace` and `database`
+    pub(crate) fn select<'a>(&'a self, namespace: &str, database: &str) -> Selected<'a> {
+        Selected {
+            session: self,
+            prefix: format!("USE NS {} DB {};\n", escape(namespace), escape(database)),
+            namespace: namespace.to_string(),
+            database: database.to_string(),
+        }
+    }
+}
+
+/// Escape a namespace or database name for a `USE` statement
+fn escape(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+/// The connection of one service's database
+pub(crate) struct Selected<'a> {
+    session: &'a Session,
+    /// `USE` statement every query starts with
+    prefix: String,
+    namespace: String,
+    database: String,
+}
+
+impl<'a> Selected<'a> {
+    /// Statements run on the service's database
+    ///
+    /// Result indexes count from the first statement of `sql`.
+    pub(crate) fn query(&self, sql: impl AsRef<str>) -> Query<'a> {
+        Query(self.session.db.query(format!("{}{}", self.prefix, sql.as_ref())))
+    }
+
+    /// The connection with the service's database selected on the session,
+    /// for calls that read the session, until dropped
+    ///
+    /// Calls that read the session on their own take turns, so hold the
+    /// result only until the call has started.
+    pub(crate) async fn switch(&self) -> Result<Switched<'a>, DbError> {
+        let guard = self.session.switch.lock().await;
+        self.session.db.use_ns(self.namespace.as_str()).use_db(self.database.as_str()).await?;
+        Ok(Switched {
+            db: &self.session.db,
+            _guard: guard,
+        })
+    }
+}
+
+/// The connection with a service's database selected on the session
+pub(crate) struct Switched<'a> {
+    db: &'a Surreal<Db>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Deref for Switched<'_> {
+    type Target = Surreal<Db>;
+
+    fn deref(&self) -> &Surreal<Db> {
+        self.db
+    }
+}
+
+/// Statements of [`Selected::query`], awaiting their response
+pub(crate) struct Query<'a>(surrealdb::method::Query<'a, Db>);
+
+impl<'a> Query<'a> {
+    /// Bind variables of the statements
+    pub(crate) fn bind(self, bindings: impl Serialize + 'static) -> Self {
+        Self(self.0.bind(bindings))
+    }
+
+    /// Append further statements; their results follow those before them
+    pub(crate) fn query(self, sql: impl AsRef<str>) -> Self {
+        Self(self.0.query(sql.as_ref().to_string()))
+    }
+}
+
+impl<'a> IntoFuture for Query<'a> {
+    type Output = Result<Response, DbError>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move { Ok(Response(self.0.await?)) })
+    }
+}
+
+/// Response of [`Selected::query`], without the result of its `USE`
+pub(crate) struct Response(surrealdb::Response);
+
+impl Response {
+    /// Fail with the first error of a statement
+    pub(crate) fn check(self) -> Result<Self, DbError> {
+        Ok(Self(self.0.check()?))
+    }
+
+    /// Take the result of the statement at `index`
+    pub(crate) fn take<R>(&mut self, index: usize) -> Result<R, DbError>
+    where
+        R: DeserializeOwned,
+        usize: QueryResult<R>,
+    {
+        Ok(self.0.take(index + 1)?)
+    }
+
+    /// Take the errors of the statements, by index
+    pub(crate) fn take_errors(&mut self) -> HashMap<usize, surrealdb::Error> {
+        self.0
+            .take_errors()
+            .into_iter()
+            .map(|(index, error)| (index.saturating_sub(1), error))
+            .collect()
+    }
+}
+
+/// Open the configured storage engine
+pub(crate) async fn open_engine(config: &DbConfig) -> Result<Surreal<Db>, DbError> {
+    match config.engine {
+        StorageEngine::Memory => {
+            info!("📦 Opening in-memory database (test mode, data is not persisted)");
+            Surreal::new::<Mem>(())
+                .await
+                .map_err(|e| DbError::Connection(e.to_string()))
+        }
+        StorageEngine::RocksDb => open_rocksdb(config).await,
+    }
+}
+
+#[cfg(not(feature = "rocksdb"))]
+async fn open_rocksdb(_config: &DbConfig) -> Result<Surreal<Db>, DbError> {
+    Err(DbError::Connection(
+        "this build has no RocksDB support; rebuild with `--features rocksdb`".to_string(),
+    ))
+}
+
+#[cfg(feature = "rocksdb")]
+async fn open_rocksdb(config: &DbConfig) -> Result<Surreal<Db>, DbError> {
+    let path = &config.path;
+    info!("📦 Opening RocksDB database at {}", path.display());
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| DbError::Connection(e.to_string()))?;
+    }
+
+    let marker = config.open_marker();
+    if marker.exists() {
+        warn!(
+            "Database at {} was not shut down cleanly, recovering from write-ahead log",
+            path.display()
+        );
+    }
+
+    // A previous handle (same process or a process that is still exiting) may
+    // hold the RocksDB LOCK file for a short while, so retry before giving up.
+    let mut attempt = 0;
+    let db = loop {
+        match Surreal::new::<RocksDb>(path.as_path()).await {
+            Ok(db) => break db,
+            Err(e) if attempt < OPEN_RETRIES && is_lock_error(&e) => {
+                attempt += 1;
+                warn!("Database lock is held, retrying ({}/{})", attempt, OPEN_RETRIES);
+                tokio::time::sleep(OPEN_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(DbError::Connection(e.to_string())),
+        }
+    };
+
+    write_marker(&marker)?;
+    Ok(db)
+}
+
+#[cfg(feature = "rocksdb")]
+fn is_lock_error(error: &surrealdb::Error) -> bool {
+    let message = error.to_string();
+    message.contains("LOCK") || message.contains("lock hold")
+}
+
+#[cfg(feature = "rocksdb")]
+fn write_marker(marker: &Path) -> Result<(), DbError> {
+    std::fs::write(marker, std::process::id().to_string())
+        .map_err(|e| DbError::Connection(format!("Failed to write open marker: {}", e)))
+}
+
+/// Remove the open marker after a clean shutdown
+pub(crate) fn clear_marker(config: &DbConfig) -> Result<(), DbError> {
+    let marker = config.open_marker();
+    match std::fs::remove_file(&marker) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(DbError::Connection(format!("Failed to remove open marker: {}", e))),
+    }
+}
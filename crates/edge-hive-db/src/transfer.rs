@@ -0,0 +1,1013 @@
+//! Bulk export and import of single tables
+//!
+//! Tables travel as CSV, NDJSON or Parquet. An export pages through a
+//! [`ListQuery`], so filters and the caller's row policy apply, and produces
+//! the file one chunk per page. An import writes the rows of a file one by
+//! one: it creates records or, with a key column, updates the record with
+//! that key and only creates it when it does not exist yet.
+//!
+//! A [`ColumnMap`] (`title:headline,views`) picks columns and renames them:
+//! from field to column on export, from column to field on import. CSV has
+//! no types, so numbers, booleans and JSON objects or arrays written by an
+//! export are read back as such; every other cell is a string.
+
+use crate::filter::{Cursor, ListQuery};
+use crate::policy::ScopedDatabase;
+use crate::query::{RecordId, Table};
+use crate::schema::SchemaViolation;
+use crate::{DatabaseService, DbError};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+};
+use arrow_schema::{DataType, Field as ArrowField, Schema};
+use bytes::Bytes;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Rows read per page of an export
+const EXPORT_BATCH: u64 = 500;
+
+/// Rows between two progress reports of an import
+const PROGRESS_INTERVAL: u64 = 500;
+
+/// Failed rows listed in an [`ImportReport`]; later failures are only counted
+const MAX_REPORTED_ERRORS: usize = 100;
+
+fn transfer_error(e: impl Display) -> DbError {
+    DbError::Transfer(e.to_string())
+}
+
+/// File format of an export or import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+    Csv,
+    #[serde(alias = "jsonl")]
+    Ndjson,
+    Parquet,
+}
+
+impl TransferFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TransferFormat::Csv => "text/csv",
+            TransferFormat::Ndjson => "application/x-ndjson",
+            TransferFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TransferFormat::Csv => "csv",
+            TransferFormat::Ndjson => "ndjson",
+            TransferFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for TransferFormat {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(TransferFormat::Csv),
+            "ndjson" | "jsonl" => Ok(TransferFormat::Ndjson),
+            "parquet" => Ok(TransferFormat::Parquet),
+            other => Err(DbError::InvalidQuery(format!("unknown format '{}'", other))),
+        }
+    }
+}
+
+/// Columns to transfer, each `source:target` or a name kept as is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMap(Vec<(String, String)>);
+
+impl ColumnMap {
+    /// `(source, target)` pairs, in file order
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.0
+    }
+}
+
+impl FromStr for ColumnMap {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (source, target) = entry.split_once(':').unwrap_or((entry, entry));
+                let (source, target) = (source.trim(), target.trim());
+                if source.is_empty() || target.is_empty() {
+                    return Err(DbError::InvalidQuery(format!("bad column mapping '{}'", entry)));
+                }
+                Ok((source.to_string(), target.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if pairs.is_empty() {
+            return Err(DbError::InvalidQuery("column mapping is empty".to_string()));
+        }
+        Ok(Self(pairs))
+    }
+}
+
+/// Value at a dotted path (`address.city`) of a record
+fn lookup<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(record, |value, key| value.get(key))
+}
+
+/// Set the value at a dotted path, creating the objects on the way
+fn set_path(record: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            let child = record
+                .entry(head.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = child {
+                set_path(child, rest, value);
+            }
+        }
+        None => {
+            record.insert(path.to_string(), value);
+        }
+    }
+}
+
+/// Text of a CSV cell: strings as they are, anything else as JSON
+fn cell_text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Value of a CSV cell
+///
+/// Numbers and booleans are only taken as such when they read back the same,
+/// so `007` or `1e3` stay strings.
+fn parse_cell(cell: &str) -> Value {
+    match serde_json::from_str::<Value>(cell) {
+        Ok(value @ (Value::Object(_) | Value::Array(_))) => value,
+        Ok(value @ (Value::Number(_) | Value::Bool(_))) if value.to_string().as_str() == cell => value,
+        _ => Value::String(cell.to_string()),
+    }
+}
+
+/// Options of a table export
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: TransferFormat,
+    /// Fields to export and their column names (defaults to every field)
+    pub columns: Option<ColumnMap>,
+}
+
+/// Type of a Parquet column, inferred from the first page of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Boolean,
+    Int64,
+    Float64,
+    Utf8,
+}
+
+impl ColumnType {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnType::Boolean),
+            Value::Number(n) if n.is_i64() => Some(ColumnType::Int64),
+            Value::Number(_) => Some(ColumnType::Float64),
+            _ => Some(ColumnType::Utf8),
+        }
+    }
+
+    fn infer<'a>(values: impl Iterator<Item = Option<&'a Value>>) -> Self {
+        values
+            .flatten()
+            .filter_map(Self::of)
+            .reduce(|a, b| match (a, b) {
+                (a, b) if a == b => a,
+                (ColumnType::Int64, ColumnType::Float64)
+                | (ColumnType::Float64, ColumnType::Int64) => ColumnType::Float64,
+                _ => ColumnType::Utf8,
+            })
+            .unwrap_or(ColumnType::Utf8)
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Utf8 => DataType::Utf8,
+        }
+    }
+
+    fn array(&self, column: &str, values: &[Option<&Value>]) -> Result<ArrayRef, DbError> {
+        fn convert<T>(
+            column: &str,
+            values: &[Option<&Value>],
+            get: impl Fn(&Value) -> Option<T>,
+        ) -> Result<Vec<Option<T>>, DbError> {
+            values
+                .iter()
+                .map(|value| match value {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(value) => get(value).map(Some).ok_or_else(|| {
+                        transfer_error(format!(
+                            "column '{}' holds {} after rows of another type",
+                            column, value
+                        ))
+                    }),
+                })
+                .collect()
+        }
+
+        Ok(match self {
+            ColumnType::Boolean => {
+                Arc::new(BooleanArray::from(convert(column, values, Value::as_bool)?))
+            }
+            ColumnType::Int64 => {
+                Arc::new(Int64Array::from(convert(column, values, Value::as_i64)?))
+            }
+            ColumnType::Float64 => {
+                Arc::new(Float64Array::from(convert(column, values, Value::as_f64)?))
+            }
+            ColumnType::Utf8 => {
+                let texts: Vec<Option<String>> = values.iter().map(|v| cell_text(*v)).collect();
+                Arc::new(StringArray::from(texts))
+            }
+        })
+    }
+}
+
+/// File encoder, set up from the first page
+enum Encoder {
+    Csv,
+    Ndjson,
+    Parquet {
+        writer: Box<ArrowWriter<Vec<u8>>>,
+        schema: Arc<Schema>,
+        types: Vec<ColumnType>,
+    },
+}
+
+enum NextPage {
+    First,
+    After(Cursor),
+    Done,
+}
+
+/// A table export in progress, producing the file chunk by chunk
+///
+/// The export does not borrow the database, so it can be driven from a
+/// response body stream.
+pub struct TableExport {
+    query: ListQuery,
+    options: ExportOptions,
+    /// `(field, column)` pairs, known once the first page is read
+    columns: Vec<(String, String)>,
+    encoder: Option<Encoder>,
+    next: NextPage,
+    finished: bool,
+    rows: u64,
+}
+
+impl TableExport {
+    /// Export every row matching `query`, whatever its limit
+    pub fn new(mut query: ListQuery, options: ExportOptions) -> Self {
+        query.limit = EXPORT_BATCH;
+        Self {
+            query,
+            options,
+            columns: Vec::new(),
+            encoder: None,
+            next: NextPage::First,
+            finished: false,
+            rows: 0,
+        }
+    }
+
+    /// Rows exported so far
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Next chunk of the file; `None` once it is complete
+    pub async fn next_chunk(&mut self, db: &DatabaseService) -> Result<Option<Vec<u8>>, DbError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        match self.next_page(db).await? {
+            Some(rows) => {
+                self.rows += rows.len() as u64;
+                self.encode(&rows).map(Some)
+            }
+            None => {
+                self.finished = true;
+                match self.encoder.take() {
+                    Some(Encoder::Parquet { writer, .. }) => {
+                        writer.into_inner().map(Some).map_err(transfer_error)
+                    }
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Run the whole export into `writer`, returning the number of rows
+    pub async fn write_to<W: Write>(
+        mut self,
+        db: &DatabaseService,
+        mut writer: W,
+    ) -> Result<u64, DbError> {
+        while let Some(chunk) = self.next_chunk(db).await? {
+            writer.write_all(&chunk).map_err(transfer_error)?;
+        }
+        writer.flush().map_err(transfer_error)?;
+        Ok(self.rows)
+    }
+
+    async fn next_page(&mut self, db: &DatabaseService) -> Result<Option<Vec<Value>>, DbError> {
+        let page = match std::mem::replace(&mut self.next, NextPage::Done) {
+            // The first page goes through the checks of a normal read
+            NextPage::First => db.list_records(&self.query).await?,
+            NextPage::After(cursor) => {
+                let query = ListQuery {
+                    cursor: Some(cursor),
+                    offset: 0,
+                    ..self.query.clone()
+                };
+                let mut page = query.to_page(db.execute(query.page_query()).await?, 0);
+                page.rows = db.open_rows(&query.table, page.rows)?;
+                page
+            }
+            NextPage::Done => return Ok(None),
+        };
+
+        if let Some(cursor) = &page.next_cursor {
+            self.next = NextPage::After(Cursor::decode(cursor)?);
+        }
+        Ok(Some(page.rows))
+    }
+
+    fn encode(&mut self, rows: &[Value]) -> Result<Vec<u8>, DbError> {
+        let mut chunk = Vec::new();
+        if self.encoder.is_none() {
+            self.start(rows, &mut chunk)?;
+        }
+
+        match self.encoder.as_mut() {
+            Some(Encoder::Csv) => {
+                let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(chunk);
+                for row in rows {
+                    let cells = self.columns.iter().map(|(field, _)| {
+                        cell_text(lookup(row, field)).unwrap_or_default()
+                    });
+                    writer.write_record(cells).map_err(transfer_error)?;
+                }
+                writer.into_inner().map_err(transfer_error)
+            }
+            Some(Encoder::Ndjson) => {
+                for row in rows {
+                    let line = if self.options.columns.is_some() {
+                        self.mapped(row)
+                    } else {
+                        row.clone()
+                    };
+                    serde_json::to_writer(&mut chunk, &line).map_err(transfer_error)?;
+                    chunk.push(b'\n');
+                }
+                Ok(chunk)
+            }
+            Some(Encoder::Parquet { writer, schema, types }) => {
+                if !rows.is_empty() {
+                    let arrays = self
+                        .columns
+                        .iter()
+                        .zip(types.iter())
+                        .map(|((field, column), kind)| {
+                            let values: Vec<Option<&Value>> =
+                                rows.iter().map(|row| lookup(row, field)).collect();
+                            kind.array(column, &values)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let batch = RecordBatch::try_new(schema.clone(), arrays)
+                        .map_err(transfer_error)?;
+                    writer.write(&batch).map_err(transfer_error)?;
+                    // One row group per page, handed out as soon as it is written
+                    writer.flush().map_err(transfer_error)?;
+                }
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+            None => Ok(chunk),
+        }
+    }
+
+    /// Settle the columns and write the start of the file
+    fn start(&mut self, rows: &[Value], chunk: &mut Vec<u8>) -> Result<(), DbError> {
+        self.columns = match &self.options.columns {
+            Some(map) => map.pairs().to_vec(),
+            None => {
+                let mut fields: Vec<String> = Vec::new();
+                for key in rows.iter().filter_map(Value::as_object).flat_map(|row| row.keys()) {
+                    if !fields.contains(key) {
+                        fields.push(key.clone());
+                    }
+                }
+                // `id` leads, so keyed imports find it in the first column
+                fields.sort_by_key(|field| field != "id");
+                fields.into_iter().map(|field| (field.clone(), field)).collect()
+            }
+        };
+
+        let encoder = match self.options.format {
+            TransferFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut *chunk);
+                writer
+                    .write_record(self.columns.iter().map(|(_, column)| column))
+                    .map_err(transfer_error)?;
+                writer.flush().map_err(transfer_error)?;
+                Encoder::Csv
+            }
+            TransferFormat::Ndjson => Encoder::Ndjson,
+            TransferFormat::Parquet => {
+                // Parquet needs at least one column, even for an empty table
+                if self.columns.is_empty() {
+                    self.columns.push(("id".to_string(), "id".to_string()));
+                }
+                let types: Vec<ColumnType> = self
+                    .columns
+                    .iter()
+                    .map(|(field, _)| ColumnType::infer(rows.iter().map(|row| lookup(row, field))))
+                    .collect();
+                let schema = Arc::new(Schema::new(
+                    self.columns
+                        .iter()
+                        .zip(types.iter())
+                        .map(|((_, column), kind)| ArrowField::new(column, kind.data_type(), true))
+                        .collect::<Vec<_>>(),
+                ));
+                let properties =
+                    WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+                let writer = ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))
+                    .map_err(transfer_error)?;
+                Encoder::Parquet {
+                    writer: Box::new(writer),
+                    schema,
+                    types,
+                }
+            }
+        };
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    /// A row as an object of its mapped columns
+    fn mapped(&self, row: &Value) -> Value {
+        let mut object = Map::new();
+        for (field, column) in &self.columns {
+            if let Some(value) = lookup(row, field) {
+                object.insert(column.clone(), value.clone());
+            }
+        }
+        Value::Object(object)
+    }
+}
+
+/// Options of a table import
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub format: TransferFormat,
+    /// Columns to import and the fields they go to (defaults to every column)
+    pub columns: Option<ColumnMap>,
+    /// Field (after mapping) holding the record key; rows with a key update
+    /// the existing record, if any, instead of creating a new one
+    pub key: Option<String>,
+    /// Check every row without writing anything
+    pub dry_run: bool,
+}
+
+impl ImportOptions {
+    pub fn new(format: TransferFormat) -> Self {
+        Self {
+            format,
+            columns: None,
+            key: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// A row that could not be imported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowError {
+    /// 1-based number of the row in the file, not counting a header
+    pub row: u64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<SchemaViolation>,
+}
+
+/// Outcome of an import, also reported while it runs
+///
+/// On a dry run, `created` and `updated` count the rows that would be.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Rows read so far
+    pub rows: u64,
+    pub created: u64,
+    pub updated: u64,
+    pub failed: u64,
+    /// The first failures
+    pub errors: Vec<RowError>,
+    pub dry_run: bool,
+}
+
+impl ImportReport {
+    fn fail(&mut self, row: u64, error: DbError) {
+        self.failed += 1;
+        if self.errors.len() >= MAX_REPORTED_ERRORS {
+            return;
+        }
+        let (message, violations) = match error {
+            DbError::Validation(violations) => {
+                ("record does not match the table schema".to_string(), violations)
+            }
+            other => (other.to_string(), Vec::new()),
+        };
+        self.errors.push(RowError {
+            row,
+            message,
+            violations,
+        });
+    }
+}
+
+type Rows<'a> = Box<dyn Iterator<Item = Result<Map<String, Value>, DbError>> + Send + 'a>;
+
+/// Rows of a file, as objects keyed by column
+fn read_rows<'a, R: Read + Send + 'a>(
+    mut reader: R,
+    format: TransferFormat,
+) -> Result<Rows<'a>, DbError> {
+    Ok(match format {
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = reader.headers().map_err(transfer_error)?.clone();
+            Box::new(reader.into_records().map(move |record| {
+                let record = record.map_err(transfer_error)?;
+                Ok(headers
+                    .iter()
+                    .zip(record.iter())
+                    .filter(|(_, cell)| !cell.is_empty())
+                    .map(|(column, cell)| (column.to_string(), parse_cell(cell)))
+                    .collect())
+            }))
+        }
+        TransferFormat::Ndjson => Box::new(
+            BufReader::new(reader)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| match serde_json::from_str(&line.map_err(transfer_error)?) {
+                    Ok(Value::Object(row)) => Ok(row),
+                    Ok(_) => Err(transfer_error("expected a JSON object")),
+                    Err(e) => Err(transfer_error(e)),
+                }),
+        ),
+        TransferFormat::Parquet => {
+            // The footer is at the end, so the whole file is needed
+            let mut file = Vec::new();
+            reader.read_to_end(&mut file).map_err(transfer_error)?;
+            let batches = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file))
+                .and_then(|builder| builder.build())
+                .map_err(transfer_error)?;
+
+            let mut writer = arrow_json::ArrayWriter::new(Vec::new());
+            for batch in batches {
+                writer.write(&batch.map_err(transfer_error)?).map_err(transfer_error)?;
+            }
+            writer.finish().map_err(transfer_error)?;
+            let json = writer.into_inner();
+            let rows: Vec<Map<String, Value>> = if json.is_empty() {
+                Vec::new()
+            } else {
+                serde_json::from_slice(&json).map_err(transfer_error)?
+            };
+
+            // Objects and arrays were exported as JSON text
+            Box::new(rows.into_iter().map(|row| {
+                Ok(row
+                    .into_iter()
+                    .map(|(column, value)| match value {
+                        Value::String(text) => (column, parse_cell(&text)),
+                        other => (column, other),
+                    })
+                    .collect())
+            }))
+        }
+    })
+}
+
+/// Where imported rows are written
+enum Target<'a> {
+    /// As the node itself, without row policies
+    Direct(&'a DatabaseService),
+    /// As the caller of a [`ScopedDatabase`]
+    Scoped(&'a ScopedDatabase<'a>),
+}
+
+enum Written {
+    Created,
+    Updated,
+}
+
+impl Target<'_> {
+    fn db(&self) -> &DatabaseService {
+        match self {
+            Target::Direct(db) => db,
+            Target::Scoped(scoped) => scoped.db,
+        }
+    }
+
+    async fn create(&self, table: &Table, content: Value) -> Result<Value, DbError> {
+        match self {
+            Target::Direct(db) => db.create_record(table, content).await,
+            Target::Scoped(scoped) => scoped.create_record(table, content).await,
+        }
+    }
+
+    async fn create_at(&self, id: &RecordId, content: Value) -> Result<Value, DbError> {
+        match self {
+            Target::Direct(db) => db.create_record_at(id, content).await,
+            Target::Scoped(scoped) => scoped.create_record_at(id, content).await,
+        }
+    }
+
+    async fn select(&self, id: &RecordId) -> Result<Option<Value>, DbError> {
+        match self {
+            Target::Direct(db) => db.select_record(id).await,
+            Target::Scoped(scoped) => scoped.select_record(id).await,
+        }
+    }
+
+    async fn merge(&self, id: &RecordId, patch: Value) -> Result<Option<Value>, DbError> {
+        match self {
+            Target::Direct(db) => db.merge_record(id, patch).await,
+            Target::Scoped(scoped) => scoped.merge_record(id, patch).await,
+        }
+    }
+
+    async fn import<R: Read + Send>(
+        &self,
+        table: &Table,
+        reader: R,
+        options: &ImportOptions,
+        mut progress: impl FnMut(&ImportReport) + Send,
+    ) -> Result<ImportReport, DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+
+        let mut report = ImportReport {
+            dry_run: options.dry_run,
+            ..Default::default()
+        };
+        for row in read_rows(reader, options.format)? {
+            report.rows += 1;
+            let written = match row {
+                Ok(row) => self.import_row(table, row, options).await,
+                Err(e) => Err(e),
+            };
+            match written {
+                Ok(Written::Created) => report.created += 1,
+                Ok(Written::Updated) => report.updated += 1,
+                Err(e) => report.fail(report.rows, e),
+            }
+
+            if report.rows % PROGRESS_INTERVAL == 0 {
+                progress(&report);
+            }
+        }
+
+        progress(&report);
+        Ok(report)
+    }
+
+    async fn import_row(
+        &self,
+        table: &Table,
+        row: Map<String, Value>,
+        options: &ImportOptions,
+    ) -> Result<Written, DbError> {
+        let mut content = match &options.columns {
+            Some(map) => {
+                let mut row = row;
+                let mut content = Map::new();
+                for (column, field) in map.pairs() {
+                    if let Some(value) = row.remove(column) {
+                        set_path(&mut content, field, value);
+                    }
+                }
+                content
+            }
+            None => row,
+        };
+
+        let key = options.key.as_deref().and_then(|key| content.remove(key));
+        // Records get their ID from the key or the database, never from a field
+        content.remove("id");
+        let content = Value::Object(content);
+
+        let id = match key {
+            None | Some(Value::Null) => None,
+            Some(key) => {
+                let key = match key {
+                    Value::String(key) => key,
+                    other => other.to_string(),
+                };
+                let prefix = format!("{}:", table);
+                let key = key.strip_prefix(&prefix).unwrap_or(&key).to_string();
+                Some(RecordId::new(table.clone(), key)?)
+            }
+        };
+
+        if options.dry_run {
+            // Records the caller may not read are as good as missing
+            let existing = match &id {
+                Some(id) => self.select(id).await?,
+                None => None,
+            };
+            let db = self.db();
+            return match existing {
+                Some(current) => {
+                    db.validate_merge(table, &current, &content).await?;
+                    Ok(Written::Updated)
+                }
+                None => {
+                    db.validate_record(table, &content).await?;
+                    Ok(Written::Created)
+                }
+            };
+        }
+
+        match id {
+            Some(id) => match self.merge(&id, content.clone()).await? {
+                Some(_) => Ok(Written::Updated),
+                None => self.create_at(&id, content).await.map(|_| Written::Created),
+            },
+            None => self.create(table, content).await.map(|_| Written::Created),
+        }
+    }
+}
+
+impl DatabaseService {
+    /// Export the rows of `query`, whatever the row policies
+    pub fn export_table(&self, query: ListQuery, options: ExportOptions) -> TableExport {
+        TableExport::new(query, options)
+    }
+
+    /// Import a file into `table` as the node itself, whatever the row policies
+    ///
+    /// `progress` is called every few hundred rows and once at the end. Rows
+    /// are written one by one: a failed row is reported and skipped.
+    pub async fn import_table<R: Read + Send>(
+        &self,
+        table: &Table,
+        reader: R,
+        options: &ImportOptions,
+        progress: impl FnMut(&ImportReport) + Send,
+    ) -> Result<ImportReport, DbError> {
+        Target::Direct(self).import(table, reader, options, progress).await
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// Export the rows of `query` the caller may read
+    pub fn export_table(
+        &self,
+        query: ListQuery,
+        options: ExportOptions,
+    ) -> Result<TableExport, DbError> {
+        Ok(TableExport::new(self.restrict(query)?, options))
+    }
+
+    /// Import a file into `table` as the caller
+    ///
+    /// Like [`DatabaseService::import_table`], with the row policies of the
+    /// caller. Dry runs check the file and the table's JSON Schema only.
+    pub async fn import_table<R: Read + Send>(
+        &self,
+        table: &Table,
+        reader: R,
+        options: &ImportOptions,
+        progress: impl FnMut(&ImportReport) + Send,
+    ) -> Result<ImportReport, DbError> {
+        self.policies.policy(table)?;
+        Target::Scoped(self).import(table, reader, options, progress).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+    use serde_json::json;
+
+    async fn posts(db: &DatabaseService) -> Table {
+        let table = Table::new("posts").unwrap();
+        for (title, views) in [("a", 1), ("b", 2), ("c", 3)] {
+            db.create_record(&table, json!({"title": title, "views": views, "tags": ["x"]}))
+                .await
+                .unwrap();
+        }
+        table
+    }
+
+    async fn export(db: &DatabaseService, table: &Table, options: ExportOptions) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut query = ListQuery::new(table.clone());
+        query.order = vec![crate::filter::Order::parse("views").unwrap()];
+        db.export_table(query, options).write_to(db, &mut file).await.unwrap();
+        file
+    }
+
+    #[test]
+    fn test_cells_keep_their_types() {
+        assert_eq!(parse_cell("18"), json!(18));
+        assert_eq!(parse_cell("true"), json!(true));
+        assert_eq!(parse_cell("[1,2]"), json!([1, 2]));
+        assert_eq!(parse_cell("007"), json!("007"));
+        assert_eq!(parse_cell("hello"), json!("hello"));
+        assert_eq!("title:headline,views".parse::<ColumnMap>().unwrap().pairs()[0].1, "headline");
+        assert!("title:".parse::<ColumnMap>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_csv_export_maps_columns() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = posts(&db).await;
+
+        let options = ExportOptions {
+            format: TransferFormat::Csv,
+            columns: Some("title:headline,views".parse().unwrap()),
+        };
+        let file = String::from_utf8(export(&db, &table, options).await).unwrap();
+        assert_eq!(file, "headline,views\na,1\nb,2\nc,3\n");
+    }
+
+    #[tokio::test]
+    async fn test_round_trips() {
+        for format in [TransferFormat::Csv, TransferFormat::Ndjson, TransferFormat::Parquet] {
+            let source = DatabaseService::new_in_memory().await.unwrap();
+            let table = posts(&source).await;
+            let options = ExportOptions {
+                format,
+                columns: None,
+            };
+            let file = export(&source, &table, options).await;
+
+            let target = DatabaseService::new_in_memory().await.unwrap();
+            let options = ImportOptions {
+                key: Some("id".to_string()),
+                ..ImportOptions::new(format)
+            };
+            let report =
+                target.import_table(&table, file.as_slice(), &options, |_| {}).await.unwrap();
+            assert_eq!((report.rows, report.created, report.failed), (3, 3, 0), "{:?}", format);
+
+            let mut query = ListQuery::new(table.clone());
+            query.order = vec![crate::filter::Order::parse("views").unwrap()];
+            let rows = target.list_records(&query).await.unwrap().rows;
+            assert_eq!(rows[2]["title"], "c");
+            assert_eq!(rows[2]["views"], 3);
+            assert_eq!(rows[2]["tags"], json!(["x"]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upsert_by_key_and_dry_run() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.set_table_schema(&table, &json!({"required": ["title"]})).await.unwrap();
+
+        let file = "slug,title\nfirst,One\nsecond,\n";
+        let mut options = ImportOptions {
+            columns: Some("slug,title".parse().unwrap()),
+            key: Some("slug".to_string()),
+            dry_run: true,
+            ..ImportOptions::new(TransferFormat::Csv)
+        };
+        let report = db.import_table(&table, file.as_bytes(), &options, |_| {}).await.unwrap();
+        assert_eq!((report.created, report.failed), (1, 1));
+        assert_eq!(report.errors[0].row, 2);
+        assert!(!report.errors[0].violations.is_empty());
+        assert!(db.select_records(&table).await.unwrap().is_empty());
+
+        options.dry_run = false;
+        db.import_table(&table, file.as_bytes(), &options, |_| {}).await.unwrap();
+        let file = "slug,title\nfirst,Uno\nthird,Three\n";
+        let mut reports = Vec::new();
+        let report = db
+            .import_table(&table, file.as_bytes(), &options, |r| reports.push(r.clone()))
+            .await
+            .unwrap();
+        assert_eq!((report.created, report.updated), (1, 1));
+        assert_eq!(reports.last(), Some(&report));
+
+        let id = RecordId::new(table.clone(), "first").unwrap();
+        assert_eq!(db.select_record(&id).await.unwrap().unwrap()["title"], "Uno");
+    }
+
+    #[tokio::test]
+    async fn test_scoped_transfers_follow_policies() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let table = Table::new("notes").unwrap();
+
+        let file = "{\"text\": \"mine\"}\n\n{\"text\": \"also mine\"}\n";
+        let options = ImportOptions::new(TransferFormat::Ndjson);
+        let report = db
+            .scoped(&policies, &alice)
+            .import_table(&table, file.as_bytes(), &options, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(report.created, 2);
+
+        let options = ExportOptions {
+            format: TransferFormat::Ndjson,
+            columns: Some("text".parse().unwrap()),
+        };
+        let export = db
+            .scoped(&policies, &bob)
+            .export_table(ListQuery::new(table.clone()), options.clone())
+            .unwrap();
+        assert_eq!(export.write_to(&db, Vec::new()).await.unwrap(), 0);
+
+        let mut file = Vec::new();
+        let export = db
+            .scoped(&policies, &alice)
+            .export_table(ListQuery::new(table.clone()), options)
+            .unwrap();
+        assert_eq!(export.write_to(&db, &mut file).await.unwrap(), 2);
+        assert!(String::from_utf8(file).unwrap().contains("{\"text\":\"mine\"}"));
+
+        let users = Table::new("users").unwrap();
+        let options = ImportOptions::new(TransferFormat::Ndjson);
+        let denied = db
+            .scoped(&policies, &alice)
+            .import_table(&users, "{}".as_bytes(), &options, |_| {})
+            .await;
+        assert!(matches!(denied, Err(DbError::PermissionDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_scoped_dry_run_only_sees_readable_records() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+        let table = Table::new("notes").unwrap();
+
+        let file = "{\"slug\": \"todo\", \"text\": \"mine\"}\n";
+        let mut options = ImportOptions {
+            key: Some("slug".to_string()),
+            ..ImportOptions::new(TransferFormat::Ndjson)
+        };
+        db.scoped(&policies, &alice)
+            .import_table(&table, file.as_bytes(), &options, |_| {})
+            .await
+            .unwrap();
+
+        options.dry_run = true;
+        for (identity, updated) in [(&alice, 1), (&bob, 0)] {
+            let report = db
+                .scoped(&policies, identity)
+                .import_table(&table, file.as_bytes(), &options, |_| {})
+                .await
+                .unwrap();
+            assert_eq!(report.updated, updated, "{}", identity.sub);
+        }
+    }
+}
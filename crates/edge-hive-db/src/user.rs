@@ -15,10 +15,28 @@ pub struct StoredUser {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     pub password_hash: String,
+    /// Identity provider the user logs in with (see `get_user_by_provider`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// ID of the user at `provider`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    /// Role used by row-level policies (`user` or `admin`)
+    #[serde(default = "default_role")]
+    pub role: String,
+    /// Whether the owner of `email` confirmed it
+    #[serde(default)]
+    pub email_verified: bool,
     pub created_at: Datetime,
     pub updated_at: Datetime,
 }
 
+fn default_role() -> String {
+    "user".to_string()
+}
+
 #[derive(Debug, Clone)]
 pub struct HashedPassword(String);
 
@@ -32,8 +50,8 @@ impl HashedPassword {
     }
 }
 
-impl ToString for HashedPassword {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl std::fmt::Display for HashedPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
 }
@@ -0,0 +1,414 @@
+//! Vector embeddings and nearest-neighbour queries
+//!
+//! Embeddings are produced by clients and stored as plain number arrays. A
+//! table declares its vector fields with [`DatabaseService::define_vectors`]:
+//! each field becomes a typed `array<float, N>` with an HNSW (default) or
+//! M-tree index, and the declaration is kept in the `config` table so
+//! queries can check the dimension and pick the right KNN operator.
+
+use crate::filter::{Field, Filter, Guard};
+use crate::policy::{ScopedDatabase, IDENTITY_VAR};
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Largest number of neighbours returned by one query
+pub const MAX_K: u64 = 100;
+
+/// Candidate list size of HNSW searches (raised to `k` when smaller)
+const HNSW_EF: u64 = 40;
+
+/// Extra projection carrying the distance (stripped from records)
+const DISTANCE_FIELD: &str = "__distance";
+
+/// Variable holding the ids a guarded query may return
+const ALLOWED_VAR: &str = "allowed";
+
+fn config_key(table: &Table) -> String {
+    format!("vector.{}", table)
+}
+
+fn index_name(table: &Table, field: &Field) -> String {
+    format!("vector_{}_{}", table, field.as_str().replace('.', "_"))
+}
+
+/// Index structure of a vector field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorIndexKind {
+    /// Approximate search, fast on large tables
+    #[default]
+    Hnsw,
+    /// Exact search
+    Mtree,
+}
+
+/// Distance metric of a vector field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    #[default]
+    Cosine,
+    Euclidean,
+    Manhattan,
+}
+
+impl Distance {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Distance::Cosine => "COSINE",
+            Distance::Euclidean => "EUCLIDEAN",
+            Distance::Manhattan => "MANHATTAN",
+        }
+    }
+}
+
+/// Declaration of one vector field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorField {
+    pub field: Field,
+    /// Number of components of every vector
+    pub dimension: u32,
+    #[serde(default)]
+    pub index: VectorIndexKind,
+    #[serde(default)]
+    pub distance: Distance,
+}
+
+/// Body of a nearest-neighbour request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnnRequest {
+    /// Query vector
+    pub vector: Vec<f64>,
+    /// Number of neighbours
+    pub k: u64,
+    /// Vector field to search (optional when the table has a single one)
+    #[serde(default)]
+    pub field: Option<Field>,
+    /// PostgREST-style filters, e.g. `{"lang": "eq.en"}`
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
+/// A nearest-neighbour query over one table
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnnQuery {
+    pub table: Table,
+    pub vector: Vec<f64>,
+    pub k: u64,
+    pub field: Option<Field>,
+    /// Conditions, combined with `AND`
+    pub filters: Vec<Filter>,
+    /// Row policy added by [`ScopedDatabase`]
+    pub(crate) guard: Option<Guard>,
+}
+
+/// One neighbour, nearest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnnHit {
+    /// The record, without its vector field
+    pub record: Value,
+    pub distance: f64,
+}
+
+impl KnnQuery {
+    pub fn new(table: Table, request: KnnRequest) -> Result<Self, DbError> {
+        if request.k == 0 || request.k > MAX_K {
+            return Err(DbError::InvalidQuery(format!("'k' must be between 1 and {}", MAX_K)));
+        }
+        if request.vector.is_empty() {
+            return Err(DbError::InvalidQuery("'vector' must not be empty".to_string()));
+        }
+
+        let filters = request
+            .filters
+            .iter()
+            .map(|(field, expr)| Filter::parse(Field::new(field.as_str())?, expr))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            table,
+            vector: request.vector,
+            k: request.k,
+            field: request.field,
+            filters,
+            guard: None,
+        })
+    }
+
+    /// Pick the declared field this query searches and check the dimension
+    fn target<'a>(&self, declared: &'a [VectorField]) -> Result<&'a VectorField, DbError> {
+        let target = match &self.field {
+            Some(field) => declared.iter().find(|v| &v.field == field),
+            None if declared.len() == 1 => declared.first(),
+            None => {
+                return Err(DbError::InvalidQuery(format!(
+                    "table '{}' has several vector fields, 'field' is required",
+                    self.table
+                )))
+            }
+        }
+        .ok_or_else(|| {
+            DbError::InvalidQuery(format!("table '{}' has no such vector field", self.table))
+        })?;
+
+        if self.vector.len() != target.dimension as usize {
+            return Err(DbError::InvalidQuery(format!(
+                "'{}' has dimension {}, got a vector of {}",
+                target.field,
+                target.dimension,
+                self.vector.len()
+            )));
+        }
+        Ok(target)
+    }
+
+    // Like full-text matches, the KNN operator needs literal table and field
+    // names; both are validated identifiers
+    fn render(&self, target: &VectorField) -> BoundQuery {
+        let mut vars = Map::new();
+        vars.insert("vector".into(), self.vector.clone().into());
+
+        let operator = match target.index {
+            VectorIndexKind::Hnsw => format!("<|{},{}|>", self.k, self.k.max(HNSW_EF)),
+            VectorIndexKind::Mtree => format!("<|{}|>", self.k),
+        };
+        let mut conditions = vec![format!("{} {} $vector", target.field, operator)];
+        conditions.extend(
+            self.filters
+                .iter()
+                .enumerate()
+                .map(|(i, filter)| filter.render(&format!("f{}", i), &mut vars)),
+        );
+
+        // The KNN operator finds nothing next to a condition holding an OR, as
+        // most policy rules do, so the readable ids are collected first
+        let mut allowed = None;
+        if let Some(guard) = &self.guard {
+            vars.insert(IDENTITY_VAR.into(), guard.identity.clone());
+            conditions.push(format!("id INSIDE ${}", ALLOWED_VAR));
+            allowed = Some(format!(
+                "LET ${} = (SELECT VALUE id FROM {} WHERE ({}));",
+                ALLOWED_VAR, self.table, guard.predicate
+            ));
+        }
+
+        let select = format!(
+            "SELECT *, vector::distance::knn() AS {} OMIT {} FROM {} WHERE {} ORDER BY {}",
+            DISTANCE_FIELD,
+            target.field,
+            self.table,
+            conditions.join(" AND "),
+            DISTANCE_FIELD
+        );
+        let sql = match allowed {
+            Some(allowed) => format!("{{ {} RETURN {} }}", allowed, select),
+            None => select,
+        };
+
+        BoundQuery::new(sql).bind_all(vars)
+    }
+}
+
+fn into_hit(mut row: Value) -> KnnHit {
+    let distance = row
+        .as_object_mut()
+        .and_then(|object| object.remove(DISTANCE_FIELD))
+        .and_then(|d| d.as_f64())
+        .unwrap_or(f64::MAX);
+
+    KnnHit {
+        record: row,
+        distance,
+    }
+}
+
+impl DatabaseService {
+    /// Declare the vector fields of `table`, replacing any previous declaration
+    pub async fn define_vectors(
+        &self,
+        table: &Table,
+        fields: &[VectorField],
+    ) -> Result<(), DbError> {
+        if table.is_system() {
+            return Err(DbError::PermissionDenied(format!(
+                "table '{}' is not accessible",
+                table
+            )));
+        }
+
+        let mut sql = String::new();
+        for previous in self.vector_fields(table).await? {
+            if !fields.iter().any(|f| f.field == previous.field) {
+                sql.push_str(&format!(
+                    "REMOVE INDEX IF EXISTS {name} ON TABLE {table};\n\
+                     REMOVE FIELD IF EXISTS {field} ON TABLE {table};\n",
+                    name = index_name(table, &previous.field),
+                    table = table,
+                    field = previous.field,
+                ));
+            }
+        }
+        for vector in fields {
+            if vector.dimension == 0 {
+                return Err(DbError::InvalidQuery(format!(
+                    "'{}' needs a dimension of at least 1",
+                    vector.field
+                )));
+            }
+            let index = match vector.index {
+                VectorIndexKind::Hnsw => "HNSW",
+                VectorIndexKind::Mtree => "MTREE",
+            };
+            sql.push_str(&format!(
+                "DEFINE FIELD OVERWRITE {field} ON TABLE {table} \
+                 TYPE option<array<float, {dimension}>>;\n\
+                 DEFINE INDEX OVERWRITE {name} ON TABLE {table} FIELDS {field} \
+                 {index} DIMENSION {dimension} DIST {distance};\n",
+                field = vector.field,
+                table = table,
+                dimension = vector.dimension,
+                name = index_name(table, &vector.field),
+                index = index,
+                distance = vector.distance.as_sql(),
+            ));
+        }
+        if !sql.is_empty() {
+            self.db().query(sql).await?.check()?;
+        }
+
+        self.set_config(&config_key(table), &fields).await
+    }
+
+    /// Vector fields declared for `table`
+    pub async fn vector_fields(&self, table: &Table) -> Result<Vec<VectorField>, DbError> {
+        Ok(self.get_config(&config_key(table)).await?.unwrap_or_default())
+    }
+
+    /// Find the `k` records nearest to a vector
+    pub async fn knn(&self, query: &KnnQuery) -> Result<Vec<KnnHit>, DbError> {
+        let declared = self.vector_fields(&query.table).await?;
+        let target = query.target(&declared)?;
+
+        let rows = self.execute(query.render(target)).await?;
+        let rows = self.open_rows(&query.table, rows)?;
+        Ok(rows.into_iter().map(into_hit).collect())
+    }
+}
+
+impl ScopedDatabase<'_> {
+    /// Nearest neighbours among the rows the caller may read
+    pub async fn knn(&self, mut query: KnnQuery) -> Result<Vec<KnnHit>, DbError> {
+        let policy = self.policies.policy(&query.table)?;
+        query.guard = Some(Guard {
+            predicate: policy.rule(&policy.select),
+            identity: self.identity.to_value(),
+        });
+        self.db.knn(&query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Identity, PolicySet};
+
+    fn request(value: Value) -> KnnRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    async fn setup(index: VectorIndexKind) -> (DatabaseService, Table) {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let table = Table::new("chunks").unwrap();
+        let field = VectorField {
+            field: Field::new("embedding").unwrap(),
+            dimension: 3,
+            index,
+            distance: Distance::Euclidean,
+        };
+        db.define_vectors(&table, &[field]).await.unwrap();
+        (db, table)
+    }
+
+    #[test]
+    fn test_request_validation() {
+        let table = Table::new("chunks").unwrap();
+        let zero_k = request(serde_json::json!({"vector": [1.0], "k": 0}));
+        assert!(KnnQuery::new(table.clone(), zero_k).is_err());
+
+        let bad_filter = request(serde_json::json!({
+            "vector": [1.0], "k": 1, "filters": {"lang": "between.1"}
+        }));
+        assert!(KnnQuery::new(table, bad_filter).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_knn_orders_by_distance() {
+        for index in [VectorIndexKind::Hnsw, VectorIndexKind::Mtree] {
+            let (db, table) = setup(index).await;
+            let points = [("a", [0.0, 0.0, 1.0]), ("b", [0.0, 1.0, 0.0]), ("c", [1.0, 0.0, 0.0])];
+            for (name, embedding) in points {
+                let content = serde_json::json!({"name": name, "embedding": embedding});
+                db.create_record(&table, content).await.unwrap();
+            }
+
+            let query = KnnQuery::new(
+                table,
+                request(serde_json::json!({"vector": [0.9, 0.1, 0.0], "k": 2})),
+            )
+            .unwrap();
+            let hits = db.knn(&query).await.unwrap();
+
+            assert_eq!(hits.len(), 2);
+            assert_eq!(hits[0].record["name"], "c");
+            assert!(hits[0].distance <= hits[1].distance);
+            assert!(hits[0].record.get("embedding").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_knn_checks_dimension_and_type() {
+        let (db, table) = setup(VectorIndexKind::Hnsw).await;
+
+        let wrong_len = db
+            .create_record(&table, serde_json::json!({"embedding": [1.0, 2.0]}))
+            .await;
+        assert!(wrong_len.is_err());
+
+        let query = KnnQuery::new(
+            table,
+            request(serde_json::json!({"vector": [1.0, 2.0], "k": 1})),
+        )
+        .unwrap();
+        assert!(matches!(db.knn(&query).await, Err(DbError::InvalidQuery(_))));
+    }
+
+    #[tokio::test]
+    async fn test_scoped_knn_applies_filters_and_policy() {
+        let (db, table) = setup(VectorIndexKind::Mtree).await;
+        let policies = PolicySet::default();
+        let alice = Identity::new("users:alice", "user", vec![]);
+        let bob = Identity::new("users:bob", "user", vec![]);
+
+        for (owner, lang) in [(&alice, "en"), (&alice, "es"), (&bob, "en")] {
+            db.scoped(&policies, owner)
+                .create_record(&table, serde_json::json!({"lang": lang, "embedding": [1, 0, 0]}))
+                .await
+                .unwrap();
+        }
+
+        let query = KnnQuery::new(
+            table,
+            request(serde_json::json!({
+                "vector": [1.0, 0.0, 0.0], "k": 10, "filters": {"lang": "eq.en"}
+            })),
+        )
+        .unwrap();
+        let hits = db.scoped(&policies, &alice).knn(query).await.unwrap();
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].record["owner"], "users:alice");
+    }
+}
@@ -0,0 +1,460 @@
+//! Outbound webhooks fed by the change log
+//!
+//! A webhook subscribes a URL to some actions on one table; creating it turns
+//! on change capture for that table (see `changes.rs`).
+//! [`DatabaseService::fan_out_changes`] turns every undispatched change into
+//! one pending delivery per matching webhook. The dispatcher sends due
+//! deliveries and reports back here: failures are retried with exponential
+//! backoff until the [`RetryPolicy`] gives up and the delivery is
+//! dead-lettered, where it stays until an admin retries it.
+
+use crate::changes::{parse_rows, Change, ChangeAction, CHANGE_FIELDS};
+use crate::query::{BoundQuery, Table};
+use crate::{DatabaseService, DbError};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Largest page of deliveries returned at once
+pub const MAX_DELIVERIES: u64 = 1000;
+
+const WEBHOOK_FIELDS: &str = "record::id(id) AS id, `table`, actions, url, secret, active, \
+     <string> created_at AS created_at";
+
+const DELIVERY_FIELDS: &str = "record::id(id) AS id, record::id(webhook) AS webhook, \
+     record::id(change) AS change, status, attempts, \
+     <string> next_attempt_at AS next_attempt_at, last_error, \
+     <string> created_at AS created_at, \
+     (IF delivered_at THEN <string> delivered_at END) AS delivered_at";
+
+/// A subscription of a URL to changes of one table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub table: Table,
+    pub actions: Vec<ChangeAction>,
+    pub url: String,
+    /// Key of the HMAC signature sent with every delivery
+    pub secret: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fields of a new webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewWebhook {
+    pub table: Table,
+    /// Actions to deliver (all of them when empty)
+    #[serde(default)]
+    pub actions: Vec<ChangeAction>,
+    pub url: String,
+    /// Signing secret (generated when missing)
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// State of a delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting for its next attempt
+    Pending,
+    Delivered,
+    /// Gave up after the last attempt
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+/// One change to send to one webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+    pub webhook: String,
+    /// ID of the change being delivered
+    pub change: String,
+    pub status: DeliveryStatus,
+    /// Failed attempts so far
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// A delivery whose next attempt is due, with what it needs to be sent
+#[derive(Debug, Clone, Deserialize)]
+pub struct DueDelivery {
+    #[serde(flatten)]
+    pub delivery: Delivery,
+    #[serde(rename = "hook")]
+    pub webhook: Webhook,
+    #[serde(rename = "event")]
+    pub change: Change,
+}
+
+impl DueDelivery {
+    /// `table.action`, e.g. `posts.create`
+    pub fn event(&self) -> String {
+        format!("{}.{}", self.change.table, self.change.action.as_str())
+    }
+
+    /// JSON body sent to the webhook
+    pub fn payload(&self) -> Value {
+        json!({
+            "delivery": self.delivery.id,
+            "event": self.event(),
+            "change": self.change,
+        })
+    }
+}
+
+/// Filters of a delivery listing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeliveryQuery {
+    pub webhook: Option<String>,
+    pub status: Option<DeliveryStatus>,
+    pub limit: u64,
+}
+
+/// When failed deliveries are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts before a delivery is dead-lettered
+    pub max_attempts: u32,
+    /// Delay after the first failure, doubled after each further one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt, after `failures` failed attempts
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+pub(crate) fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl DatabaseService {
+    /// Subscribe a URL to changes of a table
+    pub async fn create_webhook(&self, webhook: NewWebhook) -> Result<Webhook, DbError> {
+        if !(webhook.url.starts_with("http://") || webhook.url.starts_with("https://")) {
+            return Err(DbError::InvalidQuery(format!(
+                "webhook URL '{}' must be http(s)",
+                webhook.url
+            )));
+        }
+        let actions = if webhook.actions.is_empty() {
+            vec![ChangeAction::Create, ChangeAction::Update, ChangeAction::Delete]
+        } else {
+            webhook.actions
+        };
+
+        self.capture_changes(&webhook.table).await?;
+
+        let content = json!({
+            "table": webhook.table,
+            "actions": actions,
+            "url": webhook.url,
+            "secret": webhook.secret.unwrap_or_else(generate_secret),
+        });
+        let query = BoundQuery::new(format!(
+            "{{ LET $created = (CREATE _webhooks CONTENT $content); \
+             RETURN SELECT {} FROM $created }}",
+            WEBHOOK_FIELDS
+        ))
+        .bind("content", content);
+
+        parse_rows(self.execute(query).await?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DbError::Query("Webhook creation returned no record".to_string()))
+    }
+
+    /// Every webhook, oldest first
+    pub async fn webhooks(&self) -> Result<Vec<Webhook>, DbError> {
+        let query = BoundQuery::new(format!("SELECT {} FROM _webhooks", WEBHOOK_FIELDS));
+        let mut webhooks: Vec<Webhook> = parse_rows(self.execute(query).await?)?;
+        webhooks.sort_by_key(|w| w.created_at);
+        Ok(webhooks)
+    }
+
+    /// A webhook by ID
+    pub async fn webhook(&self, id: &str) -> Result<Option<Webhook>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {} FROM type::thing('_webhooks', $id)",
+            WEBHOOK_FIELDS
+        ))
+        .bind("id", id);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+
+    /// Remove a webhook and its deliveries; `false` if it did not exist
+    pub async fn delete_webhook(&self, id: &str) -> Result<bool, DbError> {
+        let query = BoundQuery::new(
+            "{ LET $hook = type::thing('_webhooks', $id); \
+             DELETE _webhook_deliveries WHERE webhook = $hook; \
+             RETURN DELETE $hook RETURN BEFORE }",
+        )
+        .bind("id", id);
+
+        Ok(!self.execute(query).await?.is_empty())
+    }
+
+    /// Queue deliveries for up to `limit` undispatched changes
+    ///
+    /// Returns the number of changes dispatched.
+    pub async fn fan_out_changes(&self, limit: u64) -> Result<usize, DbError> {
+        let query = BoundQuery::new(
+            "{ LET $changes = (SELECT id, `table`, action, at FROM _changes \
+             WHERE dispatched = false ORDER BY at, id LIMIT $limit); \
+             FOR $change IN $changes { \
+             FOR $hook IN (SELECT VALUE id FROM _webhooks WHERE active = true \
+             AND `table` = $change.`table` AND actions CONTAINS $change.action) { \
+             CREATE _webhook_deliveries CONTENT { webhook: $hook, change: $change.id, \
+             status: 'pending', attempts: 0, next_attempt_at: time::now() }; }; \
+             UPDATE $change.id SET dispatched = true; }; \
+             RETURN $changes.id }",
+        )
+        .bind("limit", limit);
+
+        Ok(self.execute(query).await?.len())
+    }
+
+    /// Pending deliveries whose next attempt is due
+    pub async fn due_deliveries(&self, limit: u64) -> Result<Vec<DueDelivery>, DbError> {
+        let query = BoundQuery::new(format!(
+            "SELECT {}, (SELECT {} FROM ONLY $parent.webhook) AS hook, \
+             (SELECT {} FROM ONLY $parent.change) AS event \
+             FROM _webhook_deliveries \
+             WHERE status = 'pending' AND next_attempt_at <= time::now() LIMIT $limit",
+            DELIVERY_FIELDS, WEBHOOK_FIELDS, CHANGE_FIELDS
+        ))
+        .bind("limit", limit);
+
+        parse_rows(self.execute(query).await?)
+    }
+
+    /// Mark a delivery as delivered
+    pub async fn record_delivery_success(&self, id: &str) -> Result<(), DbError> {
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_webhook_deliveries', $id) \
+             SET status = 'delivered', delivered_at = time::now(), last_error = NONE",
+        )
+        .bind("id", id);
+
+        self.execute(query).await?;
+        Ok(())
+    }
+
+    /// Record a failed attempt, scheduling a retry or dead-lettering the delivery
+    pub async fn record_delivery_failure(
+        &self,
+        delivery: &Delivery,
+        error: &str,
+        policy: &RetryPolicy,
+    ) -> Result<DeliveryStatus, DbError> {
+        let attempts = delivery.attempts + 1;
+        let status = if attempts >= policy.max_attempts {
+            DeliveryStatus::Dead
+        } else {
+            DeliveryStatus::Pending
+        };
+        let delay = chrono::Duration::from_std(policy.backoff(attempts))
+            .map_err(|e| DbError::InvalidQuery(e.to_string()))?;
+
+        let query = BoundQuery::new(
+            "UPDATE type::thing('_webhook_deliveries', $id) SET status = $status, \
+             attempts = $attempts, last_error = $error, next_attempt_at = <datetime> $next",
+        )
+        .bind("id", delivery.id.as_str())
+        .bind("status", status.as_str())
+        .bind("attempts", attempts)
+        .bind("error", error)
+        .bind("next", (Utc::now() + delay).to_rfc3339());
+
+        self.execute(query).await?;
+        Ok(status)
+    }
+
+    /// List deliveries, newest first
+    pub async fn deliveries(&self, query: &DeliveryQuery) -> Result<Vec<Delivery>, DbError> {
+        let mut conditions = Vec::new();
+        let mut statement =
+            BoundQuery::new("").bind("limit", query.limit.clamp(1, MAX_DELIVERIES));
+        if let Some(webhook) = &query.webhook {
+            conditions.push("webhook = type::thing('_webhooks', $webhook)");
+            statement = statement.bind("webhook", webhook.as_str());
+        }
+        if let Some(status) = &query.status {
+            conditions.push("status = $status");
+            statement = statement.bind("status", status.as_str());
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        statement.sql = format!(
+            "SELECT {}, created_at AS __created FROM _webhook_deliveries{} \
+             ORDER BY __created DESC LIMIT $limit",
+            DELIVERY_FIELDS, filter
+        );
+
+        parse_rows(self.execute(statement).await?)
+    }
+
+    /// Send a dead or pending delivery again right away, with a fresh attempt budget
+    pub async fn retry_delivery(&self, id: &str) -> Result<Option<Delivery>, DbError> {
+        let query = BoundQuery::new(format!(
+            "{{ LET $updated = (UPDATE type::thing('_webhook_deliveries', $id) \
+             SET status = 'pending', attempts = 0, next_attempt_at = time::now() \
+             WHERE status != 'delivered'); \
+             RETURN SELECT {} FROM $updated }}",
+            DELIVERY_FIELDS
+        ))
+        .bind("id", id);
+
+        Ok(parse_rows(self.execute(query).await?)?.into_iter().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn new_webhook(actions: Vec<ChangeAction>) -> NewWebhook {
+        NewWebhook {
+            table: Table::new("posts").unwrap(),
+            actions,
+            url: "http://127.0.0.1:9/hook".to_string(),
+            secret: None,
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+        let delays: Vec<u64> = (1..=5).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_changes_fan_out_to_matching_webhooks() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let all = db.create_webhook(new_webhook(vec![])).await.unwrap();
+        let deletes = db.create_webhook(new_webhook(vec![ChangeAction::Delete])).await.unwrap();
+        assert_eq!(all.actions.len(), 3);
+        assert!(!all.secret.is_empty());
+
+        let table = Table::new("posts").unwrap();
+        db.create_record(&table, json!({"title": "a"})).await.unwrap();
+        assert_eq!(db.fan_out_changes(100).await.unwrap(), 1);
+        assert_eq!(db.fan_out_changes(100).await.unwrap(), 0);
+
+        let due = db.due_deliveries(10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].webhook.id, all.id);
+        assert_eq!(due[0].event(), "posts.create");
+        assert_eq!(due[0].payload()["change"]["after"]["title"], "a");
+
+        db.record_delivery_success(&due[0].delivery.id).await.unwrap();
+        assert!(db.due_deliveries(10).await.unwrap().is_empty());
+
+        let delivered = db
+            .deliveries(&DeliveryQuery {
+                webhook: Some(all.id.clone()),
+                status: Some(DeliveryStatus::Delivered),
+                limit: 10,
+            })
+            .await
+            .unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert!(delivered[0].delivered_at.is_some());
+
+        assert!(db.delete_webhook(&deletes.id).await.unwrap());
+        assert!(!db.delete_webhook(&deletes.id).await.unwrap());
+        assert_eq!(db.webhooks().await.unwrap(), vec![all]);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_retried_then_dead_lettered() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        db.create_webhook(new_webhook(vec![])).await.unwrap();
+        let table = Table::new("posts").unwrap();
+        db.create_record(&table, json!({"title": "a"})).await.unwrap();
+        db.fan_out_changes(100).await.unwrap();
+
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        };
+        let due = db.due_deliveries(10).await.unwrap().remove(0);
+        let status = db.record_delivery_failure(&due.delivery, "HTTP 500", &policy).await;
+        assert_eq!(status.unwrap(), DeliveryStatus::Pending);
+
+        let due = db.due_deliveries(10).await.unwrap().remove(0);
+        assert_eq!(due.delivery.attempts, 1);
+        let status = db.record_delivery_failure(&due.delivery, "HTTP 500", &policy).await;
+        assert_eq!(status.unwrap(), DeliveryStatus::Dead);
+        assert!(db.due_deliveries(10).await.unwrap().is_empty());
+
+        let dead = db
+            .deliveries(&DeliveryQuery {
+                status: Some(DeliveryStatus::Dead),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(dead[0].last_error.as_deref(), Some("HTTP 500"));
+
+        let retried = db.retry_delivery(&dead[0].id).await.unwrap().unwrap();
+        assert_eq!(retried.status, DeliveryStatus::Pending);
+        assert_eq!(retried.attempts, 0);
+        assert_eq!(db.due_deliveries(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_url_must_be_http() {
+        let db = DatabaseService::new_in_memory().await.unwrap();
+        let mut webhook = new_webhook(vec![]);
+        webhook.url = "file:///etc/passwd".to_string();
+        assert!(matches!(db.create_webhook(webhook).await, Err(DbError::InvalidQuery(_))));
+    }
+}
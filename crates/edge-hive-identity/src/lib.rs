@@ -101,6 +101,32 @@ impl NodeIdentity {
         self.keypair.to_bytes()
     }
 
+    /// age key derived from the node key, used to encrypt backups to this node
+    ///
+    /// This is the X25519 form of the Ed25519 secret, the same conversion
+    /// age applies to `ssh-ed25519` keys.
+    pub fn age_identity(&self) -> Result<age::x25519::Identity, IdentityError> {
+        use bech32::ToBase32;
+
+        let mut scalar = self.keypair.to_scalar_bytes();
+        let encoded =
+            bech32::encode("age-secret-key-", scalar.to_base32(), bech32::Variant::Bech32);
+        scalar.zeroize();
+
+        let mut encoded = encoded.map_err(|e| IdentityError::Encryption(e.to_string()))?;
+        let identity = encoded
+            .to_uppercase()
+            .parse()
+            .map_err(|e: &str| IdentityError::Encryption(e.to_string()));
+        encoded.zeroize();
+        identity
+    }
+
+    /// Public half of [`Self::age_identity`]
+    pub fn age_recipient(&self) -> Result<age::x25519::Recipient, IdentityError> {
+        Ok(self.age_identity()?.to_public())
+    }
+
     /// Sign a message
     pub fn sign(&self, message: &[u8]) -> Signature {
         self.keypair.sign(message)
@@ -234,6 +260,20 @@ mod tests {
         assert!(identity.verify(message, &signature).is_ok());
     }
 
+    #[test]
+    fn test_age_identity_is_stable() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+
+        let identity = NodeIdentity::generate().unwrap();
+        identity.save(&path, None).unwrap();
+        let loaded = NodeIdentity::load(&path, None).unwrap();
+
+        let recipient = identity.age_recipient().unwrap().to_string();
+        assert!(recipient.starts_with("age1"));
+        assert_eq!(recipient, loaded.age_recipient().unwrap().to_string());
+    }
+
     #[test]
     fn test_save_load() {
         let dir = tempdir().unwrap();
@@ -1,6 +1,9 @@
 //! Edge Hive MCP Server
 //!
 //! Model Context Protocol server that exposes Edge Hive admin operations as tools.
+//!
+//! With [`MCPServer::with_data`] the data API is exposed as well; data tools
+//! and resources run under the caller's row-level policies.
 
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -8,6 +11,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use edge_hive_auth::{middleware::AuthenticatedUser, TokenValidator};
+use edge_hive_db::{DatabaseService, DbError, Identity, KnnQuery, KnnRequest, PolicySet, Table};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MCPRequest {
@@ -43,6 +47,30 @@ impl MCPError {
             data: None,
         }
     }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: -32602,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<DbError> for MCPError {
+    fn from(error: DbError) -> Self {
+        match error {
+            DbError::InvalidIdentifier(_) | DbError::InvalidQuery(_) => {
+                Self::invalid_params(error.to_string())
+            }
+            DbError::PermissionDenied(_) => Self::insufficient_permissions(),
+            _ => Self {
+                code: -32000,
+                message: error.to_string(),
+                data: None,
+            },
+        }
+    }
 }
 
 
@@ -53,6 +81,18 @@ pub struct Tool {
     pub input_schema: Value,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+}
+
+/// URI of the resource describing the data tables
+pub const DATA_SCHEMA_URI: &str = "edge-hive://data/schema";
+
 // ===== Dashboard Data Types =====
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,10 +116,18 @@ pub struct Node {
 
 // ===== MCP Server =====
 
+/// Database access of the data tools
+struct DataAccess {
+    db: Arc<DatabaseService>,
+    policies: Arc<PolicySet>,
+}
+
 pub struct MCPServer {
     tools: HashMap<String, Tool>,
+    resources: HashMap<String, Resource>,
     stats: Arc<RwLock<DashboardStats>>,
     nodes: Arc<RwLock<Vec<Node>>>,
+    data: Option<DataAccess>,
 }
 
 impl MCPServer {
@@ -174,7 +222,69 @@ impl MCPServer {
 
         let nodes = Arc::new(RwLock::new(Vec::new()));
 
-        Self { tools, stats, nodes }
+        Self {
+            tools,
+            resources: HashMap::new(),
+            stats,
+            nodes,
+            data: None,
+        }
+    }
+
+    /// Expose the data API as tools and resources
+    pub fn with_data(mut self, db: Arc<DatabaseService>, policies: Arc<PolicySet>) -> Self {
+        // Define data_knn tool
+        self.tools.insert(
+            "data_knn".to_string(),
+            Tool {
+                name: "data_knn".to_string(),
+                description: "Find the records of a table nearest to a vector".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "table": {
+                            "type": "string",
+                            "description": "Table with a vector field"
+                        },
+                        "vector": {
+                            "type": "array",
+                            "items": { "type": "number" },
+                            "description": "Query vector"
+                        },
+                        "k": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Number of neighbours"
+                        },
+                        "field": {
+                            "type": "string",
+                            "description": "Vector field (when the table has several)"
+                        },
+                        "filters": {
+                            "type": "object",
+                            "additionalProperties": { "type": "string" },
+                            "description": "Filters such as {\"lang\": \"eq.en\"}"
+                        }
+                    },
+                    "required": ["table", "vector", "k"]
+                }),
+            },
+        );
+
+        // Define the data schema resource
+        self.resources.insert(
+            DATA_SCHEMA_URI.to_string(),
+            Resource {
+                uri: DATA_SCHEMA_URI.to_string(),
+                name: "Data schema".to_string(),
+                description: "Tables of the data API with their fields, indexes and record counts"
+                    .to_string(),
+                mime_type: "application/json".to_string(),
+            },
+        );
+
+        self.data = Some(DataAccess { db, policies });
+        self
     }
 
     /// Update system stats (called periodically from Tauri backend)
@@ -190,9 +300,28 @@ impl MCPServer {
     }
 
     pub async fn handle_request(&self, request: MCPRequest) -> MCPResponse {
+        self.handle_request_as(request, None).await
+    }
+
+    /// Handle a request on behalf of `identity` (required by the data tools)
+    pub async fn handle_request_as(
+        &self,
+        request: MCPRequest,
+        identity: Option<Identity>,
+    ) -> MCPResponse {
         match request.method.as_str() {
             "tools/list" => self.list_tools(request.id),
-            "tools/call" => self.call_tool(request.id, request.params).await,
+            "tools/call" => self.call_tool(request.id, request.params, identity.as_ref()).await,
+            "resources/list" => self.list_resources(request.id),
+            "resources/read" => {
+                let result = self.read_resource(request.params, identity.as_ref()).await;
+                MCPResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: request.id,
+                    result: result.as_ref().ok().cloned(),
+                    error: result.err(),
+                }
+            }
             _ => MCPResponse {
                 jsonrpc: "2.0".to_string(),
                 id: request.id,
@@ -216,7 +345,58 @@ impl MCPServer {
         }
     }
 
-    async fn call_tool(&self, id: Option<Value>, params: Option<Value>) -> MCPResponse {
+    fn list_resources(&self, id: Option<Value>) -> MCPResponse {
+        let resources: Vec<&Resource> = self.resources.values().collect();
+        MCPResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(json!({ "resources": resources })),
+            error: None,
+        }
+    }
+
+    async fn read_resource(
+        &self,
+        params: Option<Value>,
+        identity: Option<&Identity>,
+    ) -> Result<Value, MCPError> {
+        let uri = params
+            .as_ref()
+            .and_then(|p| p.get("uri"))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| MCPError::invalid_params("Missing resource URI"))?;
+        let resource = self
+            .resources
+            .get(uri)
+            .ok_or_else(|| MCPError::invalid_params(format!("Unknown resource: {}", uri)))?;
+
+        let contents = match uri {
+            DATA_SCHEMA_URI => {
+                let (Some(data), Some(identity)) = (&self.data, identity) else {
+                    return Err(MCPError::insufficient_permissions());
+                };
+                let tables = data.db.scoped(&data.policies, identity).describe().await?;
+                serde_json::to_string(&tables)
+                    .map_err(|e| MCPError::invalid_params(e.to_string()))?
+            }
+            _ => return Err(MCPError::invalid_params(format!("Unknown resource: {}", uri))),
+        };
+
+        Ok(json!({
+            "contents": [{
+                "uri": resource.uri,
+                "mimeType": resource.mime_type,
+                "text": contents,
+            }]
+        }))
+    }
+
+    async fn call_tool(
+        &self,
+        id: Option<Value>,
+        params: Option<Value>,
+        identity: Option<&Identity>,
+    ) -> MCPResponse {
         let params = match params {
             Some(p) => p,
             None => {
@@ -251,7 +431,7 @@ impl MCPServer {
 
         let arguments = params.get("arguments").cloned();
 
-        match self.execute_tool(tool_name, arguments).await {
+        match self.execute_tool(tool_name, arguments, identity).await {
             Ok(result) => MCPResponse {
                 jsonrpc: "2.0".to_string(),
                 id,
@@ -267,7 +447,12 @@ impl MCPServer {
         }
     }
 
-    async fn execute_tool(&self, tool_name: &str, arguments: Option<Value>) -> Result<Value, MCPError> {
+    async fn execute_tool(
+        &self,
+        tool_name: &str,
+        arguments: Option<Value>,
+        identity: Option<&Identity>,
+    ) -> Result<Value, MCPError> {
         match tool_name {
             "admin_get_dashboard_stats" => {
                 let stats = self.stats.read().await.clone();
@@ -352,6 +537,7 @@ impl MCPServer {
                     })
                 }
             }
+            "data_knn" if self.data.is_some() => self.data_knn(arguments, identity).await,
             _ => Err(MCPError {
                 code: -32601,
                 message: format!("Unknown tool: {}", tool_name),
@@ -359,6 +545,32 @@ impl MCPServer {
             }),
         }
     }
+
+    async fn data_knn(
+        &self,
+        arguments: Option<Value>,
+        identity: Option<&Identity>,
+    ) -> Result<Value, MCPError> {
+        let (Some(data), Some(identity)) = (&self.data, identity) else {
+            return Err(MCPError::insufficient_permissions());
+        };
+        let arguments = arguments.ok_or_else(|| MCPError::invalid_params("Missing arguments"))?;
+
+        let table = arguments
+            .get("table")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| MCPError::invalid_params("Missing table parameter"))?;
+        let table = Table::new(table)?;
+        let request: KnnRequest = serde_json::from_value(arguments)
+            .map_err(|e| MCPError::invalid_params(e.to_string()))?;
+
+        let hits = data
+            .db
+            .scoped(&data.policies, identity)
+            .knn(KnnQuery::new(table, request)?)
+            .await?;
+        Ok(json!(hits))
+    }
 }
 
 // ===== Authenticated MCP Server =====
@@ -376,10 +588,17 @@ impl AuthenticatedMCPServer {
         }
     }
 
+    /// Expose the data API as tools and resources (see [`MCPServer::with_data`])
+    pub fn with_data(mut self, db: Arc<DatabaseService>, policies: Arc<PolicySet>) -> Self {
+        self.inner = self.inner.with_data(db, policies);
+        self
+    }
+
     pub async fn handle_request(&self, request: MCPRequest, user: AuthenticatedUser) -> MCPResponse {
         let required_scopes = match request.method.as_str() {
             "tools/list" => vec!["mcp:read".to_string()],
             "tools/call" => vec!["mcp:call".to_string()],
+            "resources/list" | "resources/read" => vec!["mcp:read".to_string()],
             _ => return MCPResponse {
                 jsonrpc: "2.0".to_string(),
                 id: request.id,
@@ -401,7 +620,9 @@ impl AuthenticatedMCPServer {
             };
         }
 
-        self.inner.handle_request(request).await
+        let claims = &user.claims;
+        let identity = Identity::new(claims.sub.clone(), claims.role(), claims.scopes.clone());
+        self.inner.handle_request_as(request, Some(identity)).await
     }
 
     /// Update system stats (called periodically from Tauri backend)
@@ -581,6 +802,7 @@ mod tests {
                 jti: "test_jti".to_string(),
                 node_id: None,
                 scopes,
+                role: None,
             },
         }
     }
@@ -634,6 +856,115 @@ mod tests {
         assert!(response.error.is_none());
     }
 
+    async fn create_data_server() -> AuthenticatedMCPServer {
+        let db = Arc::new(DatabaseService::new_in_memory().await.unwrap());
+        let table = Table::new("docs").unwrap();
+        let field = serde_json::from_value(json!({"field": "embedding", "dimension": 2})).unwrap();
+        db.define_vectors(&table, &[field]).await.unwrap();
+
+        let policies = Arc::new(PolicySet::default());
+        for (owner, name, embedding) in [
+            ("test_client", "near", [1.0, 0.0]),
+            ("test_client", "far", [0.0, 1.0]),
+            ("someone_else", "hidden", [1.0, 0.0]),
+        ] {
+            let identity = Identity::new(owner, "user", vec![]);
+            db.scoped(&policies, &identity)
+                .create_record(&table, json!({"name": name, "embedding": embedding}))
+                .await
+                .unwrap();
+        }
+
+        AuthenticatedMCPServer::new(create_test_validator()).with_data(db, policies)
+    }
+
+    #[tokio::test]
+    async fn test_auth_server_data_knn() {
+        let server = create_data_server().await;
+        let user = create_test_user(vec!["mcp:call".to_string()]);
+        let request = MCPRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(json!(3)),
+            method: "tools/call".to_string(),
+            params: Some(json!({
+                "name": "data_knn",
+                "arguments": {"table": "docs", "vector": [0.9, 0.1], "k": 5}
+            })),
+        };
+
+        let response = server.handle_request(request, user).await;
+        let hits = response.result.unwrap();
+        let names: Vec<&str> = hits
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| h["record"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["near", "far"]);
+    }
+
+    #[tokio::test]
+    async fn test_data_knn_requires_identity() {
+        let server = MCPServer::new();
+        let request = MCPRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(json!(4)),
+            method: "tools/call".to_string(),
+            params: Some(json!({
+                "name": "data_knn",
+                "arguments": {"table": "docs", "vector": [1.0, 0.0], "k": 1}
+            })),
+        };
+
+        // Without data access the tool does not exist
+        let response = server.handle_request(request).await;
+        assert_eq!(response.error.unwrap().code, -32601);
+
+        let server = create_data_server().await;
+        let request = MCPRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(json!(5)),
+            method: "tools/call".to_string(),
+            params: Some(json!({
+                "name": "data_knn",
+                "arguments": {"table": "docs", "vector": [1.0, 0.0], "k": 1}
+            })),
+        };
+        let response = server.inner.handle_request(request).await;
+        assert_eq!(response.error, Some(MCPError::insufficient_permissions()));
+    }
+
+    #[tokio::test]
+    async fn test_auth_server_read_data_schema() {
+        let server = create_data_server().await;
+        let request = MCPRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(json!(6)),
+            method: "resources/list".to_string(),
+            params: None,
+        };
+        let response =
+            server.handle_request(request, create_test_user(vec!["mcp:read".to_string()])).await;
+        let resources = response.result.unwrap();
+        assert_eq!(resources["resources"][0]["uri"], DATA_SCHEMA_URI);
+
+        let request = MCPRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(json!(7)),
+            method: "resources/read".to_string(),
+            params: Some(json!({ "uri": DATA_SCHEMA_URI })),
+        };
+        let response =
+            server.handle_request(request, create_test_user(vec!["mcp:read".to_string()])).await;
+        let result = response.result.unwrap();
+        let text = result["contents"][0]["text"].as_str().unwrap();
+        let tables: Vec<edge_hive_db::TableInfo> = serde_json::from_str(text).unwrap();
+        let docs = tables.iter().find(|t| t.name == "docs").unwrap();
+        // Only the caller's own rows are counted
+        assert_eq!(docs.count, 2);
+        assert!(docs.indexes.iter().any(|i| i.kind == edge_hive_db::IndexKind::Hnsw));
+    }
+
     #[tokio::test]
     async fn test_auth_server_call_tool_fail_no_scope() {
         let validator = create_test_validator();
@@ -106,6 +106,16 @@ impl RealtimeServer {
         });
     }
 
+    /// Receive the events of a topic in-process
+    ///
+    /// Like a WebSocket subscription, this starts the topic's Live Query when
+    /// the server has a database.
+    pub fn subscribe(&self, topic: &str) -> broadcast::Receiver<ServerMessage> {
+        let sender = self.get_or_create_topic(topic);
+        ensure_live_query(&self.db, &self.live_tasks, topic, &sender);
+        sender.subscribe()
+    }
+
     fn get_or_create_topic(&self, topic: &str) -> broadcast::Sender<ServerMessage> {
         if let Some(entry) = self.topics.get(topic) {
             return entry.clone();
@@ -143,6 +153,65 @@ impl RealtimeServer {
     }
 }
 
+type LiveTasks = DashMap<String, tokio::task::JoinHandle<()>>;
+
+/// Start the Live Query pump feeding `topic` (one per topic), if there is a DB
+fn ensure_live_query(
+    db: &Option<Arc<DatabaseService>>,
+    live_tasks: &LiveTasks,
+    topic: &str,
+    sender: &broadcast::Sender<ServerMessage>,
+) {
+    let Some(db) = db.clone() else {
+        return;
+    };
+    let Entry::Vacant(entry) = live_tasks.entry(topic.to_string()) else {
+        return;
+    };
+
+    let sender = sender.clone();
+    let topic_for_task = topic.to_string();
+    let handle = tokio::spawn(async move {
+        match db.live_table(&topic_for_task).await {
+            Ok(mut stream) => {
+                while let Some(notification_result) = stream.next().await {
+                    let notification = match notification_result {
+                        Ok(n) => n,
+                        Err(e) => {
+                            warn!(
+                                "Live query notification error for topic={}: {}",
+                                topic_for_task, e
+                            );
+                            continue;
+                        }
+                    };
+
+                    let action = match notification.action {
+                        surrealdb::Action::Create => "create",
+                        surrealdb::Action::Update => "update",
+                        surrealdb::Action::Delete => "delete",
+                        _ => "unknown",
+                    };
+
+                    let data = serde_json::to_value(&notification.data)
+                        .unwrap_or(serde_json::Value::Null);
+
+                    let _ = sender.send(ServerMessage::Event {
+                        topic: topic_for_task.clone(),
+                        action: action.to_string(),
+                        data,
+                    });
+                }
+            }
+            Err(e) => {
+                warn!("Live query failed for topic={}: {}", topic_for_task, e);
+            }
+        }
+    });
+
+    entry.insert(handle);
+}
+
 async fn handle_connection(
     stream: TcpStream,
     addr: SocketAddr,
@@ -218,62 +287,7 @@ async fn handle_connection(
                 };
 
                 // If DB is available, ensure a single Live Query pump exists for this topic.
-                if db.is_some() {
-                    match live_tasks.entry(topic.clone()) {
-                        Entry::Occupied(_) => {}
-                        Entry::Vacant(entry) => {
-                            let db = db.clone();
-                            let sender = sender.clone();
-                            let topic_for_task = topic.clone();
-
-                            let handle = tokio::spawn(async move {
-                                let Some(db) = db else {
-                                    return;
-                                };
-                                match db.live_table(&topic_for_task).await {
-                                    Ok(mut stream) => {
-                                        while let Some(notification_result) = stream.next().await {
-                                            let notification = match notification_result {
-                                                Ok(n) => n,
-                                                Err(e) => {
-                                                    warn!(
-                                                        "Live query notification error for topic={}: {}",
-                                                        topic_for_task, e
-                                                    );
-                                                    continue;
-                                                }
-                                            };
-
-                                            let action = match notification.action {
-                                                surrealdb::Action::Create => "create",
-                                                surrealdb::Action::Update => "update",
-                                                surrealdb::Action::Delete => "delete",
-                                                _ => "unknown",
-                                            };
-
-                                            let data = serde_json::to_value(&notification.data)
-                                                .unwrap_or(serde_json::Value::Null);
-
-                                            let _ = sender.send(ServerMessage::Event {
-                                                topic: topic_for_task.clone(),
-                                                action: action.to_string(),
-                                                data,
-                                            });
-                                        }
-                                    }
-                                    Err(e) => {
-                                        warn!(
-                                            "Live query failed for topic={}: {}",
-                                            topic_for_task, e
-                                        );
-                                    }
-                                }
-                            });
-
-                            entry.insert(handle);
-                        }
-                    }
-                }
+                ensure_live_query(&db, &live_tasks, &topic, &sender);
 
                 let mut rx = sender.subscribe();
                 let out_tx_clone = out_tx.clone();
@@ -415,62 +429,7 @@ async fn handle_axum_connection(
                     tx
                 };
 
-                if db.is_some() {
-                    match live_tasks.entry(topic.clone()) {
-                        Entry::Occupied(_) => {}
-                        Entry::Vacant(entry) => {
-                            let db = db.clone();
-                            let sender = sender.clone();
-                            let topic_for_task = topic.clone();
-
-                            let handle = tokio::spawn(async move {
-                                let Some(db) = db else {
-                                    return;
-                                };
-                                match db.live_table(&topic_for_task).await {
-                                    Ok(mut stream) => {
-                                        while let Some(notification_result) = stream.next().await {
-                                            let notification = match notification_result {
-                                                Ok(n) => n,
-                                                Err(e) => {
-                                                    warn!(
-                                                        "Live query notification error for topic={}: {}",
-                                                        topic_for_task, e
-                                                    );
-                                                    continue;
-                                                }
-                                            };
-
-                                            let action = match notification.action {
-                                                surrealdb::Action::Create => "create",
-                                                surrealdb::Action::Update => "update",
-                                                surrealdb::Action::Delete => "delete",
-                                                _ => "unknown",
-                                            };
-
-                                            let data = serde_json::to_value(&notification.data)
-                                                .unwrap_or(serde_json::Value::Null);
-
-                                            let _ = sender.send(ServerMessage::Event {
-                                                topic: topic_for_task.clone(),
-                                                action: action.to_string(),
-                                                data,
-                                            });
-                                        }
-                                    }
-                                    Err(e) => {
-                                        warn!(
-                                            "Live query failed for topic={}: {}",
-                                            topic_for_task, e
-                                        );
-                                    }
-                                }
-                            });
-
-                            entry.insert(handle);
-                        }
-                    }
-                }
+                ensure_live_query(&db, &live_tasks, &topic, &sender);
 
                 let mut rx = sender.subscribe();
                 let out_tx_clone = out_tx.clone();
@@ -1,5 +1,5 @@
 //! System metrics monitor
-use sysinfo::{CpuExt, System, SystemExt};
+use sysinfo::System;
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct SystemMetrics {
@@ -5,7 +5,8 @@
 use crate::host::HostContext;
 use crate::limits::StoreLimits;
 use crate::WasmError;
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
 use wasmtime::*;
@@ -118,8 +119,9 @@ impl<H: HostContext> EdgeFunction<H> {
 
         // Write request data to WASM memory
         let mem_data = memory.data_mut(&mut store);
-        mem_data[req_ptr as usize..(req_ptr as usize + req_len as usize)]
-            .copy_from_slice(request_json.as_bytes());
+        let range = guest_range(req_ptr, req_len, mem_data.len())
+            .ok_or_else(|| WasmError::Call("allocate returned an invalid pointer".into()))?;
+        mem_data[range].copy_from_slice(request_json.as_bytes());
 
         // Call the handle_request function
         let handle_request = instance
@@ -138,8 +140,9 @@ impl<H: HostContext> EdgeFunction<H> {
         // Read response data
         let response_json = {
             let mem_data = memory.data(&store);
-            let response_bytes = &mem_data[resp_ptr as usize..(resp_ptr as usize + resp_len as usize)];
-            std::str::from_utf8(response_bytes)
+            let range = guest_range(resp_ptr, resp_len, mem_data.len())
+                .ok_or_else(|| WasmError::Call("Response out of bounds".into()))?;
+            std::str::from_utf8(&mem_data[range])
                 .map_err(|e| WasmError::Call(format!("Invalid UTF-8 in response: {}", e)))?
                 .to_string()
         };
@@ -160,60 +163,66 @@ impl<H: HostContext> EdgeFunction<H> {
 
     /// Link host functions to the linker
     fn link_host_functions(&self, linker: &mut Linker<StoreData<H>>) -> Result<(), WasmError> {
-        // db_query(sql_ptr: i32, sql_len: i32) -> result_ptr: i32
+        // db_query(req_ptr: i32, req_len: i32) -> result_ptr: i32
+        // The request is either `{"sql": "...", "vars": {...}}` or plain SQL text
         linker
             .func_wrap_async(
                 "edge_hive",
                 "db_query",
-                |mut caller: Caller<'_, StoreData<H>>, (sql_ptr, sql_len): (i32, i32)| {
+                |mut caller: Caller<'_, StoreData<H>>, (req_ptr, req_len): (i32, i32)| {
                     Box::new(async move {
                         let host = caller.data().host.clone();
+                        let request = read_guest_str(&mut caller, req_ptr, req_len)?;
+                        let (sql, vars) = parse_query_request(&request);
 
-                        // Read SQL from memory
-                        let memory = caller
-                            .get_export("memory")
-                            .and_then(|e| e.into_memory())
-                            .ok_or_else(|| anyhow::anyhow!("No memory export"))?;
+                        // Execute query via host
+                        let result = host.query(&sql, &vars).await;
 
-                        let mem_data = memory.data(&caller);
-                        let sql_bytes =
-                            &mem_data[sql_ptr as usize..(sql_ptr as usize + sql_len as usize)];
-                        let sql = std::str::from_utf8(sql_bytes)?;
+                        write_guest_json(&mut caller, &serde_json::to_string(&result)?).await
+                    })
+                },
+            )
+            .map_err(|e| WasmError::Instantiate(e.to_string()))?;
 
-                        // Execute query via host
-                        let result = host.query(sql);
-
-                        // Serialize result
-                        let result_json = serde_json::to_string(&result)?;
-                        let result_len = result_json.len() as i32;
-
-                        // Allocate memory for result
-                        let allocate = caller
-                            .get_export("allocate")
-                            .and_then(|e| e.into_func())
-                            .ok_or_else(|| anyhow::anyhow!("No allocate export"))?;
-
-                        let mut results = [Val::I32(0)];
-                        allocate
-                            .call_async(&mut caller, &[Val::I32(result_len + 4)], &mut results)
-                            .await?;
-
-                        let result_ptr = results[0].unwrap_i32();
-
-                        // Write result length and data
-                        let memory = caller
-                            .get_export("memory")
-                            .and_then(|e| e.into_memory())
-                            .ok_or_else(|| anyhow::anyhow!("No memory export"))?;
-
-                        let mem_data = memory.data_mut(&mut caller);
-                        mem_data[result_ptr as usize..result_ptr as usize + 4]
-                            .copy_from_slice(&result_len.to_le_bytes());
-                        mem_data[result_ptr as usize + 4
-                            ..result_ptr as usize + 4 + result_len as usize]
-                            .copy_from_slice(result_json.as_bytes());
-
-                        Ok(result_ptr + 4)
+        // db_record(req_ptr: i32, req_len: i32) -> result_ptr: i32
+        // The request is `{"op": "select", "table": "...", "id": "...", "data": {...}}`
+        linker
+            .func_wrap_async(
+                "edge_hive",
+                "db_record",
+                |mut caller: Caller<'_, StoreData<H>>, (req_ptr, req_len): (i32, i32)| {
+                    Box::new(async move {
+                        let host = caller.data().host.clone();
+                        let request = read_guest_str(&mut caller, req_ptr, req_len)?;
+
+                        let result = match serde_json::from_str::<Value>(&request) {
+                            Ok(request) => host.record(&request).await,
+                            Err(e) => Err(format!("invalid record request: {}", e)),
+                        };
+
+                        write_guest_json(&mut caller, &serde_json::to_string(&result)?).await
+                    })
+                },
+            )
+            .map_err(|e| WasmError::Instantiate(e.to_string()))?;
+
+        // db_knn(req_ptr: i32, req_len: i32) -> result_ptr: i32
+        // The request is `{"table": "...", "vector": [...], "k": 5, "filters": {...}}`
+        linker
+            .func_wrap_async(
+                "edge_hive",
+                "db_knn",
+                |mut caller: Caller<'_, StoreData<H>>, (req_ptr, req_len): (i32, i32)| {
+                    Box::new(async move {
+                        let host = caller.data().host.clone();
+                        let request = read_guest_str(&mut caller, req_ptr, req_len)?;
+
+                        let result = match serde_json::from_str::<Value>(&request) {
+                            Ok(request) => host.knn(&request).await,
+                            Err(e) => Err(format!("invalid knn request: {}", e)),
+                        };
+
+                        write_guest_json(&mut caller, &serde_json::to_string(&result)?).await
                     })
                 },
             )
@@ -229,15 +238,7 @@ impl<H: HostContext> EdgeFunction<H> {
                         let host = caller.data().host.clone();
 
                         // Read message from memory
-                        let memory = caller
-                            .get_export("memory")
-                            .and_then(|e| e.into_memory())
-                            .ok_or_else(|| anyhow::anyhow!("No memory export"))?;
-
-                        let mem_data = memory.data(&caller);
-                        let msg_bytes =
-                            &mem_data[msg_ptr as usize..(msg_ptr as usize + msg_len as usize)];
-                        let msg = std::str::from_utf8(msg_bytes)?;
+                        let msg = read_guest_str(&mut caller, msg_ptr, msg_len)?;
 
                         // Convert level and log
                         let log_level = match level {
@@ -248,7 +249,7 @@ impl<H: HostContext> EdgeFunction<H> {
                             _ => crate::host::LogLevel::Error,
                         };
 
-                        host.log(log_level, msg);
+                        host.log(log_level, &msg);
 
                         Ok(())
                     })
@@ -260,6 +261,89 @@ impl<H: HostContext> EdgeFunction<H> {
     }
 }
 
+/// Read a UTF-8 string the guest placed at `ptr..ptr + len`
+fn read_guest_str<H: HostContext>(
+    caller: &mut Caller<'_, StoreData<H>>,
+    ptr: i32,
+    len: i32,
+) -> anyhow::Result<String> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|e| e.into_memory())
+        .ok_or_else(|| anyhow::anyhow!("No memory export"))?;
+
+    let mem_data = memory.data(&caller);
+    let range = guest_range(ptr, len, mem_data.len())
+        .ok_or_else(|| anyhow::anyhow!("Request out of bounds"))?;
+    Ok(std::str::from_utf8(&mem_data[range])?.to_string())
+}
+
+/// Copy a host call result into guest memory
+///
+/// The result is written as a little-endian `u32` length followed by the JSON
+/// bytes; the returned pointer is the start of the JSON.
+async fn write_guest_json<H: HostContext>(
+    caller: &mut Caller<'_, StoreData<H>>,
+    json: &str,
+) -> anyhow::Result<i32> {
+    let result_len = json.len() as i32;
+
+    // Allocate memory for result
+    let allocate = caller
+        .get_export("allocate")
+        .and_then(|e| e.into_func())
+        .ok_or_else(|| anyhow::anyhow!("No allocate export"))?;
+
+    let mut results = [Val::I32(0)];
+    allocate
+        .call_async(&mut *caller, &[Val::I32(result_len + 4)], &mut results)
+        .await?;
+
+    let result_ptr = results[0].unwrap_i32();
+
+    // Write result length and data
+    let memory = caller
+        .get_export("memory")
+        .and_then(|e| e.into_memory())
+        .ok_or_else(|| anyhow::anyhow!("No memory export"))?;
+
+    let mem_data = memory.data_mut(&mut *caller);
+    let range = guest_range(result_ptr, result_len + 4, mem_data.len())
+        .ok_or_else(|| anyhow::anyhow!("allocate returned an invalid pointer"))?;
+    let (len_bytes, data) = mem_data[range].split_at_mut(4);
+    len_bytes.copy_from_slice(&result_len.to_le_bytes());
+    data.copy_from_slice(json.as_bytes());
+
+    Ok(result_ptr + 4)
+}
+
+/// Byte range `ptr..ptr + len` of a guest memory of `size` bytes
+///
+/// Pointers and lengths come from the guest as `i32`: negative values and
+/// ranges past the end of memory are rejected instead of wrapped.
+fn guest_range(ptr: i32, len: i32, size: usize) -> Option<Range<usize>> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    (end <= size).then_some(start..end)
+}
+
+/// Split a `db_query` request into its statement and bound variables
+///
+/// Guests should send `{"sql": "...", "vars": {...}}`; plain SQL text is still
+/// accepted and runs without variables.
+fn parse_query_request(request: &str) -> (String, Map<String, Value>) {
+    if let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(request) {
+        if let Some(Value::String(sql)) = object.remove("sql") {
+            let vars = match object.remove("vars") {
+                Some(Value::Object(vars)) => vars,
+                _ => Map::new(),
+            };
+            return (sql, vars);
+        }
+    }
+    (request.to_string(), Map::new())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -272,4 +356,30 @@ mod tests {
         let result = EdgeFunction::from_bytes(&engine, &[0x00, 0x01, 0x02], host);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_guest_range_rejects_negative_and_overflowing_values() {
+        assert_eq!(guest_range(16, 8, 64), Some(16..24));
+        assert_eq!(guest_range(56, 8, 64), Some(56..64));
+        assert_eq!(guest_range(60, 8, 64), None);
+        assert_eq!(guest_range(-8, 8, 64), None);
+        assert_eq!(guest_range(8, -1, 64), None);
+        assert_eq!(guest_range(i32::MIN, i32::MAX, 64), None);
+    }
+
+    #[test]
+    fn test_parse_query_request_with_vars() {
+        let (sql, vars) = parse_query_request(
+            r#"{"sql": "SELECT * FROM type::table($tb)", "vars": {"tb": "posts"}}"#,
+        );
+        assert_eq!(sql, "SELECT * FROM type::table($tb)");
+        assert_eq!(vars["tb"], "posts");
+    }
+
+    #[test]
+    fn test_parse_query_request_plain_sql() {
+        let (sql, vars) = parse_query_request("SELECT * FROM posts");
+        assert_eq!(sql, "SELECT * FROM posts");
+        assert!(vars.is_empty());
+    }
 }
@@ -2,8 +2,12 @@
 //!
 //! This module defines the trait that allows the WASM runtime to interact
 //! with the host environment without creating a circular dependency.
+//!
+//! Host calls are async: guests run on the async runtime, and a host call is
+//! awaited like any other I/O instead of blocking a worker thread.
 
-use serde_json::Value;
+use async_trait::async_trait;
+use serde_json::{Map, Value};
 use std::sync::Arc;
 
 /// Log level for host logging
@@ -20,15 +24,39 @@ pub enum LogLevel {
 ///
 /// This trait allows the WASM runtime to receive implementations from the host
 /// without depending on specific crates like edge-hive-db.
+#[async_trait]
 pub trait HostContext: Send + Sync + 'static {
     /// Execute a database query
     ///
     /// # Arguments
-    /// * `sql` - SQL query string
+    /// * `sql` - SQL query string, referencing variables as `$name`
+    /// * `vars` - Variables bound to the query (never spliced into `sql`)
     ///
     /// # Returns
     /// Result containing query results as JSON or error message
-    fn query(&self, sql: &str) -> Result<Value, String>;
+    async fn query(&self, sql: &str, vars: &Map<String, Value>) -> Result<Value, String>;
+
+    /// Read or write a single record on behalf of the caller
+    ///
+    /// # Arguments
+    /// * `request` - `{"op": "select" | "create" | "merge" | "delete", "table": "...", "id": "...", "data": {...}}`
+    ///
+    /// # Returns
+    /// Result containing the record (`null` when it is missing) or error message
+    async fn record(&self, _request: &Value) -> Result<Value, String> {
+        Err("record access is not supported by this host".to_string())
+    }
+
+    /// Find the nearest neighbours of a vector
+    ///
+    /// # Arguments
+    /// * `request` - `{"table": "...", "vector": [...], "k": 5, "field": "...", "filters": {...}}`
+    ///
+    /// # Returns
+    /// Result containing the hits (`[{"record": {...}, "distance": 0.1}]`) or error message
+    async fn knn(&self, _request: &Value) -> Result<Value, String> {
+        Err("knn is not supported by this host".to_string())
+    }
 
     /// Log a message
     ///
@@ -42,8 +70,9 @@ pub trait HostContext: Send + Sync + 'static {
 #[derive(Debug, Clone)]
 pub struct NoOpHostContext;
 
+#[async_trait]
 impl HostContext for NoOpHostContext {
-    fn query(&self, _sql: &str) -> Result<Value, String> {
+    async fn query(&self, _sql: &str, _vars: &Map<String, Value>) -> Result<Value, String> {
         Err("NoOpHostContext: query not implemented".to_string())
     }
 
@@ -8,6 +8,7 @@ pub mod limits;
 pub mod prelude;
 pub mod runtime;
 
+pub use async_trait::async_trait;
 pub use function::EdgeFunction;
 pub use host::{HostContext, LogLevel, NoOpHostContext, SharedHostContext};
 pub use runtime::WasmRuntime;
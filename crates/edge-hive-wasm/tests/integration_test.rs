@@ -3,7 +3,7 @@
 //! These tests verify the complete functionality of the WASM runtime
 //! with real WASM modules.
 
-use edge_hive_wasm::{HostContext, LogLevel, WasmRuntime};
+use edge_hive_wasm::{async_trait, HostContext, LogLevel, WasmRuntime};
 use serde_json::json;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -34,8 +34,13 @@ impl TestHostContext {
     }
 }
 
+#[async_trait]
 impl HostContext for TestHostContext {
-    fn query(&self, sql: &str) -> Result<serde_json::Value, String> {
+    async fn query(
+        &self,
+        sql: &str,
+        _vars: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
         self.queries.lock().unwrap().push(sql.to_string());
         Ok(json!({
             "result": "ok",
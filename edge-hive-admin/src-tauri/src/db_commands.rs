@@ -1,5 +1,5 @@
-use edge_hive_db::DatabaseService;
-use serde_json::Value;
+use edge_hive_db::{BoundQuery, DatabaseService};
+use serde_json::{Map, Value};
 use std::sync::Arc;
 use tauri::State;
 
@@ -19,19 +19,22 @@ impl DatabaseState {
 pub async fn db_query(
     state: State<'_, DatabaseState>,
     sql: String,
+    vars: Option<Map<String, Value>>,
 ) -> Result<Vec<Value>, String> {
-    let mut result = state.db_service.query(&sql).await.map_err(|e| e.to_string())?;
-    result.take(0).map_err(|e| e.to_string())
+    let query = BoundQuery::new(sql).bind_all(vars.unwrap_or_default());
+    state.db_service.execute(query).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 pub async fn db_execute(
     state: State<'_, DatabaseState>,
     sql: String,
+    vars: Option<Map<String, Value>>,
 ) -> Result<String, String> {
+    let query = BoundQuery::new(sql).bind_all(vars.unwrap_or_default());
     state
         .db_service
-        .query_json(&sql)
+        .execute(query)
         .await
         .map(|_| "OK".to_string())
         .map_err(|e| e.to_string())
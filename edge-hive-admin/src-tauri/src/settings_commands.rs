@@ -7,6 +7,7 @@
 //! - Access logs
 
 use tauri::{State, AppHandle};
+use edge_hive_api::mail::{Mailer, SmtpSecurity, SmtpSettings};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
@@ -297,15 +298,24 @@ pub async fn send_test_email(
     state: State<'_, SettingsState>,
     to: String,
 ) -> Result<(), String> {
-    let smtp = state.smtp_config.read().await;
-    let config = smtp.as_ref().ok_or("SMTP not configured")?;
-
-    // For now, just validate config exists
-    // Real implementation would use lettre crate
+    let config = state.smtp_config.read().await.clone().ok_or("SMTP not configured")?;
     if config.host.is_empty() {
         return Err("SMTP host not configured".to_string());
     }
 
+    let settings = SmtpSettings {
+        host: config.host,
+        port: config.port,
+        security: if config.secure { SmtpSecurity::Tls } else { SmtpSecurity::StartTls },
+        username: Some(config.username).filter(|username| !username.is_empty()),
+        password: Some(config.password).filter(|password| !password.is_empty()),
+        from: config.from_email,
+    };
+    let result = match Mailer::new(&settings) {
+        Ok(mailer) => mailer.send_test(&to).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
     // Log the attempt
     let mut logs = state.access_logs.write().await;
     logs.push(AccessLog {
@@ -314,10 +324,10 @@ pub async fn send_test_email(
         user: "system".to_string(),
         action: format!("Test email sent to {}", to),
         ip: "127.0.0.1".to_string(),
-        success: true,
+        success: result.is_ok(),
     });
 
-    Ok(())
+    result
 }
 
 /// Get access logs
@@ -88,6 +88,9 @@ enum Commands {
 
     /// Manage OAuth2 authentication (client credentials)
     Auth(edge_hive_core::commands::auth::AuthArgs),
+
+    /// Manage the node database (migrations, dump and restore)
+    Db(edge_hive_core::commands::db::DbArgs),
 }
 
 #[derive(Subcommand)]
@@ -137,6 +140,10 @@ async fn main() -> Result<()> {
             let data_dir = expand_path(&cli.config_dir);
             edge_hive_core::commands::auth::run(args, &data_dir).await?;
         }
+        Commands::Db(args) => {
+            let data_dir = expand_path(&cli.config_dir);
+            edge_hive_core::commands::db::run(args, &data_dir).await?;
+        }
     }
 
     Ok(())